@@ -0,0 +1,245 @@
+use colorsquash_rs::{
+	difference::{self, DiffFn, RGB8},
+	selection::{HighestBits, Kmeans, SortSelect},
+	Squasher, SquasherBuilder,
+};
+use numpy::{PyArray1, PyArray2, PyArrayDyn, PyReadonlyArray2, PyReadonlyArrayDyn};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// Quantize the image, returning a tuple of the palette and the indices into
+/// it. The indices have the shape of the image without the colour axis
+#[pyfunction]
+#[pyo3(signature = (image, max_colors = 256, selector = "sorsel", difference = "rgb", tolerance = None, scale = 100))]
+fn quantize<'py>(
+	py: Python<'py>,
+	image: PyReadonlyArrayDyn<'py, u8>,
+	max_colors: usize,
+	selector: &str,
+	difference: &str,
+	tolerance: Option<f32>,
+	scale: u8,
+) -> PyResult<(&'py PyArray2<u8>, &'py PyArrayDyn<u8>)> {
+	let (rgb, shape) = image_data(&image)?;
+	let difference = difference_fn(difference)?;
+
+	let mut squasher = builder(max_colors, selector, difference, tolerance, scale)?.build(rgb);
+	let indices = map_indices(&mut squasher, rgb, &shape, py)?;
+
+	Ok((palette_array(squasher.palette(), py)?, indices))
+}
+
+/// Select a palette for the image, returning it as an `(n, 3)` array
+#[pyfunction]
+#[pyo3(signature = (image, max_colors = 256, selector = "sorsel", difference = "rgb", tolerance = None, scale = 100))]
+fn palette<'py>(
+	py: Python<'py>,
+	image: PyReadonlyArrayDyn<'py, u8>,
+	max_colors: usize,
+	selector: &str,
+	difference: &str,
+	tolerance: Option<f32>,
+	scale: u8,
+) -> PyResult<&'py PyArray2<u8>> {
+	let (rgb, _) = image_data(&image)?;
+	let difference = difference_fn(difference)?;
+
+	let squasher = builder(max_colors, selector, difference, tolerance, scale)?.build(rgb);
+	palette_array(squasher.palette(), py)
+}
+
+/// Map every pixel of the image to the closest colour in an `(n, 3)` palette
+#[pyfunction]
+#[pyo3(signature = (image, palette, difference = "rgb"))]
+fn map<'py>(
+	py: Python<'py>,
+	image: PyReadonlyArrayDyn<'py, u8>,
+	palette: PyReadonlyArray2<'py, u8>,
+	difference: &str,
+) -> PyResult<&'py PyArrayDyn<u8>> {
+	let (rgb, shape) = image_data(&image)?;
+	let difference = difference_fn(difference)?;
+
+	let palette = palette_colors(&palette)?;
+	let mut squasher = SquasherBuilder::new()
+		.mapper_difference(difference)
+		.build_with_palette(palette);
+
+	map_indices(&mut squasher, rgb, &shape, py)
+}
+
+/// A palette kept between calls, so the frames of a video or a batch of
+/// sprites can be mapped to the same colours without selecting it every time
+#[pyclass(name = "Squasher", unsendable)]
+struct PySquasher {
+	squasher: Squasher<u8>,
+}
+
+#[pymethods]
+impl PySquasher {
+	/// Select a palette for the image, like `palette` does
+	#[new]
+	#[pyo3(signature = (image, max_colors = 256, selector = "sorsel", difference = "rgb", tolerance = None, scale = 100))]
+	fn new(
+		image: PyReadonlyArrayDyn<u8>,
+		max_colors: usize,
+		selector: &str,
+		difference: &str,
+		tolerance: Option<f32>,
+		scale: u8,
+	) -> PyResult<Self> {
+		let (rgb, _) = image_data(&image)?;
+		let difference = difference_fn(difference)?;
+
+		Ok(Self {
+			squasher: builder(max_colors, selector, difference, tolerance, scale)?.build(rgb),
+		})
+	}
+
+	/// Map to an `(n, 3)` palette you already have instead of selecting one
+	#[staticmethod]
+	#[pyo3(signature = (palette, difference = "rgb"))]
+	fn from_palette(palette: PyReadonlyArray2<u8>, difference: &str) -> PyResult<Self> {
+		let difference = difference_fn(difference)?;
+		let palette = palette_colors(&palette)?;
+
+		Ok(Self {
+			squasher: SquasherBuilder::new()
+				.mapper_difference(difference)
+				.build_with_palette(palette),
+		})
+	}
+
+	/// The palette as an `(n, 3)` array
+	#[getter]
+	fn palette<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray2<u8>> {
+		palette_array(self.squasher.palette(), py)
+	}
+
+	/// Select a new palette from another image, with the same settings
+	fn recolor(&mut self, image: PyReadonlyArrayDyn<u8>) -> PyResult<()> {
+		let (rgb, _) = image_data(&image)?;
+		self.squasher.recolor(rgb);
+		Ok(())
+	}
+
+	/// Map every pixel of the image to its closest colour in the palette
+	fn map<'py>(
+		&mut self,
+		py: Python<'py>,
+		image: PyReadonlyArrayDyn<'py, u8>,
+	) -> PyResult<&'py PyArrayDyn<u8>> {
+		let (rgb, shape) = image_data(&image)?;
+		map_indices(&mut self.squasher, rgb, &shape, py)
+	}
+}
+
+#[pymodule]
+fn colorsquash(_py: Python, m: &PyModule) -> PyResult<()> {
+	m.add_function(wrap_pyfunction!(quantize, m)?)?;
+	m.add_function(wrap_pyfunction!(palette, m)?)?;
+	m.add_function(wrap_pyfunction!(map, m)?)?;
+	m.add_class::<PySquasher>()?;
+	Ok(())
+}
+
+fn builder(
+	max_colors: usize,
+	selector: &str,
+	difference: &'static DiffFn,
+	tolerance: Option<f32>,
+	scale: u8,
+) -> PyResult<SquasherBuilder<u8>> {
+	if max_colors == 0 || max_colors > 256 {
+		return Err(PyValueError::new_err("max_colors must be >= 1 and <= 256"));
+	}
+
+	if scale == 0 || scale > 100 {
+		return Err(PyValueError::new_err("scale must be >= 1 and <= 100"));
+	}
+
+	let builder = SquasherBuilder::new()
+		.max_colors((max_colors - 1) as u8)
+		.mapper_difference(difference)
+		.scale(scale);
+
+	Ok(match selector {
+		"sorsel" | "sort/select" => {
+			let mut sorsel = SortSelect::default().difference(difference);
+			if let Some(tol) = tolerance {
+				sorsel = sorsel.tolerance(tol);
+			}
+
+			builder.selector(sorsel)
+		}
+		"kmeans" => builder.selector(Kmeans {
+			max_iter: 10,
+			..Default::default()
+		}),
+		"highest-bits" => builder.selector(HighestBits::default()),
+		_ => {
+			return Err(PyValueError::new_err(format!(
+				"'{selector}' is not a selector. expected sorsel, kmeans, or highest-bits"
+			)))
+		}
+	})
+}
+
+fn difference_fn(name: &str) -> PyResult<&'static DiffFn> {
+	match name {
+		"rgb" => Ok(&difference::rgb),
+		"redmean" => Ok(&difference::redmean),
+		_ => Err(PyValueError::new_err(format!(
+			"'{name}' is not a difference function. expected rgb or redmean"
+		))),
+	}
+}
+
+/// The image as RGB and its shape without the colour axis
+fn image_data<'a>(image: &'a PyReadonlyArrayDyn<u8>) -> PyResult<(&'a [u8], Vec<usize>)> {
+	let shape = image.shape();
+	match shape.split_last() {
+		Some((3, rest)) => {
+			let data = image
+				.as_slice()
+				.map_err(|_| PyValueError::new_err("image must be C-contiguous"))?;
+
+			Ok((data, rest.to_vec()))
+		}
+		_ => Err(PyValueError::new_err(
+			"image must be uint8 with a last axis of length 3",
+		)),
+	}
+}
+
+fn palette_colors(palette: &PyReadonlyArray2<u8>) -> PyResult<Vec<RGB8>> {
+	let array = palette.as_array();
+
+	if array.ncols() != 3 || array.nrows() == 0 || array.nrows() > 256 {
+		return Err(PyValueError::new_err(
+			"palette must be an (n, 3) array where n is >= 1 and <= 256",
+		));
+	}
+
+	Ok(array
+		.rows()
+		.into_iter()
+		.map(|row| RGB8::new(row[0], row[1], row[2]))
+		.collect())
+}
+
+fn palette_array<'py>(palette: &[RGB8], py: Python<'py>) -> PyResult<&'py PyArray2<u8>> {
+	let bytes: Vec<u8> = palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+	PyArray1::from_vec(py, bytes).reshape([palette.len(), 3])
+}
+
+fn map_indices<'py>(
+	squasher: &mut Squasher<u8>,
+	rgb: &[u8],
+	shape: &[usize],
+	py: Python<'py>,
+) -> PyResult<&'py PyArrayDyn<u8>> {
+	let mut indices = vec![0; rgb.len() / 3];
+	squasher.map(rgb, &mut indices);
+
+	PyArray1::from_vec(py, indices).reshape(shape.to_vec())
+}
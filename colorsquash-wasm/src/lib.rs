@@ -0,0 +1,6 @@
+//! The WebAssembly build of colorsquash. The bindings themselves are in the
+//! `wasm` module of colorsquash; this crate only exists so the cdylib wasm-pack
+//! needs is built here and not for everyone depending on colorsquash. See the
+//! `js` directory for the package that uses it.
+
+pub use colorsquash_rs::wasm::*;
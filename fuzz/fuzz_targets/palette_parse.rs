@@ -0,0 +1,20 @@
+#![no_main]
+
+use colorsquash::palette::parse::{self, Format};
+use libfuzzer_sys::fuzz_target;
+
+// run with `cargo +nightly fuzz run palette_parse` from the root of the repo
+fuzz_target!(|data: &[u8]| {
+	for format in [
+		Format::Gpl,
+		Format::Act,
+		Format::Ase,
+		Format::Pal,
+		Format::Hex,
+	] {
+		if let Ok(palette) = parse::parse(data, format) {
+			assert!(!palette.is_empty());
+			assert!(palette.len() <= parse::MAX_COLORS);
+		}
+	}
+});
@@ -0,0 +1,24 @@
+export interface QuantizeOptions {
+	/** Number of colours in the palette, 1 to 256. Default 256 */
+	maxColors?: number;
+	/** 3 for RGB pixels or 4 for RGBA. Default 4 */
+	channels?: 3 | 4;
+	/** How different colours have to be to enter the palette. Default 3 */
+	tolerance?: number;
+}
+
+export interface Quantized {
+	/** RGB, three bytes per colour */
+	palette: Uint8Array;
+	/** One palette index per pixel */
+	indices: Uint8Array;
+	width: number;
+	height: number;
+}
+
+export function quantize(
+	pixels: Uint8Array | Uint8ClampedArray,
+	width: number,
+	height: number,
+	options?: QuantizeOptions,
+): Promise<Quantized>;
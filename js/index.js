@@ -0,0 +1,43 @@
+import init, { quantize as wasmQuantize } from "./pkg/colorsquash.js";
+
+let ready = null;
+
+// the web build fetches the .wasm next to it, but Node can't fetch file: URLs,
+// so there it's read from disk and handed over
+async function load() {
+	if (typeof process !== "undefined" && process.versions?.node) {
+		const { readFile } = await import("node:fs/promises");
+		const bytes = await readFile(new URL("./pkg/colorsquash_bg.wasm", import.meta.url));
+		return init({ module_or_path: bytes });
+	}
+
+	return init();
+}
+
+/**
+ * Quantize an image. `pixels` is a Uint8Array or Uint8ClampedArray of RGB or
+ * RGBA pixels, like the `data` of a canvas ImageData.
+ */
+export async function quantize(pixels, width, height, options = {}) {
+	const { maxColors = 256, channels = 4, tolerance } = options;
+
+	ready ??= load();
+	await ready;
+
+	// a view of only the pixels, as they might be part of a larger buffer
+	const bytes =
+		pixels instanceof Uint8Array
+			? pixels
+			: new Uint8Array(pixels.buffer, pixels.byteOffset, pixels.byteLength);
+	const result = wasmQuantize(bytes, width, height, channels, maxColors, tolerance);
+
+	const quantized = {
+		palette: result.palette,
+		indices: result.indices,
+		width: result.width,
+		height: result.height,
+	};
+	result.free();
+
+	return quantized;
+}
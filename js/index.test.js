@@ -0,0 +1,24 @@
+import { test } from "node:test";
+import assert from "node:assert/strict";
+
+import { quantize } from "./index.js";
+
+test("quantizes in node", async () => {
+	// two red pixels and two blue ones, RGBA
+	const pixels = new Uint8ClampedArray([
+		255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 255,
+	]);
+	const { palette, indices, width, height } = await quantize(pixels, 2, 2, { maxColors: 2 });
+
+	assert.equal(width, 2);
+	assert.equal(height, 2);
+	assert.equal(palette.length, 6);
+	assert.equal(indices.length, 4);
+	assert.equal(indices[0], indices[1]);
+	assert.equal(indices[2], indices[3]);
+	assert.notEqual(indices[0], indices[2]);
+});
+
+test("rejects the wrong number of pixels", async () => {
+	await assert.rejects(quantize(new Uint8Array(5), 2, 2));
+});
@@ -0,0 +1,384 @@
+//! Runs every selector over a directory of images and reports how good each
+//! palette is and how long it took, so a new selector can be compared against
+//! the others on the same images instead of by eye.
+//!
+//! `cargo run --release --bin quality-report -- <directory>`, see `help=`.
+
+use std::{
+	fmt::Write as _,
+	time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use camino::Utf8PathBuf;
+use colorsquash::{
+	difference,
+	metrics::{self, Measurements},
+	selection::{
+		self, Agglomerative, BestOf, BinarySplit, GridKmeans, HeuristicSorsel, HighestBits, Kmeans,
+		MiniBatchKmeans, SortSelect,
+	},
+	Squasher, SquasherBuilder,
+};
+
+// the CLI's image loading. saving isn't used here
+#[allow(dead_code)]
+#[path = "../image.rs"]
+mod image;
+
+/// Every selector included with the crate, configured the way squash uses them
+const SELECTORS: &[&str] = &[
+	"sorsel",
+	"heuristic",
+	"kmeans",
+	"grid-kmeans",
+	"minibatch-kmeans",
+	"agglomerative",
+	"binary-split",
+	"highest-bits",
+	"best",
+];
+
+fn with_selector(builder: SquasherBuilder<u8>, name: &str) -> SquasherBuilder<u8> {
+	match name {
+		"sorsel" => builder.selector(SortSelect::default()),
+		"heuristic" => builder.selector(HeuristicSorsel::default()),
+		"kmeans" => builder.selector(Kmeans {
+			max_iter: 10,
+			..Default::default()
+		}),
+		"grid-kmeans" => builder.selector(GridKmeans::default()),
+		"minibatch-kmeans" => builder.selector(MiniBatchKmeans::default()),
+		"agglomerative" => builder.selector(Agglomerative::default()),
+		"binary-split" => builder.selector(BinarySplit {}),
+		"highest-bits" => builder.selector(HighestBits::default()),
+		"best" => builder.selector(BestOf::default()),
+		_ => unreachable!(),
+	}
+}
+
+struct Options {
+	corpus: Utf8PathBuf,
+	/// One minus max, like [SquasherBuilder::max_colors]
+	colors: u8,
+	scale: u8,
+	selectors: Vec<&'static str>,
+	csv: Option<Utf8PathBuf>,
+	html: Option<Utf8PathBuf>,
+}
+
+/// One selector on one image
+struct Row {
+	image: String,
+	selector: &'static str,
+	palette_len: usize,
+	select: Duration,
+	map: Duration,
+	psnr: f64,
+	measurements: Measurements,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+	let options = options();
+
+	let mut paths: Vec<Utf8PathBuf> = options
+		.corpus
+		.read_dir_utf8()?
+		.filter_map(Result::ok)
+		.map(|entry| entry.path().to_path_buf())
+		.filter(|path| matches!(path.extension(), Some("png" | "jpg" | "jpeg")))
+		.collect();
+	paths.sort();
+
+	if paths.is_empty() {
+		bail!("no png or jpeg images in {}", options.corpus);
+	}
+
+	let mut rows = vec![];
+	for path in &paths {
+		let name = path.file_name().unwrap_or(path.as_str()).to_owned();
+		eprintln!("{name}");
+
+		let mut image = match path.extension() {
+			Some("png") => image::get_png(path)?,
+			_ => image::get_jpg(path)?,
+		};
+		image.expand_gray();
+
+		for &selector_name in &options.selectors {
+			rows.push(measure(&options, &name, &image.data, selector_name));
+		}
+	}
+
+	let csv = csv(&rows);
+	match &options.csv {
+		Some(path) => std::fs::write(path, csv)?,
+		None if options.html.is_none() => print!("{csv}"),
+		None => (),
+	}
+
+	if let Some(path) = &options.html {
+		std::fs::write(path, html(&options, &rows))?;
+	}
+
+	Ok(())
+}
+
+fn measure(options: &Options, image: &str, rgb: &[u8], selector_name: &'static str) -> Row {
+	let start = Instant::now();
+	let builder = SquasherBuilder::new()
+		.max_colors(options.colors)
+		.scale(options.scale);
+	let mut squasher: Squasher<u8> = with_selector(builder, selector_name).build(rgb);
+	let select = start.elapsed();
+
+	let mut indices = vec![0; rgb.len() / 3];
+	let start = Instant::now();
+	squasher.map(rgb, &mut indices);
+	let map = start.elapsed();
+
+	let palette = squasher.palette();
+	let quantized: Vec<u8> = indices
+		.iter()
+		.flat_map(|&idx| {
+			let colour = palette[idx as usize];
+			[colour.r, colour.g, colour.b]
+		})
+		.collect();
+
+	Row {
+		image: image.to_owned(),
+		selector: selector_name,
+		palette_len: palette.len(),
+		select,
+		map,
+		psnr: metrics::psnr(rgb, &quantized),
+		measurements: Measurements::new(&selection::histogram(rgb), palette, &difference::rgb),
+	}
+}
+
+fn csv(rows: &[Row]) -> String {
+	let mut csv = String::from(
+		"image,selector,colors,select_ms,map_ms,psnr,mean_error,max_error,diversity,bits_per_pixel\n",
+	);
+
+	for row in rows {
+		// names with commas or quotes have to be quoted
+		let image = if row.image.contains([',', '"']) {
+			format!("\"{}\"", row.image.replace('"', "\"\""))
+		} else {
+			row.image.clone()
+		};
+
+		writeln!(
+			csv,
+			"{image},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+			row.selector,
+			row.palette_len,
+			millis(row.select),
+			millis(row.map),
+			row.psnr,
+			row.measurements.mean_error,
+			row.measurements.max_error,
+			row.measurements.diversity,
+			row.measurements.bits_per_pixel,
+		)
+		.unwrap();
+	}
+
+	csv
+}
+
+fn html(options: &Options, rows: &[Row]) -> String {
+	let mut html = String::new();
+	html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+	html.push_str("<title>colorsquash quality report</title>\n");
+	html.push_str("<style>\n");
+	html.push_str("body { font-family: sans-serif; }\n");
+	html.push_str("table { border-collapse: collapse; margin-bottom: 2em; }\n");
+	html.push_str("th, td { border: 1px solid #999; padding: 0.2em 0.6em; text-align: right; }\n");
+	html.push_str("th:first-child, td:first-child { text-align: left; }\n");
+	html.push_str(".best { font-weight: bold; background: #dfd; }\n");
+	html.push_str("</style>\n</head>\n<body>\n");
+	writeln!(
+		html,
+		"<h1>quality report</h1>\n<p>{} images from {}, {} colors, scale {}%</p>",
+		rows.len() / options.selectors.len().max(1),
+		escape(options.corpus.as_str()),
+		options.colors as usize + 1,
+		options.scale,
+	)
+	.unwrap();
+
+	// averaged over every image so one number per selector can be compared
+	html.push_str("<h2>summary</h2>\n<table>\n");
+	html.push_str("<tr><th>selector</th><th>mean PSNR</th><th>worst PSNR</th><th>mean error</th><th>total select ms</th><th>total map ms</th></tr>\n");
+	let summaries: Vec<(&str, f64, f64, f64, Duration, Duration)> = options
+		.selectors
+		.iter()
+		.map(|&name| {
+			let mine: Vec<&Row> = rows.iter().filter(|row| row.selector == name).collect();
+			let count = mine.len().max(1) as f64;
+
+			(
+				name,
+				mine.iter().map(|row| row.psnr).sum::<f64>() / count,
+				mine.iter()
+					.map(|row| row.psnr)
+					.fold(f64::INFINITY, f64::min),
+				mine.iter()
+					.map(|row| row.measurements.mean_error as f64)
+					.sum::<f64>() / count,
+				mine.iter().map(|row| row.select).sum(),
+				mine.iter().map(|row| row.map).sum(),
+			)
+		})
+		.collect();
+	let best_mean = summaries
+		.iter()
+		.map(|summary| summary.1)
+		.fold(f64::NEG_INFINITY, f64::max);
+
+	for (name, mean, worst, error, select, map) in &summaries {
+		let class = if *mean == best_mean {
+			" class=\"best\""
+		} else {
+			""
+		};
+		writeln!(
+			html,
+			"<tr><td>{name}</td><td{class}>{mean:.2}</td><td>{worst:.2}</td><td>{error:.2}</td><td>{:.1}</td><td>{:.1}</td></tr>",
+			millis(*select),
+			millis(*map),
+		)
+		.unwrap();
+	}
+	html.push_str("</table>\n");
+
+	html.push_str("<h2>images</h2>\n<table>\n");
+	html.push_str("<tr><th>image</th><th>selector</th><th>colors</th><th>select ms</th><th>map ms</th><th>PSNR</th><th>mean error</th><th>max error</th><th>diversity</th><th>bits/pixel</th></tr>\n");
+	for image in rows.chunks(options.selectors.len().max(1)) {
+		let best = image
+			.iter()
+			.map(|row| row.psnr)
+			.fold(f64::NEG_INFINITY, f64::max);
+
+		for row in image {
+			let class = if row.psnr == best {
+				" class=\"best\""
+			} else {
+				""
+			};
+			writeln!(
+				html,
+				"<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td><td>{:.1}</td><td{class}>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td></tr>",
+				escape(&row.image),
+				row.selector,
+				row.palette_len,
+				millis(row.select),
+				millis(row.map),
+				row.psnr,
+				row.measurements.mean_error,
+				row.measurements.max_error,
+				row.measurements.diversity,
+				row.measurements.bits_per_pixel,
+			)
+			.unwrap();
+		}
+	}
+	html.push_str("</table>\n</body>\n</html>\n");
+
+	html
+}
+
+fn millis(duration: Duration) -> f64 {
+	duration.as_secs_f64() * 1000.0
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+fn options() -> Options {
+	let mut corpus = None;
+	let mut options = Options {
+		corpus: Utf8PathBuf::new(),
+		colors: 255,
+		scale: 25,
+		selectors: SELECTORS.to_vec(),
+		csv: None,
+		html: None,
+	};
+
+	for arg in std::env::args().skip(1) {
+		match arg.split_once('=') {
+			None => {
+				if corpus.replace(Utf8PathBuf::from(arg)).is_some() {
+					die("only one corpus directory can be given");
+				}
+			}
+			Some(("colors", value)) | Some(("clrs", value)) => match value.parse::<usize>() {
+				Ok(count) if (1..=256).contains(&count) => options.colors = (count - 1) as u8,
+				_ => die("colors must be a whole number > 0 and <= 256"),
+			},
+			Some(("scale", value)) => match value.parse::<u8>() {
+				Ok(scale) if (1..=100).contains(&scale) => options.scale = scale,
+				_ => die("scale must be a whole number > 0 and <= 100"),
+			},
+			Some(("selectors", value)) | Some(("sel", value)) => {
+				options.selectors = value
+					.split(',')
+					.map(
+						|name| match SELECTORS.iter().find(|&&known| known == name) {
+							Some(known) => *known,
+							None => die(&format!(
+								"'{name}' isn't a selector. one of: {}",
+								SELECTORS.join(", ")
+							)),
+						},
+					)
+					.collect();
+			}
+			Some(("csv", path)) => options.csv = Some(path.into()),
+			Some(("html", path)) => options.html = Some(path.into()),
+			Some(("help", _)) => print_help(),
+			Some((key, _)) => die(&format!("unrecognised key {key}")),
+		}
+	}
+
+	match corpus {
+		Some(path) if path.is_dir() => options.corpus = path,
+		Some(path) => die(&format!("{path} isn't a directory")),
+		None => die("didn't get a corpus directory! 'help=' for help"),
+	}
+
+	options
+}
+
+fn die(message: &str) -> ! {
+	eprintln!("{message}");
+	std::process::exit(1);
+}
+
+fn print_help() -> ! {
+	println!("usage: quality-report [arguments ...] <directory>\n");
+	println!("<directory> a directory of jpeg and png images to quantize. every");
+	println!("            selector is run on every image and compared.\n");
+	println!("ARGUMENTS:");
+	println!("    colors=<int> | clrs=<int>");
+	println!("        the number of colours to select. [Default 256]\n");
+	println!("    scale=<int>");
+	println!("        the percent of pixels to select from, like squash. [Default 25]\n");
+	println!("    selectors=<name>,... | sel=<name>,...");
+	println!("        which selectors to run. [Default all of them]");
+	println!("        {}\n", SELECTORS.join(", "));
+	println!("    csv=<file>");
+	println!("        write a row for each image and selector to a CSV file. if neither");
+	println!("        this nor html= is given the CSV is printed instead\n");
+	println!("    html=<file>");
+	println!("        write a page with the same table and a summary of each selector");
+	std::process::exit(0)
+}
@@ -1,18 +1,123 @@
-use std::cmp::Ordering;
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Display,
+	str::FromStr,
+};
 
-use camino::Utf8PathBuf;
-use colorsquash::difference::{self, DiffFn};
+use camino::{Utf8Path, Utf8PathBuf};
+use colorsquash::{
+	difference::{self, SyncDiffFn},
+	metrics::{Objective, Target},
+	palette::parse::Format,
+	sweep::Range,
+};
+
+use crate::preset;
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+
+/// What to squash: one image, or many with the same settings. Or two images to
+/// compare
+pub enum Run {
+	One(Box<Cli>),
+	Batch {
+		images: Vec<Cli>,
+		/// how many images are squashed at once
+		threads: usize,
+	},
+	Compare {
+		original: (Utf8PathBuf, InType),
+		quantized: (Utf8PathBuf, InType),
+		json: bool,
+	},
+}
+
+/// The first argument, if it's one of [COMMANDS]. Without one it's quantize,
+/// which is how squash always worked
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Command {
+	Quantize,
+	Palette,
+	Apply,
+	Compare,
+}
 
 pub struct Cli {
 	pub color_count: u8,
-	pub tolerance: Option<f32>,
+	pub tolerance: f32,
 	pub scale: u8,
 	pub selector: Selector,
-	pub difference: &'static DiffFn,
+	/// how far heuristic moves the tolerance at first
+	pub variance: f32,
+	/// how many times heuristic moves the tolerance at most
+	pub attempts: usize,
+	/// how many tolerances heuristic tries each time it moves
+	pub candidates: usize,
+	/// tolerances heuristic searches from instead of tolerance=
+	pub starts: Option<Vec<f32>>,
+	/// the bits of red, green, and blue highest-bits keeps
+	pub bits: Option<[u8; 3]>,
+	/// iterations of k-means to polish the selected palette with
+	pub refine: usize,
+	/// move the selected colours to the closest colours in the image
+	pub snap: bool,
+	pub difference: &'static SyncDiffFn,
+	/// an option the grayscale path doesn't have was given, like sel= or
+	/// difference=, so grayscale images are squashed as RGB
+	pub rgb_selection: bool,
+	/// a palette file to use instead of selecting one
+	pub palette: Option<(Utf8PathBuf, PaletteFormat)>,
+	/// an earlier palette whose entries keep their index if they're still used
+	pub anchor: Option<(Utf8PathBuf, PaletteFormat)>,
+	/// percent of the image an anchor has to be closest to for it to be kept
+	pub anchor_usage: f32,
+	/// where to write the full colour map for the palette
+	pub emit_map: Option<Utf8PathBuf>,
+	/// where to save the colours counted from the image
+	pub histogram_out: Option<Utf8PathBuf>,
+	/// colours counted earlier to select the palette from instead of counting
+	pub histogram_in: Option<Utf8PathBuf>,
+	/// try a range of values for a setting and keep the best
+	pub sweep: Option<(Knob, Range)>,
+	/// what sweep= thinks is best, if not the PSNR
+	pub objective: Option<Objective>,
+	/// use the fewest colours that are this good
+	pub target: Option<Target>,
+	/// percent of the palette that should come out the same from a different
+	/// sample of the image
+	pub stable: Option<f32>,
+	/// percent of the image that has to be nearly white or black to pin them
+	pub auto_white_black: Option<f32>,
+	/// rectangles of the image that count more when selecting the palette
+	pub focus: Vec<Focus>,
+	/// how much more pixels that stand out from their neighbours count
+	pub saliency: Option<f32>,
+	/// compare the quantized image to the input and check it's good enough
+	pub verify: Option<Verify>,
+	/// print a line of JSON about the image to stdout
+	pub json: bool,
+	/// where to write the input and output next to each other
+	pub compare: Option<Compare>,
+	/// what to scale the image to before it's squashed
+	pub resize: Option<Resize>,
+	/// draw a progress bar for big images. not when a batch is squashing
+	/// several at once, their bars would be drawn over each other
+	pub progress: bool,
+	pub resize_filter: ResizeFilter,
+	/// whether text, EXIF, and XMP are copied from the input to the output
+	pub metadata: Metadata,
+	/// whether gamma, chromaticities, sRGB, and ICC profiles are, apart from
+	/// metadata=
+	pub color_profile: Metadata,
+	pub dither: Dither,
+	/// how much of the usual amount of dithering, 1.0 being all of it
+	pub dither_strength: f32,
+	/// every setting and the versions that made the image, to write into it
+	/// so it can be made again exactly
+	pub archive: Option<String>,
 	pub input: Utf8PathBuf,
 	pub in_type: InType,
 	pub output: Utf8PathBuf,
@@ -22,60 +127,237 @@ pub struct Cli {
 
 // It's not a builder, but I think the builder/building name is useful
 // here because it's used while not all things are populated.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 struct BuildingCli {
-	pub color_count: Option<u8>,
-	pub tolerance: Option<f32>,
-	pub scale: Option<u8>,
+	/// One minus max
+	pub color_count: u8,
+	pub tolerance: f32,
+	pub scale: u8,
 	pub difference: DifferenceFn,
 	pub selector: Selector,
+	pub variance: f32,
+	pub attempts: usize,
+	pub candidates: usize,
+	pub starts: Option<Vec<f32>>,
+	pub bits: Option<[u8; 3]>,
+	pub refine: usize,
+	pub snap: bool,
+	pub palette: Option<Utf8PathBuf>,
+	pub anchor: Option<Utf8PathBuf>,
+	pub anchor_usage: f32,
+	pub emit_map: Option<Utf8PathBuf>,
+	pub histogram_out: Option<Utf8PathBuf>,
+	pub histogram_in: Option<Utf8PathBuf>,
+	pub sweep: Option<(Knob, Range)>,
+	pub objective: Option<Objective>,
+	pub target: Option<f32>,
+	pub stable: Option<f32>,
+	pub auto_white_black: Option<f32>,
+	pub focus: Vec<Focus>,
+	pub saliency: Option<f32>,
+	pub verify: bool,
+	pub min_psnr: Option<f64>,
+	pub json: bool,
+	pub compare: Option<Utf8PathBuf>,
+	pub compare_layout: Layout,
+	pub compare_heatmap: bool,
+	pub resize: Option<Resize>,
+	pub resize_filter: ResizeFilter,
+	pub metadata: Metadata,
+	pub color_profile: Metadata,
+	pub dither: Dither,
+	pub dither_strength: f32,
+	pub archival: bool,
+	pub format: OutType,
+	pub threads: usize,
 	pub verbose: bool,
+	/// Each argument given on the command line by its name, rather than an
+	/// alias, and the value, in order
+	pub arguments: Vec<(&'static str, String)>,
+	/// The names of the arguments given on the command line, as opposed to
+	/// being left at their defaults
+	pub given: HashSet<&'static str>,
+	/// The names of the arguments that came from preset= and weren't given
+	/// again on the command line. A command that doesn't use one leaves it out
+	/// rather than refusing it
+	pub preset: HashSet<&'static str>,
 }
 
 impl BuildingCli {
-	// One minus max
-	const DEFAULT_COLORS: u8 = 255;
+	/// Whether the argument was given on the command line, rather than only by
+	/// a preset or not at all
+	fn on_command_line(&self, name: &str) -> bool {
+		self.given.contains(name) && !self.preset.contains(name)
+	}
 
-	pub fn build_or_die(self, input: &str, output: &str) -> Cli {
+	fn build_or_die(mut self, input: &str, output: &str, command: Command) -> Cli {
 		let input: Utf8PathBuf = input.into();
-		let in_type = match input.extension() {
-			None => {
-				eprintln!("can't determine input filetype!\nSupported input types: PNG, JPG");
-				std::process::exit(1);
-			}
-			Some("png") => InType::Png,
-			Some("jpg") | Some("jpeg") => InType::Jpeg,
-			Some(ext) => {
-				eprintln!("unknown filetype '{ext}'!\nSupported input types: PNG, JPG");
-				std::process::exit(1);
-			}
-		};
+		let in_type = in_type_or_die(&input);
 
 		let output: Utf8PathBuf = output.into();
-		let out_type = match output.extension() {
-			None => {
-				eprintln!("can't determine output filetype!");
+		let out_type =
+			match (output.extension(), command) {
+				(None, _) => {
+					eprintln!("can't determine output filetype!");
+					std::process::exit(1);
+				}
+				(Some(ext), Command::Palette) => match Export::from_extension(ext) {
+					Some(export) => OutType::Palette(export),
+					None => {
+						eprintln!("unknown palette filetype '{ext}'!\nSupported palette types: GPL, ACT, HEX");
+						std::process::exit(1);
+					}
+				},
+				(Some("png"), _) => OutType::Png,
+				(Some("gif"), _) => OutType::Gif,
+				(Some(ext), _) => {
+					eprintln!("unknown filetype '{ext}'!\nSupport output types are: GIF, PNG");
+					std::process::exit(1);
+				}
+			};
+
+		let difference = match self.difference {
+			DifferenceFn::Rgb => &difference::rgb as &SyncDiffFn,
+			DifferenceFn::Redmean => &difference::redmean as &SyncDiffFn,
+		};
+
+		let palette = self.palette.take().map(palette_format);
+		let anchor = self.anchor.take().map(palette_format);
+
+		// grayscale is picked with sorsel and tolerance= alone
+		let rgb_selection = [
+			"scale",
+			"stable",
+			"difference",
+			"selector",
+			"refine",
+			"snap",
+			"auto-bw",
+		]
+		.iter()
+		.any(|name| self.given.contains(name));
+
+		if anchor.is_some() && palette.is_some() {
+			eprintln!("anchor= can't be used with palette=, there's nothing to select");
+			std::process::exit(1);
+		}
+
+		// stable= samples the image itself however many times it needs
+		if self.stable.is_some() && (self.histogram_in.is_some() || self.histogram_out.is_some()) {
+			eprintln!("stable= can't be used with histogram-in= or histogram-out=");
+			std::process::exit(1);
+		}
+
+		if self.on_command_line("bits") && !matches!(self.selector, Selector::HighestBits) {
+			eprintln!("bits= is only used by selector=highest-bits");
+			std::process::exit(1);
+		}
+
+		let heuristic_only = ["variance", "attempts", "candidates", "starts"];
+		if heuristic_only.iter().any(|name| self.on_command_line(name))
+			&& !matches!(self.selector, Selector::HeuristicSorsel)
+		{
+			eprintln!(
+				"variance=, attempts=, candidates=, and starts= are only used by selector=heuristic"
+			);
+			std::process::exit(1);
+		}
+
+		if self.objective.is_some() && self.sweep.is_none() && self.target.is_none() {
+			eprintln!("score= is only used by sweep= and target=");
+			std::process::exit(1);
+		}
+
+		if self.target.is_some() && (palette.is_some() || self.sweep.is_some()) {
+			eprintln!("target= can't be used with palette= or sweep=");
+			std::process::exit(1);
+		}
+
+		if self.resize.is_none() && self.on_command_line("resize-filter") {
+			eprintln!("resize-filter= is only used by resize=");
+			std::process::exit(1);
+		}
+
+		let compare_only = ["compare-layout", "compare-heatmap"];
+		if self.compare.is_none() && compare_only.iter().any(|name| self.on_command_line(name)) {
+			eprintln!("compare-layout= and compare-heatmap= are only used by compare=");
+			std::process::exit(1);
+		}
+
+		if self.json && (self.verbose || self.sweep.is_some()) {
+			eprintln!("json= can't be used with loud= or sweep=, they print to stdout too");
+			std::process::exit(1);
+		}
+
+		if let Some((knob, _)) = &self.sweep {
+			if palette.is_some() || self.histogram_in.is_some() || self.stable.is_some() {
+				eprintln!("sweep= can't be used with palette=, histogram-in=, or stable=");
 				std::process::exit(1);
 			}
-			Some("png") => OutType::Png,
-			Some("gif") => OutType::Gif,
-			Some(ext) => {
-				eprintln!("unknown filetype '{ext}'!\nSupport output types are: GIF, PNG");
+
+			if *knob == Knob::Tolerance && !matches!(self.selector, Selector::SortSelect) {
+				eprintln!("sweep=tolerance only does anything with selector=sorsel");
 				std::process::exit(1);
 			}
-		};
+		}
 
-		let difference = match self.difference {
-			DifferenceFn::Rgb => &difference::rgb as &DiffFn,
-			DifferenceFn::Redmean => &difference::redmean as &DiffFn,
-		};
+		if self.archival && !matches!(out_type, OutType::Png) {
+			if self.on_command_line("archival") {
+				eprintln!("archival= needs a png output to write the settings into");
+				std::process::exit(1);
+			}
+			// a preset's archival= only applies where there's a png to write to
+			self.archival = false;
+		}
+		let archive = self.archival.then(|| self.archive(&input));
 
 		Cli {
-			color_count: self.color_count.unwrap_or(Self::DEFAULT_COLORS),
+			color_count: self.color_count,
 			tolerance: self.tolerance,
 			selector: self.selector,
-			scale: self.scale.unwrap_or(25),
+			variance: self.variance,
+			attempts: self.attempts,
+			candidates: self.candidates,
+			starts: self.starts,
+			bits: self.bits,
+			refine: self.refine,
+			snap: self.snap,
+			scale: self.scale,
 			difference,
+			rgb_selection,
+			palette,
+			anchor,
+			anchor_usage: self.anchor_usage,
+			emit_map: self.emit_map,
+			histogram_out: self.histogram_out,
+			histogram_in: self.histogram_in,
+			sweep: self.sweep,
+			objective: self.objective,
+			target: self.target.map(|max_score| Target {
+				objective: self.objective.unwrap_or_default(),
+				max_score,
+			}),
+			stable: self.stable,
+			auto_white_black: self.auto_white_black,
+			focus: self.focus,
+			saliency: self.saliency,
+			verify: (self.verify || self.min_psnr.is_some()).then_some(Verify {
+				min_psnr: self.min_psnr,
+			}),
+			json: self.json,
+			compare: self.compare.map(|path| Compare {
+				path,
+				layout: self.compare_layout,
+				heatmap: self.compare_heatmap,
+			}),
+			resize: self.resize,
+			resize_filter: self.resize_filter,
+			progress: true,
+			metadata: self.metadata,
+			color_profile: self.color_profile,
+			dither: self.dither,
+			dither_strength: self.dither_strength,
+			archive,
 			input,
 			in_type,
 			output,
@@ -85,191 +367,1749 @@ impl BuildingCli {
 	}
 }
 
+impl BuildingCli {
+	/// The versions, and every argument that changes the output with its value,
+	/// one to a line. Those that weren't given are there with their defaults,
+	/// so a new default doesn't change how the image is made again.
+	fn archive(&self, input: &Utf8Path) -> String {
+		let mut archive = format!(
+			"made by {NAME} {VERSION} with colorsquash {} from {}\n",
+			colorsquash::VERSION,
+			input.file_name().unwrap_or(input.as_str())
+		);
+
+		let defaults = ARGS.iter().filter_map(|arg| {
+			let default = arg.default?;
+			(!self.given.contains(arg.name())).then(|| (arg.name(), default.to_owned()))
+		});
+
+		// these only change what's printed, and a preset's arguments are
+		// written themselves
+		let skipped = [
+			"loud",
+			"verify",
+			"min-psnr",
+			"json",
+			"threads",
+			"preset",
+			"compare",
+			"compare-layout",
+			"compare-heatmap",
+		];
+		for (name, value) in self.arguments.iter().cloned().chain(defaults) {
+			if skipped.contains(&name) {
+				continue;
+			}
+
+			archive.push_str(&format!("{name}={value}\n"));
+		}
+
+		archive
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Focus {
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+	pub weight: usize,
+}
+
+pub struct Verify {
+	pub min_psnr: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Compare {
+	pub path: Utf8PathBuf,
+	pub layout: Layout,
+	/// add a panel with the delta-E of each pixel
+	pub heatmap: bool,
+}
+
+/// What resize= scales the image to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Resize {
+	Percent(f32),
+	/// If only one is given the other keeps the aspect ratio
+	Size {
+		width: Option<usize>,
+		height: Option<usize>,
+	},
+}
+
+impl Resize {
+	/// The size to scale an image that's `width` by `height` to. Never less
+	/// than a pixel
+	pub fn scale(&self, width: usize, height: usize) -> (usize, usize) {
+		let by = |length: usize, factor: f32| ((length as f32 * factor).round() as usize).max(1);
+
+		match *self {
+			Resize::Percent(percent) => (by(width, percent / 100.0), by(height, percent / 100.0)),
+			Resize::Size {
+				width: Some(new_width),
+				height: Some(new_height),
+			} => (new_width, new_height),
+			Resize::Size {
+				width: Some(new_width),
+				height: None,
+			} => (new_width, by(height, new_width as f32 / width as f32)),
+			Resize::Size {
+				width: None,
+				height: Some(new_height),
+			} => (by(width, new_height as f32 / height as f32), new_height),
+			Resize::Size {
+				width: None,
+				height: None,
+			} => (width, height),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ResizeFilter {
+	#[default]
+	Area,
+	Nearest,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Layout {
+	#[default]
+	SideBySide,
+	Split,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Metadata {
+	Keep,
+	#[default]
+	Strip,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Dither {
+	#[default]
+	None,
+	FloydSteinberg,
+	Ordered,
+	Atkinson,
+}
+
+#[derive(Copy, Clone, Debug)]
 pub enum InType {
 	Jpeg,
 	Png,
+	Bmp,
+	Tga,
+	Gif,
+}
+
+impl InType {
+	/// The type of image from a file extension, without the dot. Case
+	/// insensitive.
+	fn from_extension(ext: &str) -> Option<Self> {
+		match ext.to_ascii_lowercase().as_str() {
+			"png" => Some(InType::Png),
+			"jpg" | "jpeg" => Some(InType::Jpeg),
+			"bmp" => Some(InType::Bmp),
+			"tga" => Some(InType::Tga),
+			"gif" => Some(InType::Gif),
+			_ => None,
+		}
+	}
+}
+
+/// How to read a palette file
+#[derive(Copy, Clone, Debug)]
+pub enum PaletteFormat {
+	/// one of the palette formats colorsquash parses
+	File(Format),
+	/// an image, like a swatch, whose colours are the palette in the order
+	/// they first appear
+	Image(InType),
 }
 
+#[derive(Copy, Clone, Debug, Default)]
 pub enum OutType {
+	#[default]
 	Png,
 	Gif,
+	/// just the palette, from squash palette
+	Palette(Export),
+}
+
+/// The palette files squash palette can write
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Export {
+	Gpl,
+	Act,
+	/// a hex colour a line
+	Hex,
 }
 
-#[derive(Debug, Default)]
+impl Export {
+	fn from_extension(ext: &str) -> Option<Self> {
+		match ext.to_ascii_lowercase().as_str() {
+			"gpl" => Some(Export::Gpl),
+			"act" => Some(Export::Act),
+			"hex" | "txt" => Some(Export::Hex),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default)]
 pub enum DifferenceFn {
 	#[default]
 	Rgb,
 	Redmean,
 }
 
-#[derive(Debug, Default)]
+/// The settings sweep= can try a range of
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Knob {
+	Tolerance,
+	Scale,
+	Colors,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
 pub enum Selector {
 	#[default]
 	SortSelect,
+	HeuristicSorsel,
 	Kmeans,
+	GridKmeans,
+	MiniBatchKmeans,
+	Agglomerative,
+	BinarySplit,
 	HighestBits,
+	BestOf,
 }
 
-pub fn build() -> Cli {
-	let mut free = vec![];
-	let mut building = BuildingCli::default();
+/// A key=value argument. Parsing, help=, man=, and completions= all work from
+/// the table of these, [ARGS], so they can't disagree.
+struct Arg {
+	/// The name and then any aliases. Keys starting with a dash, like `-h`,
+	/// are given on their own without an `=`.
+	keys: &'static [&'static str],
+	/// What the value looks like, like `<int>`, or nothing if it's ignored
+	value: &'static str,
+	help: &'static str,
+	/// Parsed before the command line, so the default in help= is always the
+	/// one that's used
+	default: Option<&'static str>,
+	/// The values it can be, if there's a fixed set. Offered by the
+	/// completions, and listed in help= for arguments with a value
+	choices: fn() -> Vec<&'static str>,
+	parse: fn(&mut BuildingCli, &str) -> Result<(), String>,
+}
 
-	for arg in std::env::args().skip(1) {
-		// Handle the special cases we want to obey.
-		// -h/--help are standards and, even though we're playing with a
-		// dd-style syntax, we want to respect these.
-		// we'll do -V/--version
-		if arg == "-h" || arg == "--help" {
-			print_help()
-		} else if arg == "-V" || arg == "--version" {
-			print_version()
-		}
-
-		match arg.split_once('=') {
-			None => free.push(arg),
-			Some(("colors", value)) | Some(("colours", value)) | Some(("clrs", value)) => {
-				match value.parse::<usize>() {
-					Err(_) => {
-						eprintln!("color must be a whole number > 0 and <= 256");
-						std::process::exit(1);
-					}
-					Ok(count) if count == 0 || count > 256 => {
-						eprintln!("color must be a whole number >= 1 and <= 256");
-						std::process::exit(1);
-					}
-					Ok(count) => {
-						//TODO: error if this's been set already?
-						building.color_count = Some((count - 1) as u8);
-					}
+impl Arg {
+	fn name(&self) -> &'static str {
+		self.keys[0]
+	}
+
+	/// Every way of writing it, like `colors=<int> | clrs=<int>`
+	fn usage(&self) -> String {
+		self.keys
+			.iter()
+			.map(|key| {
+				if key.starts_with('-') {
+					key.to_string()
+				} else {
+					format!("{key}={}", self.value)
 				}
+			})
+			.collect::<Vec<_>>()
+			.join(" | ")
+	}
+
+	/// The help with the choices on the end
+	fn description(&self) -> String {
+		let choices = (self.choices)();
+		if !self.value.is_empty() && !choices.is_empty() {
+			format!("{} one of: {}.", self.help, choices.join(", "))
+		} else {
+			self.help.to_owned()
+		}
+	}
+
+	/// Whether the value is a path
+	fn takes_file(&self) -> bool {
+		self.value == "<file>"
+	}
+}
+
+/// One of the values an argument with a fixed set can be
+struct Choice<T> {
+	/// The name and then any aliases
+	names: &'static [&'static str],
+	value: T,
+	help: &'static str,
+}
+
+static SELECTORS: &[Choice<Selector>] = &[
+	Choice {
+		names: &["sorsel", "sort/select"],
+		value: Selector::SortSelect,
+		help: "the original colorsquash algorithm. sorts colors from most to least \
+			frequent and then picks the most frequent colors so long as they are \
+			sufficiently different (configurable with tolerance=)",
+	},
+	Choice {
+		names: &["heuristic", "heuristic-sorsel"],
+		value: Selector::HeuristicSorsel,
+		help: "sorsel that looks for the best tolerance itself, starting from \
+			tolerance= and moving it up or down by variance= while the palette \
+			gets better, up to attempts= times. candidates= and starts= let it \
+			try more tolerances at once. loud= prints where it ended up",
+	},
+	Choice {
+		names: &["kmeans"],
+		value: Selector::Kmeans,
+		help: "uses the kmeans clustering algorithm to select colours. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["grid-kmeans"],
+		value: Selector::GridKmeans,
+		help: "kmeans on the average colours of a 32x32x32 grid rather than every \
+			colour. nearly as good as kmeans and much faster on big photos. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["minibatch-kmeans"],
+		value: Selector::MiniBatchKmeans,
+		help: "like kmeans but each iteration only looks at a sample of the colours. \
+			much faster on large images for a palette that's nearly as good. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["agglomerative"],
+		value: Selector::Agglomerative,
+		help: "groups the colours into cells and merges the closest two, weighted \
+			by how many pixels they cover, until the palette fits. very stable, \
+			and keeps a small area of a vivid colour that sorsel might lose. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["binary-split"],
+		value: Selector::BinarySplit,
+		help: "starts with every colour in one group and splits the group with the \
+			most error in two, across the way it's most spread out, until the \
+			palette fits. fast, and always the same palette for the same image. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["highest-bits"],
+		value: Selector::HighestBits,
+		help: "quantizes the colours by shifting the bits of their components until \
+			they all fit in the palette, or to the depths given by bits=. \
+			Ignores tolerance=",
+	},
+	Choice {
+		names: &["best"],
+		value: Selector::BestOf,
+		help: "runs sorsel, kmeans, agglomerative, and binary-split and keeps \
+			whichever palette is closest to the image. for when you don't know \
+			which suits it, and it takes as long as all four. \
+			Ignores tolerance=",
+	},
+];
+
+static ALGORITHMS: &[Choice<DifferenceFn>] = &[
+	Choice {
+		names: &["rgb"],
+		value: DifferenceFn::Rgb,
+		help: "a straight, rather naïve, RGB comparison. It sums the channel \
+			differences. This is it, really: \
+			|a.red - b.red| + |a.green - b.green| + |a.blue - b.blue|",
+	},
+	Choice {
+		names: &["redmean"],
+		value: DifferenceFn::Redmean,
+		help: "a slightly more intelligent algorithm that weighs the channels \
+			in an attempt to more better align with human color perception.",
+	},
+];
+
+static METADATA: &[Choice<Metadata>] = &[
+	Choice {
+		names: &["keep"],
+		value: Metadata::Keep,
+		help: "copy it",
+	},
+	Choice {
+		names: &["strip"],
+		value: Metadata::Strip,
+		help: "leave it out",
+	},
+];
+
+static DITHERS: &[Choice<Dither>] = &[
+	Choice {
+		names: &["none"],
+		value: Dither::None,
+		help: "map every pixel to its nearest colour. flat areas stay flat and \
+			gradients come out as bands",
+	},
+	Choice {
+		names: &["floyd", "floyd-steinberg"],
+		value: Dither::FloydSteinberg,
+		help: "Floyd-Steinberg error diffusion. the difference between each pixel \
+			and its colour is spread onto the pixels to the right and below, so \
+			gradients come out smooth as a fine, irregular grain. the best \
+			looking for photos, but it compresses worse and a small change to \
+			the image changes the grain everywhere after it",
+	},
+	Choice {
+		names: &["ordered"],
+		value: Dither::Ordered,
+		help: "a Bayer pattern is added to the image before mapping it, so \
+			gradients come out as a regular crosshatch. each pixel is dithered \
+			on its own, which compresses well and stays still between frames \
+			of an animation",
+	},
+	Choice {
+		names: &["atkinson"],
+		value: Dither::Atkinson,
+		help: "error diffusion as on the old Macintosh. only three quarters of \
+			the difference is spread, but further, so it has more contrast and \
+			cleaner flat areas than floyd, and loses some detail in the \
+			lightest and darkest parts. it suits small palettes best",
+	},
+];
+
+static RESIZE_FILTERS: &[Choice<ResizeFilter>] = &[
+	Choice {
+		names: &["area"],
+		value: ResizeFilter::Area,
+		help: "each pixel is the average of the pixels it covers. smooth, and \
+			what to use to shrink a photo",
+	},
+	Choice {
+		names: &["nearest"],
+		value: ResizeFilter::Nearest,
+		help: "each pixel is the one nearest its middle, so no new colours are \
+			made. blocky, and what to use to scale pixel art by whole numbers",
+	},
+];
+
+static LAYOUTS: &[Choice<Layout>] = &[
+	Choice {
+		names: &["side-by-side"],
+		value: Layout::SideBySide,
+		help: "the input on the left and the output on the right",
+	},
+	Choice {
+		names: &["split"],
+		value: Layout::Split,
+		help: "one image, the left half of the input and the right half of the \
+			output",
+	},
+];
+
+static COMMANDS: &[Choice<Command>] = &[
+	Choice {
+		names: &["quantize"],
+		value: Command::Quantize,
+		help: "select a palette for each input and write it with only those \
+			colours. also what happens without a command",
+	},
+	Choice {
+		names: &["palette"],
+		value: Command::Palette,
+		help: "select a palette for the input and write just the palette, to a \
+			gpl, act, or hex file, rather than an image. like squash palette \
+			colors=16 art.png art.gpl",
+	},
+	Choice {
+		names: &["apply"],
+		value: Command::Apply,
+		help: "map the inputs to the colours of palette=, which has to be given, \
+			rather than selecting a palette. like squash apply palette=art.gpl \
+			sprites/ out/",
+	},
+	Choice {
+		names: &["compare"],
+		value: Command::Compare,
+		help: "print how different two images are, the PSNR, delta-E, and SSIM \
+			like verify=, or as JSON with json=. like squash compare \
+			original.png squashed.png",
+	},
+];
+
+/// The help topics, the shells there are completions for
+const TOPICS: &[&str] = &["algorithms", "selectors", "dithering"];
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// The first name of each choice
+fn names<T>(choices: &[Choice<T>]) -> Vec<&'static str> {
+	choices.iter().map(|choice| choice.names[0]).collect()
+}
+
+fn choose<T: Copy>(choices: &[Choice<T>], value: &str, what: &str) -> Result<T, String> {
+	choices
+		.iter()
+		.find(|choice| choice.names.contains(&value))
+		.map(|choice| choice.value)
+		.ok_or_else(|| format!("'{value}' is not recognized as {what}"))
+}
+
+/// Parse a number and check it with `ok`. `should` finishes the sentence
+/// "`name` must be" for when it's not.
+fn number<T: FromStr>(value: &str, ok: impl Fn(&T) -> bool, should: &str) -> Result<T, String> {
+	match value.parse::<T>() {
+		Ok(number) if ok(&number) => Ok(number),
+		_ => Err(should.to_owned()),
+	}
+}
+
+static ARGS: &[Arg] = &[
+	Arg {
+		keys: &["colors", "colours", "clrs"],
+		value: "<int>",
+		help: "the number of colours the final image should contain. \
+			a whole number more than 0 and less than, or equal, 256",
+		default: Some("256"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			let count: usize = number(
+				value,
+				|count| (1..=256).contains(count),
+				"color must be a whole number >= 1 and <= 256",
+			)?;
+			cli.color_count = (count - 1) as u8;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["scale"],
+		value: "<int>",
+		help: "the percent of pixels to consider when selecting the palette \
+			for the image. Whole number 1 to 100, inclusive.",
+		default: Some("25"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.scale = number(
+				value,
+				|scale| (1..=100).contains(scale),
+				"scale must be >= 1 and <= 100",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["stable"],
+		value: "<float>",
+		help: "sample more pixels than scale= if that's not enough for this percent \
+			of the palette to come out the same from a different sample. the \
+			sample size doubles until it is. a number > 0 and <= 100",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.stable = Some(number(
+				value,
+				|percent| *percent > 0.0 && *percent <= 100.0,
+				"stable must be > 0.0 and <= 100.0",
+			)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["difference", "dif"],
+		value: "<algorithm>",
+		help: "the color comparison function to use. \
+			for more details use help=algorithms.",
+		default: Some("rgb"),
+		choices: || names(ALGORITHMS),
+		parse: |cli, value| {
+			cli.difference = choose(ALGORITHMS, value, "an algorithm. See help=algorithms")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["selector", "selection", "sel"],
+		value: "<selector>",
+		help: "the algorithm for picking the palette. \
+			for more details use help=selectors.",
+		default: Some("sorsel"),
+		choices: || names(SELECTORS),
+		parse: |cli, value| {
+			cli.selector = choose(SELECTORS, value, "a selector. See help=selectors")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["variance"],
+		value: "<float>",
+		help: "how far heuristic moves the tolerance each way at first. it's \
+			halved whenever neither way is better. a number > 0",
+		default: Some("0.25"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.variance = number(
+				value,
+				|variance| *variance > 0.0,
+				"variance must be a number > 0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["attempts"],
+		value: "<int>",
+		help: "the most times heuristic moves the tolerance before it stops. \
+			a whole number > 0",
+		default: Some("10"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.attempts = number(
+				value,
+				|attempts| *attempts > 0,
+				"attempts must be a whole number > 0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["candidates"],
+		value: "<int>",
+		help: "how many tolerances heuristic tries each time it moves, half above \
+			and half below, a variance= apart. they're tried at the same time on \
+			every core, so more go further each time for not much longer. \
+			a whole number, at least 1",
+		default: Some("2"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.candidates = number(
+				value,
+				|candidates| *candidates > 0,
+				"candidates must be a whole number > 0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["starts"],
+		value: "<float>,...",
+		help: "tolerances for heuristic to search from at once instead of \
+			tolerance=, keeping the best palette any of them finds. spread out \
+			they're less likely to all get stuck. like starts=1,3,8",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			let starts = value
+				.split(',')
+				.map(|part| {
+					number(
+						part.trim(),
+						|tol| *tol > 0.0 && *tol <= 100.0,
+						"starts must be numbers > 0.0 and <= 100.0, like 1,3,8",
+					)
+				})
+				.collect::<Result<_, _>>()?;
+			cli.starts = Some(starts);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["bits"],
+		value: "<int>,<int>,<int>",
+		help: "how many bits of red, green, and blue highest-bits keeps, like \
+			5,6,5. each 0 to 8. without it, as many as fit in colors=",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.bits = Some(parse_bits(value)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["refine"],
+		value: "<int>",
+		help: "polish the selected palette with this many iterations of k-means. \
+			each moves every colour to the average of the pixels closest to \
+			it. helps sorsel the most. a whole number >= 0",
+		default: Some("0"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.refine = number(value, |_| true, "refine must be a whole number >= 0")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["snap"],
+		value: "",
+		help: "move every selected colour to the closest colour that's actually \
+			in the image. good for pixel art and logos, where averaged colours \
+			mean no pixel comes out exactly right",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.snap = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["palette", "pal"],
+		value: "<file>",
+		help: "use the colours in a palette file instead of selecting them from \
+			the image. ignores colors=, scale=, and selector=. \
+			one of: .gpl, .act, .ase, .pal, .hex, or an image, like a png of \
+			swatches, whose colours are the palette in the order they first \
+			appear going across and then down",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			cli.palette = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["anchor"],
+		value: "<file>",
+		help: "a palette file, like the palette of an earlier version of the image. \
+			entries that are still used keep their index and only the rest are \
+			selected again. same formats as palette=",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			cli.anchor = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["anchor-usage"],
+		value: "<float>",
+		help: "the percent of the image that has to be closest to an anchor for it \
+			to be kept. a number >= 0 and <= 100",
+		default: Some("0.1"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.anchor_usage = number(
+				value,
+				|percent| (0.0..=100.0).contains(percent),
+				"anchor-usage must be >= 0.0 and <= 100.0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["emit-map"],
+		value: "<file>",
+		help: "also write the colour map for the palette to a file. every 24-bit \
+			colour is mapped so this is slow, and the file is 16MB. it can \
+			be loaded with colorsquash::lut::MapView. if the file ends in .rle \
+			the map is run-length encoded, which is usually around a megabyte",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			cli.emit_map = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["histogram-out"],
+		value: "<file>",
+		help: "save the colours counted from the image, and how many times each \
+			appears, to a file. only the pixels picked by scale= are counted",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			cli.histogram_out = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["histogram-in"],
+		value: "<file>",
+		help: "select the palette from a file saved by histogram-out= rather than \
+			counting the image's colours. the image is still mapped to it. \
+			scale= has no effect, it was applied when the file was saved",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			cli.histogram_in = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["sweep"],
+		value: "<setting>:<start>..<end>:<step>",
+		help: "select a palette for every value of a setting from start to end, \
+			inclusive, and keep whichever has the best PSNR. the setting is one \
+			of: tolerance, scale, colors. like sweep=tolerance:1..10:0.5",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.sweep = Some(parse_sweep(value)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["score"],
+		value: "<measurement>:<weight>,...",
+		help: "what sweep= picks the best by instead of the PSNR, and what target= \
+			measures. the lowest weighted sum wins. the measurements are mean \
+			and max, the mean and largest difference to the palette; diversity, \
+			how far apart palette entries are, which is subtracted; and size, \
+			an estimate of bits per pixel. like score=mean:1,max:0.2,size:5",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.objective = Some(parse_objective(value)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["target"],
+		value: "<float>",
+		help: "use the fewest colours, up to colors=, that score at most this. \
+			the score is the mean difference to the palette unless score= is \
+			given. selecting takes a few times longer, like target=20",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.target = Some(number(
+				value,
+				|score| *score >= 0.0,
+				"target must be a number >= 0",
+			)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["auto-bw"],
+		value: "<float>",
+		help: "if at least this percent of the image is nearly white, make sure \
+			pure white is in the palette. the same for black. a number > 0 \
+			and <= 100, 5 is a good start.",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.auto_white_black = Some(number(
+				value,
+				|percent| *percent > 0.0 && *percent <= 100.0,
+				"auto-bw must be > 0.0 and <= 100.0",
+			)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["focus"],
+		value: "<x>,<y>,<width>,<height>,<weight>",
+		help: "count the pixels in this rectangle weight times each when selecting \
+			the palette, so that part of the image gets more of the colours. \
+			in pixels from the top left. can be given more than once, like \
+			focus=100,50,200,200,4 for a subject in the middle",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.focus.push(parse_focus(value)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["saliency"],
+		value: "<float>",
+		help: "count pixels that stand out from the ones around them more, so a \
+			big flat background doesn't take all the colours from the details. \
+			a number > 0, between 0.3 and 3 is a good start",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.saliency = Some(number(
+				value,
+				|strength| *strength > 0.0,
+				"saliency must be a number > 0",
+			)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["verify"],
+		value: "",
+		help: "compare the output to the input and print the PSNR, the peak \
+			signal-to-noise ratio, in decibels, overall and of each channel. \
+			higher is better. also print the mean, 95th percentile, and max \
+			delta-E, how different the colours look. up to 1 or 2 is hard to \
+			see. and the SSIM, how much of the structure is left, like smooth \
+			gradients and texture. 1 is all of it.",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.verify = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["min-psnr"],
+		value: "<float>",
+		help: "like verify= but exit with an error if the PSNR is below this. \
+			the output is still written. 30 to 40 is a reasonable gate.",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.min_psnr = Some(number(
+				value,
+				|psnr| *psnr > 0.0,
+				"min-psnr must be a number > 0",
+			)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["json"],
+		value: "",
+		help: "print a line of JSON to stdout for each image squashed, with the \
+			palette in hex, how many pixels are each colour, how long each stage \
+			took in seconds, and the PSNR, delta-E, and SSIM like verify=. \
+			with verify= too, only the JSON is printed.",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.json = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["resize"],
+		value: "<width>x<height>|<percent>%",
+		help: "scale the image before squashing it, like resize=320x240 or \
+			resize=50%. leave out the width or the height to keep the aspect \
+			ratio, like resize=320x. focus= is in pixels of the scaled image",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.resize = Some(parse_resize(value)?);
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["resize-filter"],
+		value: "<area|nearest>",
+		help: "how resize= scales. area averages, for photos, and nearest keeps \
+			hard edges and the colours there were, for pixel art",
+		default: Some("area"),
+		choices: || names(RESIZE_FILTERS),
+		parse: |cli, value| {
+			cli.resize_filter = choose(RESIZE_FILTERS, value, "a resize-filter")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["compare"],
+		value: "<path>",
+		help: "also write a png of the input and the output next to each other, \
+			to see what changing a setting does",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, path| {
+			if !path.to_ascii_lowercase().ends_with(".png") {
+				return Err("compare= writes a png, so the path has to end in .png".into());
 			}
-			Some(("tolerance", value)) | Some(("tol", value)) => match value.parse::<f32>() {
-				Err(_) => {
-					eprintln!("tolerance must be > 0.0 and <= 100.0");
-					std::process::exit(1);
-				}
-				Ok(tol) if tol <= 0.0 || tol > 100.0 => {
-					eprintln!("tolerance must be > 0.0 and <= 100.0");
-					std::process::exit(1);
-				}
-				Ok(tol) => {
-					//TODO: error if this's been set already
-					building.tolerance = Some(tol);
-				}
-			},
-			Some(("scale", scale)) => match scale.parse::<u8>() {
-				Err(_) => {
-					eprintln!("scale must be >= 1 and <= 100");
-					std::process::exit(1);
-				}
-				Ok(scale) if (1..=100).contains(&scale) => {
-					eprintln!("scale must be >= 1 and <= 100");
-					std::process::exit(1);
-				}
-				Ok(scale) => {
-					building.scale = Some(scale);
-				}
-			},
-			Some(("difference", algo)) | Some(("dif", algo)) => match algo {
-				"rgb" => building.difference = DifferenceFn::Rgb,
-				"redmean" => building.difference = DifferenceFn::Redmean,
-				_ => {
-					eprintln!("'{algo}' is not recognized as an algorithm. See help=algorithms");
-					std::process::exit(1);
-				}
-			},
-			Some(("selector", sel)) | Some(("sel", sel)) => match sel {
-				"sort/select" | "sorsel" => building.selector = Selector::SortSelect,
-				"kmeans" => building.selector = Selector::Kmeans,
-				"highest-bits" => building.selector = Selector::HighestBits,
+
+			cli.compare = Some(path.into());
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["compare-layout"],
+		value: "<side-by-side|split>",
+		help: "side-by-side puts the input and the output next to each other. \
+			split is the left half of the input and the right half of the output.",
+		default: Some("side-by-side"),
+		choices: || names(LAYOUTS),
+		parse: |cli, value| {
+			cli.compare_layout = choose(LAYOUTS, value, "a compare-layout")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["compare-heatmap"],
+		value: "",
+		help: "add a panel to compare= of how different each pixel looks, black \
+			where it's the same, then red, yellow, and white at a delta-E of 10 \
+			or more",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.compare_heatmap = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["metadata"],
+		value: "<keep|strip>",
+		help: "whether PNG text, EXIF, XMP, and JPEG comments are copied from the \
+			input to the output, or left out. PNGs can hold all of it and GIFs \
+			none. the colour profile is kept apart by color-profile=.",
+		default: Some("strip"),
+		choices: || names(METADATA),
+		parse: |cli, value| {
+			cli.metadata = choose(METADATA, value, "a metadata mode. use keep or strip")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["color-profile", "colour-profile"],
+		value: "<keep|strip>",
+		help: "whether the gamma, chromaticities, sRGB intent, and ICC profile of a \
+			PNG are copied to a PNG output. they say how its colours should be \
+			shown, so they're kept unless you know the output doesn't need them.",
+		default: Some("keep"),
+		choices: || names(METADATA),
+		parse: |cli, value| {
+			cli.color_profile = choose(METADATA, value, "a color-profile mode. use keep or strip")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["dither"],
+		value: "<none|floyd|ordered|atkinson>",
+		help: "how gradients are kept when there aren't enough colours for them. \
+			See help=dithering.",
+		default: Some("none"),
+		choices: || names(DITHERS),
+		parse: |cli, value| {
+			cli.dither = choose(DITHERS, value, "a dither. See help=dithering")?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["dither-strength"],
+		value: "<float>",
+		help: "how much to dither, as a fraction of the usual amount. less keeps \
+			more flat areas flat, more hides banding better. a number > 0",
+		default: Some("1"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.dither_strength = number(
+				value,
+				|strength| *strength > 0.0,
+				"dither-strength must be a number > 0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["archival"],
+		value: "",
+		help: "make the output so it can be made again byte for byte from the same \
+			input. k-means starts from a fixed seed, heuristic runs on \
+			one thread, and every setting, defaults and all, goes in a PNG text \
+			chunk called colorsquash with the versions of squash and \
+			colorsquash. only works with a png output",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.archival = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["format"],
+		value: "<png|gif>",
+		help: "what the images are written as when the output is a directory",
+		default: Some("png"),
+		choices: || vec!["png", "gif"],
+		parse: |cli, value| {
+			cli.format = match value {
+				"png" => OutType::Png,
+				"gif" => OutType::Gif,
 				_ => {
-					eprintln!("'{sel}' is not recognized as a selector. See help=selectors");
-					std::process::exit(1);
+					return Err(format!(
+						"'{value}' is not recognized as a format. use png or gif"
+					))
 				}
-			},
-			Some(("loud", _)) | Some(("verbose", _)) => {
-				building.verbose = true;
+			};
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["threads"],
+		value: "<int>",
+		help: "how many images to squash at once when the output is a directory. \
+			a whole number > 0",
+		default: Some("1"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.threads = number(
+				value,
+				|threads| *threads > 0,
+				"threads must be a whole number > 0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["tolerance", "tol"],
+		value: "<float>",
+		help: "how different colours should be to be added to the palette. \
+			only sorsel and heuristic use this value. heuristic starts from it. \
+			a number > 0 and <= 100",
+		default: Some("3"),
+		choices: Vec::new,
+		parse: |cli, value| {
+			cli.tolerance = number(
+				value,
+				|tol| *tol > 0.0 && *tol <= 100.0,
+				"tolerance must be > 0.0 and <= 100.0",
+			)?;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["loud", "verbose"],
+		value: "",
+		help: "print information about the image and palette. if scale= is less \
+			than 100 this includes how stable the palette is between samples.",
+		default: None,
+		choices: Vec::new,
+		parse: |cli, _| {
+			cli.verbose = true;
+			Ok(())
+		},
+	},
+	Arg {
+		keys: &["preset"],
+		value: "<name>",
+		help: "use the arguments of a preset from squash/config.toml in \
+			$XDG_CONFIG_HOME or ~/.config, where each [name] is a preset and \
+			each key = value in it an argument, like colors = 16 or \
+			dither = \"ordered\". the rest of the command line wins over it",
+		default: None,
+		choices: Vec::new,
+		// it's applied before everything else, in build
+		parse: |_, _| Ok(()),
+	},
+	Arg {
+		keys: &["help", "-h", "--help"],
+		value: "",
+		help: "print this message and exit. help=algorithms, help=selectors, and \
+			help=dithering describe those instead",
+		default: None,
+		choices: || TOPICS.to_vec(),
+		parse: |_, topic| match topic {
+			"algorithms" => print_choices("ALGORITHMS", ALGORITHMS),
+			"selectors" => print_choices("SELECTORS", SELECTORS),
+			"dithering" => print_choices("DITHERING", DITHERS),
+			_ => print_help(),
+		},
+	},
+	Arg {
+		keys: &["man"],
+		value: "",
+		help: "print a man page for squash and exit, like \
+			squash man= > squash.1",
+		default: None,
+		choices: Vec::new,
+		parse: |_, _| print_man(),
+	},
+	Arg {
+		keys: &["completions"],
+		value: "<shell>",
+		help: "print completions for a shell and exit. for bash, source the output \
+			from your .bashrc; for zsh, save it as _squash somewhere in your $fpath; \
+			for fish, save it as squash.fish in ~/.config/fish/completions.",
+		default: None,
+		choices: || SHELLS.to_vec(),
+		parse: |_, shell| match shell {
+			"bash" => print_bash(),
+			"zsh" => print_zsh(),
+			"fish" => print_fish(),
+			_ => Err(format!(
+				"no completions for '{shell}'. one of: {}",
+				SHELLS.join(", ")
+			)),
+		},
+	},
+	Arg {
+		keys: &["version", "-V", "--version"],
+		value: "",
+		help: "print the version and authors and exit",
+		default: None,
+		choices: Vec::new,
+		parse: |_, _| print_version(),
+	},
+];
+
+fn find(key: &str) -> Option<&'static Arg> {
+	ARGS.iter().find(|arg| arg.keys.contains(&key))
+}
+
+pub fn build() -> Run {
+	let mut free = vec![];
+	let mut building = BuildingCli::default();
+
+	let mut args: Vec<String> = std::env::args().skip(1).collect();
+	let command = match args
+		.first()
+		.and_then(|first| COMMANDS.iter().find(|c| c.names.contains(&first.as_str())))
+	{
+		Some(command) => {
+			args.remove(0);
+			command.value
+		}
+		None => Command::Quantize,
+	};
+
+	for arg in ARGS {
+		if let Some(default) = arg.default {
+			if let Err(e) = (arg.parse)(&mut building, default) {
+				panic!("the default for {}= doesn't parse: {e}", arg.name());
 			}
-			Some(("help", "algorithms")) => print_help_algorithms(),
-			Some(("help", "selectors")) => print_help_selectors(),
-			Some(("help", _)) => print_help(),
-			Some(("version", _)) => print_version(),
-			Some((key, _)) => {
-				eprintln!("unrecognised key {key}");
+		}
+	}
+
+	// presets go first wherever they are, so the command line wins over them
+	for name in args.iter().filter_map(|arg| arg.strip_prefix("preset=")) {
+		preset_or_die(&mut building, name);
+	}
+
+	for arg in args {
+		// -h/--help are standards and, even though we're playing with a
+		// dd-style syntax, we want to respect these. we'll do -V/--version.
+		// they're in the table as keys that start with a dash
+		let (key, value) = match arg.split_once('=') {
+			_ if arg.starts_with('-') && find(&arg).is_some() => (arg.as_str(), ""),
+			Some((key, value)) => (key, value),
+			None => {
+				free.push(arg);
+				continue;
+			}
+		};
+
+		let Some(found) = find(key) else {
+			eprintln!("unrecognised key {key}");
+			std::process::exit(1);
+		};
+
+		//TODO: error if this's been set already?
+		building.given.insert(found.name());
+		building.preset.remove(found.name());
+		building.arguments.push((found.name(), value.to_owned()));
+		if let Err(e) = (found.parse)(&mut building, value) {
+			eprintln!("{e}");
+			std::process::exit(1);
+		}
+	}
+
+	if free.len() < 2 {
+		eprintln!("didn't get enough arguments! 'help=' for help");
+		std::process::exit(1);
+	}
+
+	match command {
+		Command::Compare => return compare_or_die(&building, &free),
+		Command::Palette => return palette_or_die(building, &free),
+		Command::Apply if building.palette.is_none() => {
+			eprintln!("squash apply needs palette=, the colours to map to");
+			std::process::exit(1);
+		}
+		Command::Apply | Command::Quantize => (),
+	}
+
+	// the last is the output and everything before it is input
+	let output = free.pop().unwrap_or_default();
+	let batch = free.len() > 1
+		|| output.ends_with('/')
+		|| Utf8Path::new(&output).is_dir()
+		|| free
+			.iter()
+			.any(|input| is_pattern(input) || Utf8Path::new(input).is_dir());
+
+	if batch {
+		return batch_or_die(building, &free, &output, command);
+	}
+
+	for name in ["format", "threads"] {
+		if building.on_command_line(name) {
+			eprintln!("{name}= is only used when the output is a directory");
+			std::process::exit(1);
+		}
+	}
+
+	Run::One(Box::new(building.build_or_die(&free[0], &output, command)))
+}
+
+/// Parse the arguments of the preset as though they were given
+fn preset_or_die(building: &mut BuildingCli, name: &str) {
+	let Some(path) = preset::path() else {
+		eprintln!("preset= needs a config directory, but neither XDG_CONFIG_HOME nor HOME is set");
+		std::process::exit(1);
+	};
+
+	let toml = match std::fs::read_to_string(&path) {
+		Ok(toml) => toml,
+		Err(e) => {
+			eprintln!("couldn't read the presets from {path}: {e}");
+			std::process::exit(1);
+		}
+	};
+
+	let arguments = match preset::find(&toml, name) {
+		Ok(Some(arguments)) => arguments,
+		Ok(None) => {
+			eprintln!("there's no preset called '{name}' in {path}");
+			std::process::exit(1);
+		}
+		Err(e) => {
+			eprintln!("{e}");
+			std::process::exit(1);
+		}
+	};
+
+	for (key, value) in arguments {
+		// ones that print something and exit, or would look for more presets
+		let found = find(&key).filter(|arg| {
+			!matches!(
+				arg.name(),
+				"preset" | "help" | "man" | "completions" | "version"
+			)
+		});
+		let Some(found) = found else {
+			eprintln!("{key} in preset '{name}' isn't an argument a preset can have");
+			std::process::exit(1);
+		};
+
+		building.given.insert(found.name());
+		building.preset.insert(found.name());
+		building.arguments.push((found.name(), value.clone()));
+		if let Err(e) = (found.parse)(building, &value) {
+			eprintln!("preset '{name}': {e}");
+			std::process::exit(1);
+		}
+	}
+}
+
+/// squash palette, which writes a palette file rather than an image
+fn palette_or_die(building: BuildingCli, free: &[String]) -> Run {
+	let [input, output] = free else {
+		eprintln!("squash palette takes one image and the palette file to write");
+		std::process::exit(1);
+	};
+
+	// these only change how the image is mapped, or what's written with it
+	let unused = [
+		"palette",
+		"emit-map",
+		"dither",
+		"dither-strength",
+		"verify",
+		"min-psnr",
+		"json",
+		"compare",
+		"compare-layout",
+		"compare-heatmap",
+		"metadata",
+		"color-profile",
+		"archival",
+		"format",
+		"threads",
+	];
+	if let Some((name, _)) = building
+		.arguments
+		.iter()
+		.find(|(name, _)| unused.contains(name) && !building.preset.contains(name))
+	{
+		eprintln!("{name}= isn't used by squash palette, which doesn't write an image");
+		std::process::exit(1);
+	}
+
+	Run::One(Box::new(building.build_or_die(
+		input,
+		output,
+		Command::Palette,
+	)))
+}
+
+/// squash compare, which only measures two images
+fn compare_or_die(building: &BuildingCli, free: &[String]) -> Run {
+	if let Some((name, _)) = building.arguments.iter().find(|(name, _)| *name != "json") {
+		eprintln!("squash compare only takes json=, not {name}=");
+		std::process::exit(1);
+	}
+
+	let [original, quantized] = free else {
+		eprintln!("squash compare takes two images, the original and the squashed one");
+		std::process::exit(1);
+	};
+
+	let image = |path: &str| {
+		let path = Utf8Path::new(path);
+		(path.to_path_buf(), in_type_or_die(path))
+	};
+	Run::Compare {
+		original: image(original),
+		quantized: image(quantized),
+		json: building.json,
+	}
+}
+
+fn in_type_or_die(path: &Utf8Path) -> InType {
+	match path.extension() {
+		None => {
+			eprintln!(
+				"can't determine input filetype!\nSupported input types: PNG, JPG, BMP, TGA, GIF"
+			);
+			std::process::exit(1);
+		}
+		Some(ext) => match InType::from_extension(ext) {
+			Some(in_type) => in_type,
+			None => {
+				eprintln!(
+					"unknown filetype '{ext}'!\nSupported input types: PNG, JPG, BMP, TGA, GIF"
+				);
 				std::process::exit(1);
 			}
+		},
+	}
+}
+
+/// Whether the file name has `*` or `?` in it, meaning it's a pattern for the
+/// files in its directory
+fn is_pattern(path: &str) -> bool {
+	Utf8Path::new(path)
+		.file_name()
+		.is_some_and(|name| name.contains(['*', '?']))
+}
+
+/// Whether `name` fits a pattern where `*` is anything, even nothing, and `?`
+/// is any one character
+fn matches(pattern: &[char], name: &[char]) -> bool {
+	match (pattern.split_first(), name.split_first()) {
+		(None, None) => true,
+		(Some(('*', rest)), _) => {
+			matches(rest, name) || (!name.is_empty() && matches(pattern, &name[1..]))
 		}
+		(Some(('?', rest)), Some((_, name))) => matches(rest, name),
+		(Some((p, rest)), Some((n, name))) if p == n => matches(rest, name),
+		_ => false,
 	}
+}
 
-	match free.len().cmp(&2) {
-		Ordering::Less => {
-			eprintln!("didn't get enough arguments! 'help=' for help");
+/// The images in a directory whose names fit the pattern, in order
+fn images_in(directory: &Utf8Path, pattern: &str) -> Vec<Utf8PathBuf> {
+	let entries = match directory.read_dir_utf8() {
+		Ok(entries) => entries,
+		Err(e) => {
+			eprintln!("couldn't read the directory {directory}: {e}");
 			std::process::exit(1);
 		}
-		Ordering::Greater => {
-			eprintln!("got too many arguments! 'help=' for help");
+	};
+
+	let pattern: Vec<char> = pattern.chars().collect();
+	let mut images: Vec<Utf8PathBuf> = entries
+		.filter_map(Result::ok)
+		.map(|entry| entry.path().to_path_buf())
+		.filter(|path| {
+			path.extension()
+				.is_some_and(|ext| InType::from_extension(ext).is_some())
+		})
+		.filter(|path| {
+			let name: Vec<char> = path.file_name().unwrap_or_default().chars().collect();
+			matches(&pattern, &name)
+		})
+		.collect();
+	images.sort();
+	images
+}
+
+/// Every image to squash into the output directory. Inputs can be images,
+/// directories, which are every image in them, or patterns like `sprites/*.png`
+fn batch_or_die(building: BuildingCli, inputs: &[String], output: &str, command: Command) -> Run {
+	let output = Utf8PathBuf::from(output);
+	if output.is_file() {
+		eprintln!("the output has to be a directory when the input is more than one image");
+		std::process::exit(1);
+	}
+
+	// they'd all write the same file
+	for name in ["histogram-out", "emit-map", "compare"] {
+		if building.given.contains(name) {
+			eprintln!("{name}= can't be used when the output is a directory");
 			std::process::exit(1);
 		}
-		Ordering::Equal => building.build_or_die(&free[0], &free[1]),
 	}
+
+	let mut paths = vec![];
+	for input in inputs {
+		let input = Utf8Path::new(input);
+		if input.is_dir() {
+			paths.extend(images_in(input, "*"));
+		} else if is_pattern(input.as_str()) {
+			let directory = match input.parent() {
+				Some(parent) if !parent.as_str().is_empty() => parent,
+				_ => Utf8Path::new("."),
+			};
+			paths.extend(images_in(directory, input.file_name().unwrap_or("*")));
+		} else {
+			paths.push(input.to_path_buf());
+		}
+	}
+
+	if paths.is_empty() {
+		eprintln!("there aren't any images to squash!");
+		std::process::exit(1);
+	}
+
+	let extension = match building.format {
+		OutType::Png => "png",
+		OutType::Gif => "gif",
+		OutType::Palette(_) => unreachable!("format= is only png or gif"),
+	};
+
+	let mut written: HashMap<Utf8PathBuf, Utf8PathBuf> = HashMap::new();
+	let mut images = Vec::with_capacity(paths.len());
+	for path in paths {
+		let name = format!("{}.{extension}", path.file_stem().unwrap_or("image"));
+		let to = output.join(name);
+		if let Some(other) = written.insert(to.clone(), path.clone()) {
+			eprintln!("{other} and {path} would both be written to {to}");
+			std::process::exit(1);
+		}
+
+		let mut cli = building
+			.clone()
+			.build_or_die(path.as_str(), to.as_str(), command);
+		cli.progress = building.threads == 1;
+		images.push(cli);
+	}
+
+	if let Err(e) = std::fs::create_dir_all(&output) {
+		eprintln!("couldn't make the output directory {output}: {e}");
+		std::process::exit(1);
+	}
+
+	Run::Batch {
+		images,
+		threads: building.threads,
+	}
+}
+
+fn palette_format(path: Utf8PathBuf) -> (Utf8PathBuf, PaletteFormat) {
+	let ext = path.extension().unwrap_or_default();
+	if let Some(format) = Format::from_extension(ext) {
+		(path, PaletteFormat::File(format))
+	} else if let Some(in_type) = InType::from_extension(ext) {
+		(path, PaletteFormat::Image(in_type))
+	} else {
+		eprintln!("can't determine the palette's filetype!\nSupported palette types: GPL, ACT, ASE, PAL, HEX, or an image like PNG");
+		std::process::exit(1);
+	}
+}
+
+/// `x,y,width,height,weight`, like `100,50,200,200,4`
+fn parse_focus(value: &str) -> Result<Focus, String> {
+	let numbers: Vec<usize> = value
+		.split(',')
+		.map(|part| part.trim().parse::<usize>())
+		.collect::<Result<_, _>>()
+		.unwrap_or_default();
+
+	match numbers[..] {
+		[x, y, width, height, weight] if weight > 0 => Ok(Focus {
+			x,
+			y,
+			width,
+			height,
+			weight,
+		}),
+		_ => Err(
+			"focus must be five whole numbers, x,y,width,height,weight, with a weight > 0".into(),
+		),
+	}
+}
+
+/// `r,g,b`, like `5,6,5`
+fn parse_bits(value: &str) -> Result<[u8; 3], String> {
+	let should = "bits must be three whole numbers from 0 to 8, like 5,6,5";
+	let bits: Vec<u8> = value
+		.split(',')
+		.map(|part| number(part.trim(), |bits| *bits <= 8, should))
+		.collect::<Result<_, _>>()?;
+
+	match bits[..] {
+		[r, g, b] => Ok([r, g, b]),
+		_ => Err(should.into()),
+	}
+}
+
+/// `<width>x<height>`, either can be left out, or `<percent>%`
+fn parse_resize(value: &str) -> Result<Resize, String> {
+	let malformed = || {
+		"resize must look like <width>x<height> or <percent>%, like 320x240, 320x, or 50%"
+			.to_owned()
+	};
+
+	if let Some(percent) = value.strip_suffix('%') {
+		return match percent.parse::<f32>() {
+			Ok(percent) if percent > 0.0 && percent.is_finite() => Ok(Resize::Percent(percent)),
+			_ => Err("the percent of resize must be a number > 0".into()),
+		};
+	}
+
+	let (width, height) = value.split_once('x').ok_or_else(malformed)?;
+	let length = |length: &str| -> Result<Option<usize>, String> {
+		match length {
+			"" => Ok(None),
+			length => match length.parse::<usize>() {
+				Ok(length) if length > 0 => Ok(Some(length)),
+				_ => Err(malformed()),
+			},
+		}
+	};
+
+	let (width, height) = (length(width)?, length(height)?);
+	if width.is_none() && height.is_none() {
+		return Err(malformed());
+	}
+
+	Ok(Resize::Size { width, height })
+}
+
+/// `knob:start..end:step`, like `tolerance:1..10:0.5`
+fn parse_sweep(value: &str) -> Result<(Knob, Range), String> {
+	let malformed = || {
+		"sweep must look like <setting>:<start>..<end>:<step>, like tolerance:1..10:0.5".to_owned()
+	};
+
+	let mut parts = value.split(':');
+	let (Some(knob), Some(range), Some(step), None) =
+		(parts.next(), parts.next(), parts.next(), parts.next())
+	else {
+		return Err(malformed());
+	};
+
+	let knob = match knob {
+		"tolerance" | "tol" => Knob::Tolerance,
+		"scale" => Knob::Scale,
+		"colors" | "colours" | "clrs" => Knob::Colors,
+		_ => {
+			return Err(format!(
+				"can't sweep '{knob}'. one of: tolerance, scale, colors"
+			))
+		}
+	};
+
+	let Some((start, end)) = range.split_once("..") else {
+		return Err(malformed());
+	};
+	let (Ok(start), Ok(end), Ok(step)) = (start.parse(), end.parse(), step.parse()) else {
+		return Err(malformed());
+	};
+
+	let range = Range::new(start, end, step);
+	if range.values().is_empty() {
+		return Err("sweep range is empty. the step must be > 0 and the start <= the end".into());
+	}
+
+	Ok((knob, range))
+}
+
+/// `measurement:weight,...`, like `mean:1,size:0.5`. Anything left out is 0
+fn parse_objective(value: &str) -> Result<Objective, String> {
+	let mut objective = Objective {
+		mean_error: 0.0,
+		max_error: 0.0,
+		diversity: 0.0,
+		bits_per_pixel: 0.0,
+	};
+
+	for part in value.split(',') {
+		let Some((name, Ok(weight))) = part.split_once(':').map(|(n, w)| (n, w.parse::<f32>()))
+		else {
+			return Err(
+				"score must look like <measurement>:<weight>,..., like mean:1,size:0.5".into(),
+			);
+		};
+
+		match name {
+			"mean" => objective.mean_error = weight,
+			"max" => objective.max_error = weight,
+			"diversity" => objective.diversity = weight,
+			"size" => objective.bits_per_pixel = weight,
+			_ => {
+				return Err(format!(
+					"can't score by '{name}'. one of: mean, max, diversity, size"
+				))
+			}
+		}
+	}
+
+	Ok(objective)
+}
+
+/// Break text into lines of at most `width` characters, not counting the
+/// indent, which goes before every line.
+fn wrap(text: &str, indent: usize, width: usize) -> String {
+	let mut wrapped = String::new();
+	let mut line_len = 0;
+
+	for word in text.split_whitespace() {
+		let len = word.chars().count();
+		if line_len > 0 && line_len + 1 + len > width {
+			wrapped.push('\n');
+			line_len = 0;
+		}
+
+		if line_len == 0 {
+			wrapped.push_str(&" ".repeat(indent));
+		} else {
+			wrapped.push(' ');
+			line_len += 1;
+		}
+
+		wrapped.push_str(word);
+		line_len += len;
+	}
+
+	wrapped
 }
 
 fn print_help() -> ! {
-	println!("usage: {NAME} [arguments ...] <input> <output>\n");
-	println!("<input>  path to a jpeg or png file");
-	println!("<output> path to write a png or gif file to\n");
+	println!("usage: {NAME} [command] [arguments ...] <input> ... <output>\n");
+	println!("<input>  path to a jpeg, png, bmp, tga, or gif file. with more than one,");
+	println!("         or a directory, or a pattern like 'sprites/*.png', every image");
+	println!("         is squashed into the output directory");
+	println!("<output> path to write a png or gif file to, or a directory\n");
+	println!("COMMANDS:");
+	for command in COMMANDS {
+		println!("    {}", command.names.join(" | "));
+		println!("{}", wrap(command.help, 8, 72));
+	}
+	println!();
 	println!("ARGUMENTS:");
-	println!("    colors=<int> | clrs=<int>");
-	println!("        the number of colours the final image should contain");
-	println!("        a whole number more than 0 and less than, or equal, 256");
-	println!("        [Default 256]\n");
-	println!("    scale=<int>");
-	println!("        the percent of pixels to consider when selecting the palette");
-	println!("        for the image. Whole number 1 to 100, inclusive. [Default 25]\n");
-	println!("    difference=<algorithm> | dif=<algorithm>");
-	println!("        the color comparison function to use. one of: rgb, redmean");
-	println!("        for more details use help=algorithms. [Default rgb]\n");
-	println!("    selection=<selector> | sel=<selector>");
-	println!("        the algorithm for picking the palette. one of: means, sort/select");
-	println!("        for more details use help=selectors. [Default sorsel]\n");
-	println!("    tolerance=<float> | tol=<float>");
-	println!("        how different colours should be to be added to the palette");
-	println!("        only sorsel usese this value.");
-	println!("        a number > 0 and <= 100 [Default 3]\n");
-	println!("    loud= | verbose=");
-	println!("        print information about the image and palette.\n");
-	println!("    help= | -h | --help");
-	println!("        print this message and exit\n");
-	println!("    version= | -V | --version");
-	println!("        print the version and authors and exit");
-	std::process::exit(0)
-}
+	for (idx, arg) in ARGS.iter().enumerate() {
+		if idx > 0 {
+			println!();
+		}
 
-fn print_help_algorithms() -> ! {
-	println!("ALGORITHMS");
-	println!("rgb:");
-	println!("    a straight, rather naïve, RGB comparison. It sums the channel");
-	println!("    differences. This is it, really:");
-	println!("    |a.red - b.red| + |a.green - b.green| + |a.blue - b.blue|\n");
-	println!("redmean:");
-	println!("    a slightly more intelligent algorithm that weighs the channels");
-	println!("    in an attempt to more better align with human color perception.");
+		println!("    {}", arg.usage());
+		println!("{}", wrap(&arg.description(), 8, 72));
+		if let Some(default) = arg.default {
+			println!("        [Default {default}]");
+		}
+	}
 	std::process::exit(0)
 }
 
-fn print_help_selectors() -> ! {
-	println!("SELECTORS:");
-	println!("sorsel:");
-	println!("    the original colorsquash algorithm. sorts colors from most to least");
-	println!("    frequent and then picks the most frequent colors so long as they are");
-	println!("    sufficiently different (configurable with tolerance=)\n");
-	println!("kmeans:");
-	println!("    uses the kmeans clustering algorithm to select colours.");
-	println!("    Ignores tolerance=\n");
-	println!("highest-bits:");
-	println!("    quantizes the colours by shifting the bits of their components until");
-	println!("    they all fit in the palette.");
-	println!("    Ignores tolerance=");
+fn print_choices<T>(title: &str, choices: &[Choice<T>]) -> ! {
+	println!("{title}:");
+	for (idx, choice) in choices.iter().enumerate() {
+		if idx > 0 {
+			println!();
+		}
+
+		println!("{}:", choice.names.join(" | "));
+		println!("{}", wrap(choice.help, 4, 72));
+	}
 	std::process::exit(0)
 }
 
@@ -278,3 +2118,212 @@ fn print_version() -> ! {
 	println!("written by {AUTHORS}");
 	std::process::exit(0)
 }
+
+/// Escape text for roff, so dashes are dashes rather than hyphens and lines
+/// can't be taken for requests.
+fn roff(text: impl Display) -> String {
+	let escaped = text.to_string().replace('\\', "\\e").replace('-', "\\-");
+	if escaped.starts_with('.') || escaped.starts_with('\'') {
+		format!("\\&{escaped}")
+	} else {
+		escaped
+	}
+}
+
+fn print_man() -> ! {
+	println!(".TH {} 1 \"\" \"{NAME} {VERSION}\"", NAME.to_uppercase());
+	println!(".SH NAME");
+	println!("{} \\- {}", roff(NAME), roff(DESCRIPTION));
+	println!(".SH SYNOPSIS");
+	println!(".B {}", roff(NAME));
+	println!("[\\fIcommand\\fR] [\\fIarguments\\fR ...] \\fIinput\\fR ... \\fIoutput\\fR");
+	println!(".SH DESCRIPTION");
+	println!(
+		"Selects a palette for \\fIinput\\fR, a JPEG, PNG, BMP, TGA, or GIF, and writes it to \
+		\\fIoutput\\fR, a PNG or GIF, with those colours."
+	);
+	println!("Arguments are \\fIkey\\fR=\\fIvalue\\fR, in any order, after the \\fIcommand\\fR, if there is one.");
+	println!(
+		"An animated GIF written to a GIF stays animated, with one palette for \
+		every frame."
+	);
+	println!(".PP");
+	println!(
+		"If \\fIoutput\\fR is a directory, there can be any number of \\fIinput\\fRs, \
+		and each can be an image, a directory of them, or a pattern like \
+		\\fIsprites/*.png\\fR. Every image is squashed with the same arguments \
+		and written to \\fIoutput\\fR, named after the input, as format=."
+	);
+	println!(".SH ARGUMENTS");
+	for arg in ARGS {
+		println!(".TP");
+		println!(".B {}", roff(arg.usage()));
+		println!("{}", roff(arg.description()));
+		if let Some(default) = arg.default {
+			println!(".br");
+			println!("[Default {}]", roff(default));
+		}
+	}
+
+	for (title, choices) in [
+		("COMMANDS", names_and_help(COMMANDS)),
+		("SELECTORS", names_and_help(SELECTORS)),
+		("ALGORITHMS", names_and_help(ALGORITHMS)),
+		("DITHERING", names_and_help(DITHERS)),
+	] {
+		println!(".SH {title}");
+		for (names, help) in choices {
+			println!(".TP");
+			println!(".B {}", roff(names));
+			println!("{}", roff(help));
+		}
+	}
+
+	println!(".SH AUTHORS");
+	println!("{}", roff(AUTHORS));
+	std::process::exit(0)
+}
+
+fn names_and_help<T>(choices: &[Choice<T>]) -> Vec<(String, &'static str)> {
+	choices
+		.iter()
+		.map(|choice| (choice.names.join(" | "), choice.help))
+		.collect()
+}
+
+/// The first sentence of the help, for completions that show a description
+/// next to each key
+fn summary(help: &str) -> &str {
+	help.split_once(". ").map_or(help, |(first, _)| first)
+}
+
+/// Quote for a shell, in single quotes
+fn quote(text: &str) -> String {
+	format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The keys that can be completed, which leaves out the dashed ones
+fn completable(arg: &Arg) -> impl Iterator<Item = &'static str> {
+	arg.keys.iter().copied().filter(|key| !key.starts_with('-'))
+}
+
+fn print_bash() -> ! {
+	let keys: Vec<String> = ARGS
+		.iter()
+		.flat_map(completable)
+		.map(|key| format!("{key}="))
+		.collect();
+
+	println!("_{NAME}() {{");
+	println!("\tlocal cur=${{COMP_WORDS[COMP_CWORD]}} key=");
+	println!("\t# bash splits key=value into key, =, and value");
+	println!("\tif [[ $cur == = ]]; then");
+	println!("\t\tkey=${{COMP_WORDS[COMP_CWORD-1]}}");
+	println!("\t\tcur=");
+	println!("\telif [[ ${{COMP_WORDS[COMP_CWORD-1]}} == = ]]; then");
+	println!("\t\tkey=${{COMP_WORDS[COMP_CWORD-2]}}");
+	println!("\tfi");
+	println!();
+	println!("\tcase $key in");
+	for arg in ARGS {
+		let choices = (arg.choices)();
+		let pattern = completable(arg).collect::<Vec<_>>().join("|");
+		if arg.takes_file() {
+			println!("\t\t{pattern}) COMPREPLY=($(compgen -f -- \"$cur\")) ;;");
+		} else if !choices.is_empty() {
+			println!(
+				"\t\t{pattern}) COMPREPLY=($(compgen -W {} -- \"$cur\")) ;;",
+				quote(&choices.join(" "))
+			);
+		}
+	}
+	println!("\t\t'')");
+	println!("\t\t\tlocal words={}", quote(&keys.join(" ")));
+	println!(
+		"\t\t\t(( COMP_CWORD == 1 )) && words+={}",
+		quote(&format!(" {}", names(COMMANDS).join(" ")))
+	);
+	println!("\t\t\tCOMPREPLY=($(compgen -W \"$words\" -- \"$cur\") $(compgen -f -- \"$cur\"))");
+	println!(
+		"\t\t\t[[ ${{#COMPREPLY[@]}} == 1 && ${{COMPREPLY[0]}} == *= ]] && compopt -o nospace"
+	);
+	println!("\t\t\t;;");
+	println!("\t\t*) COMPREPLY=() ;;");
+	println!("\tesac");
+	println!("}}");
+	println!("complete -F _{NAME} {NAME}");
+	std::process::exit(0)
+}
+
+fn print_zsh() -> ! {
+	println!("#compdef {NAME}");
+	println!();
+	println!("local -a keys");
+	println!("keys=(");
+	for arg in ARGS {
+		for key in completable(arg) {
+			let described = format!("{key}=:{}", summary(arg.help).replace(':', "\\:"));
+			println!("\t{}", quote(&described));
+		}
+	}
+	println!(")");
+	println!();
+	println!("if compset -P '*='; then");
+	println!("\tcase ${{IPREFIX%=}} in");
+	for arg in ARGS {
+		let choices = (arg.choices)();
+		let pattern = completable(arg).collect::<Vec<_>>().join("|");
+		if arg.takes_file() {
+			println!("\t\t{pattern}) _files ;;");
+		} else if !choices.is_empty() {
+			println!("\t\t{pattern}) compadd -- {} ;;", choices.join(" "));
+		}
+	}
+	println!("\tesac");
+	println!("else");
+	println!("\tif (( CURRENT == 2 )); then");
+	println!("\t\tlocal -a commands");
+	println!("\t\tcommands=(");
+	for command in COMMANDS {
+		let described = format!(
+			"{}:{}",
+			command.names[0],
+			summary(command.help).replace(':', "\\:")
+		);
+		println!("\t\t\t{}", quote(&described));
+	}
+	println!("\t\t)");
+	println!("\t\t_describe 'command' commands");
+	println!("\tfi");
+	println!("\t_describe 'argument' keys -S ''");
+	println!("\t_files");
+	println!("fi");
+	std::process::exit(0)
+}
+
+fn print_fish() -> ! {
+	for command in COMMANDS {
+		println!(
+			"complete -c {NAME} -n __fish_use_subcommand -a {} -d {}",
+			command.names[0],
+			quote(summary(command.help))
+		);
+	}
+	for arg in ARGS {
+		let description = quote(summary(arg.help));
+		for key in completable(arg) {
+			println!(
+				"complete -c {NAME} -a {} -d {description}",
+				quote(&format!("{key}="))
+			);
+
+			for choice in (arg.choices)() {
+				println!(
+					"complete -c {NAME} -a {}",
+					quote(&format!("{key}={choice}"))
+				);
+			}
+		}
+	}
+	std::process::exit(0)
+}
@@ -0,0 +1,57 @@
+//! What compare= writes: the input and the output next to each other, so a
+//! change to tolerance= or selector= can be seen rather than read off
+//! verify=. With compare-heatmap= there's one more panel with the delta-E of
+//! each pixel, black where it's the same, through red and yellow, to white
+//! where it's [HEATMAP_MAX] or more.
+
+use crate::cli::Layout;
+
+/// The delta-E that's white in the heatmap. Anything past 10 is plainly a
+/// different colour
+const HEATMAP_MAX: f32 = 10.0;
+
+/// The comparison of an image the size of `width` and `height`. `original` and
+/// `quantized` are RGB, `heatmap` is the delta-E of each pixel. Returns the
+/// width and height of the comparison and its pixels as RGB.
+pub fn compare(
+	layout: Layout,
+	width: usize,
+	height: usize,
+	original: &[u8],
+	quantized: &[u8],
+	heatmap: Option<&[f32]>,
+) -> (usize, usize, Vec<u8>) {
+	let heatmap = heatmap.map(|heatmap| heatmap.iter().flat_map(|&e| heat(e)).collect::<Vec<u8>>());
+
+	let mut panels: Vec<Vec<u8>> = match layout {
+		Layout::SideBySide => vec![original.to_vec(), quantized.to_vec()],
+		Layout::Split => {
+			// the left half is the input and the right the output, so an edge
+			// running across the middle shows the difference
+			let mut split = original.to_vec();
+			let half = width / 2;
+			for (row, quantized) in split.chunks_mut(width * 3).zip(quantized.chunks(width * 3)) {
+				row[half * 3..].copy_from_slice(&quantized[half * 3..]);
+			}
+			vec![split]
+		}
+	};
+	panels.extend(heatmap);
+
+	let stride = width * 3;
+	let mut pixels = Vec::with_capacity(panels.len() * stride * height);
+	for y in 0..height {
+		for panel in &panels {
+			pixels.extend_from_slice(&panel[y * stride..(y + 1) * stride]);
+		}
+	}
+
+	(width * panels.len(), height, pixels)
+}
+
+/// Black at 0, red at a third of [HEATMAP_MAX], yellow at two thirds, and
+/// white at it or more
+fn heat(delta_e: f32) -> [u8; 3] {
+	let t = (delta_e / HEATMAP_MAX).clamp(0.0, 1.0) * 3.0;
+	[t, t - 1.0, t - 2.0].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
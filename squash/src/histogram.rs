@@ -0,0 +1,74 @@
+//! Saving the colours counted from an image so the count can be skipped next
+//! time. The file is the magic `CSQHIST`, the number of colours as a
+//! little endian u32, and then each colour as RGB followed by its count as a
+//! little endian u64.
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufReader, BufWriter, Read, Write},
+};
+
+use anyhow::bail;
+use camino::Utf8Path;
+use rgb::RGB8;
+
+const MAGIC: &[u8; 7] = b"CSQHIST";
+
+pub fn save<P: AsRef<Utf8Path>>(
+	path: P,
+	histogram: &HashMap<RGB8, usize>,
+) -> Result<(), anyhow::Error> {
+	let mut file = BufWriter::new(File::create(path.as_ref())?);
+	file.write_all(MAGIC)?;
+	file.write_all(&(histogram.len() as u32).to_le_bytes())?;
+
+	// sorted, so the same colours always make the same file
+	let mut colours: Vec<_> = histogram.iter().collect();
+	colours.sort_unstable_by_key(|(colour, _)| (colour.r, colour.g, colour.b));
+
+	for (colour, count) in colours {
+		file.write_all(&[colour.r, colour.g, colour.b])?;
+		file.write_all(&(*count as u64).to_le_bytes())?;
+	}
+
+	file.flush()?;
+	Ok(())
+}
+
+pub fn load<P: AsRef<Utf8Path>>(path: P) -> Result<Vec<(RGB8, usize)>, anyhow::Error> {
+	let mut file = BufReader::new(File::open(path.as_ref())?);
+
+	let mut magic = [0; 7];
+	file.read_exact(&mut magic)?;
+	if &magic != MAGIC {
+		bail!(
+			"{} is not a histogram saved by histogram-out=",
+			path.as_ref()
+		);
+	}
+
+	let mut len = [0; 4];
+	file.read_exact(&mut len)?;
+	let len = u32::from_le_bytes(len) as usize;
+
+	// every 24-bit colour is the most there can be
+	if len > 256 * 256 * 256 {
+		bail!("histogram has {len} colours, more than there are");
+	}
+
+	let mut colours = Vec::with_capacity(len);
+	let mut entry = [0; 3 + 8];
+	for _ in 0..len {
+		file.read_exact(&mut entry)?;
+
+		let mut count = [0; 8];
+		count.copy_from_slice(&entry[3..]);
+		colours.push((
+			RGB8::new(entry[0], entry[1], entry[2]),
+			u64::from_le_bytes(count) as usize,
+		));
+	}
+
+	Ok(colours)
+}
@@ -2,29 +2,330 @@ use std::{fs::File, io::BufWriter};
 
 use anyhow::{anyhow, bail};
 use camino::{Utf8Path, Utf8PathBuf};
-use colorsquash::Squasher;
-use gifed::{writer::ImageBuilder, Gif};
-use png::{ColorType, Decoder, Encoder};
+use colorsquash::depth;
+use gifed::{
+	block::{extension::DisposalMethod, Block, LoopCount},
+	writer::ImageBuilder,
+	Gif,
+};
+use png::{
+	chunk, BitDepth, ColorType, Decoder, Encoder, Info, ScaledFloat, SourceChromaticities,
+	SrgbRenderingIntent, Transformations,
+};
 use zune_jpeg::{zune_core::colorspace::ColorSpace, JpegDecoder};
 
 pub struct Image {
 	pub width: usize,
 	pub height: usize,
 	pub data: Vec<u8>,
+	/// one channel of gray instead of RGB
+	pub gray: bool,
+	pub metadata: Metadata,
+}
+
+/// The XMP keyword of a PNG iTXt chunk
+const PNG_XMP: &str = "XML:com.adobe.xmp";
+/// What starts the APP1 segment of a JPEG with XMP in it
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// What starts the APP1 segment of a JPEG with EXIF in it
+const JPEG_EXIF: &[u8] = b"Exif\0\0";
+
+/// Who made an image and how, read from the input so it can be written to the
+/// output. What's kept is whatever the output format can hold.
+#[derive(Debug, Default)]
+pub struct Metadata {
+	/// keyword and text. from PNG text chunks, or JPEG comments which have
+	/// the keyword Comment
+	pub text: Vec<(String, String)>,
+	/// EXIF as a PNG eXIf chunk has it, without the JPEG header
+	pub exif: Option<Vec<u8>>,
+	pub xmp: Option<String>,
+	/// kept unless color-profile=strip, even when the rest is stripped
+	pub color: ColorProfile,
+}
+
+/// How the colours of a PNG are meant to be shown, from its gAMA, cHRM, sRGB,
+/// and iCCP chunks. Leaving it out can change how the output looks.
+#[derive(Debug, Default)]
+pub struct ColorProfile {
+	pub gamma: Option<ScaledFloat>,
+	pub chromaticities: Option<SourceChromaticities>,
+	pub srgb: Option<SrgbRenderingIntent>,
+	/// the ICC profile, decompressed
+	pub icc: Option<Vec<u8>>,
+}
+
+impl Metadata {
+	/// Whether there's nothing but the colour profile. GIFs can't hold that
+	/// either, but it's there for most PNGs, so it's dropped without a word.
+	pub fn is_empty(&self) -> bool {
+		self.text.is_empty() && self.exif.is_none() && self.xmp.is_none()
+	}
+
+	fn from_png(info: &Info) -> Self {
+		let mut metadata = Metadata {
+			exif: info.exif_metadata.as_ref().map(|exif| exif.to_vec()),
+			color: ColorProfile {
+				gamma: info.source_gamma,
+				chromaticities: info.source_chromaticities,
+				srgb: info.srgb,
+				icc: info.icc_profile.as_ref().map(|icc| icc.to_vec()),
+			},
+			..Default::default()
+		};
+
+		for chunk in &info.uncompressed_latin1_text {
+			metadata
+				.text
+				.push((chunk.keyword.clone(), chunk.text.clone()));
+		}
+		for chunk in &info.compressed_latin1_text {
+			// a chunk that doesn't decompress isn't worth failing the image over
+			if let Ok(text) = chunk.get_text() {
+				metadata.text.push((chunk.keyword.clone(), text));
+			}
+		}
+		for chunk in &info.utf8_text {
+			match chunk.get_text() {
+				Ok(text) if chunk.keyword == PNG_XMP => metadata.xmp = Some(text),
+				Ok(text) => metadata.text.push((chunk.keyword.clone(), text)),
+				Err(_) => (),
+			}
+		}
+
+		metadata
+	}
+
+	/// Pick the EXIF, XMP, and comments out of the segments before the image
+	/// data. Each is a 0xFF, a marker byte, and a big endian length that counts
+	/// itself but not the marker.
+	fn from_jpeg(bytes: &[u8]) -> Self {
+		let mut metadata = Metadata::default();
+
+		// skip the start of image marker
+		let mut at = 2;
+		while at + 4 <= bytes.len() && bytes[at] == 0xFF {
+			let marker = bytes[at + 1];
+			match marker {
+				// padding
+				0xFF => {
+					at += 1;
+					continue;
+				}
+				// start of scan, where the image data is, or end of image
+				0xDA | 0xD9 => break,
+				_ => (),
+			}
+
+			let length = u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]) as usize;
+			let Some(segment) = bytes.get(at + 4..at + 2 + length.max(2)) else {
+				break;
+			};
+
+			match marker {
+				0xE1 if segment.starts_with(JPEG_EXIF) => {
+					metadata.exif = Some(segment[JPEG_EXIF.len()..].to_vec())
+				}
+				0xE1 if segment.starts_with(JPEG_XMP) => {
+					let xmp = String::from_utf8_lossy(&segment[JPEG_XMP.len()..]);
+					metadata.xmp = Some(xmp.into_owned());
+				}
+				0xFE => {
+					let comment = String::from_utf8_lossy(segment);
+					metadata.text.push(("Comment".into(), comment.into_owned()));
+				}
+				_ => (),
+			}
+
+			at += 2 + length.max(2);
+		}
+
+		metadata
+	}
+}
+
+impl Image {
+	/// The pixels as RGB, expanding gray if it has to.
+	pub fn rgb(&self) -> Vec<u8> {
+		if self.gray {
+			self.data.iter().flat_map(|&l| [l, l, l]).collect()
+		} else {
+			self.data.clone()
+		}
+	}
+
+	/// Turn a gray image into an RGB one. Does nothing if it's already RGB.
+	pub fn expand_gray(&mut self) {
+		if self.gray {
+			self.data = self.rgb();
+			self.gray = false;
+		}
+	}
+
+	/// Scale the image to `width` by `height`. `area` averages each pixel of
+	/// the output from the pixels of the input under it, otherwise it's the
+	/// nearest pixel, which keeps pixel art sharp.
+	pub fn resize(&mut self, width: usize, height: usize, area: bool) {
+		if (width, height) == (self.width, self.height) {
+			return;
+		}
+
+		let channels = if self.gray { 1 } else { 3 };
+		let columns = resize_weights(self.width, width, area);
+		let rows = resize_weights(self.height, height, area);
+
+		// across first, into floats so rounding only happens once
+		let mut across = vec![0.0f32; width * self.height * channels];
+		for (row, out) in self
+			.data
+			.chunks(self.width * channels)
+			.zip(across.chunks_mut(width * channels))
+		{
+			for (weights, out) in columns.iter().zip(out.chunks_mut(channels)) {
+				for &(from, weight) in weights {
+					for (out, value) in out.iter_mut().zip(&row[from * channels..]) {
+						*out += *value as f32 * weight;
+					}
+				}
+			}
+		}
+
+		let stride = width * channels;
+		let mut data = vec![0; stride * height];
+		for (weights, out) in rows.iter().zip(data.chunks_mut(stride)) {
+			for (idx, out) in out.iter_mut().enumerate() {
+				let value: f32 = weights
+					.iter()
+					.map(|&(from, weight)| across[from * stride + idx] * weight)
+					.sum();
+				*out = value.round().clamp(0.0, 255.0) as u8;
+			}
+		}
+
+		self.width = width;
+		self.height = height;
+		self.data = data;
+	}
+
+	/// Turn and flip the pixels so they're the right way up for an EXIF
+	/// orientation. 1 is already upright, 2 through 4 are flipped or upside
+	/// down, and 5 through 8 are on their side so width and height swap.
+	pub fn orient(&mut self, orientation: u16) {
+		if !(2..=8).contains(&orientation) {
+			return;
+		}
+
+		let channels = if self.gray { 1 } else { 3 };
+		let (width, height) = (self.width, self.height);
+		let (out_width, out_height) = if orientation >= 5 {
+			(height, width)
+		} else {
+			(width, height)
+		};
+
+		let mut data = Vec::with_capacity(self.data.len());
+		for y in 0..out_height {
+			for x in 0..out_width {
+				// where this pixel of the output is in the input
+				let (from_x, from_y) = match orientation {
+					2 => (width - 1 - x, y),
+					3 => (width - 1 - x, height - 1 - y),
+					4 => (x, height - 1 - y),
+					5 => (y, x),
+					6 => (y, height - 1 - x),
+					7 => (width - 1 - y, height - 1 - x),
+					_ => (width - 1 - y, x),
+				};
+				let at = (from_y * width + from_x) * channels;
+				data.extend_from_slice(&self.data[at..at + channels]);
+			}
+		}
+
+		self.width = out_width;
+		self.height = out_height;
+		self.data = data;
+	}
+}
+
+/// For each pixel of a row or column `to` long, which pixels of one `from`
+/// long make it up and how much of it each is
+fn resize_weights(from: usize, to: usize, area: bool) -> Vec<Vec<(usize, f32)>> {
+	let scale = from as f32 / to as f32;
+
+	(0..to)
+		.map(|idx| {
+			if !area {
+				let nearest = ((idx as f32 + 0.5) * scale) as usize;
+				return vec![(nearest.min(from - 1), 1.0)];
+			}
+
+			let start = idx as f32 * scale;
+			let end = ((idx + 1) as f32 * scale).min(from as f32);
+			let first = start.floor() as usize;
+			let last = (end.ceil() as usize).clamp(first + 1, from);
+
+			(first..last)
+				.map(|from| {
+					let covered = end.min(from as f32 + 1.0) - start.max(from as f32);
+					(from, covered.max(0.0) / (end - start))
+				})
+				.collect()
+		})
+		.collect()
 }
 
 pub fn get_png<P: AsRef<Utf8Path>>(path: P) -> Result<Image, anyhow::Error> {
-	let decoder = Decoder::new(File::open(path.as_ref())?);
+	let mut decoder = Decoder::new(File::open(path.as_ref())?);
+	// grayscale less than 8-bit to 8-bit, palettes to RGB, tRNS to alpha
+	decoder.set_transformations(Transformations::EXPAND);
 	let mut reader = decoder.read_info()?;
 
 	let mut data = vec![0; reader.output_buffer_size()];
 	let info = reader.next_frame(&mut data)?;
 	data.resize(info.buffer_size(), 0);
 
+	// text can come after the image data, so read to the end
+	reader.finish()?;
+	let metadata = Metadata::from_png(reader.info());
+
 	let colors = info.color_type;
 	match colors {
-		ColorType::Grayscale | ColorType::GrayscaleAlpha | ColorType::Indexed => {
-			bail!("colortype {colors:?} not supported")
+		ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+			let channels = if colors == ColorType::GrayscaleAlpha {
+				2
+			} else {
+				1
+			};
+
+			let data = if info.bit_depth == BitDepth::Sixteen {
+				// the gray goes through the dither as if it were RGB
+				let wide = depth::rgb16_from_gray16_be_bytes(&data, channels);
+				let narrow = depth::dither_rgb16(&wide, info.width as usize);
+				narrow.iter().map(|c| c.r).collect()
+			} else {
+				data.iter().step_by(channels).copied().collect()
+			};
+
+			Ok(Image {
+				width: info.width as usize,
+				height: info.height as usize,
+				data,
+				gray: true,
+				metadata,
+			})
+		}
+		ColorType::Rgb | ColorType::Rgba if info.bit_depth == BitDepth::Sixteen => {
+			let channels = if colors == ColorType::Rgba { 4 } else { 3 };
+			let wide = depth::rgb16_from_be_bytes(&data, channels);
+			let narrow = depth::dither_rgb16(&wide, info.width as usize);
+
+			Ok(Image {
+				width: info.width as usize,
+				height: info.height as usize,
+				data: narrow.iter().flat_map(|c| [c.r, c.g, c.b]).collect(),
+				gray: false,
+				metadata,
+			})
 		}
 		ColorType::Rgba => {
 			let pixels = info.width as usize * info.height as usize;
@@ -41,12 +342,17 @@ pub fn get_png<P: AsRef<Utf8Path>>(path: P) -> Result<Image, anyhow::Error> {
 				width: info.width as usize,
 				height: info.height as usize,
 				data,
+				gray: false,
+				metadata,
 			})
 		}
-		ColorType::Rgb => Ok(Image {
+		// EXPAND turned any palette into RGB, so that's what Indexed would be
+		ColorType::Rgb | ColorType::Indexed => Ok(Image {
 			width: info.width as usize,
 			height: info.height as usize,
 			data,
+			gray: false,
+			metadata,
 		}),
 	}
 }
@@ -65,39 +371,961 @@ pub fn get_jpg<P: AsRef<Utf8Path>>(path: P) -> Result<Image, anyhow::Error> {
 		_ => bail!("colorspace {colorspace:?} not supported"),
 	}
 
-	Ok(Image {
+	let mut image = Image {
 		width: info.width as usize,
 		height: info.height as usize,
 		data: pixels,
+		gray: false,
+		metadata: Metadata::from_jpeg(&content),
+	};
+
+	// cameras write the pixels the way the sensor was held and say which way
+	// up they go in the EXIF
+	if let Some(orientation) = image
+		.metadata
+		.exif
+		.as_mut()
+		.and_then(|exif| take_orientation(exif))
+	{
+		image.orient(orientation);
+	}
+
+	Ok(image)
+}
+
+/// The orientation tag of the first IFD of EXIF, 1 through 8. It's set to 1
+/// because the pixels are about to be turned, and a kept EXIF that still said
+/// to turn them would have viewers do it again.
+fn take_orientation(exif: &mut [u8]) -> Option<u16> {
+	let big = match exif.get(..4)? {
+		b"MM\0*" => true,
+		b"II*\0" => false,
+		_ => return None,
+	};
+	let read = |bytes: &[u8], at: usize, len: usize| {
+		let field = bytes.get(at..at + len)?;
+		Some(if big {
+			field.iter().fold(0, |n, &b| n << 8 | b as u32)
+		} else {
+			field.iter().rev().fold(0, |n, &b| n << 8 | b as u32)
+		})
+	};
+
+	let ifd = read(exif, 4, 4)? as usize;
+	let entries = read(exif, ifd, 2)? as usize;
+	for entry in 0..entries {
+		// each entry is a tag, type, count, and the value
+		let at = ifd + 2 + entry * 12;
+		if read(exif, at, 2)? != 0x0112 {
+			continue;
+		}
+
+		let orientation = read(exif, at + 8, 2)? as u16;
+		let upright = if big { [0, 1] } else { [1, 0] };
+		exif[at + 8..at + 10].copy_from_slice(&upright);
+		return Some(orientation).filter(|orientation| (1..=8).contains(orientation));
+	}
+
+	None
+}
+
+/// Read a little endian number of `len` bytes
+fn le(bytes: &[u8], at: usize, len: usize) -> Result<u32, anyhow::Error> {
+	let field = bytes
+		.get(at..at + len)
+		.ok_or(anyhow!("file is too short to be an image"))?;
+	Ok(field.iter().rev().fold(0, |n, &b| n << 8 | b as u32))
+}
+
+/// Bits from under a mask, scaled to 0 through 255
+fn masked(value: u32, mask: u32) -> u8 {
+	if mask == 0 {
+		return 0;
+	}
+
+	let max = (mask >> mask.trailing_zeros()) as u64;
+	let bits = ((value & mask) >> mask.trailing_zeros()) as u64;
+	((bits * 255 + max / 2) / max) as u8
+}
+
+pub fn get_bmp<P: AsRef<Utf8Path>>(path: P) -> Result<Image, anyhow::Error> {
+	let bytes = std::fs::read(path.as_ref())?;
+	if !bytes.starts_with(b"BM") {
+		bail!("not a BMP, it doesn't start with BM");
+	}
+
+	let offset = le(&bytes, 10, 4)? as usize;
+	let header = le(&bytes, 14, 4)? as usize;
+
+	// the oldest header has 16-bit sizes, no compression, and three byte
+	// palette entries. the rest start the same as BITMAPINFOHEADER. a negative
+	// height is a BMP stored from the top down
+	let (width, height, depth, compression, used) = if header == 12 {
+		let (width, height) = (le(&bytes, 18, 2)?, le(&bytes, 20, 2)?);
+		(width as i32, height as i32, le(&bytes, 24, 2)?, 0, 0)
+	} else {
+		(
+			le(&bytes, 18, 4)? as i32,
+			le(&bytes, 22, 4)? as i32,
+			le(&bytes, 28, 2)?,
+			le(&bytes, 30, 4)?,
+			le(&bytes, 46, 4)?,
+		)
+	};
+	let depth = depth as usize;
+
+	let masks = match (compression, depth) {
+		(0, 1 | 2 | 4 | 8 | 24) => [0; 3],
+		(0, 16) => [0x7C00, 0x03E0, 0x001F],
+		(0, 32) => [0xFF0000, 0x00FF00, 0x0000FF],
+		// BI_BITFIELDS and BI_ALPHABITFIELDS. the masks come right after
+		// BITMAPINFOHEADER, which is where they are in the later headers too
+		(3 | 6, 16 | 32) => [le(&bytes, 54, 4)?, le(&bytes, 58, 4)?, le(&bytes, 62, 4)?],
+		(1 | 2, _) => bail!("run-length encoded BMPs aren't supported"),
+		(0 | 3 | 6, _) => bail!("{depth}-bit BMPs aren't supported"),
+		_ => bail!("BMP compression {compression} not supported"),
+	};
+
+	let palette: Vec<[u8; 3]> = if depth <= 8 {
+		let entry = if header == 12 { 3 } else { 4 };
+		let count = if used == 0 { 1 << depth } else { used as usize };
+		let start = 14 + header;
+		let end = start
+			.saturating_add(count.saturating_mul(entry))
+			.min(offset);
+
+		bytes
+			.get(start..end)
+			.ok_or(anyhow!("BMP palette is cut short"))?
+			.chunks_exact(entry)
+			.map(|bgr| [bgr[2], bgr[1], bgr[0]])
+			.collect()
+	} else {
+		vec![]
+	};
+
+	let top_down = height < 0;
+	let width = width.unsigned_abs() as usize;
+	let height = height.unsigned_abs() as usize;
+	// rows are padded out to four bytes. the header can say anything, so make
+	// sure the pixels are really there before making room for them
+	let stride = width
+		.checked_mul(depth)
+		.map(|bits| bits.div_ceil(32) * 4)
+		.ok_or(anyhow!("BMP is too wide"))?;
+	let end = stride
+		.checked_mul(height)
+		.and_then(|len| len.checked_add(offset));
+	if end.is_none_or(|end| end > bytes.len()) {
+		bail!("BMP is missing pixels");
+	}
+
+	let mut data = Vec::with_capacity(width * height * 3);
+	for y in 0..height {
+		let row = if top_down { y } else { height - 1 - y };
+		let start = offset + row * stride;
+		let row = bytes
+			.get(start..start + stride)
+			.ok_or(anyhow!("BMP is missing pixels"))?;
+
+		for x in 0..width {
+			let rgb = match depth {
+				24 => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3]],
+				16 | 32 => {
+					let value = le(row, x * depth / 8, depth / 8)?;
+					masks.map(|mask| masked(value, mask))
+				}
+				_ => {
+					let bit = x * depth;
+					let index = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+					*palette
+						.get(index as usize)
+						.ok_or(anyhow!("BMP has a pixel that isn't in its palette"))?
+				}
+			};
+			data.extend(rgb);
+		}
+	}
+
+	Ok(Image {
+		width,
+		height,
+		data,
+		gray: false,
+		metadata: Metadata::default(),
 	})
 }
 
-pub fn save_png(
-	image: Image,
-	squasher: Squasher<u8>,
-	path: Utf8PathBuf,
+/// A TGA colour, little endian BGR or BGRA, or 5 bits of each in two bytes
+fn tga_colour(pixel: &[u8]) -> [u8; 3] {
+	match pixel {
+		&[low, high] => {
+			let value = u16::from_le_bytes([low, high]) as u32;
+			[0x7C00, 0x03E0, 0x001F].map(|mask| masked(value, mask))
+		}
+		_ => [pixel[2], pixel[1], pixel[0]],
+	}
+}
+
+/// Undo the run-length encoding of a TGA. Each packet starts with a byte with
+/// the count, less one, in the low seven bits. If the high bit's set one pixel
+/// follows to repeat that many times, if not that many pixels follow.
+fn unpack_tga(mut bytes: &[u8], pixel: usize, size: usize) -> Result<Vec<u8>, anyhow::Error> {
+	// a packet of 1 + pixel bytes is at most 128 pixels, so anything more than
+	// that can only be a lie in the header
+	if bytes.len().saturating_mul(128) < size {
+		bail!("TGA is missing pixels");
+	}
+
+	let mut raw = Vec::with_capacity(size);
+	while raw.len() < size {
+		let (&packet, rest) = bytes
+			.split_first()
+			.ok_or(anyhow!("TGA is missing pixels"))?;
+		let count = (packet & 0x7F) as usize + 1;
+		let repeat = packet & 0x80 != 0;
+
+		let len = if repeat { pixel } else { pixel * count };
+		let run = rest.get(..len).ok_or(anyhow!("TGA is missing pixels"))?;
+		if repeat {
+			for _ in 0..count {
+				raw.extend_from_slice(run);
+			}
+		} else {
+			raw.extend_from_slice(run);
+		}
+
+		bytes = &rest[len..];
+	}
+
+	raw.truncate(size);
+	Ok(raw)
+}
+
+pub fn get_tga<P: AsRef<Utf8Path>>(path: P) -> Result<Image, anyhow::Error> {
+	let bytes = std::fs::read(path.as_ref())?;
+
+	let id = le(&bytes, 0, 1)? as usize;
+	let has_map = le(&bytes, 1, 1)? == 1;
+	let kind = le(&bytes, 2, 1)?;
+	let map_first = le(&bytes, 3, 2)? as usize;
+	let map_len = le(&bytes, 5, 2)? as usize;
+	let map_bits = le(&bytes, 7, 1)? as usize;
+	let width = le(&bytes, 12, 2)? as usize;
+	let height = le(&bytes, 14, 2)? as usize;
+	let depth = le(&bytes, 16, 1)? as usize;
+	let descriptor = le(&bytes, 17, 1)?;
+
+	// colour mapped, true colour, and gray, and then the same again run-length
+	// encoded
+	let (mapped, gray, rle) = match kind {
+		1 | 2 | 3 | 9 | 10 | 11 => (kind % 8 == 1, kind % 8 == 3, kind > 8),
+		0 => bail!("TGA has no image data"),
+		_ => bail!("TGA image type {kind} not supported"),
+	};
+	match depth {
+		8 | 16 if mapped && has_map => (),
+		8 | 16 if gray => (),
+		15 | 16 | 24 | 32 if !mapped && !gray => (),
+		_ if mapped && !has_map => bail!("colour mapped TGA has no colour map"),
+		_ => bail!("{depth}-bit TGAs of image type {kind} aren't supported"),
+	}
+
+	// the colour map is there even for images that don't use it
+	let map_start = 18 + id;
+	let map_entry = map_bits.div_ceil(8);
+	let map_size = if has_map { map_len * map_entry } else { 0 };
+	let map = bytes
+		.get(map_start..map_start + map_size)
+		.ok_or(anyhow!("TGA colour map is cut short"))?;
+	let palette: Vec<[u8; 3]> = match map_entry {
+		2..=4 => map.chunks_exact(map_entry).map(tga_colour).collect(),
+		_ if mapped => bail!("{map_bits}-bit TGA colour maps aren't supported"),
+		_ => vec![],
+	};
+
+	let pixel = depth.div_ceil(8);
+	let size = width
+		.checked_mul(height)
+		.and_then(|pixels| pixels.checked_mul(pixel))
+		.ok_or(anyhow!("TGA is too large"))?;
+	let rest = bytes.get(map_start + map_size..).unwrap_or_default();
+	let raw = if rle {
+		unpack_tga(rest, pixel, size)?
+	} else {
+		rest.get(..size)
+			.ok_or(anyhow!("TGA is missing pixels"))?
+			.to_vec()
+	};
+
+	// the pixels start at the bottom left unless these say otherwise
+	let right_to_left = descriptor & 0x10 != 0;
+	let top_down = descriptor & 0x20 != 0;
+
+	let channels = if gray { 1 } else { 3 };
+	let mut data = vec![0; width * height * channels];
+	for (idx, pixel) in raw.chunks_exact(pixel).enumerate() {
+		let (row, column) = (idx / width, idx % width);
+		let y = if top_down { row } else { height - 1 - row };
+		let x = if right_to_left {
+			width - 1 - column
+		} else {
+			column
+		};
+		let at = (y * width + x) * channels;
+
+		if gray {
+			// 16-bit gray is gray and alpha
+			data[at] = pixel[0];
+		} else if mapped {
+			let index = le(pixel, 0, pixel.len())? as usize;
+			let rgb = index
+				.checked_sub(map_first)
+				.and_then(|index| palette.get(index))
+				.ok_or(anyhow!("TGA has a pixel that isn't in its colour map"))?;
+			data[at..at + 3].copy_from_slice(rgb);
+		} else {
+			data[at..at + 3].copy_from_slice(&tga_colour(pixel));
+		}
+	}
+
+	Ok(Image {
+		width,
+		height,
+		data,
+		gray,
+		metadata: Metadata::default(),
+	})
+}
+
+/// One image of a GIF, drawn over what the ones before it left
+pub struct GifFrame {
+	pub left: usize,
+	pub top: usize,
+	pub width: usize,
+	pub height: usize,
+	/// RGB, or None where it's transparent and what's under it shows through.
+	/// If the image data is cut short there are fewer than `width * height`
+	/// and the rest shows through too
+	pub pixels: Vec<Option<[u8; 3]>>,
+	/// in hundredths of a second
+	pub delay: u16,
+	/// what happens to the frame's rectangle before the next frame is drawn.
+	/// 2 clears it to the background and 3 puts back what was there before
+	pub disposal: u8,
+}
+
+pub struct Animation {
+	pub width: usize,
+	pub height: usize,
+	/// what the canvas starts as and is cleared to
+	pub background: [u8; 3],
+	pub frames: Vec<GifFrame>,
+	/// how many more times it plays after the first, 0 being forever. None if
+	/// it plays once
+	pub repeat: Option<u16>,
+	pub metadata: Metadata,
+}
+
+impl Animation {
+	/// Every frame as the whole canvas would look when it's shown, as RGB
+	pub fn composite(&self) -> Vec<Vec<u8>> {
+		let mut canvas: Vec<u8> = self.background.repeat(self.width * self.height);
+		let mut shown = Vec::with_capacity(self.frames.len());
+
+		for frame in &self.frames {
+			let before = (frame.disposal == 3).then(|| canvas.clone());
+
+			self.draw(&mut canvas, frame, |pixel| *pixel);
+			shown.push(canvas.clone());
+
+			match (frame.disposal, before) {
+				(3, Some(before)) => canvas = before,
+				(2, _) => self.draw(&mut canvas, frame, |_| Some(self.background)),
+				_ => (),
+			}
+		}
+
+		shown
+	}
+
+	/// The first frame as an image, with anything transparent as the
+	/// background
+	pub fn into_first(self) -> Image {
+		if self.frames.len() > 1 {
+			eprintln!(
+				"GIF has {} frames, only the first is used",
+				self.frames.len()
+			);
+		}
+
+		// nothing's been drawn before the first, so there's no disposal to do
+		let mut canvas = self.background.repeat(self.width * self.height);
+		if let Some(first) = self.frames.first() {
+			self.draw(&mut canvas, first, |pixel| *pixel);
+		}
+
+		Image {
+			width: self.width,
+			height: self.height,
+			data: canvas,
+			gray: false,
+			metadata: self.metadata,
+		}
+	}
+
+	/// Put the pixels of a frame on the canvas, leaving what's under
+	/// transparent ones and anything outside the canvas
+	fn draw<F>(&self, canvas: &mut [u8], frame: &GifFrame, colour: F)
+	where
+		F: Fn(&Option<[u8; 3]>) -> Option<[u8; 3]>,
+	{
+		for (idx, pixel) in frame.pixels.iter().enumerate() {
+			let x = frame.left + idx % frame.width;
+			let y = frame.top + idx / frame.width;
+			if x >= self.width || y >= self.height {
+				continue;
+			}
+
+			if let Some(rgb) = colour(pixel) {
+				let at = (y * self.width + x) * 3;
+				canvas[at..at + 3].copy_from_slice(&rgb);
+			}
+		}
+	}
+}
+
+/// The data of a GIF block, which is in sub-blocks that each start with their
+/// length and finish with an empty one
+fn sub_blocks(bytes: &[u8], at: &mut usize) -> Result<Vec<u8>, anyhow::Error> {
+	let mut data = vec![];
+	loop {
+		let len = le(bytes, *at, 1)? as usize;
+		*at += 1;
+		if len == 0 {
+			return Ok(data);
+		}
+
+		data.extend_from_slice(
+			bytes
+				.get(*at..*at + len)
+				.ok_or(anyhow!("GIF is cut short"))?,
+		);
+		*at += len;
+	}
+}
+
+/// A GIF colour table of `2 << size` RGB entries
+fn gif_palette(bytes: &[u8], at: &mut usize, size: u8) -> Result<Vec<[u8; 3]>, anyhow::Error> {
+	let len = (2 << size) * 3;
+	let table = bytes
+		.get(*at..*at + len)
+		.ok_or(anyhow!("GIF colour table is cut short"))?;
+	*at += len;
+
+	Ok(table.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+/// The most indices `len` bytes of LZW could decompress to. Every code is at
+/// least `min + 1` bits and none is more than 4096 indices.
+fn lzw_limit(len: usize, min: u8) -> usize {
+	(len.saturating_mul(8) / (min as usize + 1)).saturating_mul(4096)
+}
+
+/// Decompress the LZW of a GIF image into `size` indices, or fewer if the data
+/// stops early. Codes start a bit wider than the smallest index size and grow
+/// as the table fills, up to 12 bits, and are packed from the lowest bit of
+/// each byte.
+fn unpack_lzw(data: &[u8], min: u8, size: usize) -> Result<Vec<u8>, anyhow::Error> {
+	if !(1..=11).contains(&min) {
+		bail!("GIF has a code size of {min}, it has to be 1 to 11");
+	}
+
+	// the size is from the header, which can say anything
+	if lzw_limit(data.len(), min) < size {
+		bail!("GIF is missing pixels");
+	}
+
+	let clear = 1usize << min;
+	let end = clear + 1;
+
+	// each string in the table is the string `prefix` is for, then `suffix`.
+	// `first` is the first index of it, and `length` how long it is
+	let mut prefix = [0u16; 4096];
+	let mut suffix = [0u8; 4096];
+	let mut first = [0u8; 4096];
+	let mut length = [0usize; 4096];
+	for code in 0..clear {
+		suffix[code] = code as u8;
+		first[code] = code as u8;
+		length[code] = 1;
+	}
+
+	let mut out = Vec::with_capacity(size.min(data.len() * 4));
+	let mut next = end + 1;
+	let mut width = min as usize + 1;
+	let mut previous: Option<usize> = None;
+
+	let (mut bits, mut held) = (0u32, 0usize);
+	let mut bytes = data.iter();
+	while out.len() < size {
+		while held < width {
+			let Some(&byte) = bytes.next() else {
+				// some encoders stop early
+				return Ok(out);
+			};
+			bits |= (byte as u32) << held;
+			held += 8;
+		}
+		let code = (bits & ((1 << width) - 1)) as usize;
+		bits >>= width;
+		held -= width;
+
+		if code == clear {
+			next = end + 1;
+			width = min as usize + 1;
+			previous = None;
+			continue;
+		} else if code == end {
+			break;
+		}
+
+		match previous {
+			// a code that's about to be made: the previous string and its own
+			// first index
+			Some(previous) if code <= next && next < 4096 => {
+				prefix[next] = previous as u16;
+				suffix[next] = if code == next {
+					first[previous]
+				} else {
+					first[code]
+				};
+				first[next] = first[previous];
+				length[next] = length[previous] + 1;
+
+				next += 1;
+				if next == 1 << width && width < 12 {
+					width += 1;
+				}
+			}
+			// the table's full, so it stays as it is until a clear
+			Some(_) if code < next => (),
+			None if code < clear => (),
+			_ => bail!("GIF image data is corrupt"),
+		}
+
+		let start = out.len();
+		out.resize(start + length[code], 0);
+		let mut string = code;
+		for at in (start..out.len()).rev() {
+			out[at] = suffix[string];
+			string = prefix[string] as usize;
+		}
+
+		previous = Some(code);
+	}
+
+	// the last string can go past the end
+	out.truncate(size);
+	Ok(out)
+}
+
+/// Reorder the rows of an interlaced GIF image, which are every eighth row
+/// from the first, every eighth from the fifth, every fourth from the third,
+/// and then every other from the second. Rows that were cut short are
+/// transparent.
+fn deinterlace(pixels: Vec<Option<[u8; 3]>>, width: usize, height: usize) -> Vec<Option<[u8; 3]>> {
+	let rows = (0..height)
+		.step_by(8)
+		.chain((4..height).step_by(8))
+		.chain((2..height).step_by(4))
+		.chain((1..height).step_by(2));
+
+	let mut out = vec![None; width * height];
+	for (row, to) in pixels.chunks(width.max(1)).zip(rows) {
+		out[to * width..to * width + row.len()].copy_from_slice(row);
+	}
+	out
+}
+
+pub fn read_gif<P: AsRef<Utf8Path>>(path: P) -> Result<Animation, anyhow::Error> {
+	let bytes = std::fs::read(path.as_ref())?;
+	if !bytes.starts_with(b"GIF87a") && !bytes.starts_with(b"GIF89a") {
+		bail!("not a GIF, it doesn't start with GIF87a or GIF89a");
+	}
+
+	let width = le(&bytes, 6, 2)? as usize;
+	let height = le(&bytes, 8, 2)? as usize;
+	// every frame has to be on the screen, so pixels beyond what all of the
+	// file could hold can only be a lie
+	if lzw_limit(bytes.len(), 1) < width * height {
+		bail!("GIF is larger than its data could fill");
+	}
+	let packed = le(&bytes, 10, 1)? as u8;
+	let background = le(&bytes, 11, 1)? as usize;
+
+	let mut at = 13;
+	let global = if packed & 0x80 != 0 {
+		gif_palette(&bytes, &mut at, packed & 7)?
+	} else {
+		vec![]
+	};
+
+	let mut animation = Animation {
+		width,
+		height,
+		background: global.get(background).copied().unwrap_or([0; 3]),
+		frames: vec![],
+		repeat: None,
+		metadata: Metadata::default(),
+	};
+
+	// the graphic control extension is for the image right after it
+	let (mut delay, mut disposal, mut transparent) = (0, 0, None);
+	loop {
+		let block = le(&bytes, at, 1).map_err(|_| anyhow!("GIF is cut short"))?;
+		at += 1;
+
+		match block {
+			// extensions
+			0x21 => {
+				let label = le(&bytes, at, 1)?;
+				at += 1;
+				let data = sub_blocks(&bytes, &mut at)?;
+
+				match label {
+					0xF9 if data.len() >= 4 => {
+						disposal = (data[0] >> 2) & 7;
+						delay = u16::from_le_bytes([data[1], data[2]]);
+						transparent = (data[0] & 1 != 0).then_some(data[3]);
+					}
+					// the Netscape extension, which has the loop count
+					0xFF if data.len() >= 14 && data.starts_with(b"NETSCAPE2.0") => {
+						animation.repeat = Some(u16::from_le_bytes([data[12], data[13]]));
+					}
+					0xFE => {
+						let comment = String::from_utf8_lossy(&data);
+						animation
+							.metadata
+							.text
+							.push(("Comment".into(), comment.into_owned()));
+					}
+					_ => (),
+				}
+			}
+			// an image
+			0x2C => {
+				let left = le(&bytes, at, 2)? as usize;
+				let top = le(&bytes, at + 2, 2)? as usize;
+				let frame_width = le(&bytes, at + 4, 2)? as usize;
+				let frame_height = le(&bytes, at + 6, 2)? as usize;
+				let packed = le(&bytes, at + 8, 1)? as u8;
+				at += 9;
+
+				if left + frame_width > width || top + frame_height > height {
+					bail!(
+						"GIF has a {frame_width}x{frame_height} image at {left},{top}, \
+						which isn't on its {width}x{height} screen"
+					);
+				}
+
+				let local = if packed & 0x80 != 0 {
+					Some(gif_palette(&bytes, &mut at, packed & 7)?)
+				} else {
+					None
+				};
+				let palette = local.as_ref().unwrap_or(&global);
+
+				let min = le(&bytes, at, 1)? as u8;
+				at += 1;
+				let data = sub_blocks(&bytes, &mut at)?;
+
+				let size = frame_width * frame_height;
+				let indices = unpack_lzw(&data, min, size)?;
+
+				let mut pixels = indices
+					.into_iter()
+					.map(|index| match transparent {
+						Some(transparent) if transparent == index => None,
+						// an index past the end of the table is usually black
+						_ => Some(palette.get(index as usize).copied().unwrap_or([0; 3])),
+					})
+					.collect();
+				if packed & 0x40 != 0 {
+					pixels = deinterlace(pixels, frame_width, frame_height);
+				}
+
+				animation.frames.push(GifFrame {
+					left,
+					top,
+					width: frame_width,
+					height: frame_height,
+					pixels,
+					delay,
+					disposal,
+				});
+				(delay, disposal, transparent) = (0, 0, None);
+			}
+			// the trailer
+			0x3B => break,
+			_ => bail!("GIF has an unknown block {block:#04x}"),
+		}
+	}
+
+	if animation.frames.is_empty() {
+		bail!("GIF has no images in it");
+	}
+
+	Ok(animation)
+}
+
+/// `palette` is RGB, three bytes per colour. A palette of 16 colours or fewer
+/// is written with 4, 2, or 1 bit indices, which is a lot smaller.
+pub fn save_png(image: Image, palette: Vec<u8>, path: Utf8PathBuf) -> Result<(), anyhow::Error> {
+	let file = File::create(path)?;
+	let bufw = BufWriter::new(file);
+
+	let (depth, bits) = match palette.len() / 3 {
+		0..=2 => (BitDepth::One, 1),
+		3..=4 => (BitDepth::Two, 2),
+		5..=16 => (BitDepth::Four, 4),
+		_ => (BitDepth::Eight, 8),
+	};
+
+	let Metadata {
+		text,
+		exif,
+		xmp,
+		color,
+	} = image.metadata;
+
+	let mut info = Info::with_size(image.width as u32, image.height as u32);
+	info.color_type = ColorType::Indexed;
+	info.bit_depth = depth;
+	info.palette = Some(palette.into());
+	info.source_gamma = color.gamma;
+	info.source_chromaticities = color.chromaticities;
+	info.srgb = color.srgb;
+	// the palette is always RGB, so a profile for gray doesn't fit it. the
+	// colour space of an ICC profile is the four bytes at 16
+	info.icc_profile = color
+		.icc
+		.filter(|icc| icc.get(16..20) != Some(b"GRAY"))
+		.map(Into::into);
+
+	let mut enc = Encoder::with_info(bufw, info)?;
+	for (keyword, text) in text {
+		if text.chars().all(|c| (c as u32) < 256) {
+			enc.add_text_chunk(keyword, text)?;
+		} else {
+			enc.add_itxt_chunk(keyword, text)?;
+		}
+	}
+	if let Some(xmp) = xmp {
+		enc.add_itxt_chunk(PNG_XMP.into(), xmp)?;
+	}
+
+	let mut writer = enc.write_header()?;
+	if let Some(exif) = exif {
+		writer.write_chunk(chunk::eXIf, &exif)?;
+	}
+	if bits == 8 {
+		writer.write_image_data(&image.data)?;
+	} else {
+		writer.write_image_data(&pack_indices(&image.data, image.width, bits))?;
+	}
+
+	Ok(())
+}
+
+/// Pack indices into `bits` each, the first in the highest bits of a byte like
+/// PNG wants. Every row starts on a new byte.
+fn pack_indices(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+	let per_byte = 8 / bits;
+	let stride = width.div_ceil(per_byte);
+
+	let mut packed = Vec::with_capacity(stride * indices.len() / width.max(1));
+	for row in indices.chunks(width.max(1)) {
+		for pixels in row.chunks(per_byte) {
+			let mut byte = 0;
+			for (idx, index) in pixels.iter().enumerate() {
+				byte |= index << (8 - bits * (idx + 1));
+			}
+			packed.push(byte);
+		}
+	}
+
+	packed
+}
+
+/// Write an image that's RGB rather than indexed, like the comparison from
+/// compare=
+pub fn save_rgb_png(
+	width: usize,
+	height: usize,
+	data: &[u8],
+	path: &Utf8Path,
 ) -> Result<(), anyhow::Error> {
 	let file = File::create(path)?;
 	let bufw = BufWriter::new(file);
 
-	let mut enc = Encoder::new(bufw, image.width as u32, image.height as u32);
-	enc.set_color(ColorType::Indexed);
+	let mut enc = Encoder::new(bufw, width as u32, height as u32);
+	enc.set_color(ColorType::Rgb);
 	enc.set_depth(png::BitDepth::Eight);
-	enc.set_palette(squasher.palette_bytes());
-	enc.write_header()?.write_image_data(&image.data)?;
+
+	let mut writer = enc.write_header()?;
+	writer.write_image_data(data)?;
 
 	Ok(())
 }
 
-pub fn save_gif(
-	image: Image,
-	squasher: Squasher<u8>,
-	path: Utf8PathBuf,
-) -> Result<(), anyhow::Error> {
+/// `palette` is RGB, three bytes per colour
+pub fn save_gif(image: Image, palette: Vec<u8>, path: Utf8PathBuf) -> Result<(), anyhow::Error> {
+	if !image.metadata.is_empty() {
+		eprintln!("GIFs can't hold the metadata of the input, so it wasn't kept");
+	}
+
 	let mut gif = Gif::new(image.width as u16, image.height as u16);
-	gif.set_palette(Some(squasher.palette_gifed()));
+	let palette = palette
+		.as_slice()
+		.try_into()
+		.map_err(|_| anyhow!("palette doesn't fit in a gif"))?;
+	gif.set_palette(Some(palette));
 	gif.push(ImageBuilder::new(image.width as u16, image.height as u16).build(image.data)?);
 	gif.save(path)?;
 
 	Ok(())
 }
+
+/// Write the frames of an animation again, each as `indices` into `palette`,
+/// with the same place, timing, and disposal. `transparent` is the index of
+/// the pixels that were transparent.
+pub fn save_animation(
+	animation: &Animation,
+	indices: Vec<Vec<u8>>,
+	palette: Vec<u8>,
+	transparent: Option<u8>,
+	path: Utf8PathBuf,
+) -> Result<(), anyhow::Error> {
+	let mut gif = Gif::new(animation.width as u16, animation.height as u16);
+	let palette = palette
+		.as_slice()
+		.try_into()
+		.map_err(|_| anyhow!("palette doesn't fit in a gif"))?;
+	gif.set_palette(Some(palette));
+
+	if let Some(repeat) = animation.repeat {
+		let count = match repeat {
+			0 => LoopCount::Forever,
+			n => LoopCount::Number(n),
+		};
+		gif.push(Block::LoopingExtension(count));
+	}
+
+	for (frame, indices) in animation.frames.iter().zip(indices) {
+		let disposal = match frame.disposal {
+			1 => DisposalMethod::DoNotDispose,
+			2 => DisposalMethod::RestoreBackground,
+			3 => DisposalMethod::RestorePrevious,
+			_ => DisposalMethod::NoAction,
+		};
+		let transparent = transparent.filter(|_| frame.pixels.contains(&None));
+
+		let image = ImageBuilder::new(frame.width as u16, frame.height as u16)
+			.offset(frame.left as u16, frame.top as u16)
+			.delay(frame.delay)
+			.disposal_method(disposal)
+			.transparent_index(transparent)
+			.build(indices)?;
+		gif.push(image);
+	}
+	gif.save(path)?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// LZW that never compresses: every index is its own code, with a clear
+	/// before the table would make the codes wider
+	fn pack_lzw(indices: &[u8], min: u8) -> Vec<u8> {
+		let clear = 1u32 << min;
+		let width = min as usize + 1;
+		let room = (1 << width) - clear as usize - 2;
+
+		let mut codes = vec![];
+		for run in indices.chunks(room) {
+			codes.push(clear);
+			codes.extend(run.iter().map(|&index| index as u32));
+		}
+		codes.push(clear + 1);
+
+		let mut out = vec![];
+		let (mut bits, mut held) = (0u32, 0);
+		for code in codes {
+			bits |= code << held;
+			held += width;
+			while held >= 8 {
+				out.push(bits as u8);
+				bits >>= 8;
+				held -= 8;
+			}
+		}
+		if held > 0 {
+			out.push(bits as u8);
+		}
+		out
+	}
+
+	#[test]
+	fn lzw_round_trips() {
+		let indices: Vec<u8> = (0..2000u32).map(|i| (i * 7 % 13) as u8).collect();
+		let packed = pack_lzw(&indices, 4);
+
+		assert_eq!(unpack_lzw(&packed, 4, indices.len()).unwrap(), indices);
+	}
+
+	#[test]
+	fn lzw_sample_image() {
+		// the 10x10 example image from "What's in a GIF", which makes strings of
+		// earlier codes and grows the code width
+		let packed = [
+			0x8C, 0x2D, 0x99, 0x87, 0x2A, 0x1C, 0xDC, 0x33, 0xA0, 0x02, 0x75, 0xEC, 0x95, 0xFA,
+			0xA8, 0xDE, 0x60, 0x8C, 0x04, 0x91, 0x4C, 0x01,
+		];
+		let rows: [&[u8; 10]; 10] = [
+			b"1111122222",
+			b"1111122222",
+			b"1111122222",
+			b"1110000222",
+			b"1110000222",
+			b"2220000111",
+			b"2220000111",
+			b"2222211111",
+			b"2222211111",
+			b"2222211111",
+		];
+		let expected: Vec<u8> = rows.iter().flat_map(|row| row.map(|c| c - b'0')).collect();
+
+		assert_eq!(unpack_lzw(&packed, 2, 100).unwrap(), expected);
+	}
+
+	#[test]
+	fn truncated_lzw() {
+		let indices: Vec<u8> = (0..2000u32).map(|i| (i * 7 % 13) as u8).collect();
+		let packed = pack_lzw(&indices, 4);
+
+		// what's there is decoded and nothing more, like a short GIF
+		let short = unpack_lzw(&packed[..packed.len() / 2], 4, indices.len()).unwrap();
+		assert!(short.len() > 500 && short.len() < indices.len());
+		assert_eq!(short[..], indices[..short.len()]);
+
+		// a header that says there's more than the data could ever hold
+		assert!(unpack_lzw(&packed[..4], 4, 1 << 24).is_err());
+
+		// a code that hasn't been made yet is corrupt
+		assert!(unpack_lzw(&[0x10, 0x0F], 2, 10).is_err());
+	}
+}
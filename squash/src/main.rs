@@ -1,45 +1,795 @@
-use std::time::Duration;
+use std::{
+	collections::HashSet,
+	fs::File,
+	io::{BufWriter, Write},
+	sync::atomic::{AtomicUsize, Ordering},
+	time::Duration,
+};
 
+use anyhow::bail;
+use camino::{Utf8Path, Utf8PathBuf};
 use colorsquash::{
-	selection::{HighestBits, Kmeans, SortSelect},
-	SquasherBuilder,
+	backend::MapBackend,
+	difference::ColorDifference,
+	dither::{Diffusion, Kernel, Ordered, Weights},
+	gray::GraySquasher,
+	metrics,
+	palette::{export, parse},
+	selection::{
+		Agglomerative, BestOf, BinarySplit, GridKmeans, HeuristicSorsel, HighestBits, Kmeans,
+		MiniBatchKmeans, SortSelect, ToleranceReport,
+	},
+	sweep::{self, Range},
+	ImageData, Squasher, SquasherBuilder,
 };
+use rgb::{FromSlice, RGB8};
 
-use crate::cli::{InType, OutType};
+use crate::{
+	cli::{Cli, Dither, Export, InType, Knob, Metadata, OutType, PaletteFormat, ResizeFilter, Run},
+	image::{Animation, Image},
+	progress::Bar,
+	report::{Errors, Report, Timings},
+};
 
 mod cli;
+mod compare;
+mod histogram;
 mod image;
+mod preset;
+mod progress;
+mod report;
+
+/// How many samples stable= and verbose= select palettes from to compare
+const STABLE_ROUNDS: usize = 4;
+/// How far apart, by the difference function, entries can be and still count
+/// as the same between samples
+const STABLE_TOLERANCE: f32 = 16.0;
+/// Images with fewer pixels than this are done before a progress bar would be
+/// worth looking at
+const PROGRESS_PIXELS: usize = 4_000_000;
 
 fn main() -> Result<(), anyhow::Error> {
 	//gen: I should use clap or at least getopt, but this is fine.
 	//gen: I like experimenting with the cli :)
-	let cli = cli::build();
+	match cli::build() {
+		Run::One(cli) => squash(&cli),
+		Run::Batch { images, threads } => batch(&images, threads),
+		Run::Compare {
+			original,
+			quantized,
+			json,
+		} => compare_images(original, quantized, json),
+	}
+}
+
+/// Squash every image, `threads` at a time, carrying on past any that fail
+fn batch(images: &[Cli], threads: usize) -> Result<(), anyhow::Error> {
+	let next = AtomicUsize::new(0);
+	let failed = AtomicUsize::new(0);
+
+	std::thread::scope(|scope| {
+		for _ in 0..threads.min(images.len()) {
+			scope.spawn(|| {
+				while let Some(cli) = images.get(next.fetch_add(1, Ordering::Relaxed)) {
+					if let Err(e) = squash(cli) {
+						eprintln!("{}: {e}", cli.input);
+						failed.fetch_add(1, Ordering::Relaxed);
+					}
+				}
+			});
+		}
+	});
 
+	match failed.into_inner() {
+		0 => Ok(()),
+		failed => bail!("{failed} of {} images couldn't be squashed", images.len()),
+	}
+}
+
+fn squash(cli: &Cli) -> Result<(), anyhow::Error> {
+	if let OutType::Palette(export) = cli.out_type {
+		return export_palette(cli, export);
+	}
+
+	let mut timings = Timings::default();
+
+	let start = std::time::Instant::now();
 	let mut image = match cli.in_type {
-		InType::Png => image::get_png(cli.input)?,
-		InType::Jpeg => image::get_jpg(cli.input)?,
+		InType::Gif => {
+			let animation = image::read_gif(&cli.input)?;
+			if animation.frames.len() > 1 && matches!(cli.out_type, OutType::Gif) {
+				timings.push("decode", start.elapsed());
+				return squash_animation(cli, animation, timings);
+			}
+
+			animation.into_first()
+		}
+		in_type => read_image(&cli.input, in_type)?,
+	};
+	timings.push("decode", start.elapsed());
+
+	if let Some(resize) = cli.resize {
+		let start = std::time::Instant::now();
+		let (width, height) = resize.scale(image.width, image.height);
+		image.resize(width, height, cli.resize_filter == ResizeFilter::Area);
+		timings.push("resize", start.elapsed());
+	}
+
+	let color = std::mem::take(&mut image.metadata.color);
+	if cli.metadata == Metadata::Strip {
+		image.metadata = Default::default();
+	}
+	if cli.color_profile == Metadata::Keep {
+		image.metadata.color = color;
+	}
+
+	if let Some(archive) = &cli.archive {
+		image
+			.metadata
+			.text
+			.push(("colorsquash".into(), archive.clone()));
+	}
+
+	// palette files, maps, histograms, sweeps, anchors, focus regions,
+	// saliency, targets, dithering, and most of how the palette is selected
+	// are RGB, so the image has to be too
+	let rgb_only = cli.rgb_selection
+		|| !cli.focus.is_empty()
+		|| cli.saliency.is_some()
+		|| cli.target.is_some()
+		|| cli.palette.is_some()
+		|| cli.emit_map.is_some()
+		|| cli.histogram_in.is_some()
+		|| cli.histogram_out.is_some()
+		|| cli.sweep.is_some()
+		|| cli.anchor.is_some()
+		|| cli.dither != Dither::None;
+	if rgb_only && image.gray {
+		image.expand_gray();
+	}
+
+	// mapping happens in place, so keep the original around to compare with
+	let measure = cli.verify.is_some() || cli.json;
+	let original = (measure || cli.compare.is_some()).then(|| image.rgb());
+
+	let palette = if image.gray {
+		squash_gray(cli, &mut image, &mut timings)
+	} else {
+		squash_rgb(cli, &mut image, &mut timings)?
+	};
+
+	// the error of red, green, and blue, how different it looks, and how much
+	// of the structure is left
+	let start = std::time::Instant::now();
+	let errors = original
+		.as_ref()
+		.filter(|_| measure)
+		.map(|original| -> Result<_, anyhow::Error> {
+			let indices = &image.data;
+			let original =
+				ImageData::with_stride(original, image.width, image.height, image.width * 3)?;
+
+			Ok(Errors {
+				channels: metrics::indexed_channel_mse(&original, palette.as_rgb(), indices),
+				delta_e: metrics::indexed_delta_e(&original, palette.as_rgb(), indices, None),
+				ssim: metrics::indexed_ssim(&original, palette.as_rgb(), indices)?,
+			})
+		})
+		.transpose()?;
+	if measure {
+		timings.push("measure", start.elapsed());
+	}
+
+	if let (Some(compare), Some(original)) = (&cli.compare, &original) {
+		let quantized: Vec<u8> = image
+			.data
+			.iter()
+			.flat_map(|&index| {
+				let at = index as usize * 3;
+				[palette[at], palette[at + 1], palette[at + 2]]
+			})
+			.collect();
+
+		let heatmap = compare
+			.heatmap
+			.then(|| -> Result<_, anyhow::Error> {
+				let mut heatmap = vec![0.0; image.width * image.height];
+				let shape =
+					|rgb| ImageData::with_stride(rgb, image.width, image.height, image.width * 3);
+				metrics::delta_e(shape(original)?, shape(&quantized)?, Some(&mut heatmap));
+				Ok(heatmap)
+			})
+			.transpose()?;
+
+		let (width, height, pixels) = compare::compare(
+			compare.layout,
+			image.width,
+			image.height,
+			original,
+			&quantized,
+			heatmap.as_deref(),
+		);
+		image::save_rgb_png(width, height, &pixels, &compare.path)?;
+	}
+
+	let pixels = Report::count(&palette, &image.data);
+	let (width, height) = (image.width, image.height);
+	let start = std::time::Instant::now();
+	match cli.out_type {
+		OutType::Png => image::save_png(image, palette.clone(), cli.output.clone())?,
+		OutType::Gif => image::save_gif(image, palette.clone(), cli.output.clone())?,
+		OutType::Palette(_) => unreachable!("palettes are written by export_palette"),
+	}
+	timings.push("encode", start.elapsed());
+
+	if cli.json {
+		let report = Report {
+			input: cli.input.as_str(),
+			output: cli.output.as_str(),
+			width,
+			height,
+			frames: 1,
+			palette: &palette,
+			pixels,
+			timings: &timings,
+			errors: errors.as_ref(),
+		};
+		println!("{}", report.json());
+	}
+
+	if let (Some(errors), Some(verify)) = (errors, &cli.verify) {
+		let psnr = errors.psnr();
+		if !cli.json {
+			errors.print();
+		}
+
+		if let Some(min) = verify.min_psnr {
+			if psnr < min {
+				bail!("PSNR of {psnr:.2}dB is below the minimum of {min:.2}dB");
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Quantize the image in place, returning the palette as RGB bytes.
+fn squash_rgb(
+	cli: &Cli,
+	image: &mut Image,
+	timings: &mut Timings,
+) -> Result<Vec<u8>, anyhow::Error> {
+	// a decoder handing back the wrong amount of data would otherwise quietly
+	// give a garbage palette
+	image_data(image)?;
+
+	let start = std::time::Instant::now();
+	let mut squasher = match &cli.palette {
+		Some((path, format)) => {
+			let palette = read_palette(path, *format)?;
+
+			let builder = SquasherBuilder::new().mapper_difference(cli.difference);
+			with_progress(cli, builder, image.width * image.height).build_with_palette(palette)
+		}
+		None => select(cli, image)?,
+	};
+	timings.push("select", start.elapsed());
+
+	if let Some(path) = &cli.emit_map {
+		let start = std::time::Instant::now();
+		squasher.fill_map();
+		let file = BufWriter::new(File::create(path)?);
+		if path.extension() == Some("rle") {
+			squasher.write_map_compressed(file)?;
+		} else {
+			squasher.write_map(file)?;
+		}
+		timings.push("emit-map", start.elapsed());
+
+		if cli.verbose {
+			println!("Writing the map took {}", human_time(start.elapsed()));
+		}
+	}
+
+	let start = std::time::Instant::now();
+	map_dithered(cli, &mut squasher, image);
+	timings.push("map", start.elapsed());
+
+	if cli.verbose {
+		println!("Mapping took {}", human_time(start.elapsed()));
+	}
+
+	Ok(squasher.palette_bytes())
+}
+
+/// Quantize every frame of an animated GIF to one palette, selected from all of
+/// them, and write them out again the way they were.
+fn squash_animation(
+	cli: &Cli,
+	animation: Animation,
+	mut timings: Timings,
+) -> Result<(), anyhow::Error> {
+	let still_only = [
+		(!cli.focus.is_empty(), "focus="),
+		(cli.saliency.is_some(), "saliency="),
+		(cli.target.is_some(), "target="),
+		(cli.sweep.is_some(), "sweep="),
+		(cli.stable.is_some(), "stable="),
+		(cli.verify.is_some(), "verify="),
+		(cli.compare.is_some(), "compare="),
+		(cli.resize.is_some(), "resize="),
+		(cli.emit_map.is_some(), "emit-map="),
+		(cli.histogram_in.is_some(), "histogram-in="),
+		(cli.histogram_out.is_some(), "histogram-out="),
+	];
+	if let Some((_, setting)) = still_only.iter().find(|(given, _)| *given) {
+		bail!("{setting} only works on still images, and this GIF is animated");
+	}
+
+	// each frame's rectangle as it's shown, so the transparent pixels are
+	// mapped as what's under them and dithering doesn't run into holes
+	let shown = animation.composite();
+	let mut rectangles: Vec<Image> = animation
+		.frames
+		.iter()
+		.zip(&shown)
+		.map(|(frame, canvas)| {
+			let mut data = Vec::with_capacity(frame.width * frame.height * 3);
+			for y in frame.top..(frame.top + frame.height).min(animation.height) {
+				let left = y * animation.width + frame.left.min(animation.width);
+				let right = y * animation.width + (frame.left + frame.width).min(animation.width);
+				data.extend_from_slice(&canvas[left * 3..right * 3]);
+			}
+			// whatever hangs off the canvas isn't shown, so it's black
+			data.resize(frame.width * frame.height * 3, 0);
+
+			Image {
+				width: frame.width,
+				height: frame.height,
+				data,
+				gray: false,
+				metadata: Default::default(),
+			}
+		})
+		.collect();
+
+	// transparent pixels need an index of their own, which takes one of the
+	// colours
+	let has_transparency = animation
+		.frames
+		.iter()
+		.any(|frame| frame.pixels.contains(&None));
+	let max_colors = if has_transparency {
+		cli.color_count.min(254)
+	} else {
+		cli.color_count
+	};
+
+	let pixels = animation
+		.frames
+		.iter()
+		.map(|frame| frame.pixels.len())
+		.sum();
+
+	let start = std::time::Instant::now();
+	let mut squasher = match &cli.palette {
+		Some((path, format)) => {
+			let palette = read_palette(path, *format)?;
+			if has_transparency && palette.len() > 255 {
+				bail!("the palette has 256 colours, leaving no index for the GIF's transparency");
+			}
+
+			let builder = SquasherBuilder::new().mapper_difference(cli.difference);
+			with_progress(cli, builder, pixels).build_with_palette(palette)
+		}
+		None => {
+			let anchors = match &cli.anchor {
+				Some((path, format)) => read_palette(path, *format)?,
+				None => vec![],
+			};
+
+			let builder = builder(cli, &anchors, None, None).max_colors(max_colors);
+			let mut squasher = with_progress(cli, builder, pixels).build_empty();
+			// only what each frame draws, so a still background counts once
+			// rather than once a frame
+			for frame in &animation.frames {
+				squasher.feed_pixels(
+					frame
+						.pixels
+						.iter()
+						.flatten()
+						.map(|&[r, g, b]| RGB8::new(r, g, b)),
+				);
+			}
+			squasher.finalize_palette();
+			squasher
+		}
+	};
+	timings.push("select", start.elapsed());
+
+	if cli.verbose {
+		println!(
+			"Palette is {} colors for {} frames.\nSelection took {}",
+			squasher.palette().len(),
+			animation.frames.len(),
+			human_time(start.elapsed())
+		);
+	}
+
+	let mut palette = squasher.palette_bytes();
+	let transparent = has_transparency.then_some((palette.len() / 3) as u8);
+	if has_transparency {
+		palette.extend([0; 3]);
+	}
+
+	let start = std::time::Instant::now();
+	let mut indices = vec![];
+	for (frame, rectangle) in animation.frames.iter().zip(&mut rectangles) {
+		map_dithered(cli, &mut squasher, rectangle);
+
+		let mut frame_indices = std::mem::take(&mut rectangle.data);
+		if let Some(transparent) = transparent {
+			for (index, pixel) in frame_indices.iter_mut().zip(&frame.pixels) {
+				if pixel.is_none() {
+					*index = transparent;
+				}
+			}
+		}
+		indices.push(frame_indices);
+	}
+	timings.push("map", start.elapsed());
+
+	if cli.verbose {
+		println!("Mapping took {}", human_time(start.elapsed()));
+	}
+
+	let colours = squasher.palette().len() * 3;
+	let pixels = Report::count(&palette[..colours], indices.iter().flatten());
+	let frames = animation.frames.len();
+	let (width, height) = (animation.width, animation.height);
+
+	let start = std::time::Instant::now();
+	image::save_animation(
+		&animation,
+		indices,
+		palette.clone(),
+		transparent,
+		cli.output.clone(),
+	)?;
+	timings.push("encode", start.elapsed());
+
+	if cli.json {
+		let report = Report {
+			input: cli.input.as_str(),
+			output: cli.output.as_str(),
+			width,
+			height,
+			frames,
+			palette: &palette[..colours],
+			pixels,
+			timings: &timings,
+			errors: None,
+		};
+		println!("{}", report.json());
+	}
+
+	Ok(())
+}
+
+/// Replace the image's RGB data with indices into the palette, dithering it
+/// if dither= says to.
+fn map_dithered(cli: &Cli, squasher: &mut Squasher<u8>, image: &mut Image) {
+	let strength = cli.dither_strength;
+	let mut indices = vec![0; image.width * image.height];
+
+	match cli.dither {
+		Dither::None => {
+			let size = squasher.map_over(&mut image.data);
+			image.data.resize(size, 0);
+			return;
+		}
+		Dither::Ordered => {
+			let ordered = Ordered {
+				strength: Ordered::default().strength * strength,
+				..Default::default()
+			};
+			squasher.map_ordered(&image.data, image.width, ordered, &mut indices);
+		}
+		Dither::FloydSteinberg | Dither::Atkinson => {
+			let kernel = if cli.dither == Dither::Atkinson {
+				Kernel::Atkinson
+			} else {
+				Kernel::FloydSteinberg
+			};
+			let diffusion = Diffusion {
+				kernel,
+				weights: Weights::Rgb([strength; 3]),
+				..Default::default()
+			};
+			squasher.map_diffused(&image.data, image.width, diffusion, &mut indices);
+		}
+	}
+
+	image.data = indices;
+}
+
+/// The image with its dimensions, checking they match the data
+fn image_data(image: &Image) -> Result<ImageData<'_>, colorsquash::Error> {
+	ImageData::with_stride(&image.data, image.width, image.height, image.width * 3)
+}
+
+/// Select a palette from the image
+fn select(cli: &Cli, image: &Image) -> Result<Squasher<u8>, anyhow::Error> {
+	let anchors = match &cli.anchor {
+		Some((path, format)) => read_palette(path, *format)?,
+		None => vec![],
 	};
 
+	// with the width, so focus regions and saliency can find their pixels
+	let data = image_data(image)?;
+
+	if let Some((knob, range)) = cli.sweep {
+		return Ok(sweep(cli, data, &anchors, knob, range));
+	}
+
+	let tolerance = ToleranceReport::new();
+	let builder = builder(cli, &anchors, None, Some(&tolerance));
+	let builder = with_progress(cli, builder, image.width * image.height);
+
+	let start = std::time::Instant::now();
+	let mut squasher = builder.build_empty();
+	let report = match (&cli.histogram_in, cli.stable) {
+		(Some(path), _) => {
+			squasher.feed_histogram(histogram::load(path)?);
+			squasher.finalize_palette();
+			None
+		}
+		(None, Some(percent)) => {
+			Some(squasher.recolor_stable(&data, percent / 100.0, STABLE_ROUNDS, STABLE_TOLERANCE))
+		}
+		(None, None) => {
+			squasher.feed(&data);
+			if let Some(path) = &cli.histogram_out {
+				histogram::save(path, squasher.histogram())?;
+			}
+			squasher.finalize_palette();
+			None
+		}
+	};
+
+	if cli.verbose {
+		println!(
+			"Palette is {} colors.\nSelection took {}",
+			squasher.palette().len(),
+			human_time(start.elapsed())
+		);
+
+		if let Some(tolerance) = tolerance.get() {
+			println!("Heuristic settled on a tolerance of {tolerance:.2}");
+		}
+
+		// the histogram's already been sampled, there's nothing to compare
+		let sampled = cli.scale < 100 && cli.histogram_in.is_none();
+		let report = report.or_else(|| {
+			sampled.then(|| squasher.sampling_confidence(&data, STABLE_ROUNDS, STABLE_TOLERANCE))
+		});
+		if let Some(report) = report {
+			println!(
+				"Sampled {:.1}% of pixels, {:.1}% of the palette is stable between samples",
+				report.fraction * 100.0,
+				report.confidence * 100.0
+			);
+		}
+	}
+
+	Ok(squasher)
+}
+
+/// Draw a progress bar while selecting and mapping an image of this many
+/// pixels, if it's big enough to be worth one and there's a terminal
+fn with_progress<D: ColorDifference>(
+	cli: &Cli,
+	builder: SquasherBuilder<u8, D>,
+	pixels: usize,
+) -> SquasherBuilder<u8, D> {
+	if !cli.progress || pixels < PROGRESS_PIXELS || !Bar::wanted() {
+		return builder;
+	}
+
+	let bar = Bar::default();
+	builder.progress(move |stage, done| bar.draw(stage, done))
+}
+
+/// The builder for the selector and settings given on the command line, with
+/// one of them replaced if there's a knob. selector=heuristic leaves the
+/// tolerance it chose in the report.
+fn builder(
+	cli: &Cli,
+	anchors: &[RGB8],
+	knob: Option<(Knob, f64)>,
+	report: Option<&ToleranceReport>,
+) -> SquasherBuilder<u8> {
+	let mut color_count = cli.color_count;
+	let mut tolerance = cli.tolerance;
+	let mut scale = cli.scale;
+	match knob {
+		Some((Knob::Tolerance, value)) => tolerance = value as f32,
+		Some((Knob::Scale, value)) => scale = value.round().clamp(1.0, 100.0) as u8,
+		Some((Knob::Colors, value)) => color_count = (value.round().clamp(1.0, 256.0) - 1.0) as u8,
+		None => (),
+	}
+
+	// archival= doesn't leave where k-means starts to the features squash was
+	// built with, doesn't leave anything to the threads, and maps with a table
+	// whatever memory there is
+	let archival = cli.archive.is_some();
+	let seed = archival.then_some(0);
+
 	let mut builder = SquasherBuilder::new()
-		.max_colors(cli.color_count)
+		.max_colors(color_count)
 		.mapper_difference(cli.difference);
+	if archival {
+		builder = builder.map_backend(MapBackend::LazyLut);
+	}
 
 	match cli.selector {
 		cli::Selector::SortSelect => {
-			let mut sorsel = SortSelect::default().difference(cli.difference);
-			if let Some(tol) = cli.tolerance {
-				sorsel = sorsel.tolerance(tol)
-			}
+			let sorsel = SortSelect::default()
+				.difference(cli.difference)
+				.tolerance(tolerance);
 
 			builder = builder.selector(sorsel);
 		}
-		cli::Selector::Kmeans => builder = builder.selector(Kmeans { max_iter: 10 }),
-		cli::Selector::HighestBits => builder = builder.selector(HighestBits {}),
+		cli::Selector::HeuristicSorsel => {
+			let mut heuristic = HeuristicSorsel::default()
+				.sync_difference(cli.difference)
+				.tolerance(tolerance)
+				.variance(cli.variance)
+				.max_attempts(cli.attempts)
+				.candidates(cli.candidates);
+			if !archival {
+				let threads =
+					std::thread::available_parallelism().map_or(1, |threads| threads.get());
+				heuristic = heuristic.threads(threads);
+			}
+			if let Some(starts) = &cli.starts {
+				heuristic = heuristic.starts(starts.clone());
+			}
+			if let Some(report) = report {
+				heuristic = heuristic.report(report.clone());
+			}
+
+			builder = builder.selector(heuristic);
+		}
+		cli::Selector::Kmeans => {
+			builder = builder.selector(Kmeans {
+				max_iter: 10,
+				seed,
+				..Default::default()
+			})
+		}
+		cli::Selector::GridKmeans => {
+			let mut grid = GridKmeans::default();
+			grid.kmeans.seed = seed;
+			builder = builder.selector(grid)
+		}
+		cli::Selector::MiniBatchKmeans => builder = builder.selector(MiniBatchKmeans::default()),
+		cli::Selector::Agglomerative => builder = builder.selector(Agglomerative::default()),
+		cli::Selector::BinarySplit => builder = builder.selector(BinarySplit {}),
+		cli::Selector::HighestBits => builder = builder.selector(HighestBits { bits: cli.bits }),
+		cli::Selector::BestOf => {
+			// what BestOf::default() tries, but with the seed
+			let best = BestOf::new(vec![
+				Box::new(SortSelect::default()),
+				Box::new(Kmeans {
+					max_iter: 10,
+					seed,
+					..Default::default()
+				}),
+				Box::new(Agglomerative::default()),
+				Box::new(BinarySplit {}),
+			]);
+			builder = builder.selector(best.difference(cli.difference))
+		}
 	};
 
-	let mut start = std::time::Instant::now();
-	let mut squasher = builder.scale(cli.scale).build(&image.data);
+	if let Some(percent) = cli.auto_white_black {
+		builder = builder.auto_white_black(percent / 100.0);
+	}
+
+	if cli.refine > 0 {
+		builder = builder.refine(cli.refine);
+	}
+
+	if cli.snap {
+		builder = builder.snap_to_image(true);
+	}
+
+	if !anchors.is_empty() {
+		builder = builder.anchor(anchors, cli.anchor_usage / 100.0);
+	}
+
+	for focus in &cli.focus {
+		builder = builder.focus_region(focus.x, focus.y, focus.width, focus.height, focus.weight);
+	}
+
+	if let Some(strength) = cli.saliency {
+		builder = builder.saliency(strength);
+	}
+
+	if let Some(target) = cli.target {
+		builder = builder.quality_target(target);
+	}
+
+	builder.scale(scale)
+}
+
+/// Select a palette for every value in the range and keep the best
+fn sweep(cli: &Cli, image: ImageData, anchors: &[RGB8], knob: Knob, range: Range) -> Squasher<u8> {
+	let start = std::time::Instant::now();
+	let trials = sweep::sweep(image, range, |value| {
+		builder(cli, anchors, Some((knob, value)), None)
+	});
+
+	// as f32 so 3.6999999999999997 prints as 3.7
+	for trial in &trials {
+		print!(
+			"{knob:?} {}: {} colors, PSNR {:.2}dB",
+			trial.value as f32,
+			trial.palette.len(),
+			trial.psnr
+		);
+
+		match &cli.objective {
+			Some(objective) => println!(", score {:.3}", objective.score(&trial.measurements)),
+			None => println!(),
+		}
+	}
+
+	// the range can't be empty, the cli checks
+	let best = match &cli.objective {
+		Some(objective) => sweep::best_by(&trials, objective),
+		None => sweep::best(&trials),
+	}
+	.unwrap();
+	println!(
+		"Best is {knob:?} {} at {:.2}dB",
+		best.value as f32, best.psnr
+	);
 
+	if cli.verbose {
+		println!("Sweeping took {}", human_time(start.elapsed()));
+	}
+
+	SquasherBuilder::new()
+		.mapper_difference(cli.difference)
+		.build_with_palette(best.palette.clone())
+}
+
+/// A still image, or the first frame of a GIF
+fn read_image(path: &Utf8Path, in_type: InType) -> Result<Image, anyhow::Error> {
+	Ok(match in_type {
+		InType::Png => image::get_png(path)?,
+		InType::Jpeg => image::get_jpg(path)?,
+		InType::Bmp => image::get_bmp(path)?,
+		InType::Tga => image::get_tga(path)?,
+		InType::Gif => image::read_gif(path)?.into_first(),
+	})
+}
+
+/// squash palette: select a palette for the image like squash does and write
+/// it to a palette file, rather than mapping the image to it
+fn export_palette(cli: &Cli, export: Export) -> Result<(), anyhow::Error> {
+	let mut image = read_image(&cli.input, cli.in_type)?;
+	if let Some(resize) = cli.resize {
+		let (width, height) = resize.scale(image.width, image.height);
+		image.resize(width, height, cli.resize_filter == ResizeFilter::Area);
+	}
+	// selection is RGB, and a gray palette is just as well written as RGB
+	image.expand_gray();
+
+	let start = std::time::Instant::now();
+	let squasher = select(cli, &image)?;
 	if cli.verbose {
 		println!(
 			"Palette is {} colors.\nSelection took {}",
@@ -48,19 +798,112 @@ fn main() -> Result<(), anyhow::Error> {
 		);
 	}
 
-	start = std::time::Instant::now();
-	let size = squasher.map_over(&mut image.data);
+	let palette = squasher.palette();
+	let mut file = BufWriter::new(File::create(&cli.output)?);
+	match export {
+		Export::Gpl => export::write_gpl(palette, cli.input.file_stem().unwrap_or(""), file)?,
+		Export::Act => export::write_act(palette, file)?,
+		Export::Hex => {
+			for colour in palette {
+				writeln!(file, "{:02x}{:02x}{:02x}", colour.r, colour.g, colour.b)?;
+			}
+		}
+	}
 
-	if cli.verbose {
-		println!("Mapping took {}", human_time(start.elapsed()));
+	Ok(())
+}
+
+/// squash compare: measure how different two images are, like verify= does
+/// for the output
+fn compare_images(
+	(original_path, original_type): (Utf8PathBuf, InType),
+	(quantized_path, quantized_type): (Utf8PathBuf, InType),
+	json: bool,
+) -> Result<(), anyhow::Error> {
+	let original = read_image(&original_path, original_type)?;
+	let quantized = read_image(&quantized_path, quantized_type)?;
+	if (original.width, original.height) != (quantized.width, quantized.height) {
+		bail!(
+			"the images are different sizes, {}x{} and {}x{}",
+			original.width,
+			original.height,
+			quantized.width,
+			quantized.height
+		);
 	}
 
-	image.data.resize(size, 0);
+	let (width, height) = (original.width, original.height);
+	let (original_rgb, quantized_rgb) = (original.rgb(), quantized.rgb());
+	let original = ImageData::with_stride(&original_rgb, width, height, width * 3)?;
+	let quantized = ImageData::with_stride(&quantized_rgb, width, height, width * 3)?;
 
-	match cli.out_type {
-		OutType::Png => image::save_png(image, squasher, cli.output),
-		OutType::Gif => image::save_gif(image, squasher, cli.output),
+	let errors = Errors {
+		channels: metrics::channel_mse(&original, &quantized),
+		delta_e: metrics::delta_e(&original, &quantized, None),
+		ssim: metrics::ssim(original, quantized)?,
+	};
+
+	if json {
+		println!(
+			"{}",
+			report::compare_json(
+				original_path.as_str(),
+				quantized_path.as_str(),
+				width,
+				height,
+				&errors
+			)
+		);
+	} else {
+		errors.print();
+	}
+
+	Ok(())
+}
+
+fn read_palette(path: &Utf8Path, format: PaletteFormat) -> Result<Vec<RGB8>, anyhow::Error> {
+	let palette = match format {
+		PaletteFormat::File(format) => parse::parse(&std::fs::read(path)?, format)?,
+		PaletteFormat::Image(in_type) => {
+			let image = read_image(path, in_type)?;
+
+			// each colour where it's first seen, going across and then down
+			let mut seen = HashSet::new();
+			image
+				.rgb()
+				.as_rgb()
+				.iter()
+				.filter(|colour| seen.insert(**colour))
+				.copied()
+				.collect()
+		}
+	};
+	if palette.len() > 256 {
+		bail!("palette has {} colors but at most 256 fit", palette.len());
+	}
+
+	Ok(palette)
+}
+
+/// Quantize the grayscale image in place, returning the palette as RGB bytes.
+fn squash_gray(cli: &Cli, image: &mut Image, timings: &mut Timings) -> Vec<u8> {
+	let start = std::time::Instant::now();
+	let squasher = GraySquasher::new(cli.color_count, cli.tolerance, &image.data);
+	timings.push("select", start.elapsed());
+
+	if cli.verbose {
+		println!(
+			"Image is grayscale. Palette is {} colors.\nSelection took {}",
+			squasher.palette().len(),
+			human_time(start.elapsed())
+		);
 	}
+
+	let start = std::time::Instant::now();
+	squasher.map_over(&mut image.data);
+	timings.push("map", start.elapsed());
+
+	squasher.palette_bytes()
 }
 
 fn human_time(duration: Duration) -> String {
@@ -0,0 +1,196 @@
+//! Presets for preset=, read from `squash/config.toml` in the config directory,
+//! which is `$XDG_CONFIG_HOME` or `~/.config`. Each table is a preset, like
+//! `[pixel]`, and each key in it is an argument with its value, like
+//! `colors = 16` or `dither = "ordered"`.
+//!
+//! Arguments without a value, like snap=, are `true` to give them and `false`
+//! to leave them out, and ones that can be given more than once, like focus=,
+//! can be an array. Only that much of TOML is understood: tables, strings,
+//! numbers, booleans, and arrays of them on one line.
+
+use camino::Utf8PathBuf;
+
+/// Where the presets are, if there's a config directory
+pub fn path() -> Option<Utf8PathBuf> {
+	let config = match std::env::var("XDG_CONFIG_HOME") {
+		Ok(config) if !config.is_empty() => Utf8PathBuf::from(config.as_str()),
+		_ => Utf8PathBuf::from(std::env::var("HOME").ok()?.as_str()).join(".config"),
+	};
+
+	Some(config.join("squash").join("config.toml"))
+}
+
+/// The arguments of the preset called `name` in the file, in the order they're
+/// written. Each value is as it'd be given on the command line, and a `true`
+/// is an empty value. Keys that are `false` are left out.
+pub fn find(toml: &str, name: &str) -> Result<Option<Vec<(String, String)>>, String> {
+	let mut table: Option<String> = None;
+	let mut found: Option<Vec<(String, String)>> = None;
+
+	for (idx, line) in toml.lines().enumerate() {
+		let bad = |reason: &str| format!("line {} of the presets {reason}", idx + 1);
+
+		let line = without_comment(line).trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		if line.starts_with("[[") {
+			return Err(bad("has an array of tables, which presets can't be"));
+		} else if let Some(header) = line.strip_prefix('[') {
+			let header = header
+				.strip_suffix(']')
+				.ok_or_else(|| bad("is missing the ] of a table"))?;
+			table = Some(unquote(header.trim()).to_owned());
+			if table.as_deref() == Some(name) {
+				found.get_or_insert_with(Vec::new);
+			}
+			continue;
+		}
+
+		let Some((key, value)) = line.split_once('=') else {
+			return Err(bad("should look like key = value"));
+		};
+		if table.is_none() {
+			return Err(bad("isn't in a [preset]"));
+		}
+		if table.as_deref() != Some(name) {
+			continue;
+		}
+
+		let key = unquote(key.trim()).to_owned();
+		let values = match value.trim() {
+			array if array.starts_with('[') => array
+				.strip_prefix('[')
+				.and_then(|array| array.strip_suffix(']'))
+				.map(split_array)
+				.ok_or_else(|| bad("has an array without its ]"))?,
+			value => vec![value],
+		};
+
+		let arguments = found.get_or_insert_with(Vec::new);
+		for value in values {
+			match value {
+				"true" => arguments.push((key.clone(), String::new())),
+				"false" => (),
+				value if value.starts_with('"') || value.starts_with('\'') => {
+					let quote = &value[..1];
+					let string = value[1..]
+						.strip_suffix(quote)
+						.ok_or_else(|| bad("has a string without its closing quote"))?;
+					arguments.push((key.clone(), string.to_owned()));
+				}
+				number => arguments.push((key.clone(), number.to_owned())),
+			}
+		}
+	}
+
+	Ok(found)
+}
+
+/// The line up to a `#` that isn't in a string
+fn without_comment(line: &str) -> &str {
+	let mut quote = None;
+	for (idx, c) in line.char_indices() {
+		match (c, quote) {
+			('"' | '\'', None) => quote = Some(c),
+			(c, Some(open)) if c == open => quote = None,
+			('#', None) => return &line[..idx],
+			_ => (),
+		}
+	}
+	line
+}
+
+/// The values of an array, split at the commas that aren't in strings
+fn split_array(array: &str) -> Vec<&str> {
+	let mut values = vec![];
+	let mut quote = None;
+	let mut start = 0;
+	for (idx, c) in array.char_indices() {
+		match (c, quote) {
+			('"' | '\'', None) => quote = Some(c),
+			(c, Some(open)) if c == open => quote = None,
+			(',', None) => {
+				values.push(array[start..idx].trim());
+				start = idx + 1;
+			}
+			_ => (),
+		}
+	}
+	values.push(array[start..].trim());
+
+	// a trailing comma is fine
+	values.retain(|value| !value.is_empty());
+	values
+}
+
+/// Keys and table names can be quoted
+fn unquote(text: &str) -> &str {
+	text.strip_prefix('"')
+		.and_then(|text| text.strip_suffix('"'))
+		.unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PRESETS: &str = r#"
+# for sprites
+[pixel]
+colors = 16
+dither = "ordered" # a comment after a value
+snap = true
+refine = false
+focus = ["0,0,8,8,4", '8,8,8,8,2',]
+
+["with space"]
+tolerance = 2.5
+palette = "a # in a string.gpl"
+"#;
+
+	fn pairs(arguments: &[(&str, &str)]) -> Vec<(String, String)> {
+		arguments
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn finds_a_preset() {
+		assert_eq!(
+			find(PRESETS, "pixel").unwrap(),
+			Some(pairs(&[
+				("colors", "16"),
+				("dither", "ordered"),
+				("snap", ""),
+				("focus", "0,0,8,8,4"),
+				("focus", "8,8,8,8,2"),
+			]))
+		);
+		assert_eq!(
+			find(PRESETS, "with space").unwrap(),
+			Some(pairs(&[
+				("tolerance", "2.5"),
+				("palette", "a # in a string.gpl")
+			]))
+		);
+		assert_eq!(find(PRESETS, "missing").unwrap(), None);
+	}
+
+	#[test]
+	fn empty_preset() {
+		assert_eq!(find("[empty]\n", "empty").unwrap(), Some(vec![]));
+	}
+
+	#[test]
+	fn bad_presets() {
+		assert!(find("colors = 16\n", "pixel").is_err());
+		assert!(find("[pixel\ncolors = 16\n", "pixel").is_err());
+		assert!(find("[pixel]\ndither = \"ordered\n", "pixel").is_err());
+		assert!(find("[pixel]\nfocus = [\"0,0,8,8,4\"\n", "pixel").is_err());
+		assert!(find("[pixel]\ncolors\n", "pixel").is_err());
+		assert!(find("[[pixel]]\ncolors = 16\n", "pixel").is_err());
+	}
+}
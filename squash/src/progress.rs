@@ -0,0 +1,68 @@
+//! The progress bar on stderr while a big image is selected from and mapped,
+//! drawn from the progress the Squasher reports. It's only drawn when stderr is
+//! a terminal, and only redrawn when it'd look different.
+
+use std::{
+	cell::Cell,
+	io::{IsTerminal, Write},
+};
+
+use colorsquash::progress::Stage;
+
+/// How many characters wide the bar is, not counting the words around it
+const WIDTH: usize = 30;
+
+#[derive(Default)]
+pub struct Bar {
+	/// what's on the line now, so it's not drawn again
+	drawn: Cell<Option<(Stage, usize)>>,
+}
+
+impl Bar {
+	/// Whether there's a terminal to draw a bar on
+	pub fn wanted() -> bool {
+		std::io::stderr().is_terminal()
+	}
+
+	pub fn draw(&self, stage: Stage, done: f32) {
+		// a finished stage is cleared, so anything printed after it starts at
+		// the beginning of the line
+		if done >= 1.0 {
+			self.clear();
+			return;
+		}
+
+		let done = done.max(0.0);
+		let filled = (done * WIDTH as f32).round() as usize;
+		if self.drawn.get() == Some((stage, filled)) {
+			return;
+		}
+		self.drawn.set(Some((stage, filled)));
+
+		let stage = match stage {
+			Stage::Selecting => "selecting",
+			Stage::Mapping => "mapping",
+		};
+		let mut stderr = std::io::stderr().lock();
+		let _ = write!(
+			stderr,
+			"\r{stage:>9} [{}{}] {:3.0}%",
+			"#".repeat(filled),
+			" ".repeat(WIDTH - filled),
+			done * 100.0
+		);
+		let _ = stderr.flush();
+	}
+
+	fn clear(&self) {
+		if self.drawn.take().is_some() {
+			let _ = write!(std::io::stderr(), "\r\x1b[K");
+		}
+	}
+}
+
+impl Drop for Bar {
+	fn drop(&mut self) {
+		self.clear();
+	}
+}
@@ -0,0 +1,189 @@
+//! What json= prints: one line of JSON for each image squashed. There's the
+//! input and output paths; the width, height, and number of frames; the
+//! palette, each entry with its `color` in hex, the `pixels` of that colour,
+//! and the `coverage` of the image they make up; the `timings` of each stage
+//! in seconds; and the `errors`, the PSNR, delta-E, and SSIM of the output
+//! compared to the input, or null for an animation. JSON has no infinity, so a
+//! PSNR is null if the output is exactly the input.
+
+use std::{fmt::Write, time::Duration};
+
+use colorsquash::metrics::{self, DeltaE};
+
+/// How long each stage took, in the order they happened
+#[derive(Debug, Default)]
+pub struct Timings(Vec<(&'static str, Duration)>);
+
+impl Timings {
+	pub fn push(&mut self, stage: &'static str, took: Duration) {
+		self.0.push((stage, took));
+	}
+}
+
+/// How different the output is from the input
+pub struct Errors {
+	/// the mean squared error of red, green, and blue
+	pub channels: [f64; 3],
+	pub delta_e: DeltaE,
+	pub ssim: f64,
+}
+
+impl Errors {
+	pub fn psnr(&self) -> f64 {
+		metrics::psnr_from_mse(self.channels.iter().sum::<f64>() / 3.0)
+	}
+
+	/// The PSNR of red, green, and blue
+	pub fn channel_psnr(&self) -> [f64; 3] {
+		self.channels.map(metrics::psnr_from_mse)
+	}
+
+	/// Print them like verify= does
+	pub fn print(&self) {
+		let psnr = self.psnr();
+		let [red, green, blue] = self.channel_psnr();
+		println!("PSNR {psnr:.2}dB (red {red:.2}dB, green {green:.2}dB, blue {blue:.2}dB)");
+		println!(
+			"delta-E mean {:.2}, 95th percentile {:.2}, max {:.2}",
+			self.delta_e.mean, self.delta_e.p95, self.delta_e.max
+		);
+		println!("SSIM {:.4}", self.ssim);
+	}
+
+	pub fn json(&self) -> String {
+		let [red, green, blue] = self.channel_psnr();
+		format!(
+			"{{\"psnr\":{},\"psnr_red\":{},\"psnr_green\":{},\"psnr_blue\":{},\
+			\"delta_e_mean\":{},\"delta_e_p95\":{},\"delta_e_max\":{},\"ssim\":{}}}",
+			number(self.psnr()),
+			number(red),
+			number(green),
+			number(blue),
+			number(self.delta_e.mean as f64),
+			number(self.delta_e.p95 as f64),
+			number(self.delta_e.max as f64),
+			number(self.ssim)
+		)
+	}
+}
+
+pub struct Report<'a> {
+	pub input: &'a str,
+	pub output: &'a str,
+	pub width: usize,
+	pub height: usize,
+	pub frames: usize,
+	/// RGB, three bytes per colour
+	pub palette: &'a [u8],
+	/// how many pixels are each index of the palette
+	pub pixels: Vec<usize>,
+	pub timings: &'a Timings,
+	pub errors: Option<&'a Errors>,
+}
+
+impl Report<'_> {
+	/// Count how many pixels are each index of the palette
+	pub fn count<'i, I>(palette: &[u8], indices: I) -> Vec<usize>
+	where
+		I: IntoIterator<Item = &'i u8>,
+	{
+		let mut pixels = vec![0; palette.len() / 3];
+		for &index in indices {
+			if let Some(count) = pixels.get_mut(index as usize) {
+				*count += 1;
+			}
+		}
+		pixels
+	}
+
+	pub fn json(&self) -> String {
+		let mut json = format!(
+			"{{\"input\":{},\"output\":{},\"width\":{},\"height\":{},\"frames\":{}",
+			string(self.input),
+			string(self.output),
+			self.width,
+			self.height,
+			self.frames
+		);
+
+		let total = self.pixels.iter().sum::<usize>().max(1);
+		json.push_str(",\"palette\":[");
+		for (idx, (rgb, pixels)) in self.palette.chunks(3).zip(&self.pixels).enumerate() {
+			if idx > 0 {
+				json.push(',');
+			}
+
+			let _ = write!(
+				json,
+				"{{\"color\":\"#{:02x}{:02x}{:02x}\",\"pixels\":{pixels},\"coverage\":{}}}",
+				rgb[0],
+				rgb[1],
+				rgb[2],
+				number(*pixels as f64 / total as f64)
+			);
+		}
+
+		json.push_str("],\"timings\":{");
+		for (idx, (stage, took)) in self.timings.0.iter().enumerate() {
+			if idx > 0 {
+				json.push(',');
+			}
+			let _ = write!(json, "\"{stage}\":{}", number(took.as_secs_f64()));
+		}
+		json.push('}');
+
+		match self.errors {
+			None => json.push_str(",\"errors\":null"),
+			Some(errors) => {
+				let _ = write!(json, ",\"errors\":{}", errors.json());
+			}
+		}
+
+		json.push('}');
+		json
+	}
+}
+
+/// What squash compare prints with json=: both paths, the size, and the errors
+pub fn compare_json(
+	original: &str,
+	quantized: &str,
+	width: usize,
+	height: usize,
+	errors: &Errors,
+) -> String {
+	format!(
+		"{{\"original\":{},\"quantized\":{},\"width\":{width},\"height\":{height},\"errors\":{}}}",
+		string(original),
+		string(quantized),
+		errors.json()
+	)
+}
+
+/// JSON has no infinity or NaN, so they're null
+fn number(value: f64) -> String {
+	if value.is_finite() {
+		value.to_string()
+	} else {
+		"null".into()
+	}
+}
+
+fn string(text: &str) -> String {
+	let mut json = String::with_capacity(text.len() + 2);
+	json.push('"');
+	for c in text.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			'\n' => json.push_str("\\n"),
+			'\t' => json.push_str("\\t"),
+			c if (c as u32) < 0x20 => {
+				let _ = write!(json, "\\u{:04x}", c as u32);
+			}
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
@@ -0,0 +1,286 @@
+//! Helpers for changing a palette after it's been selected. See
+//! [Squasher::grow_palette](crate::Squasher::grow_palette),
+//! [Squasher::shrink_palette](crate::Squasher::shrink_palette),
+//! [SquasherBuilder::constrain](crate::SquasherBuilder::constrain),
+//! [SquasherBuilder::anchor](crate::SquasherBuilder::anchor),
+//! [SquasherBuilder::refine](crate::SquasherBuilder::refine), and
+//! [SquasherBuilder::snap_to_image](crate::SquasherBuilder::snap_to_image).
+
+use std::collections::HashSet;
+
+use rgb::RGB8;
+
+use crate::difference::{self, ColorDifference};
+
+/// A test for whether a colour may be in the palette. See
+/// [SquasherBuilder::constrain](crate::SquasherBuilder::constrain).
+pub type Constraint = dyn Fn(&RGB8) -> bool;
+
+/// Colours and how many times they appear
+type Weighted = Vec<(RGB8, usize)>;
+
+/// The mean of the colours, weighted by how often they appear.
+pub(crate) fn weighted_mean(colours: &[(RGB8, usize)]) -> RGB8 {
+	let mut sum = [0u64; 3];
+	let mut total = 0u64;
+
+	for &(colour, count) in colours {
+		let count = count as u64;
+		sum[0] += colour.r as u64 * count;
+		sum[1] += colour.g as u64 * count;
+		sum[2] += colour.b as u64 * count;
+		total += count;
+	}
+
+	if total == 0 {
+		return RGB8::default();
+	}
+
+	let channel = |sum: u64| ((sum + total / 2) / total) as u8;
+	RGB8::new(channel(sum[0]), channel(sum[1]), channel(sum[2]))
+}
+
+/// Split the colours in two across the channel with the widest range, at the
+/// weighted median. The colours must have at least two entries so both halves
+/// have something in them.
+pub(crate) fn split(mut colours: Weighted) -> (Weighted, Weighted) {
+	let range = |channel: fn(&RGB8) -> u8| {
+		let values = colours.iter().map(|(c, _)| channel(c));
+		values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+	};
+
+	let channels: [fn(&RGB8) -> u8; 3] = [|c| c.r, |c| c.g, |c| c.b];
+	let widest = channels
+		.into_iter()
+		.max_by_key(|channel| range(*channel))
+		.unwrap();
+	colours.sort_by_key(|(c, _)| widest(c));
+
+	let half = colours.iter().map(|(_, count)| count).sum::<usize>() / 2;
+	let mut seen = 0;
+	let mut at = colours.len() - 1;
+	for (idx, (_, count)) in colours.iter().enumerate() {
+		seen += count;
+		if seen >= half {
+			at = idx + 1;
+			break;
+		}
+	}
+
+	let upper = colours.split_off(at.clamp(1, colours.len() - 1));
+	(colours, upper)
+}
+
+/// The summed difference between an entry and the colours mapped to it,
+/// weighted by how often they appear.
+pub(crate) fn error<D: ColorDifference>(
+	entry: &RGB8,
+	colours: &[(RGB8, usize)],
+	difference: &D,
+) -> f32 {
+	colours
+		.iter()
+		.map(|(colour, count)| difference.difference(entry, colour) * *count as f32)
+		.sum()
+}
+
+/// Move every colour the constraint doesn't allow to the closest colour that it
+/// does, preferring colours from the image. Colours with nowhere to go are
+/// dropped, as are any duplicates this makes.
+pub(crate) fn constrain<D: ColorDifference>(
+	palette: Vec<RGB8>,
+	colours: &[(RGB8, usize)],
+	constraint: &Constraint,
+	difference: &D,
+) -> Vec<RGB8> {
+	let mut candidates: Vec<RGB8> = colours
+		.iter()
+		.map(|(c, _)| *c)
+		.filter(|c| constraint(c))
+		.collect();
+
+	// nothing in the image is allowed, so look across RGB space instead. every
+	// 8th value is 32K colours, which is plenty to find something close
+	if candidates.is_empty() {
+		candidates = (0..=255u8)
+			.step_by(8)
+			.flat_map(|r| (0..=255u8).step_by(8).map(move |g| (r, g)))
+			.flat_map(|(r, g)| (0..=255u8).step_by(8).map(move |b| RGB8::new(r, g, b)))
+			.filter(|c| constraint(c))
+			.collect();
+	}
+
+	let mut constrained: Vec<RGB8> = Vec::with_capacity(palette.len());
+	for colour in palette {
+		let allowed = if constraint(&colour) {
+			colour
+		} else {
+			match candidates.get(difference::nearest(&colour, &candidates, difference)) {
+				Some(allowed) => *allowed,
+				None => continue,
+			}
+		};
+
+		if !constrained.contains(&allowed) {
+			constrained.push(allowed);
+		}
+	}
+
+	constrained
+}
+
+/// Move every entry to the closest colour in the image that isn't already in
+/// the palette or `fixed`. `colours` are sorted most to least frequent, so of
+/// equally close colours the most frequent wins. Entries are dropped if every
+/// colour of the image is taken.
+pub(crate) fn snap<D: ColorDifference>(
+	palette: Vec<RGB8>,
+	fixed: &[RGB8],
+	colours: &[(RGB8, usize)],
+	difference: &D,
+) -> Vec<RGB8> {
+	let mut taken: HashSet<RGB8> = fixed.iter().copied().collect();
+	let mut snapped = Vec::with_capacity(palette.len());
+
+	for entry in palette {
+		let mut closest = None;
+		let mut closest_diff = f32::MAX;
+		for (colour, _) in colours {
+			if taken.contains(colour) {
+				continue;
+			}
+
+			let diff = difference.difference(&entry, colour).max(0.0);
+			if diff < closest_diff {
+				closest = Some(*colour);
+				closest_diff = diff;
+
+				if diff == 0.0 {
+					break;
+				}
+			}
+		}
+
+		let Some(closest) = closest else {
+			break;
+		};
+		taken.insert(closest);
+		snapped.push(closest);
+	}
+
+	snapped
+}
+
+/// How close every channel has to be to 0 or 255 for a colour to count as
+/// nearly black or white
+const NEARLY: u8 = 16;
+
+/// Pure white and black, if at least `coverage` of the colours are nearly
+/// that.
+pub(crate) fn white_black(colours: &[(RGB8, usize)], coverage: f32) -> Vec<RGB8> {
+	let total: usize = colours.iter().map(|(_, count)| count).sum();
+	if total == 0 {
+		return vec![];
+	}
+
+	let covers = |test: fn(&RGB8) -> bool| {
+		let count: usize = colours
+			.iter()
+			.filter(|(c, _)| test(c))
+			.map(|(_, count)| count)
+			.sum();
+
+		count as f32 / total as f32 >= coverage
+	};
+
+	let mut extremes = vec![];
+	if covers(|c| c.r >= 255 - NEARLY && c.g >= 255 - NEARLY && c.b >= 255 - NEARLY) {
+		extremes.push(RGB8::new(255, 255, 255));
+	}
+	if covers(|c| c.r <= NEARLY && c.g <= NEARLY && c.b <= NEARLY) {
+		extremes.push(RGB8::new(0, 0, 0));
+	}
+
+	extremes
+}
+
+/// The anchors that at least `min_usage` of the colours are closest to, at
+/// their index. The others are None.
+pub(crate) fn used_anchors<D: ColorDifference>(
+	anchors: &[RGB8],
+	colours: &[(RGB8, usize)],
+	min_usage: f32,
+	difference: &D,
+) -> Vec<Option<RGB8>> {
+	if anchors.is_empty() {
+		return vec![];
+	}
+
+	let mut usage = vec![0usize; anchors.len()];
+	let mut total = 0;
+	for (colour, count) in colours {
+		usage[difference::nearest(colour, anchors, difference)] += count;
+		total += count;
+	}
+
+	anchors
+		.iter()
+		.zip(usage)
+		.map(|(&anchor, used)| {
+			let fraction = if total == 0 {
+				0.0
+			} else {
+				used as f32 / total as f32
+			};
+
+			(fraction >= min_usage && used > 0).then_some(anchor)
+		})
+		.collect()
+}
+
+/// Move each entry of `palette` to the weighted mean of the colours closest to
+/// it, `iterations` times or until nothing moves. This is k-means started from
+/// the palette. Colours closest to an entry of `fixed` are left to it and the
+/// `fixed` entries don't move.
+pub(crate) fn refine<D: ColorDifference>(
+	mut palette: Vec<RGB8>,
+	fixed: &[RGB8],
+	colours: &[(RGB8, usize)],
+	iterations: usize,
+	difference: &D,
+) -> Vec<RGB8> {
+	let mut everything: Vec<RGB8> = fixed.iter().chain(&palette).copied().collect();
+	let mut members: Vec<Weighted> = vec![vec![]; palette.len()];
+
+	for _ in 0..iterations {
+		members.iter_mut().for_each(Vec::clear);
+		for &(colour, count) in colours {
+			let nearest = difference::nearest(&colour, &everything, difference);
+			if let Some(cluster) = nearest.checked_sub(fixed.len()) {
+				members[cluster].push((colour, count));
+			}
+		}
+
+		let mut moved = false;
+		for (entry, members) in palette.iter_mut().zip(&members) {
+			// nothing is closest. leave it where it is rather than at black
+			if members.is_empty() {
+				continue;
+			}
+
+			let mean = weighted_mean(members);
+			if mean != *entry {
+				*entry = mean;
+				moved = true;
+			}
+		}
+
+		if !moved {
+			break;
+		}
+		everything.truncate(fixed.len());
+		everything.extend_from_slice(&palette);
+	}
+
+	palette
+}
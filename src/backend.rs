@@ -0,0 +1,326 @@
+//! Ways of finding the palette entry for each pixel while mapping. See
+//! [SquasherBuilder::map_backend](crate::SquasherBuilder::map_backend).
+//!
+//! Finding the closest entry to a colour means comparing it with every entry in
+//! the palette, so a Squasher normally remembers the answer in a table of every
+//! 24-bit colour. That's 16MB for `u8` indices and up to 128MB for wider ones,
+//! which is a lot for a small image or a small machine, so there are a few
+//! other ways to do it. They all give the same indices, apart from
+//! [MapBackend::KdTree] with a difference function other than
+//! [difference::rgb].
+
+use std::{borrow::Cow, collections::HashMap};
+
+use rgb::RGB8;
+
+use crate::{
+	difference::{self, ColorDifference},
+	lut::MAP_LEN,
+	Count, MapLayout,
+};
+
+/// How a Squasher finds the palette entry for each pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MapBackend {
+	/// Pick one when the Squasher's built. It's a [MapBackend::LazyLut] unless
+	/// the table would take more than a quarter of the memory the OS says is
+	/// available, and then it's a [MapBackend::Hash]. Only Linux says how much
+	/// memory is available, so elsewhere it's always a table. Either way the
+	/// indices are the same, so it's fine for output that has to be
+	/// reproducible.
+	#[default]
+	Auto,
+	/// A table of every 24-bit colour, all filled in whenever the palette
+	/// changes. After that mapping is only lookups, even for colours that
+	/// haven't been seen and with
+	/// [Squasher::map_no_recolor](crate::Squasher::map_no_recolor), but filling
+	/// it compares all 16M colours with the palette, which takes seconds for
+	/// a small palette and most of a minute for 256 colours.
+	Lut,
+	/// A table of every 24-bit colour, filled in with the colours of each image
+	/// as it's mapped.
+	LazyLut,
+	/// Only the colours that have been mapped, in a HashMap. It uses memory for
+	/// the colours in the images rather than 16MB or more up front, but looking
+	/// them up is slower.
+	Hash,
+	/// No map at all. Every pixel is looked for in a k-d tree of the palette,
+	/// which for large palettes is much quicker than comparing it with every
+	/// entry, though slower than a lookup. It uses the least memory, and there
+	/// are no colours to count before mapping.
+	///
+	/// The tree assumes colours only get more different as any one channel moves
+	/// further apart. That's true of [difference::rgb]; with
+	/// [difference::redmean] a pixel very rarely maps to an entry that's a close
+	/// second.
+	KdTree,
+}
+
+impl MapBackend {
+	/// Decide what [MapBackend::Auto] means for `T` indices. Anything else is
+	/// returned as-is.
+	pub fn resolve<T>(self) -> MapBackend {
+		if self != MapBackend::Auto {
+			return self;
+		}
+
+		let table = MAP_LEN * std::mem::size_of::<T>();
+		match available_memory() {
+			Some(available) if table > available / 4 => MapBackend::Hash,
+			_ => MapBackend::LazyLut,
+		}
+	}
+}
+
+/// The memory the OS says is available to start new programs without swapping.
+#[cfg(target_os = "linux")]
+fn available_memory() -> Option<usize> {
+	let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+	let line = meminfo
+		.lines()
+		.find(|line| line.starts_with("MemAvailable:"))?;
+	let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+
+	Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available_memory() -> Option<usize> {
+	None
+}
+
+/// Where a Squasher keeps what it knows about which entry each colour maps to.
+pub(crate) enum ColourMap<T> {
+	Table {
+		map: Vec<T>,
+		layout: MapLayout,
+		/// filled in whole whenever the palette changes, see [MapBackend::Lut]
+		eager: bool,
+	},
+	Hash(HashMap<RGB8, T>),
+	Tree(KdTree),
+}
+
+impl<T: Count> ColourMap<T> {
+	pub(crate) fn new(backend: MapBackend, layout: MapLayout) -> Self {
+		match backend.resolve::<T>() {
+			MapBackend::Auto | MapBackend::LazyLut => Self::table(layout, false),
+			MapBackend::Lut => Self::table(layout, true),
+			MapBackend::Hash => ColourMap::Hash(HashMap::new()),
+			MapBackend::KdTree => ColourMap::Tree(KdTree::default()),
+		}
+	}
+
+	pub(crate) fn table(layout: MapLayout, eager: bool) -> Self {
+		ColourMap::Table {
+			map: vec![T::zero(); MAP_LEN],
+			layout,
+			eager,
+		}
+	}
+
+	pub(crate) fn backend(&self) -> MapBackend {
+		match self {
+			ColourMap::Table { eager: true, .. } => MapBackend::Lut,
+			ColourMap::Table { eager: false, .. } => MapBackend::LazyLut,
+			ColourMap::Hash(_) => MapBackend::Hash,
+			ColourMap::Tree(_) => MapBackend::KdTree,
+		}
+	}
+
+	/// Whether the colours of an image have to be mapped before looking them
+	/// up. The others already know every colour.
+	pub(crate) fn is_lazy(&self) -> bool {
+		matches!(
+			self,
+			ColourMap::Table { eager: false, .. } | ColourMap::Hash(_)
+		)
+	}
+
+	/// The index for a colour. Colours a lazy map hasn't seen are index 0.
+	#[inline(always)]
+	pub(crate) fn get<D: ColorDifference>(&self, colour: &RGB8, difference: &D) -> T {
+		match self {
+			ColourMap::Table { map, layout, .. } => map[layout.index(colour)],
+			ColourMap::Hash(map) => map.get(colour).copied().unwrap_or(T::zero()),
+			ColourMap::Tree(tree) => T::from_usize(tree.nearest(colour, difference)),
+		}
+	}
+
+	/// Remember the index of a colour. Does nothing for a tree.
+	pub(crate) fn insert(&mut self, colour: RGB8, index: T) {
+		match self {
+			ColourMap::Table { map, layout, .. } => map[layout.index(&colour)] = index,
+			ColourMap::Hash(map) => {
+				map.insert(colour, index);
+			}
+			ColourMap::Tree(_) => (),
+		}
+	}
+
+	/// Bring the map up to date with a new palette. A lazy table keeps its old
+	/// indices, which are fixed up as images are mapped.
+	pub(crate) fn palette_changed<D: ColorDifference>(&mut self, palette: &[RGB8], difference: &D) {
+		match self {
+			ColourMap::Table { eager: true, .. } => self.fill(palette, difference),
+			ColourMap::Table { eager: false, .. } => (),
+			ColourMap::Hash(map) => map.clear(),
+			ColourMap::Tree(tree) => *tree = KdTree::new(palette),
+		}
+	}
+
+	/// Change every index to `remap[index]`, or 0 if it's past the end, after
+	/// palette entries were merged.
+	pub(crate) fn remap(&mut self, remap: &[usize], palette: &[RGB8]) {
+		let new = |entry: &mut T| {
+			*entry = T::from_usize(remap.get(entry.as_usize()).copied().unwrap_or(0));
+		};
+
+		match self {
+			ColourMap::Table { map, .. } => map.iter_mut().for_each(new),
+			ColourMap::Hash(map) => map.values_mut().for_each(new),
+			ColourMap::Tree(tree) => *tree = KdTree::new(palette),
+		}
+	}
+
+	/// Map every 24-bit colour. A hash or tree becomes a lazy table first.
+	pub(crate) fn fill<D: ColorDifference>(&mut self, palette: &[RGB8], difference: &D) {
+		if !matches!(self, ColourMap::Table { .. }) {
+			*self = Self::table(MapLayout::Linear, false);
+		}
+
+		if let ColourMap::Table { map, layout, .. } = self {
+			for index in 0..MAP_LEN {
+				let colour = linear_colour(index);
+				let nearest = difference::nearest(&colour, palette, difference);
+				map[layout.index(&colour)] = T::from_usize(nearest);
+			}
+		}
+	}
+
+	/// Every index in the order of the [lut](crate::lut) format. A tree works
+	/// out every colour, which is slow; a hash gives 0 for colours it hasn't
+	/// seen.
+	pub(crate) fn linear<D: ColorDifference>(&self, difference: &D) -> Cow<'_, [T]> {
+		match self {
+			ColourMap::Table { map, layout, .. } => layout.linear(map),
+			ColourMap::Hash(_) | ColourMap::Tree(_) => (0..MAP_LEN)
+				.map(|index| self.get(&linear_colour(index), difference))
+				.collect(),
+		}
+	}
+}
+
+/// The colour at a position in a [MapLayout::Linear] map
+fn linear_colour(index: usize) -> RGB8 {
+	RGB8::new((index >> 16) as u8, (index >> 8) as u8, index as u8)
+}
+
+/// A k-d tree of palette entries, balanced and stored in an array: the middle
+/// of any range is a node, and the halves either side of it are its children.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KdTree {
+	/// Each entry and its index in the palette
+	nodes: Vec<(RGB8, usize)>,
+	/// The channel each node splits on
+	axes: Vec<u8>,
+}
+
+impl KdTree {
+	pub(crate) fn new(palette: &[RGB8]) -> Self {
+		let mut nodes: Vec<(RGB8, usize)> = palette.iter().copied().zip(0..).collect();
+		let mut axes = vec![0; nodes.len()];
+		Self::build(&mut nodes, &mut axes);
+
+		Self { nodes, axes }
+	}
+
+	fn build(nodes: &mut [(RGB8, usize)], axes: &mut [u8]) {
+		if nodes.is_empty() {
+			return;
+		}
+
+		// split on whichever channel is the most spread out
+		let axis = (0..3u8)
+			.max_by_key(|&axis| {
+				let values = nodes.iter().map(|(colour, _)| channel(colour, axis));
+				let (min, max) = values.fold((u8::MAX, 0), |(min, max), value| {
+					(min.min(value), max.max(value))
+				});
+				max.saturating_sub(min)
+			})
+			.unwrap_or(0);
+		nodes.sort_unstable_by_key(|(colour, index)| (channel(colour, axis), *index));
+
+		let middle = nodes.len() / 2;
+		axes[middle] = axis;
+
+		let (left, right) = nodes.split_at_mut(middle);
+		let (left_axes, right_axes) = axes.split_at_mut(middle);
+		Self::build(left, left_axes);
+		Self::build(&mut right[1..], &mut right_axes[1..]);
+	}
+
+	/// Index of the palette entry closest to `colour`, or `usize::MAX` if the
+	/// palette is empty. Of equally close entries it's the first, like
+	/// [difference::nearest].
+	pub(crate) fn nearest<D: ColorDifference>(&self, colour: &RGB8, difference: &D) -> usize {
+		let mut best = (f32::MAX, usize::MAX);
+		self.search(0, self.nodes.len(), colour, difference, &mut best);
+		best.1
+	}
+
+	fn search<D: ColorDifference>(
+		&self,
+		start: usize,
+		end: usize,
+		colour: &RGB8,
+		difference: &D,
+		best: &mut (f32, usize),
+	) {
+		if start >= end {
+			return;
+		}
+
+		let middle = start + (end - start) / 2;
+		let (entry, index) = self.nodes[middle];
+		let diff = difference.difference(colour, &entry).max(0.0);
+		if diff < best.0 || (diff == best.0 && index < best.1) {
+			*best = (diff, index);
+		}
+
+		let axis = self.axes[middle];
+		let (near, far) = if channel(colour, axis) < channel(&entry, axis) {
+			((start, middle), (middle + 1, end))
+		} else {
+			((middle + 1, end), (start, middle))
+		};
+
+		self.search(near.0, near.1, colour, difference, best);
+
+		// nothing on the far side can be closer than the colour moved onto the
+		// splitting plane
+		let mut plane = *colour;
+		set_channel(&mut plane, axis, channel(&entry, axis));
+		if difference.difference(colour, &plane) <= best.0 {
+			self.search(far.0, far.1, colour, difference, best);
+		}
+	}
+}
+
+#[inline(always)]
+fn channel(colour: &RGB8, axis: u8) -> u8 {
+	match axis {
+		0 => colour.r,
+		1 => colour.g,
+		_ => colour.b,
+	}
+}
+
+fn set_channel(colour: &mut RGB8, axis: u8, value: u8) {
+	match axis {
+		0 => colour.r = value,
+		1 => colour.g = value,
+		_ => colour.b = value,
+	}
+}
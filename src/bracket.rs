@@ -0,0 +1,64 @@
+//! Selecting one palette for several versions of the same scene, like the
+//! exposures of an HDR bracket tone mapped to 8 bits, or a game level rendered
+//! at noon and at midnight, so they can all share it. See
+//! [Squasher::recolor_bracketed](crate::Squasher::recolor_bracketed).
+//!
+//! Feeding every version to the same Squasher almost works, but whichever has
+//! the most pixels, or the most pixels of one colour, takes most of the
+//! palette: a bright day with a big sky leaves the night with a couple of
+//! dark blues. Here each version is counted on its own and scaled so they all
+//! count the same, and then by their weights.
+
+use std::collections::HashMap;
+
+use rgb::RGB8;
+
+use crate::ImageData;
+
+/// One of the versions of the scene, and how much it counts.
+#[derive(Clone)]
+pub struct Bracket<'a> {
+	pub image: ImageData<'a>,
+	/// How much this version counts compared to the others. If they're all 1.0
+	/// they count the same however many pixels each has. 0.0 leaves it out.
+	pub weight: f32,
+}
+
+impl<'a> Bracket<'a> {
+	pub fn new(image: impl Into<ImageData<'a>>, weight: f32) -> Self {
+		Self {
+			image: image.into(),
+			weight,
+		}
+	}
+}
+
+/// Add the colours counted from each version together, scaling them so each
+/// version's total is its weight times the largest total. Colours that would
+/// round down to nothing count once, so a version with a low weight still
+/// has all of its colours in the histogram.
+pub(crate) fn merge(counted: Vec<(HashMap<RGB8, usize>, f32)>) -> HashMap<RGB8, usize> {
+	let total = |histogram: &HashMap<RGB8, usize>| histogram.values().sum::<usize>();
+	let largest = counted
+		.iter()
+		.map(|(histogram, _)| total(histogram))
+		.max()
+		.unwrap_or(0);
+
+	let mut merged: HashMap<RGB8, usize> = HashMap::new();
+	for (histogram, weight) in counted {
+		let sum = total(&histogram);
+		if sum == 0 || weight <= 0.0 {
+			continue;
+		}
+
+		let scale = largest as f64 * weight as f64 / sum as f64;
+		for (colour, count) in histogram {
+			let scaled = ((count as f64 * scale).round() as usize).max(1);
+			let entry = merged.entry(colour).or_default();
+			*entry = entry.saturating_add(scaled);
+		}
+	}
+
+	merged
+}
@@ -0,0 +1,35 @@
+//! Stopping a quantization part way through.
+//!
+//! Give the same [CancelToken] to the [Squasher](crate::Squasher) with
+//! [SquasherBuilder::cancel_token](crate::SquasherBuilder::cancel_token) and
+//! to [Kmeans](crate::selection::Kmeans), then call [CancelToken::cancel] from
+//! another thread, like when the user changes a setting in an interactive
+//! application. Work stops at the next check instead of running to the end.
+
+use std::sync::{
+	atomic::{AtomicBool, Ordering},
+	Arc,
+};
+
+/// A flag shared between clones. Cancelling one cancels all of them.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	/// Clear the flag so the token can be used again.
+	pub fn reset(&self) {
+		self.0.store(false, Ordering::Relaxed);
+	}
+}
@@ -0,0 +1,95 @@
+//! Encoding an animation as the parts that change between frames.
+//!
+//! Most animations only change in places, so rather than storing every frame
+//! whole, [delta] crops each to the rectangle that differs from the frame
+//! before it. Used with one palette for the whole animation and
+//! [ordered dithering](crate::dither), static areas map to the same indices
+//! every frame and the rectangles stay small.
+
+/// What a GIF decoder should do with a frame before drawing the next one.
+/// These are the disposal methods of the GIF graphic control extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Disposal {
+	NoAction,
+	/// Leave the frame in place for the next to be drawn over.
+	DoNotDispose,
+	RestoreBackground,
+	RestorePrevious,
+}
+
+/// The part of a frame that changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta<T> {
+	pub left: usize,
+	pub top: usize,
+	pub width: usize,
+	pub height: usize,
+	/// The indices of the changed rectangle, row by row
+	pub indices: Vec<T>,
+	/// The disposal method to give this frame. Every rectangle is relative to
+	/// the whole frame before it, so that frame has to stay on screen.
+	pub disposal: Disposal,
+}
+
+/// Find what changed between `previous` and `current`, two indexed frames of
+/// the same size. `width` is the width of the frames in pixels.
+///
+/// If `transparent` is given, pixels in the rectangle that didn't change are
+/// set to it so the decoder shows the previous frame through them. Long runs
+/// of one index compress well, so this usually makes the GIF smaller.
+///
+/// # Returns
+/// None if the frames are the same; extend the delay of the previous frame
+/// instead of adding one.
+///
+/// # Panics
+/// If the frames are different lengths.
+pub fn delta<T: Copy + PartialEq>(
+	previous: &[T],
+	current: &[T],
+	width: usize,
+	transparent: Option<T>,
+) -> Option<Delta<T>> {
+	assert_eq!(previous.len(), current.len(), "frames are different sizes");
+	let width = width.max(1);
+
+	let mut changed: Option<(usize, usize, usize, usize)> = None;
+	for (idx, (before, after)) in previous.iter().zip(current).enumerate() {
+		if before != after {
+			let (x, y) = (idx % width, idx / width);
+			changed = Some(match changed {
+				None => (x, y, x, y),
+				Some((left, top, right, bottom)) => {
+					(left.min(x), top.min(y), right.max(x), bottom.max(y))
+				}
+			});
+		}
+	}
+
+	let (left, top, right, bottom) = changed?;
+	let (rect_width, rect_height) = (right - left + 1, bottom - top + 1);
+
+	let mut indices = Vec::with_capacity(rect_width * rect_height);
+	for y in top..=bottom {
+		let row = y * width + left..y * width + right + 1;
+
+		match transparent {
+			None => indices.extend_from_slice(&current[row]),
+			Some(clear) => indices.extend(
+				previous[row.clone()]
+					.iter()
+					.zip(&current[row])
+					.map(|(before, after)| if before == after { clear } else { *after }),
+			),
+		}
+	}
+
+	Some(Delta {
+		left,
+		top,
+		width: rect_width,
+		height: rect_height,
+		indices,
+		disposal: Disposal::DoNotDispose,
+	})
+}
@@ -0,0 +1,68 @@
+//! Bringing high bit depth images down to the 8-bit channels colorsquash works
+//! with.
+//!
+//! Simply dropping the low byte of each channel would band smooth gradients, so
+//! the low bits are instead used to dither with a 4x4 ordered pattern.
+
+use rgb::{RGB16, RGB8};
+
+use crate::dither::BAYER_4;
+
+/// Dither 16-bit per channel pixels down to 8-bit. `width` is the width of the
+/// image in pixels, used to lay the dither pattern over it.
+pub fn dither_rgb16(pixels: &[RGB16], width: usize) -> Vec<RGB8> {
+	let width = width.max(1);
+
+	pixels
+		.iter()
+		.enumerate()
+		.map(|(idx, px)| {
+			let (x, y) = (idx % width, idx / width);
+			// center the thresholds in their sixteenth, 0.03125 to 0.96875
+			let threshold = (BAYER_4[y % 4][x % 4] as f32 + 0.5) / 16.0;
+
+			RGB8::new(
+				dither_channel(px.r, threshold),
+				dither_channel(px.g, threshold),
+				dither_channel(px.b, threshold),
+			)
+		})
+		.collect()
+}
+
+/// Read pixels from big-endian, 16-bit per channel, data. This is how PNG
+/// stores them. `channels` is 3 for RGB or 4 for RGBA, where alpha is dropped.
+pub fn rgb16_from_be_bytes(bytes: &[u8], channels: usize) -> Vec<RGB16> {
+	let channel = |px: &[u8], idx: usize| u16::from_be_bytes([px[idx * 2], px[idx * 2 + 1]]);
+
+	bytes
+		.chunks_exact(channels * 2)
+		.map(|px| RGB16::new(channel(px, 0), channel(px, 1), channel(px, 2)))
+		.collect()
+}
+
+/// Read big-endian, 16-bit, gray pixels into RGB pixels with every channel
+/// set to the gray. `channels` is 1 for gray or 2 for gray and alpha, where
+/// alpha is dropped.
+pub fn rgb16_from_gray16_be_bytes(bytes: &[u8], channels: usize) -> Vec<RGB16> {
+	bytes
+		.chunks_exact(channels * 2)
+		.map(|px| {
+			let gray = u16::from_be_bytes([px[0], px[1]]);
+			RGB16::new(gray, gray, gray)
+		})
+		.collect()
+}
+
+#[inline(always)]
+fn dither_channel(value: u16, threshold: f32) -> u8 {
+	// 257 maps 65535 to exactly 255
+	let scaled = value as f32 / 257.0;
+	let base = scaled.floor();
+
+	if scaled - base > threshold {
+		(base as u8).saturating_add(1)
+	} else {
+		base as u8
+	}
+}
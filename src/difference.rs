@@ -14,6 +14,10 @@
 //!
 //! The difference functions have the possibility of being called hundreds of
 //! thousands of times; you might want to `#[inline(always)]`
+//!
+//! With the `simd` feature the [simd] module has versions of [rgb] and
+//! [redmean] that compare against eight palette entries at once, which [Rgb]
+//! and [Redmean] use to find the nearest entry.
 
 // This is used in the module level documentation just above. Without it we'd
 // have to fully qualify the interlink which is also how it'd be displayed.
@@ -25,9 +29,120 @@ use crate::{Squasher, SquasherBuilder};
 /// rexport from the [`rgb`](https://docs.rs/rgb/0.8.37/rgb/) crate.
 pub use rgb::RGB8;
 
+#[cfg(feature = "simd")]
+pub mod simd;
+
 /// Type definition for difference functions.
 pub type DiffFn = dyn Fn(&RGB8, &RGB8) -> f32;
 
+/// A [DiffFn] that can be shared between threads, for selectors that try
+/// palettes on more than one at once.
+pub type SyncDiffFn = dyn Fn(&RGB8, &RGB8) -> f32 + Sync;
+
+/// A difference function that can be part of a type, letting the compiler
+/// inline it. See [SquasherBuilder::mapper].
+///
+/// This is implemented for every `Fn(&RGB8, &RGB8) -> f32`, including the
+/// boxed [DiffFn], so the functions in this module work as-is. [Rgb] and
+/// [Redmean] are here for when you need to write the type out, and with the
+/// `simd` feature they find the nearest palette entry eight at a time.
+pub trait ColorDifference {
+	fn difference(&self, a: &RGB8, b: &RGB8) -> f32;
+
+	/// Index of the palette entry closest to `color`, or `usize::MAX` if the
+	/// palette is empty. Of equally close entries it's the first. Only
+	/// override this with something quicker that gives the same answer.
+	#[inline(always)]
+	fn nearest(&self, color: &RGB8, palette: &[RGB8]) -> usize {
+		let mut min_diff = f32::MAX;
+		let mut min_index = usize::MAX;
+
+		for (index, selected) in palette.iter().enumerate() {
+			let diff = self.difference(color, selected);
+
+			if diff.max(0.0) < min_diff {
+				min_diff = diff;
+				min_index = index;
+			}
+		}
+
+		min_index
+	}
+}
+
+impl<F: Fn(&RGB8, &RGB8) -> f32> ColorDifference for F {
+	#[inline(always)]
+	fn difference(&self, a: &RGB8, b: &RGB8) -> f32 {
+		self(a, b)
+	}
+}
+
+/// [rgb] as a type
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Rgb;
+
+impl ColorDifference for Rgb {
+	#[inline(always)]
+	fn difference(&self, a: &RGB8, b: &RGB8) -> f32 {
+		rgb(a, b)
+	}
+
+	#[cfg(feature = "simd")]
+	#[inline(always)]
+	fn nearest(&self, color: &RGB8, palette: &[RGB8]) -> usize {
+		simd::nearest_rgb(color, palette)
+	}
+}
+
+/// [redmean] as a type
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Redmean;
+
+impl ColorDifference for Redmean {
+	#[inline(always)]
+	fn difference(&self, a: &RGB8, b: &RGB8) -> f32 {
+		redmean(a, b)
+	}
+
+	#[cfg(feature = "simd")]
+	#[inline(always)]
+	fn nearest(&self, color: &RGB8, palette: &[RGB8]) -> usize {
+		simd::nearest_redmean(color, palette)
+	}
+}
+
+/// A difference function written against `palette` crate colours, like one
+/// that converts to `palette::Lab` and compares there. Wrapping it makes it a
+/// [ColorDifference] that works anywhere the functions in this module do.
+#[cfg(feature = "palette")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SrgbFn<F>(pub F);
+
+#[cfg(feature = "palette")]
+impl<F> ColorDifference for SrgbFn<F>
+where
+	F: Fn(&palette::Srgb<u8>, &palette::Srgb<u8>) -> f32,
+{
+	#[inline(always)]
+	fn difference(&self, a: &RGB8, b: &RGB8) -> f32 {
+		(self.0)(
+			&palette::Srgb::new(a.r, a.g, a.b),
+			&palette::Srgb::new(b.r, b.g, b.b),
+		)
+	}
+}
+
+/// Index of the palette entry closest to `color`, or `usize::MAX` if the
+/// palette is empty. See [ColorDifference::nearest].
+#[inline(always)]
+pub fn nearest<D: ColorDifference + ?Sized>(
+	color: &RGB8,
+	palette: &[RGB8],
+	difference: &D,
+) -> usize {
+	difference.nearest(color, palette)
+}
+
 /// A naïve comparison just summing the channel differences
 /// I.E. `|a.red - b.red| + |a.green - b.green| + |a.blue - b.blue|`
 #[allow(clippy::many_single_char_names)]
@@ -0,0 +1,96 @@
+//! SIMD versions of the difference functions that compare one colour against
+//! eight palette entries at once. Requires nightly Rust for `std::simd`.
+
+use std::simd::{f32x8, num::SimdFloat, StdFloat};
+
+use super::RGB8;
+
+/// The channels of eight palette entries, split into lanes.
+#[inline(always)]
+fn lanes(palette: &[RGB8; 8]) -> (f32x8, f32x8, f32x8) {
+	(
+		f32x8::from_array(palette.map(|c| c.r as f32)),
+		f32x8::from_array(palette.map(|c| c.g as f32)),
+		f32x8::from_array(palette.map(|c| c.b as f32)),
+	)
+}
+
+/// [rgb](super::rgb) for eight palette entries at a time.
+#[inline(always)]
+pub fn rgb_x8(color: &RGB8, palette: &[RGB8; 8]) -> [f32; 8] {
+	let (r, g, b) = lanes(palette);
+
+	let delta_r = (r - f32x8::splat(color.r as f32)).abs();
+	let delta_g = (g - f32x8::splat(color.g as f32)).abs();
+	let delta_b = (b - f32x8::splat(color.b as f32)).abs();
+
+	(delta_r + delta_g + delta_b).to_array()
+}
+
+/// [redmean](super::redmean) for eight palette entries at a time.
+#[inline(always)]
+pub fn redmean_x8(color: &RGB8, palette: &[RGB8; 8]) -> [f32; 8] {
+	let (r, g, b) = lanes(palette);
+	let color_r = f32x8::splat(color.r as f32);
+
+	let delta_r = r - color_r;
+	let delta_g = g - f32x8::splat(color.g as f32);
+	let delta_b = b - f32x8::splat(color.b as f32);
+	let r_prime = f32x8::splat(0.5) * (r + color_r);
+
+	let two = f32x8::splat(2.0);
+	let max = f32x8::splat(256.0);
+	let red_part = (two + r_prime / max) * (delta_r * delta_r);
+	let green_part = f32x8::splat(4.0) * (delta_g * delta_g);
+	let blue_part = (two + (f32x8::splat(255.0) - r_prime) / max) * (delta_b * delta_b);
+
+	(red_part + green_part + blue_part).sqrt().to_array()
+}
+
+/// Index of the palette entry closest to `color` by [rgb](super::rgb)
+pub fn nearest_rgb(color: &RGB8, palette: &[RGB8]) -> usize {
+	nearest(color, palette, rgb_x8, super::rgb)
+}
+
+/// Index of the palette entry closest to `color` by [redmean](super::redmean)
+pub fn nearest_redmean(color: &RGB8, palette: &[RGB8]) -> usize {
+	nearest(color, palette, redmean_x8, super::redmean)
+}
+
+/// Walks the palette eight at a time, falling back to the scalar function for
+/// the remainder. Returns `usize::MAX` for an empty palette.
+#[inline(always)]
+fn nearest<V, S>(color: &RGB8, palette: &[RGB8], vector: V, scalar: S) -> usize
+where
+	V: Fn(&RGB8, &[RGB8; 8]) -> [f32; 8],
+	S: Fn(&RGB8, &RGB8) -> f32,
+{
+	let mut min_diff = f32::MAX;
+	let mut min_index = usize::MAX;
+
+	let chunks = palette.chunks_exact(8);
+	let remainder = chunks.remainder();
+
+	for (chunk_idx, chunk) in chunks.enumerate() {
+		let diffs = vector(color, chunk.try_into().unwrap());
+
+		for (lane, diff) in diffs.into_iter().enumerate() {
+			if diff.max(0.0) < min_diff {
+				min_diff = diff;
+				min_index = chunk_idx * 8 + lane;
+			}
+		}
+	}
+
+	let offset = palette.len() - remainder.len();
+	for (idx, selected) in remainder.iter().enumerate() {
+		let diff = scalar(selected, color);
+
+		if diff.max(0.0) < min_diff {
+			min_diff = diff;
+			min_index = offset + idx;
+		}
+	}
+
+	min_index
+}
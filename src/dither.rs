@@ -0,0 +1,267 @@
+//! Dithering while mapping.
+//!
+//! Ordered dithering nudges each pixel by a threshold that depends only on its
+//! position, so the same pixel in the same place always maps to the same
+//! index. That makes it the one to use for animation: static parts of the
+//! image come out identical every frame instead of shimmering like they do
+//! with error diffusion, and identical frames compress far better in a GIF.
+//! See [Squasher::map_ordered](crate::Squasher::map_ordered).
+//!
+//! Error diffusion, [Diffusion], spreads the difference between a pixel and
+//! its palette colour onto the pixels after it, the way a [Kernel] says. It
+//! looks smoother than ordered dithering for still images. See
+//! [Squasher::map_diffused](crate::Squasher::map_diffused). With [Adaptive] it
+//! diffuses less of the error where dithering makes things worse, in flat areas
+//! and along hard edges like text, and all of it in gradients.
+
+use rgb::RGB8;
+
+// 4x4 Bayer matrix
+pub(crate) const BAYER_4: [[u8; 4]; 4] =
+	[[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// 8x8 Bayer matrix
+const BAYER_8: [[u8; 8]; 8] = [
+	[0, 32, 8, 40, 2, 34, 10, 42],
+	[48, 16, 56, 24, 50, 18, 58, 26],
+	[12, 44, 4, 36, 14, 46, 6, 38],
+	[60, 28, 52, 20, 62, 30, 54, 22],
+	[3, 35, 11, 43, 1, 33, 9, 41],
+	[51, 19, 59, 27, 49, 17, 57, 25],
+	[15, 47, 7, 39, 13, 45, 5, 37],
+	[63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// The threshold pattern laid over the image.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Pattern {
+	/// A 4x4 Bayer matrix. Coarser, but a more visible pattern.
+	Bayer4,
+	/// An 8x8 Bayer matrix.
+	#[default]
+	Bayer8,
+}
+
+impl Pattern {
+	/// The threshold at a pixel, between -0.5 and 0.5.
+	pub fn threshold(&self, x: usize, y: usize) -> f32 {
+		match self {
+			Pattern::Bayer4 => (BAYER_4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5,
+			Pattern::Bayer8 => (BAYER_8[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5,
+		}
+	}
+}
+
+/// Ordered dithering settings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ordered {
+	pub pattern: Pattern,
+	/// How far a channel can be pushed either way is half of this. Roughly the
+	/// distance between palette colours works well; 0.0 turns dithering off.
+	pub strength: f32,
+}
+
+impl Default for Ordered {
+	fn default() -> Self {
+		Self {
+			pattern: Pattern::default(),
+			strength: 32.0,
+		}
+	}
+}
+
+impl Ordered {
+	/// Nudge every pixel by the threshold at its position. `width` is the width
+	/// of the image in pixels.
+	pub fn apply(&self, pixels: &[RGB8], width: usize) -> Vec<RGB8> {
+		let width = width.max(1);
+
+		pixels
+			.iter()
+			.enumerate()
+			.map(|(idx, px)| {
+				let offset = self.pattern.threshold(idx % width, idx / width) * self.strength;
+				let channel = |value: u8| (value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+
+				RGB8::new(channel(px.r), channel(px.g), channel(px.b))
+			})
+			.collect()
+	}
+}
+
+/// How much of the error is passed on, per channel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Weights {
+	/// A scale for each of red, green, and blue
+	Rgb([f32; 3]),
+	/// Separate scales for the brightness and colour parts of the error. Noise
+	/// in colour is more noticeable than noise in brightness, so something
+	/// like a luma of 1.0 and a chroma of 0.5 often looks cleaner.
+	LumaChroma { luma: f32, chroma: f32 },
+}
+
+/// Where the error of a pixel goes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Kernel {
+	/// All of it, to the pixel to the right and the three below
+	#[default]
+	FloydSteinberg,
+	/// Three quarters of it, spread further: the two pixels to the right, the
+	/// three below, and the one below that. The error that's lost keeps
+	/// contrast up and flat, light, and dark areas clean, at the cost of
+	/// detail in the highlights and shadows.
+	Atkinson,
+}
+
+impl Kernel {
+	/// Each pixel that gets some of the error, as how far right and down it is,
+	/// and its share, over [Kernel::denominator]
+	pub(crate) fn taps(&self) -> &'static [(isize, usize, f32)] {
+		match self {
+			Kernel::FloydSteinberg => &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+			Kernel::Atkinson => &[
+				(1, 0, 1.0),
+				(2, 0, 1.0),
+				(-1, 1, 1.0),
+				(0, 1, 1.0),
+				(1, 1, 1.0),
+				(0, 2, 1.0),
+			],
+		}
+	}
+
+	pub(crate) fn denominator(&self) -> f32 {
+		match self {
+			Kernel::FloydSteinberg => 16.0,
+			Kernel::Atkinson => 8.0,
+		}
+	}
+
+	/// How many rows below the current one get some of the error
+	pub(crate) fn rows(&self) -> usize {
+		self.taps().iter().map(|&(_, dy, _)| dy).max().unwrap_or(0)
+	}
+}
+
+/// Error diffusion settings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Diffusion {
+	pub kernel: Kernel,
+	pub weights: Weights,
+	/// Change how much error is diffused by how busy the image is around each
+	/// pixel. If it's None the same amount is diffused everywhere.
+	pub adaptive: Option<Adaptive>,
+}
+
+impl Default for Diffusion {
+	fn default() -> Self {
+		Self {
+			kernel: Kernel::default(),
+			weights: Weights::Rgb([1.0; 3]),
+			adaptive: None,
+		}
+	}
+}
+
+/// Scales the diffused error by the standard deviation of the brightness of
+/// the 3x3 pixels around each one. Smooth gradients band without dithering so
+/// they get all of it. Flat areas, like the background of a UI, and hard edges,
+/// like text, just look speckled so they get `minimum` of it, none by default.
+/// Between these the amount changes gradually.
+///
+/// The steps of a smooth gradient are small, so even a little variation isn't
+/// flat. Only areas that are almost exactly one colour are.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Adaptive {
+	/// At and below this deviation an area is flat. Deviations between this
+	/// and twice this fade from `minimum` to the full error.
+	pub flat: f32,
+	/// At and above this deviation an area is an edge. Deviations between this
+	/// and twice this fade from the full error to `minimum`.
+	pub edge: f32,
+	/// The fraction of the error diffused in flat areas and on edges
+	pub minimum: f32,
+}
+
+impl Default for Adaptive {
+	fn default() -> Self {
+		Self {
+			flat: 0.25,
+			edge: 24.0,
+			minimum: 0.0,
+		}
+	}
+}
+
+impl Adaptive {
+	/// The fraction of the error to diffuse at a deviation
+	pub fn strength(&self, deviation: f32) -> f32 {
+		let minimum = self.minimum.clamp(0.0, 1.0);
+		let flat = self.flat.max(0.0);
+		let edge = self.edge.max(flat);
+
+		// how far from flat or an edge, 0.0 being all the way
+		let towards = if deviation <= flat {
+			0.0
+		} else if deviation < flat * 2.0 {
+			(deviation - flat) / flat
+		} else if deviation <= edge {
+			1.0
+		} else {
+			(1.0 - (deviation - edge) / edge.max(f32::EPSILON)).max(0.0)
+		};
+
+		minimum + (1.0 - minimum) * towards
+	}
+
+	/// The strength at every pixel of an image `width` pixels wide
+	pub(crate) fn strengths(&self, pixels: &[RGB8], width: usize) -> Vec<f32> {
+		let width = width.max(1);
+		let height = pixels.len().div_ceil(width);
+		let luma: Vec<f32> = pixels
+			.iter()
+			.map(|px| px.r as f32 * 0.299 + px.g as f32 * 0.587 + px.b as f32 * 0.114)
+			.collect();
+
+		(0..pixels.len())
+			.map(|idx| {
+				let (x, y) = (idx % width, idx / width);
+
+				let mut sum = 0.0;
+				let mut squares = 0.0;
+				let mut count = 0.0;
+				for ny in y.saturating_sub(1)..(y + 2).min(height) {
+					for nx in x.saturating_sub(1)..(x + 2).min(width) {
+						if let Some(&value) = luma.get(ny * width + nx) {
+							sum += value;
+							squares += value * value;
+							count += 1.0;
+						}
+					}
+				}
+
+				let mean = sum / count;
+				let variance = (squares / count - mean * mean).max(0.0);
+				self.strength(variance.sqrt())
+			})
+			.collect()
+	}
+}
+
+impl Diffusion {
+	/// Scale an RGB error by the weights
+	pub(crate) fn scale(&self, error: [f32; 3]) -> [f32; 3] {
+		match self.weights {
+			Weights::Rgb(scale) => [
+				error[0] * scale[0],
+				error[1] * scale[1],
+				error[2] * scale[2],
+			],
+			Weights::LumaChroma { luma, chroma } => {
+				// Rec. 601 luma. Whatever's left in each channel is the chroma.
+				let y = error[0] * 0.299 + error[1] * 0.587 + error[2] * 0.114;
+				error.map(|channel| y * luma + (channel - y) * chroma)
+			}
+		}
+	}
+}
@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+	#[error("output buffer too small to fit indexed image. needs {needed} but has {len}")]
+	BufferTooSmall { needed: usize, len: usize },
+	#[error("image data is {0} bytes long which isn't a multiple of 3")]
+	NotRgb(usize),
+	#[error("a {width}x{height} image is {expected} bytes but the data is {len}")]
+	WrongDimensions {
+		width: usize,
+		height: usize,
+		expected: usize,
+		len: usize,
+	},
+	#[error("a stride of {stride} bytes is shorter than a row of {width} pixels")]
+	StrideTooSmall { stride: usize, width: usize },
+	#[error("an image of {pixels} pixels needs as many weights but there are {len}")]
+	WrongWeights { pixels: usize, len: usize },
+	#[error("the width of the image isn't known. give it with ImageData::with_stride")]
+	NoDimensions,
+	#[error("no selector was given to the builder")]
+	NoSelector,
+	#[error("the selector didn't pick any colours")]
+	EmptyPalette,
+	#[error("a palette of {len} colours is more than the index type can count, which is {max}")]
+	PaletteTooLarge { len: usize, max: usize },
+	#[error("cancelled")]
+	Cancelled,
+}
@@ -0,0 +1,112 @@
+//! A quantizer for single channel, grayscale, images.
+//!
+//! Grayscale images can have at most 256 levels so this skips most of what the
+//! [Squasher](crate::Squasher) does. The palette is picked with the same
+//! sort/select method as [SortSelect](crate::selection::SortSelect) and mapping
+//! uses a 256 entry table instead of the 16MB colour cube.
+
+use rgb::RGB8;
+
+pub struct GraySquasher {
+	max_colours_min1: u8,
+	tolerance: f32,
+	palette: Vec<u8>,
+	map: [u8; 256],
+}
+
+impl GraySquasher {
+	/// Select a palette for the grayscale image. `tolerance` is how different,
+	/// as a percent, levels have to be to enter the palette. `3.0` works well.
+	pub fn new(max_colors_minus_one: u8, tolerance: f32, image: &[u8]) -> Self {
+		let mut this = Self {
+			max_colours_min1: max_colors_minus_one,
+			tolerance,
+			palette: vec![],
+			map: [0; 256],
+		};
+		this.recolor(image);
+
+		this
+	}
+
+	/// Create a new palette from the levels in the given image.
+	pub fn recolor(&mut self, image: &[u8]) {
+		let mut counts = [0usize; 256];
+		for px in image {
+			counts[*px as usize] += 1;
+		}
+
+		let mut sorted: Vec<(u8, usize)> = counts
+			.iter()
+			.enumerate()
+			.filter(|(_, count)| **count > 0)
+			.map(|(level, count)| (level as u8, *count))
+			.collect();
+		sorted
+			.sort_by(|(level1, freq1), (level2, freq2)| freq2.cmp(freq1).then(level2.cmp(level1)));
+
+		let max_colours = self.max_colours_min1 as usize + 1;
+		let tolerance = (self.tolerance / 100.0) * 255.0;
+		self.palette.clear();
+
+		for (level, _) in sorted {
+			if max_colours <= self.palette.len() {
+				break;
+			} else if self
+				.palette
+				.iter()
+				.all(|selected| (*selected as f32 - level as f32).abs() > tolerance)
+			{
+				self.palette.push(level);
+			}
+		}
+
+		// there are only 256 levels, so always fill the whole map
+		for level in 0..=255u8 {
+			self.map[level as usize] = self.closest(level);
+		}
+	}
+
+	fn closest(&self, level: u8) -> u8 {
+		self.palette
+			.iter()
+			.enumerate()
+			.min_by_key(|(_, selected)| selected.abs_diff(level))
+			.map(|(idx, _)| idx as u8)
+			.unwrap_or(0)
+	}
+
+	/// Fill the output buffer with indices into the palette. It must be at least
+	/// as long as the image.
+	pub fn map(&self, image: &[u8], buffer: &mut [u8]) {
+		if buffer.len() < image.len() {
+			panic!("output buffer too small to fit indexed image");
+		}
+
+		for (out, px) in buffer.iter_mut().zip(image) {
+			*out = self.map[*px as usize];
+		}
+	}
+
+	/// Replace the levels of the image with indices into the palette.
+	pub fn map_over(&self, image: &mut [u8]) {
+		for px in image.iter_mut() {
+			*px = self.map[*px as usize];
+		}
+	}
+
+	/// The levels of the gray palette
+	pub fn palette(&self) -> &[u8] {
+		&self.palette
+	}
+
+	/// The palette as RGB colours
+	pub fn palette_rgb(&self) -> Vec<RGB8> {
+		self.palette.iter().map(|l| RGB8::new(*l, *l, *l)).collect()
+	}
+
+	/// The palette as RGB bytes, three per colour
+	pub fn palette_bytes(&self) -> Vec<u8> {
+		self.palette.iter().flat_map(|l| [*l, *l, *l]).collect()
+	}
+}
@@ -0,0 +1,141 @@
+//! Perceptual image hashes for spotting near-duplicate images, and a cache that
+//! uses them to reuse palettes.
+//!
+//! Consecutive frames of a video, or thumbnails of them, are often nearly the
+//! same image. Selecting a palette again for each one is wasted work when the
+//! palette of a similar frame would do just as well.
+
+use rgb::RGB8;
+
+use crate::ImageData;
+
+/// The average hash. The image is shrunk to 8x8 and each bit is set if that
+/// cell is brighter than the average.
+pub fn ahash<'a, Img>(image: Img, width: usize, height: usize) -> u64
+where
+	Img: Into<ImageData<'a>>,
+{
+	let ImageData { pixels: rgb, .. } = image.into();
+	let grid = luma_grid(&rgb, width, height, 8, 8);
+	let average = grid.iter().sum::<f32>() / grid.len() as f32;
+
+	grid.iter().enumerate().fold(0, |hash, (bit, luma)| {
+		hash | ((*luma > average) as u64) << bit
+	})
+}
+
+/// The difference hash. The image is shrunk to 9x8 and each bit is set if that
+/// cell is brighter than the one to its right. This tends to hold up better
+/// than [ahash] to changes in brightness.
+pub fn dhash<'a, Img>(image: Img, width: usize, height: usize) -> u64
+where
+	Img: Into<ImageData<'a>>,
+{
+	let ImageData { pixels: rgb, .. } = image.into();
+	let grid = luma_grid(&rgb, width, height, 9, 8);
+
+	let mut hash = 0;
+	for y in 0..8 {
+		for x in 0..8 {
+			let bit = y * 8 + x;
+			hash |= ((grid[y * 9 + x] > grid[y * 9 + x + 1]) as u64) << bit;
+		}
+	}
+
+	hash
+}
+
+/// The number of bits that differ between two hashes. Zero is identical, more
+/// than about ten is usually a different image.
+pub fn distance(a: u64, b: u64) -> u32 {
+	(a ^ b).count_ones()
+}
+
+/// Average luma of a `grid_width` by `grid_height` grid laid over the image.
+fn luma_grid(
+	rgb: &[RGB8],
+	width: usize,
+	height: usize,
+	grid_width: usize,
+	grid_height: usize,
+) -> Vec<f32> {
+	let width = width.max(1);
+	let height = height.min(rgb.len() / width).max(1);
+
+	let mut grid = Vec::with_capacity(grid_width * grid_height);
+	for cy in 0..grid_height {
+		// every cell gets at least one pixel, even if the image is smaller
+		// than the grid
+		let y0 = cy * height / grid_height;
+		let y1 = ((cy + 1) * height / grid_height).max(y0 + 1);
+
+		for cx in 0..grid_width {
+			let x0 = cx * width / grid_width;
+			let x1 = ((cx + 1) * width / grid_width).max(x0 + 1);
+
+			let mut sum = 0.0;
+			let mut count = 0;
+			for y in y0..y1 {
+				for px in rgb.iter().skip(y * width + x0).take(x1 - x0) {
+					sum += 0.299 * px.r as f32 + 0.587 * px.g as f32 + 0.114 * px.b as f32;
+					count += 1;
+				}
+			}
+
+			grid.push(if count > 0 { sum / count as f32 } else { 0.0 });
+		}
+	}
+
+	grid
+}
+
+/// Palettes keyed by the [dhash] of the image they were selected from.
+pub struct PaletteCache {
+	threshold: u32,
+	capacity: usize,
+	entries: Vec<(u64, Vec<RGB8>)>,
+}
+
+impl PaletteCache {
+	/// A cache that considers images the same when their hashes have a
+	/// [distance] of `threshold` or less. When it's holding `capacity`
+	/// palettes, the oldest is dropped to make room for a new one.
+	pub fn new(threshold: u32, capacity: usize) -> Self {
+		Self {
+			threshold,
+			capacity: capacity.max(1),
+			entries: vec![],
+		}
+	}
+
+	/// Retrieve the palette whose hash is closest to this one, as long as it's
+	/// within the threshold.
+	pub fn get(&self, hash: u64) -> Option<&[RGB8]> {
+		self.entries
+			.iter()
+			.map(|(cached, palette)| (distance(*cached, hash), palette))
+			.filter(|(dist, _)| *dist <= self.threshold)
+			.min_by_key(|(dist, _)| *dist)
+			.map(|(_, palette)| palette.as_slice())
+	}
+
+	pub fn insert(&mut self, hash: u64, palette: Vec<RGB8>) {
+		if self.entries.len() >= self.capacity {
+			self.entries.remove(0);
+		}
+
+		self.entries.push((hash, palette));
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+}
@@ -0,0 +1,317 @@
+//! Rendering intents for mapping to a fixed palette that can't show most of
+//! the colours of an image, like an e-ink screen, an LED panel or a console.
+//! See [Squasher::map_with_intent](crate::Squasher::map_with_intent).
+//!
+//! Mapping straight to the nearest entry clips: a saturated colour the palette
+//! can't make goes to whatever happens to be closest, which is often a
+//! different hue or a grey, and colours just either side of the edge of the
+//! palette's gamut fall onto the same entry. An intent moves colours first, in
+//! OKLab so lightness, chroma, and hue are kept apart.
+//!
+//! The gamut of a palette here is rough. For each hue it's a triangle from the
+//! darkest entry, to the most saturated entry of that hue, to the lightest,
+//! with hues the palette doesn't have found between the ones either side.
+//! That's enough to tell a palette of seven inks from a screen.
+
+use std::f32::consts::TAU;
+
+use rgb::RGB8;
+
+use crate::oklab::Oklab;
+
+/// How colours are moved into the gamut of the palette before mapping.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Intent {
+	/// Squeeze the lightness and chroma of the whole image into what the
+	/// palette can show, so colours inside the gamut move too but stay in
+	/// proportion with each other. Good for photos.
+	Perceptual,
+	/// Leave colours inside the gamut alone and bring colours outside of it to
+	/// the edge, keeping their hue. Good when most of the image is already in
+	/// the gamut and the colours that are should be exact.
+	#[default]
+	RelativeColorimetric,
+	/// Stretch or squeeze the chroma of every colour so the most saturated of
+	/// each hue in the image is the most saturated the palette has, keeping
+	/// everything vivid even if it's less accurate. Good for charts, logos,
+	/// and pixel art.
+	Saturation,
+}
+
+/// Colours with less chroma than this are counted as grey and have no hue.
+const GREY: f32 = 0.02;
+/// The number of slices the hue circle is cut into
+const HUES: usize = 36;
+
+/// The colours a set of colours covers, roughly: for each hue, a triangle of
+/// lightness and chroma from the darkest grey to the most saturated colour of
+/// that hue to the lightest grey.
+#[derive(Clone, Debug)]
+struct Gamut {
+	lightness: (f32, f32),
+	/// The lightness and chroma of the most saturated colour for the hues of
+	/// each slice, or filled in from the slices either side if there weren't
+	/// any
+	cusps: [(f32, f32); HUES],
+}
+
+impl Gamut {
+	fn of(colours: &[RGB8]) -> Self {
+		let mut lightness = (f32::MAX, f32::MIN);
+		let mut cusps = [None::<(f32, f32)>; HUES];
+
+		for colour in colours {
+			let lch = Lch::from_rgb(*colour);
+			lightness = (lightness.0.min(lch.l), lightness.1.max(lch.l));
+
+			if lch.c >= GREY {
+				let cusp = &mut cusps[slice(lch.h)];
+				if !matches!(cusp, Some((_, c)) if *c >= lch.c) {
+					*cusp = Some((lch.l, lch.c));
+				}
+			}
+		}
+
+		if lightness.0 > lightness.1 {
+			lightness = (0.0, 1.0);
+		}
+
+		let middle = (lightness.0 + lightness.1) / 2.0;
+		Self {
+			lightness,
+			cusps: fill_gaps(cusps, (middle, 0.0)),
+		}
+	}
+
+	/// The cusp at a hue, between the two nearest slices
+	fn cusp(&self, hue: f32) -> (f32, f32) {
+		let position = hue / TAU * HUES as f32 - 0.5;
+		let below = position.floor();
+		let t = position - below;
+
+		let below = (below as isize).rem_euclid(HUES as isize) as usize;
+		let above = (below + 1) % HUES;
+		lerp(self.cusps[below], self.cusps[above], t)
+	}
+
+	/// The most chroma there is at a lightness and hue
+	fn chroma(&self, l: f32, hue: f32) -> f32 {
+		let (low, high) = self.lightness;
+		let (cusp_l, cusp_c) = self.cusp(hue);
+
+		let fraction = if l < low || l > high {
+			0.0
+		} else if l <= cusp_l {
+			(l - low) / (cusp_l - low)
+		} else {
+			(high - l) / (high - cusp_l)
+		};
+
+		// a cusp at the very end of the lightness makes 0 / 0 above
+		if fraction.is_nan() {
+			cusp_c
+		} else {
+			cusp_c * fraction.clamp(0.0, 1.0)
+		}
+	}
+
+	fn contains(&self, lch: Lch) -> bool {
+		(self.lightness.0..=self.lightness.1).contains(&lch.l)
+			&& lch.c <= self.chroma(lch.l, lch.h) + 0.0001
+	}
+
+	/// Bring a colour outside of the gamut to its edge, going in a straight line
+	/// towards the grey as light as the cusp of its hue. Colours inside are left
+	/// alone.
+	fn clip(&self, lch: Lch) -> Lch {
+		if self.contains(lch) {
+			return lch;
+		}
+
+		let (cusp_l, cusp_c) = self.cusp(lch.h);
+		if cusp_c < GREY {
+			// nothing but greys
+			return Lch {
+				l: lch.l.clamp(self.lightness.0, self.lightness.1),
+				c: 0.0,
+				h: lch.h,
+			};
+		}
+
+		// the grey is inside and the colour isn't, so find where the line
+		// between them crosses the edge
+		let towards = |t: f32| Lch {
+			l: cusp_l + (lch.l - cusp_l) * t,
+			c: lch.c * t,
+			h: lch.h,
+		};
+		let (mut inside, mut outside) = (0.0, 1.0);
+		for _ in 0..16 {
+			let t = (inside + outside) / 2.0;
+			if self.contains(towards(t)) {
+				inside = t;
+			} else {
+				outside = t;
+			}
+		}
+
+		towards(inside)
+	}
+}
+
+fn lerp(a: (f32, f32), b: (f32, f32), t: f32) -> (f32, f32) {
+	(a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+/// Which slice of the hue circle a hue is in
+fn slice(hue: f32) -> usize {
+	((hue / TAU * HUES as f32) as usize).min(HUES - 1)
+}
+
+/// Fill empty slices by going in a straight line between the nearest slices on
+/// each side that aren't. With no slices at all every one is `empty`.
+fn fill_gaps(cusps: [Option<(f32, f32)>; HUES], empty: (f32, f32)) -> [(f32, f32); HUES] {
+	let mut filled = [empty; HUES];
+
+	for (idx, out) in filled.iter_mut().enumerate() {
+		if let Some(cusp) = cusps[idx] {
+			*out = cusp;
+			continue;
+		}
+
+		let before = (1..HUES).find_map(|d| cusps[(idx + HUES - d) % HUES].map(|c| (d, c)));
+		let after = (1..HUES).find_map(|d| cusps[(idx + d) % HUES].map(|c| (d, c)));
+		if let (Some((db, before)), Some((da, after))) = (before, after) {
+			*out = lerp(before, after, db as f32 / (db + da) as f32);
+		}
+	}
+
+	filled
+}
+
+/// OKLab as lightness, chroma, and hue in radians from 0 to 2π
+#[derive(Copy, Clone, Debug)]
+struct Lch {
+	l: f32,
+	c: f32,
+	h: f32,
+}
+
+impl Lch {
+	fn from_rgb(rgb: RGB8) -> Self {
+		let lab = Oklab::from_rgb(rgb);
+		Self {
+			l: lab.l,
+			c: lab.a.hypot(lab.b),
+			h: lab.b.atan2(lab.a).rem_euclid(TAU),
+		}
+	}
+
+	fn to_rgb(self) -> RGB8 {
+		Oklab {
+			l: self.l,
+			a: self.c * self.h.cos(),
+			b: self.c * self.h.sin(),
+		}
+		.to_rgb()
+	}
+}
+
+/// Moves the colours of one image into the gamut of a palette.
+#[derive(Clone, Debug)]
+pub(crate) struct Projection {
+	intent: Intent,
+	palette: Gamut,
+	/// The gamut of the image, needed by the intents that look at the whole
+	/// image rather than a colour at a time
+	image: Gamut,
+}
+
+impl Projection {
+	/// `colours` are the colours of the image. They only need to be there once
+	/// each.
+	pub(crate) fn new(intent: Intent, palette: &[RGB8], colours: &[RGB8]) -> Self {
+		let image = match intent {
+			Intent::RelativeColorimetric => Gamut::of(&[]),
+			Intent::Perceptual | Intent::Saturation => Gamut::of(colours),
+		};
+
+		Self {
+			intent,
+			palette: Gamut::of(palette),
+			image,
+		}
+	}
+
+	/// The colour to look for in the palette instead of `colour`
+	pub(crate) fn apply(&self, colour: RGB8) -> RGB8 {
+		let lch = Lch::from_rgb(colour);
+
+		let moved = match self.intent {
+			Intent::RelativeColorimetric => lch,
+			Intent::Perceptual => self.perceptual(lch),
+			Intent::Saturation => {
+				let (to_cusp_l, most) = self.palette.cusp(lch.h);
+				let (cusp_l, image_most) = self.image.cusp(lch.h);
+				if image_most < GREY {
+					lch
+				} else {
+					// the more saturated, the closer to the lightness of the
+					// palette's cusp
+					let saturation = (lch.c / image_most).min(1.0);
+					Lch {
+						l: lch.l + (to_cusp_l - cusp_l) * saturation,
+						c: lch.c * most / image_most,
+						h: lch.h,
+					}
+				}
+			}
+		};
+
+		// whatever's still outside is clipped
+		self.palette.clip(moved).to_rgb()
+	}
+
+	/// Move a colour from the image's triangle for its hue to the palette's, so
+	/// the darkest, lightest, and most saturated colours of the image land on
+	/// the palette's. It only squeezes colours in, it doesn't spread them out.
+	fn perceptual(&self, lch: Lch) -> Lch {
+		let (low, high) = self.image.lightness;
+		let to_low = low.max(self.palette.lightness.0);
+		let to_high = high.min(self.palette.lightness.1);
+		if high <= low || to_high <= to_low {
+			return lch;
+		}
+
+		let (cusp_l, cusp_c) = self.image.cusp(lch.h);
+		let (to_cusp_l, _) = self.palette.cusp(lch.h);
+		let to_cusp_l = to_cusp_l.clamp(to_low, to_high);
+
+		// greys only follow the range of lightness, and the more saturated a
+		// colour is the more it follows the cusps
+		let straight = to_low + (lch.l - low) / (high - low) * (to_high - to_low);
+		let through_cusp = if lch.l <= cusp_l && cusp_l > low {
+			to_low + (lch.l - low) / (cusp_l - low) * (to_cusp_l - to_low)
+		} else if lch.l > cusp_l && high > cusp_l {
+			to_cusp_l + (lch.l - cusp_l) / (high - cusp_l) * (to_high - to_cusp_l)
+		} else {
+			to_cusp_l
+		};
+		let saturation = if cusp_c >= GREY {
+			(lch.c / cusp_c).min(1.0)
+		} else {
+			0.0
+		};
+		let l = straight + (through_cusp - straight) * saturation;
+
+		let most = self.palette.chroma(l, lch.h);
+		let image_most = self.image.chroma(lch.l, lch.h);
+		let c = if image_most > most {
+			lch.c * most / image_most
+		} else {
+			lch.c
+		};
+
+		Lch { l, c, h: lch.h }
+	}
+}
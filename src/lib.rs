@@ -1,35 +1,140 @@
-use std::collections::HashSet;
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+use std::{
+	borrow::Cow,
+	collections::{HashMap, HashSet},
+	mem::MaybeUninit,
+};
 
 use rgb::{ComponentBytes, FromSlice, RGB8};
 
+/// Enter a `tracing` span that lasts until the end of the enclosing block.
+/// Without the tracing feature this is nothing at all.
+macro_rules! span {
+	($name:literal $(, $($field:tt)*)?) => {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::info_span!($name $(, $($field)*)?).entered();
+	};
+}
+
+mod adjust;
+pub mod backend;
+pub mod bracket;
+pub mod cancel;
+pub mod delta;
+pub mod depth;
 pub mod difference;
-#[cfg(not(feature = "simd-kmeans"))]
+pub mod dither;
+mod error;
+pub mod gray;
+pub mod hash;
+pub mod intent;
+pub mod lut;
+pub mod metrics;
 mod nih_kmeans;
+mod oklab;
+pub mod palette;
+pub mod pipeline;
+pub mod progress;
+pub mod regions;
+mod rng;
+mod saliency;
+pub mod sampling;
 pub mod selection;
+pub mod shared;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod sweep;
+pub mod theme;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use adjust::Constraint;
+use backend::{ColourMap, MapBackend};
+use cancel::CancelToken;
+use difference::{ColorDifference, DiffFn};
+pub use error::Error;
+use metrics::{Measurements, Target};
+use progress::{ProgressFn, Stage};
+use sampling::{Sampler, Sampling, SamplingReport};
+use selection::{Fixed, Selector, SortSelect};
+use theme::Theme;
+
+/// The version of colorsquash, for writing down what made a palette alongside
+/// it so it can be made the same way again.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Select a palette of up to `max_colors` for an image and map it, returning
+/// the palette and an index into it for every pixel. It's what squash does
+/// without any arguments: [SortSelect] with its default tolerance and
+/// [difference::rgb]. Build a [Squasher] for anything else.
+///
+/// `max_colors` is clamped to 1 through 256.
+pub fn squash<'a>(image: impl Into<ImageData<'a>>, max_colors: usize) -> (Vec<RGB8>, Vec<u8>) {
+	let image = image.into();
+	let mut squasher = SquasherBuilder::<u8>::new()
+		.max_colors((max_colors.clamp(1, 256) - 1) as u8)
+		.selector(SortSelect::default())
+		.build(&image);
 
-use difference::DiffFn;
-use selection::Selector;
+	let mut indices = vec![0; image.pixels.len()];
+	squasher.map(image, &mut indices);
 
-pub struct SquasherBuilder<T: Count> {
-	scale: u8,
+	(squasher.palette().to_vec(), indices)
+}
+
+/// Builds a [Squasher]. The `D` parameter is the difference function used
+/// while mapping; see [SquasherBuilder::mapper].
+pub struct SquasherBuilder<T: Count, D = Box<DiffFn>> {
+	sampling: Sampling,
 	max_colours: T,
-	difference_fn: Box<DiffFn>,
+	difference_fn: D,
 	selector: Option<Box<dyn Selector + 'static>>,
+	pinned: Vec<RGB8>,
+	cancel: Option<CancelToken>,
+	progress: Option<Box<ProgressFn>>,
+	constraint: Option<Box<Constraint>>,
+	auto_white_black: Option<f32>,
+	anchors: Vec<RGB8>,
+	anchor_usage: f32,
+	refine: usize,
+	snap_to_image: bool,
+	focus: Vec<Focus>,
+	saliency: f32,
+	target: Option<Target>,
+	layout: MapLayout,
+	backend: MapBackend,
 }
 
 impl<T: Count> SquasherBuilder<T> {
 	// I don't want a default here because, to me anyway, Default implies a
-	// working struct and this would panic build()
+	// working struct and this would panic build() and error try_build()
 	#[allow(clippy::new_without_default)]
 	pub fn new() -> Self {
 		Self {
 			max_colours: T::zero(),
-			scale: 100,
+			sampling: Sampling::All,
 			difference_fn: Box::new(difference::rgb),
 			selector: None,
+			pinned: vec![],
+			cancel: None,
+			progress: None,
+			constraint: None,
+			auto_white_black: None,
+			anchors: vec![],
+			anchor_usage: 0.0,
+			refine: 0,
+			snap_to_image: false,
+			focus: vec![],
+			saliency: 0.0,
+			target: None,
+			layout: MapLayout::Linear,
+			backend: MapBackend::Auto,
 		}
 	}
+}
 
+impl<T: Count, D: ColorDifference> SquasherBuilder<T, D> {
 	/// The max number of colors selected for the palette, minus one.
 	///
 	/// `max_colors(255)` will attempt to make a 256 color palette
@@ -40,162 +145,1407 @@ impl<T: Count> SquasherBuilder<T> {
 
 	/// The percent of pixels to consider while selecting the palette for the
 	/// image. Integer between 1 and 100 (inclusive)
+	///
+	/// This is shorthand for [Sampling::from_scale]
 	pub fn scale(mut self, scale: u8) -> Self {
-		self.scale = scale;
+		self.sampling = Sampling::from_scale(scale);
+		self
+	}
+
+	/// How to pick the pixels considered while selecting the palette. See the
+	/// [sampling] module.
+	pub fn sampling(mut self, sampling: Sampling) -> Self {
+		self.sampling = sampling;
+		self
+	}
+
+	/// The function to use to compare colours while mapping the image.
+	///
+	/// see the [difference] module for functions included with the crate and
+	/// information on implementing your own.
+	pub fn mapper_difference(self, difference: &'static DiffFn) -> SquasherBuilder<T> {
+		self.mapper(Box::new(difference) as Box<DiffFn>)
+	}
+
+	/// Like [SquasherBuilder::mapper_difference] but the difference function
+	/// becomes part of the Squasher's type rather than being called through a
+	/// pointer. This lets the compiler inline it into the mapping loop, which
+	/// can be a lot faster.
+	///
+	/// Any `Fn(&RGB8, &RGB8) -> f32` works, like `difference::redmean`, or you
+	/// can use one of the [ColorDifference] types in the [difference] module
+	/// if you need to name the Squasher's type.
+	pub fn mapper<D2: ColorDifference>(self, difference: D2) -> SquasherBuilder<T, D2> {
+		SquasherBuilder {
+			sampling: self.sampling,
+			max_colours: self.max_colours,
+			difference_fn: difference,
+			selector: self.selector,
+			pinned: self.pinned,
+			cancel: self.cancel,
+			progress: self.progress,
+			constraint: self.constraint,
+			auto_white_black: self.auto_white_black,
+			anchors: self.anchors,
+			anchor_usage: self.anchor_usage,
+			refine: self.refine,
+			snap_to_image: self.snap_to_image,
+			focus: self.focus,
+			saliency: self.saliency,
+			target: self.target,
+			layout: self.layout,
+			backend: self.backend,
+		}
+	}
+
+	pub fn selector(mut self, selector: impl Selector + 'static) -> Self {
+		self.selector = Some(Box::new(selector));
+		self
+	}
+
+	/// Checked while mapping so it can be stopped early. See the [cancel]
+	/// module.
+	pub fn cancel_token(mut self, token: CancelToken) -> Self {
+		self.cancel = Some(token);
+		self
+	}
+
+	/// Called with how far along selecting and mapping are, to show a
+	/// progress bar. See the [progress] module.
+	pub fn progress(mut self, progress: impl Fn(Stage, f32) + 'static) -> Self {
+		self.progress = Some(Box::new(progress));
+		self
+	}
+
+	/// Only allow colours in the palette that pass the test, like colours that
+	/// can be printed or that are darker than some brightness. The selector
+	/// still sees every colour of the image, and any colour it picks that
+	/// isn't allowed is moved to the closest one that is.
+	///
+	/// Pinned colours are always allowed. Mapping isn't affected.
+	pub fn constrain(mut self, constraint: impl Fn(&RGB8) -> bool + 'static) -> Self {
+		self.constraint = Some(Box::new(constraint));
+		self
+	}
+
+	/// Colours that are always in the palette, like the colours of a logo or
+	/// pure black and white. They come first in the palette and the selector
+	/// picks the rest of the colours around them.
+	///
+	/// If there are more pinned colours than the max then only the first max
+	/// colours are kept.
+	pub fn pin_colors(mut self, colors: &[RGB8]) -> Self {
+		self.pinned = colors.to_vec();
+		self
+	}
+
+	/// Pin pure white if at least `coverage` of the image, between 0.0 and 1.0,
+	/// is nearly white, and the same for black. Without this a document or a
+	/// screenshot can come out with its white as a slightly grey or tinted
+	/// average. 0.05 is a good start.
+	pub fn auto_white_black(mut self, coverage: f32) -> Self {
+		self.auto_white_black = Some(coverage);
+		self
+	}
+
+	/// Keep entries of an earlier palette, like the last release of a sprite
+	/// sheet, at the same index if at least `min_usage` of the image, between
+	/// 0.0 and 1.0, is closest to them. Only the rest of the palette is selected
+	/// again, so tools that refer to palette indices keep working.
+	///
+	/// Pinned colours that aren't anchors and the newly selected colours fill
+	/// the indices of the anchors that weren't kept, and then go after them.
+	/// An index nothing new fills keeps its old anchor.
+	pub fn anchor(mut self, palette: impl Into<Vec<RGB8>>, min_usage: f32) -> Self {
+		self.anchors = palette.into();
+		self.anchor_usage = min_usage;
+		self
+	}
+
+	/// Polish the palette the selector picks with this many iterations of
+	/// k-means, weighted by how often each colour appears. Each entry moves to
+	/// the average of the colours closest to it, which for selectors that pick
+	/// colours straight out of the image, like [SortSelect](selection::SortSelect),
+	/// usually lowers the error a lot. Pinned colours and anchors stay put.
+	///
+	/// Every iteration costs about as much as mapping each colour of the image
+	/// once. A few are usually enough. The default is 0.
+	pub fn refine(mut self, iterations: usize) -> Self {
+		self.refine = iterations;
+		self
+	}
+
+	/// Replace every selected colour with the closest colour that's actually
+	/// in the image, preferring the most common of equally close colours.
+	/// Selectors that average, like [Kmeans](selection::Kmeans), can pick
+	/// colours that never appear, which for pixel art and logos means no pixel
+	/// comes out exactly right. This happens after [SquasherBuilder::refine].
+	pub fn snap_to_image(mut self, snap: bool) -> Self {
+		self.snap_to_image = snap;
+		self
+	}
+
+	/// How the colour map is laid out in memory, see [MapLayout]. The default
+	/// is [MapLayout::Linear]. It's only used by the [MapBackend]s with a table.
+	pub fn map_layout(mut self, layout: MapLayout) -> Self {
+		self.layout = layout;
+		self
+	}
+
+	/// How the Squasher finds the palette entry for each pixel while mapping,
+	/// see the [backend] module. The default is [MapBackend::Auto].
+	pub fn map_backend(mut self, backend: MapBackend) -> Self {
+		self.backend = backend;
+		self
+	}
+
+	/// Count the pixels in a rectangle `weight` times each when selecting the
+	/// palette, so it spends more of its colours on that part of the image. Call
+	/// it again for more rectangles; where they overlap the weights multiply,
+	/// and they multiply with [ImageData::with_weights] too.
+	///
+	/// The position and size are in pixels. Where the rectangle is depends on
+	/// the width of the image, so it's only used for images that have one, from
+	/// [ImageData::with_stride].
+	pub fn focus_region(
+		mut self,
+		x: usize,
+		y: usize,
+		width: usize,
+		height: usize,
+		weight: usize,
+	) -> Self {
+		self.focus.push(Focus {
+			x,
+			y,
+			width,
+			height,
+			weight,
+		});
+		self
+	}
+
+	/// Count pixels that stand out from their neighbours more, so a big flat
+	/// background doesn't take palette entries from small details. Each pixel
+	/// is counted `1 + strength * contrast` times, where the contrast is the
+	/// mean RGB distance to the 8 pixels around it, up to about 441. Something
+	/// between 0.3 and 3 is a good start. The default of 0.0 turns it off.
+	///
+	/// It weights edges and texture, so anti-aliased edges get more colours
+	/// than they would otherwise. Like [SquasherBuilder::focus_region] it needs
+	/// the width of the image, from [ImageData::with_stride], and
+	/// multiplies with the other weights.
+	pub fn saliency(mut self, strength: f32) -> Self {
+		self.saliency = strength.max(0.0);
 		self
 	}
 
-	/// The function to use to compare colours while mapping the image.
-	///
-	/// see the [difference] module for functions included with the crate and
-	/// information on implementing your own.
-	pub fn mapper_difference(mut self, difference: &'static DiffFn) -> Self {
-		self.difference_fn = Box::new(difference);
-		self
+	/// Use as few colours as it takes to meet the target, rather than always
+	/// using [SquasherBuilder::max_colors], which becomes the most it can use.
+	/// Palettes of different sizes are selected and measured with the
+	/// difference function the Squasher maps with, in a binary search, so
+	/// selecting takes about `log2(max_colours)` times as long.
+	///
+	/// The search assumes more colours are never worse, which is nearly true
+	/// for every selector. If even the largest palette doesn't meet the target
+	/// it's used anyway.
+	pub fn quality_target(mut self, target: Target) -> Self {
+		self.target = Some(target);
+		self
+	}
+
+	/// Build a Squasher and select its palette from the image.
+	///
+	/// # Panics
+	/// If no selector was given. See [SquasherBuilder::try_build]
+	pub fn build<'a, Img>(self, image: Img) -> Squasher<T, D>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_build(image) {
+			Ok(squasher) => squasher,
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [SquasherBuilder::build] but returns [Error::NoSelector] if no
+	/// selector was given instead of panicking.
+	pub fn try_build<'a, Img>(self, image: Img) -> Result<Squasher<T, D>, Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let mut squasher = self.try_build_empty()?;
+		squasher.recolor(image);
+
+		Ok(squasher)
+	}
+
+	/// Build a Squasher without selecting a palette, for when the image comes
+	/// later through [Squasher::feed] or [Squasher::recolor]. The palette is
+	/// empty until then.
+	///
+	/// # Panics
+	/// If no selector was given. See [SquasherBuilder::try_build_empty]
+	pub fn build_empty(self) -> Squasher<T, D> {
+		match self.try_build_empty() {
+			Ok(squasher) => squasher,
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [SquasherBuilder::build_empty] but returns [Error::NoSelector] if no
+	/// selector was given instead of panicking.
+	pub fn try_build_empty(self) -> Result<Squasher<T, D>, Error> {
+		let selector = self.selector.ok_or(Error::NoSelector)?;
+		let map = ColourMap::new(self.backend, self.layout);
+		let mut squasher = Squasher::from_parts(
+			self.sampling,
+			self.max_colours,
+			self.difference_fn,
+			selector,
+			map,
+		);
+		squasher.pinned = self.pinned;
+		squasher.cancel = self.cancel;
+		squasher.progress = self.progress;
+		squasher.constraint = self.constraint;
+		squasher.auto_white_black = self.auto_white_black;
+		squasher.anchors = self.anchors;
+		squasher.anchor_usage = self.anchor_usage;
+		squasher.refine = self.refine;
+		squasher.snap_to_image = self.snap_to_image;
+		squasher.focus = self.focus;
+		squasher.saliency = self.saliency;
+		squasher.target = self.target;
+
+		Ok(squasher)
+	}
+
+	/// Build a Squasher that uses the given palette instead of selecting one
+	/// from an image. The max colours, selector, and pinned colours set on the
+	/// builder are ignored. See [Squasher::from_palette].
+	///
+	/// With a lazy [MapBackend], like the default, only colours mapped with
+	/// [Squasher::map] are known to [Squasher::map_no_recolor].
+	///
+	/// # Panics
+	/// If the palette has more colours than `T` can index, like 300 for a
+	/// `Squasher<u8>`. See [SquasherBuilder::try_build_with_palette]
+	pub fn build_with_palette(self, palette: impl Into<Vec<RGB8>>) -> Squasher<T, D> {
+		match self.try_build_with_palette(palette) {
+			Ok(squasher) => squasher,
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [SquasherBuilder::build_with_palette] but returns an error if the
+	/// palette has more colours than `T` can index instead of panicking.
+	pub fn try_build_with_palette(
+		self,
+		palette: impl Into<Vec<RGB8>>,
+	) -> Result<Squasher<T, D>, Error> {
+		let palette = palette.into();
+		check_palette::<T>(&palette)?;
+		let map = ColourMap::new(self.backend, self.layout);
+		let mut squasher = Squasher::from_parts(
+			self.sampling,
+			T::from_usize(palette.len().saturating_sub(1)),
+			self.difference_fn,
+			Box::new(Fixed(palette.clone())),
+			map,
+		);
+		squasher.palette = palette;
+		squasher.cancel = self.cancel;
+		squasher.progress = self.progress;
+		squasher.palette_changed();
+
+		Ok(squasher)
+	}
+}
+
+/// Whether every colour of the palette has an index that fits in a `T`
+fn check_palette<T: Count>(palette: &[RGB8]) -> Result<(), Error> {
+	if palette.len() > T::max_colors() {
+		Err(Error::PaletteTooLarge {
+			len: palette.len(),
+			max: T::max_colors(),
+		})
+	} else {
+		Ok(())
+	}
+}
+
+/// The `D` parameter is the difference function used while mapping. It
+/// defaults to a boxed dynamic function; see [SquasherBuilder::mapper].
+pub struct Squasher<T, D = Box<DiffFn>> {
+	// one less than the max colours as you can't have a zero colour image.
+	max_colours_min1: T,
+	palette: Vec<RGB8>,
+	map: ColourMap<T>,
+	sampling: Sampling,
+	// counts of the sampled pixels given to feed() that haven't been selected
+	// from yet
+	histogram: HashMap<RGB8, usize>,
+	// where the sampling is up to in the pixels fed, so it carries on from one
+	// chunk to the next. None until something is fed
+	sampler: Option<Sampler>,
+	selector: Box<dyn Selector + 'static>,
+	// colours that are always in the palette. see SquasherBuilder::pin_colors
+	pinned: Vec<RGB8>,
+	cancel: Option<CancelToken>,
+	// see SquasherBuilder::progress
+	progress: Option<Box<ProgressFn>>,
+	// see SquasherBuilder::constrain
+	constraint: Option<Box<Constraint>>,
+	// see SquasherBuilder::auto_white_black
+	auto_white_black: Option<f32>,
+	// see SquasherBuilder::anchor
+	anchors: Vec<RGB8>,
+	anchor_usage: f32,
+	// see SquasherBuilder::refine
+	refine: usize,
+	// see SquasherBuilder::snap_to_image
+	snap_to_image: bool,
+	// see SquasherBuilder::focus_region
+	focus: Vec<Focus>,
+	// see SquasherBuilder::saliency
+	saliency: f32,
+	// see SquasherBuilder::quality_target
+	target: Option<Target>,
+	difference_fn: D,
+}
+
+/// A rectangle of the image whose pixels count more, see
+/// [SquasherBuilder::focus_region]
+#[derive(Copy, Clone, Debug)]
+struct Focus {
+	x: usize,
+	y: usize,
+	width: usize,
+	height: usize,
+	weight: usize,
+}
+
+impl Focus {
+	fn contains(&self, x: usize, y: usize) -> bool {
+		(self.x..self.x.saturating_add(self.width)).contains(&x)
+			&& (self.y..self.y.saturating_add(self.height)).contains(&y)
+	}
+}
+
+impl<T: Count> Squasher<T> {
+	/// Creates a new squasher and allocates a new color map. A color map
+	/// contains every 24-bit color and ends up with an amount of memory
+	/// equal to `16MB * std::mem::size_of(T)`.
+	pub fn new<'a, Img>(
+		max_colors_minus_one: T,
+		selector: impl Selector + 'static,
+		buffer: Img,
+	) -> Self
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let map = ColourMap::new(MapBackend::Auto, MapLayout::Linear);
+		let mut this = Self::from_parts(
+			Sampling::All,
+			max_colors_minus_one,
+			Box::new(difference::rgb),
+			Box::new(selector),
+			map,
+		);
+		this.recolor(buffer);
+
+		this
+	}
+
+	/// Creates a squasher for a palette you already have, like a console's or a
+	/// brand's colours, skipping selection entirely. The constants in
+	/// [palette::fixed] work here.
+	///
+	/// Every colour is mapped up front, so [Squasher::map_no_recolor] works
+	/// straight away. That's a [MapBackend::Lut], which takes a while to fill
+	/// for a large palette, or a [MapBackend::KdTree] where there isn't the
+	/// memory for a table.
+	///
+	/// Recoloring keeps the same palette. Use [SquasherBuilder::build_with_palette]
+	/// to pick the difference function or the backend used while mapping.
+	///
+	/// # Panics
+	/// If the palette has more colours than `T` can index. See
+	/// [SquasherBuilder::try_build_with_palette]
+	pub fn from_palette(palette: impl Into<Vec<RGB8>>) -> Self {
+		let palette = palette.into();
+
+		// the lazy backends only know colours of images given to map(), and a
+		// fixed palette might never be. the tree is exact for difference::rgb
+		let backend = match MapBackend::Auto.resolve::<T>() {
+			MapBackend::LazyLut => MapBackend::Lut,
+			MapBackend::Hash => MapBackend::KdTree,
+			backend => backend,
+		};
+
+		SquasherBuilder::new()
+			.map_backend(backend)
+			.build_with_palette(palette)
+	}
+
+	/// [Squasher::from_palette] for a palette of `palette` crate colours
+	#[cfg(feature = "palette")]
+	pub fn from_srgb_palette(palette: &[::palette::Srgb<u8>]) -> Self {
+		Self::from_palette(::palette::cast::into_component_slice(palette).as_rgb())
+	}
+
+	pub fn builder() -> SquasherBuilder<T> {
+		SquasherBuilder::new()
+	}
+}
+
+impl<T: Count, D: ColorDifference> Squasher<T, D> {
+	/// Create a new palette from the colours in the given image.
+	pub fn recolor<'a, Img>(&mut self, image: Img)
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		self.clear_fed();
+		self.feed(image);
+		self.finalize_palette();
+	}
+
+	/// Create one palette from the colours of several images, like the frames
+	/// of an animation or the sprites of a sheet, so they can all be mapped to
+	/// it. Every pixel counts the same whichever image it's in, so a big image
+	/// has more say than a small one; see [Squasher::recolor_bracketed] for
+	/// images that should count the same.
+	pub fn recolor_many(&mut self, images: &[ImageData]) {
+		self.clear_fed();
+		for image in images {
+			self.feed(image.clone());
+		}
+		self.finalize_palette();
+	}
+
+	/// Like [Squasher::recolor] but returns an error if the selector didn't pick
+	/// any colours, which happens when the image is empty. Mapping with an empty
+	/// palette gives meaningless indices.
+	///
+	/// Also returns [Error::Cancelled] if the cancel token was cancelled, in
+	/// which case the palette is whatever the selector had picked when it
+	/// stopped.
+	pub fn try_recolor<'a, Img>(&mut self, image: Img) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		self.recolor(image);
+
+		if self.cancelled() {
+			Err(Error::Cancelled)
+		} else if self.palette.is_empty() {
+			Err(Error::EmptyPalette)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Like [Squasher::recolor], but if the image is a near-duplicate of one
+	/// already in the cache, that palette is used instead of selecting a new
+	/// one. Otherwise the newly selected palette is added to the cache.
+	///
+	/// # Returns
+	/// True if a cached palette was used
+	pub fn recolor_cached<'a, Img>(
+		&mut self,
+		image: Img,
+		width: usize,
+		height: usize,
+		cache: &mut hash::PaletteCache,
+	) -> bool
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let data = image.into();
+		let hash = hash::dhash(&data, width, height);
+
+		match cache.get(hash) {
+			Some(palette) => {
+				self.palette = palette.to_vec();
+				self.palette_changed();
+				true
+			}
+			None => {
+				self.recolor(data);
+				cache.insert(hash, self.palette.clone());
+				false
+			}
+		}
+	}
+
+	/// Add part of an image, like a scanline or a tile, to the pixels the
+	/// palette will be selected from. Call [Squasher::finalize_palette] once
+	/// everything has been fed.
+	///
+	/// Only a count of each colour picked by the [Sampling] is kept, so a large
+	/// image can be decoded and fed a row at a time without the whole thing
+	/// ever being in memory. The sampling carries on from one chunk to the
+	/// next, so it picks the same pixels however the image is split up.
+	pub fn feed<'a, Img>(&mut self, chunk: Img)
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData {
+			pixels: rgb,
+			weights,
+			width,
+		} = chunk.into();
+		span!("histogram", pixels = rgb.len());
+
+		let focus = width.filter(|_| !self.focus.is_empty());
+		let saliency = width.filter(|_| self.saliency > 0.0);
+		let sampler = self
+			.sampler
+			.get_or_insert_with(|| Sampler::new(self.sampling));
+		if weights.is_none() && focus.is_none() && saliency.is_none() {
+			for idx in sampler.indices(rgb.len()) {
+				*self.histogram.entry(rgb[idx]).or_default() += 1;
+			}
+			return;
+		}
+
+		for idx in sampler.indices(rgb.len()) {
+			let mut weight = weights.as_ref().map_or(1, |weights| weights[idx] as usize);
+			if let Some(width) = focus {
+				let (x, y) = (idx % width, idx / width);
+				for region in self.focus.iter().filter(|region| region.contains(x, y)) {
+					weight = weight.saturating_mul(region.weight);
+				}
+			}
+			if let Some(width) = saliency {
+				let salient = saliency::weight(&rgb, width, idx, self.saliency);
+				weight = weight.saturating_mul(salient);
+			}
+
+			if weight > 0 {
+				*self.histogram.entry(rgb[idx]).or_default() += weight;
+			}
+		}
+	}
+
+	/// Like [Squasher::feed] but for pixels that aren't in a slice, like ones
+	/// being decoded or generated, so they don't have to be collected first.
+	/// The [Sampling] is applied as they go by. There's no width or weights, so
+	/// focus regions and saliency don't apply.
+	pub fn feed_pixels<I>(&mut self, pixels: I)
+	where
+		I: IntoIterator<Item = RGB8>,
+	{
+		span!("histogram");
+		let sampler = self
+			.sampler
+			.get_or_insert_with(|| Sampler::new(self.sampling));
+		for px in pixels.into_iter().filter(|_| sampler.keep()) {
+			*self.histogram.entry(px).or_default() += 1;
+		}
+	}
+
+	/// Like [Squasher::recolor] but for pixels that aren't in a slice, see
+	/// [Squasher::feed_pixels].
+	pub fn recolor_pixels<I>(&mut self, pixels: I)
+	where
+		I: IntoIterator<Item = RGB8>,
+	{
+		self.clear_fed();
+		self.feed_pixels(pixels);
+		self.finalize_palette();
+	}
+
+	/// Like [Squasher::feed] for several versions of the same scene, counted so
+	/// none of them takes the whole palette however many pixels it has; see the
+	/// [bracket] module. Each is sampled and weighted like any image fed.
+	pub fn feed_bracketed(&mut self, brackets: &[bracket::Bracket]) {
+		let saved = std::mem::take(&mut self.histogram);
+		let sampler = self.sampler.take();
+
+		let mut counted = Vec::with_capacity(brackets.len());
+		for bracket in brackets {
+			self.feed(&bracket.image);
+			counted.push((std::mem::take(&mut self.histogram), bracket.weight));
+			self.sampler = None;
+		}
+
+		self.histogram = saved;
+		self.sampler = sampler;
+		self.feed_histogram(bracket::merge(counted));
+	}
+
+	/// Select a new palette shared by several versions of the same scene, see
+	/// [Squasher::feed_bracketed].
+	pub fn recolor_bracketed(&mut self, brackets: &[bracket::Bracket]) {
+		self.clear_fed();
+		self.feed_bracketed(brackets);
+		self.finalize_palette();
+	}
+
+	/// Add colours that have already been counted, like a histogram saved from
+	/// [Squasher::histogram], to the pixels the palette will be selected from.
+	/// The sampling isn't applied to them.
+	pub fn feed_histogram<I>(&mut self, colors: I)
+	where
+		I: IntoIterator<Item = (RGB8, usize)>,
+	{
+		for (colour, count) in colors {
+			*self.histogram.entry(colour).or_default() += count;
+		}
+	}
+
+	/// The count of each colour fed since the last time the palette was
+	/// selected.
+	pub fn histogram(&self) -> &HashMap<RGB8, usize> {
+		&self.histogram
+	}
+
+	/// Select a new palette from everything given to [Squasher::feed] since the
+	/// last time the palette was selected.
+	pub fn finalize_palette(&mut self) {
+		self.palette = self.select_from_histogram();
+		self.palette_changed();
+	}
+
+	/// Select a palette from everything fed, without touching the map
+	fn select_from_histogram(&mut self) -> Vec<RGB8> {
+		span!("select", colors = self.histogram.len());
+		self.report(Stage::Selecting, 0.0);
+		let colors = selection::sort(std::mem::take(&mut self.histogram));
+		self.sampler = None;
+		let max_colours = self.max_colours_min1.as_usize() + 1;
+
+		let palette = match self.target {
+			None => self.select_palette(&colors, max_colours),
+			Some(target) => self.smallest_palette(&colors, max_colours, target),
+		};
+		self.report(Stage::Selecting, 1.0);
+
+		palette
+	}
+
+	/// Binary search for the fewest colours that meet the target, see
+	/// [SquasherBuilder::quality_target].
+	fn smallest_palette(
+		&mut self,
+		colors: &[(RGB8, usize)],
+		max_colours: usize,
+		target: Target,
+	) -> Vec<RGB8> {
+		let mut best = self.select_palette(colors, max_colours);
+		let measured = Measurements::new(colors, &best, &self.difference_fn);
+		if !target.is_met(&measured) {
+			return best;
+		}
+
+		let (mut low, mut high) = (1, best.len().min(max_colours));
+		while low < high && !self.cancelled() {
+			let middle = low + (high - low) / 2;
+			let palette = self.select_palette(colors, middle);
+
+			let measured = Measurements::new(colors, &palette, &self.difference_fn);
+			if target.is_met(&measured) {
+				high = middle.min(palette.len());
+				best = palette;
+			} else {
+				low = middle + 1;
+			}
+		}
+
+		best
+	}
+
+	/// Select a palette of up to `max_colours` from the sorted colours, with
+	/// the pinned colours, anchors, and adjustments from the builder.
+	fn select_palette(&mut self, colors: &[(RGB8, usize)], max_colours: usize) -> Vec<RGB8> {
+		let mut pinned = self.pinned.clone();
+		if let Some(coverage) = self.auto_white_black {
+			for extreme in adjust::white_black(colors, coverage) {
+				if !pinned.contains(&extreme) {
+					pinned.push(extreme);
+				}
+			}
+		}
+
+		// anchors still in use keep their place, see SquasherBuilder::anchor
+		let kept = adjust::used_anchors(
+			&self.anchors[..self.anchors.len().min(max_colours)],
+			colors,
+			self.anchor_usage,
+			&self.difference_fn,
+		);
+
+		// the colours that are in the palette whatever the selector picks
+		let mut fixed: Vec<RGB8> = kept.iter().flatten().copied().collect();
+		let kept_count = fixed.len();
+		for colour in pinned {
+			if !fixed.contains(&colour) {
+				fixed.push(colour);
+			}
+		}
+		fixed.truncate(max_colours);
+
+		let remaining = max_colours - fixed.len();
+		let mut selected = vec![];
+		if remaining > 0 {
+			selected = self.selector.select(remaining, colors);
+			if self.refine > 0 {
+				selected =
+					adjust::refine(selected, &fixed, colors, self.refine, &self.difference_fn);
+			}
+			if self.snap_to_image {
+				selected = adjust::snap(selected, &fixed, colors, &self.difference_fn);
+			}
+			if let Some(constraint) = &self.constraint {
+				selected = adjust::constrain(selected, colors, constraint, &self.difference_fn);
+			}
+
+			selected.retain(|c| !fixed.contains(c));
+			selected.truncate(remaining);
+		}
+
+		let mut new = fixed.split_off(kept_count).into_iter().chain(selected);
+		let mut palette: Vec<RGB8> = kept
+			.iter()
+			.zip(&self.anchors)
+			.map(|(kept, anchor)| kept.or_else(|| new.next()).unwrap_or(*anchor))
+			.collect();
+		palette.extend(new);
+
+		palette
+	}
+
+	/// Estimate how much the palette depends on which pixels the [Sampling]
+	/// happened to pick. A palette is selected from `rounds` samples of the image,
+	/// each as large as the current sampling takes but of different pixels,
+	/// and then they're compared. Entries within `tolerance` of each other, as
+	/// measured by the difference function, count as the same.
+	///
+	/// The palette and anything fed but not finalized are left alone.
+	pub fn sampling_confidence<'a, Img>(
+		&mut self,
+		image: Img,
+		rounds: usize,
+		tolerance: f32,
+	) -> SamplingReport
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let image = image.into();
+		let fraction = self.sampling.fraction();
+		if fraction >= 1.0 {
+			// every round would see the same pixels
+			return SamplingReport {
+				fraction,
+				confidence: 1.0,
+				spread: 0.0,
+			};
+		}
+
+		let sampling = self.sampling;
+		let histogram = std::mem::take(&mut self.histogram);
+		let sampler = self.sampler.take();
+
+		let mut palettes = Vec::with_capacity(rounds);
+		for round in 0..rounds as u64 {
+			self.sampling = sampling.reseeded(round);
+			self.feed(&image);
+			palettes.push(self.select_from_histogram());
+
+			if self.cancelled() {
+				break;
+			}
+		}
+
+		self.sampling = sampling;
+		self.histogram = histogram;
+		self.sampler = sampler;
+
+		SamplingReport::compare(fraction, &palettes, tolerance, &self.difference_fn)
+	}
+
+	/// Like [Squasher::recolor] but first keeps doubling the fraction of pixels
+	/// sampled until [Squasher::sampling_confidence] is at least `confidence`,
+	/// or every pixel is used. The sampling is changed to whatever was enough.
+	///
+	/// # Returns
+	/// The report for the sampling that was used
+	pub fn recolor_stable<'a, Img>(
+		&mut self,
+		image: Img,
+		confidence: f32,
+		rounds: usize,
+		tolerance: f32,
+	) -> SamplingReport
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let image = image.into();
+
+		let report = loop {
+			let report = self.sampling_confidence(&image, rounds, tolerance);
+			if report.confidence >= confidence || report.fraction >= 1.0 || self.cancelled() {
+				break report;
+			}
+
+			let fraction = report.fraction * 2.0;
+			self.sampling = if fraction >= 1.0 {
+				Sampling::All
+			} else {
+				Sampling::Stratified { fraction, seed: 0 }
+			};
+		};
+
+		self.recolor(image);
+		report
+	}
+
+	/// A number between 1 and 100 (inclusive) for how many pixels of the image
+	/// to consider when selecting the palette.
+	pub fn set_scale(&mut self, scale: u8) {
+		self.set_sampling(Sampling::from_scale(scale));
+	}
+
+	/// How to pick the pixels considered while selecting the palette.
+	///
+	/// Pixels fed after this are sampled as if they were the start of a new
+	/// image.
+	pub fn set_sampling(&mut self, sampling: Sampling) {
+		self.sampling = sampling;
+		self.sampler = None;
+	}
+
+	/// Replace the palette without selecting one. The selector still picks the
+	/// palette on the next recolor.
+	///
+	/// Colours already in the map keep their old index, so use
+	/// [Squasher::map] rather than [Squasher::map_no_recolor] afterwards.
+	///
+	/// # Panics
+	/// If the palette has more colours than `T` can index. See
+	/// [Squasher::try_set_palette]
+	pub fn set_palette(&mut self, palette: impl Into<Vec<RGB8>>) {
+		if let Err(e) = self.try_set_palette(palette) {
+			panic!("{e}");
+		}
+	}
+
+	/// Like [Squasher::set_palette] but returns an error if the palette has
+	/// more colours than `T` can index instead of panicking. The palette is
+	/// left as it was.
+	pub fn try_set_palette(&mut self, palette: impl Into<Vec<RGB8>>) -> Result<(), Error> {
+		let palette = palette.into();
+		check_palette::<T>(&palette)?;
+
+		self.palette = palette;
+		self.palette_changed();
+		Ok(())
+	}
+
+	/// See [SquasherBuilder::cancel_token]
+	pub fn set_cancel_token(&mut self, token: Option<CancelToken>) {
+		self.cancel = token;
+	}
+
+	/// Forget everything fed since the palette was last selected
+	fn clear_fed(&mut self) {
+		self.histogram.clear();
+		self.sampler = None;
+	}
+
+	fn cancelled(&self) -> bool {
+		self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+	}
+
+	fn report(&self, stage: Stage, done: f32) {
+		if let Some(progress) = &self.progress {
+			progress(stage, done);
+		}
+	}
+
+	/// Create a Squasher from parts. Noteably, this leave your palette empty
+	fn from_parts(
+		sampling: Sampling,
+		max_colours_min1: T,
+		difference_fn: D,
+		selector: Box<dyn Selector>,
+		map: ColourMap<T>,
+	) -> Self {
+		Self {
+			max_colours_min1,
+			palette: vec![],
+			map,
+			sampling,
+			histogram: HashMap::new(),
+			sampler: None,
+			pinned: vec![],
+			cancel: None,
+			progress: None,
+			constraint: None,
+			auto_white_black: None,
+			anchors: vec![],
+			anchor_usage: 0.0,
+			refine: 0,
+			snap_to_image: false,
+			focus: vec![],
+			saliency: 0.0,
+			target: None,
+			difference_fn,
+			selector,
+		}
+	}
+
+	/// Take an RGB image buffer and an output buffer. The function will fill
+	/// the output buffer with indexes into the Palette. The output buffer should
+	/// be a third of the size of the image buffer.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map]
+	pub fn map<'a, Img>(&mut self, image: Img, buffer: &mut [T])
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_map(image, buffer) {
+			Ok(()) | Err(Error::Cancelled) => (),
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [Squasher::map] but returns an error if the output buffer is too
+	/// small instead of panicking.
+	///
+	/// If the cancel token is cancelled this stops and returns
+	/// [Error::Cancelled], leaving the output buffer partly written.
+	pub fn try_map<'a, Img>(&mut self, image: Img, buffer: &mut [T]) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
+
+		// We have to map the colours of this image now because it might contain
+		// colours not present in the first image.
+		self.map_image(&rgb)?;
+
+		for (idx, color) in rgb.iter().enumerate() {
+			buffer[idx] = self.map.get(color, &self.difference_fn);
+		}
+
+		Ok(())
+	}
+
+	/// Like [Squasher::map] but with ordered dithering, see the [dither] module.
+	/// `width` is the width of the image in pixels.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map_ordered]
+	pub fn map_ordered<'a, Img>(
+		&mut self,
+		image: Img,
+		width: usize,
+		dither: dither::Ordered,
+		buffer: &mut [T],
+	) where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_map_ordered(image, width, dither, buffer) {
+			Ok(()) | Err(Error::Cancelled) => (),
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [Squasher::map_ordered] but returns an error if the output buffer
+	/// is too small instead of panicking.
+	pub fn try_map_ordered<'a, Img>(
+		&mut self,
+		image: Img,
+		width: usize,
+		dither: dither::Ordered,
+		buffer: &mut [T],
+	) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
+
+		let dithered = dither.apply(&rgb, width);
+		self.map_image(&dithered)?;
+
+		for (idx, color) in dithered.iter().enumerate() {
+			buffer[idx] = self.map.get(color, &self.difference_fn);
+		}
+
+		Ok(())
+	}
+
+	/// Like [Squasher::map] but with error diffusion, see the [dither] module.
+	/// `width` is the width of the image in pixels.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map_diffused]
+	pub fn map_diffused<'a, Img>(
+		&mut self,
+		image: Img,
+		width: usize,
+		dither: dither::Diffusion,
+		buffer: &mut [T],
+	) where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_map_diffused(image, width, dither, buffer) {
+			Ok(()) | Err(Error::Cancelled) => (),
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [Squasher::map_diffused] but returns an error if the output buffer
+	/// is too small instead of panicking.
+	pub fn try_map_diffused<'a, Img>(
+		&mut self,
+		image: Img,
+		width: usize,
+		dither: dither::Diffusion,
+		buffer: &mut [T],
+	) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
+		let width = width.max(1);
+
+		// error carried onto this row and the ones after it. two extra on each
+		// side so the edges don't need checking
+		let taps = dither.kernel.taps();
+		let denominator = dither.kernel.denominator();
+		let mut rows = vec![vec![[0.0f32; 3]; width + 4]; dither.kernel.rows() + 1];
+		// the colours change with the error so the map can't be filled ahead of
+		// time. remember what we've already looked up instead
+		let mut nearest: HashMap<RGB8, usize> = HashMap::new();
+		let strengths = dither
+			.adaptive
+			.map(|adaptive| adaptive.strengths(&rgb, width));
+
+		for (idx, px) in rgb.iter().enumerate() {
+			let x = idx % width;
+			if x == 0 && idx != 0 {
+				rows.rotate_left(1);
+				if let Some(last) = rows.last_mut() {
+					last.fill([0.0; 3]);
+				}
+
+				if self.cancelled() {
+					return Err(Error::Cancelled);
+				}
+				self.report(Stage::Mapping, idx as f32 / rgb.len() as f32);
+			}
+
+			let error = rows[0][x + 2];
+			let wanted = [
+				px.r as f32 + error[0],
+				px.g as f32 + error[1],
+				px.b as f32 + error[2],
+			];
+			let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+			let colour = RGB8::new(channel(wanted[0]), channel(wanted[1]), channel(wanted[2]));
+
+			let index = *nearest
+				.entry(colour)
+				.or_insert_with(|| self.nearest(&colour));
+			buffer[idx] = T::from_usize(index);
+
+			let Some(got) = self.palette.get(index) else {
+				continue;
+			};
+			let strength = strengths.as_ref().map_or(1.0, |strengths| strengths[idx]);
+			let error = dither.scale([
+				(wanted[0] - got.r as f32) * strength,
+				(wanted[1] - got.g as f32) * strength,
+				(wanted[2] - got.b as f32) * strength,
+			]);
+
+			for &(dx, dy, share) in taps {
+				let to = &mut rows[dy][(x + 2).wrapping_add_signed(dx)];
+				for channel in 0..3 {
+					to[channel] += error[channel] * share / denominator;
+				}
+			}
+		}
+
+		self.report(Stage::Mapping, 1.0);
+
+		Ok(())
+	}
+
+	/// Like [Squasher::map] but colours are moved into the gamut of the palette
+	/// first, the way `intent` says. It's meant for fixed palettes, like the
+	/// inks of an e-ink screen, that can't show most of the colours in an image;
+	/// see the [intent] module. The colour map isn't used or filled in.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map_with_intent]
+	pub fn map_with_intent<'a, Img>(&self, image: Img, intent: intent::Intent, buffer: &mut [T])
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_map_with_intent(image, intent, buffer) {
+			Ok(()) | Err(Error::Cancelled) => (),
+			Err(e) => panic!("{e}"),
+		}
+	}
+
+	/// Like [Squasher::map_with_intent] but returns an error if the output
+	/// buffer is too small instead of panicking.
+	pub fn try_map_with_intent<'a, Img>(
+		&self,
+		image: Img,
+		intent: intent::Intent,
+		buffer: &mut [T],
+	) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
+
+		let unique = Self::unique_colors(&rgb);
+		let projection = intent::Projection::new(intent, &self.palette, &unique);
+
+		let mut nearest: HashMap<RGB8, T> = HashMap::with_capacity(unique.len());
+		for chunk in unique.chunks(4096) {
+			if self.cancelled() {
+				return Err(Error::Cancelled);
+			}
+
+			for colour in chunk {
+				let index = self.nearest(&projection.apply(*colour));
+				nearest.insert(*colour, T::from_usize(index));
+			}
+		}
+
+		for (idx, color) in rgb.iter().enumerate() {
+			buffer[idx] = nearest[color];
+		}
+
+		Ok(())
 	}
 
-	pub fn selector(mut self, selector: impl Selector + 'static) -> Self {
-		self.selector = Some(Box::new(selector));
-		self
+	/// Like [Squasher::map] but it doesn't recount the input image. This will
+	/// cause colors the Squasher hasn't seen before to come out as index 0 which
+	/// may be incorrect!
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map_no_recolor]
+	//TODO: gen- Better name?
+	pub fn map_no_recolor<'a, Img>(&self, image: Img, buffer: &mut [T])
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		if let Err(e) = self.try_map_no_recolor(image, buffer) {
+			panic!("{e}");
+		}
 	}
 
-	pub fn build<'a, Img>(self, image: Img) -> Squasher<T>
+	/// Like [Squasher::map_no_recolor] but returns an error if the output
+	/// buffer is too small instead of panicking.
+	pub fn try_map_no_recolor<'a, Img>(&self, image: Img, buffer: &mut [T]) -> Result<(), Error>
 	where
 		Img: Into<ImageData<'a>>,
 	{
-		let mut squasher = Squasher::from_parts(
-			self.scale,
-			self.max_colours,
-			self.difference_fn,
-			self.selector.unwrap(),
-		);
-		squasher.recolor(image);
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
 
-		squasher
-	}
-}
+		for (idx, color) in rgb.iter().enumerate() {
+			buffer[idx] = self.map.get(color, &self.difference_fn);
+		}
 
-pub struct Squasher<T> {
-	// one less than the max colours as you can't have a zero colour image.
-	max_colours_min1: T,
-	palette: Vec<RGB8>,
-	map: Vec<T>,
-	scale: u8,
-	selector: Box<dyn Selector + 'static>,
-	difference_fn: Box<DiffFn>,
-}
+		Ok(())
+	}
 
-impl<T: Count> Squasher<T> {
-	/// Creates a new squasher and allocates a new color map. A color map
-	/// contains every 24-bit color and ends up with an amount of memory
-	/// equal to `16MB * std::mem::size_of(T)`.
-	pub fn new<'a, Img>(
-		max_colors_minus_one: T,
-		selector: impl Selector + 'static,
-		buffer: Img,
-	) -> Self
+	/// Map pixels as they're asked for, like scanlines on their way to an
+	/// encoder, without a buffer for the whole image. Pairs with
+	/// [Squasher::recolor_pixels] for pixels that never are in a slice.
+	///
+	/// A lazy map can't be written to from here, so with one each colour is
+	/// worked out the first time it comes and kept for the rest of the
+	/// iterator. Colours it hasn't seen don't come out as index 0 like they
+	/// would with [Squasher::map_no_recolor].
+	pub fn map_iter<'a, I>(&'a self, pixels: I) -> impl Iterator<Item = T> + 'a
 	where
-		Img: Into<ImageData<'a>>,
+		I: IntoIterator<Item = RGB8>,
+		I::IntoIter: 'a,
 	{
-		let mut this = Self::from_parts(
-			100,
-			max_colors_minus_one,
-			Box::new(difference::rgb),
-			Box::new(selector),
-		);
-		this.recolor(buffer);
+		let lazy = self.map.is_lazy();
+		let mut nearest: HashMap<RGB8, T> = HashMap::new();
 
-		this
+		pixels.into_iter().map(move |colour| {
+			if !lazy {
+				return self.map.get(&colour, &self.difference_fn);
+			}
+
+			*nearest
+				.entry(colour)
+				.or_insert_with(|| T::from_usize(self.nearest(&colour)))
+		})
 	}
 
-	pub fn builder() -> SquasherBuilder<T> {
-		SquasherBuilder::new()
+	/// Like [Squasher::map] but it writes the palette colour of each pixel
+	/// instead of its index, for when what comes out has to be RGB anyway. If
+	/// the palette is empty there's no colour to write and the buffer is left
+	/// alone.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Squasher::try_map_to_rgb]
+	pub fn map_to_rgb<'a, Img>(&mut self, image: Img, buffer: &mut [RGB8])
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		match self.try_map_to_rgb(image, buffer) {
+			Ok(()) | Err(Error::Cancelled) | Err(Error::EmptyPalette) => (),
+			Err(e) => panic!("{e}"),
+		}
 	}
 
-	/// Create a new palette from the colours in the given image.
-	pub fn recolor<'a, Img>(&mut self, image: Img)
+	/// Like [Squasher::map_to_rgb] but returns an error if the output buffer
+	/// is too small instead of panicking, and [Error::EmptyPalette] if there's
+	/// no palette to take colours from, like after recoloring an image whose
+	/// weights are all zero.
+	///
+	/// If the cancel token is cancelled this stops and returns
+	/// [Error::Cancelled] before anything is written.
+	pub fn try_map_to_rgb<'a, Img>(&mut self, image: Img, buffer: &mut [RGB8]) -> Result<(), Error>
 	where
 		Img: Into<ImageData<'a>>,
 	{
-		let data = image.into();
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		Self::check_buffer(&rgb, buffer.len())?;
+		if self.palette.is_empty() {
+			return Err(Error::EmptyPalette);
+		}
+		self.map_image(&rgb)?;
 
-		let nth = 100 / self.scale as usize;
-		let count = data.0.len() / nth;
-		let mut scaled = vec![RGB8::default(); count];
-		#[allow(clippy::needless_range_loop)] // sorry clippy, i like it
-		for idx in 0..count {
-			scaled[idx] = data.0[idx * nth];
+		for (out, color) in buffer.iter_mut().zip(rgb.iter()) {
+			*out = self.palette[self.map.get(color, &self.difference_fn).as_usize()];
 		}
 
-		self.palette = self
-			.selector
-			.select(self.max_colours_min1.as_usize() + 1, ImageData(&scaled));
+		Ok(())
 	}
 
-	/// A number between 1 and 100 (inclusive) for how many pixels of the image
-	/// to consider when selecting the palette.
-	pub fn set_scale(&mut self, scale: u8) {
-		assert!(scale > 0 && scale <= 100);
-		self.scale = scale;
+	/// Replace every pixel of an RGB image with its colour in the palette, so
+	/// it stays RGB but only has the colours of the palette. Bytes past the
+	/// last whole pixel are left alone, and so is the whole image if the
+	/// palette is empty or the cancel token is cancelled.
+	pub fn quantize_in_place(&mut self, image: &mut [u8]) {
+		span!("map", pixels = image.len() / 3);
+		if self.palette.is_empty() || self.map_image(image.as_rgb()).is_err() {
+			return;
+		}
+
+		for color in image.as_rgb_mut() {
+			*color = self.palette[self.map.get(color, &self.difference_fn).as_usize()];
+		}
 	}
 
-	/// Create a Squasher from parts. Noteably, this leave your palette empty
-	fn from_parts(
-		scale: u8,
-		max_colours_min1: T,
-		difference_fn: Box<DiffFn>,
-		selector: Box<dyn Selector>,
-	) -> Self {
-		Self {
-			max_colours_min1,
-			palette: vec![],
-			map: vec![T::zero(); 256 * 256 * 256],
-			scale,
-			difference_fn,
-			selector,
+	fn check_buffer(rgb: &[RGB8], len: usize) -> Result<(), Error> {
+		if len < rgb.len() {
+			Err(Error::BufferTooSmall {
+				needed: rgb.len(),
+				len,
+			})
+		} else {
+			Ok(())
 		}
 	}
 
-	/// Take an RGB image buffer and an output buffer. The function will fill
-	/// the output buffer with indexes into the Palette. The output buffer should
-	/// be a third of the size of the image buffer.
-	pub fn map<'a, Img>(&mut self, image: Img, buffer: &mut [T])
+	/// Like [Squasher::map] but it writes into uninitialised memory, such as a
+	/// buffer owned by the other side of an FFI boundary, so you don't have to
+	/// zero it or copy out of an intermediate Vec.
+	///
+	/// # Returns
+	/// The number of indices written. Every element of `buffer` before this is
+	/// initialised. Nothing is written if the cancel token was cancelled.
+	///
+	/// # Panics
+	/// If the output buffer is too small.
+	pub fn map_uninit<'a, Img>(&mut self, image: Img, buffer: &mut [MaybeUninit<T>]) -> usize
 	where
 		Img: Into<ImageData<'a>>,
 	{
-		let ImageData(rgb) = image.into();
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
 
-		if buffer.len() * 3 < rgb.len() {
-			panic!("output buffer too small to fit indexed image");
+		if let Err(e) = Self::check_buffer(&rgb, buffer.len()) {
+			panic!("{e}");
 		}
 
-		// We have to map the colours of this image now because it might contain
-		// colours not present in the first image.
-		let unique = Self::unique_colors(rgb);
-		self.map_selected(&unique);
-
-		for (idx, color) in rgb.iter().enumerate() {
-			buffer[idx] = self.map[color_index(color)];
+		if self.map_image(&rgb).is_err() {
+			return 0;
 		}
+
+		self.write_uninit(&rgb, buffer)
 	}
 
-	/// Like [Squasher::map] but it doesn't recount the input image. This will
-	/// cause colors the Squasher hasn't seen before to come out as index 0 which
-	/// may be incorrect!
-	//TODO: gen- Better name?
-	pub fn map_no_recolor<'a, Img>(&self, image: Img, buffer: &mut [T])
+	/// Like [Squasher::map_no_recolor] but it writes into uninitialised memory.
+	/// See [Squasher::map_uninit].
+	///
+	/// # Panics
+	/// If the output buffer is too small.
+	pub fn map_no_recolor_uninit<'a, Img>(&self, image: Img, buffer: &mut [MaybeUninit<T>]) -> usize
 	where
 		Img: Into<ImageData<'a>>,
 	{
-		let ImageData(rgb) = image.into();
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
 
-		if buffer.len() * 3 < rgb.len() {
-			panic!("output buffer too small to fit indexed image");
+		if let Err(e) = Self::check_buffer(&rgb, buffer.len()) {
+			panic!("{e}");
 		}
 
-		for (idx, color) in rgb.iter().enumerate() {
-			buffer[idx] = self.map[color_index(color)];
+		self.write_uninit(&rgb, buffer)
+	}
+
+	fn write_uninit(&self, rgb: &[RGB8], buffer: &mut [MaybeUninit<T>]) -> usize {
+		for (slot, color) in buffer.iter_mut().zip(rgb) {
+			slot.write(self.map.get(color, &self.difference_fn));
 		}
+
+		rgb.len()
+	}
+
+	/// Select a palette for an image from the `image` crate and map it,
+	/// returning an index for every pixel. Images that aren't 8-bit RGB are
+	/// converted to it first, which drops alpha and rounds deeper colour to
+	/// 8 bits.
+	#[cfg(feature = "image")]
+	pub fn quantize_image(&mut self, image: &image::DynamicImage) -> Vec<T> {
+		let converted;
+		let rgb = match image.as_rgb8() {
+			Some(rgb) => rgb,
+			None => {
+				converted = image.to_rgb8();
+				&converted
+			}
+		};
+
+		self.recolor(rgb);
+		let mut indices = vec![T::zero(); rgb.as_raw().len() / 3];
+		self.map(rgb, &mut indices);
+		indices
+	}
+
+	/// The palette as `palette` crate colours, without copying it
+	#[cfg(feature = "palette")]
+	pub fn palette_srgb(&self) -> &[::palette::Srgb<u8>] {
+		::palette::cast::from_component_slice(self.palette.as_bytes())
 	}
 
 	#[cfg(feature = "gifed")]
@@ -203,33 +1553,274 @@ impl<T: Count> Squasher<T> {
 		self.palette.as_slice().as_bytes().try_into().unwrap()
 	}
 
+	/// The palette as egui colours. `egui::Color32` is a rexport of
+	/// `ecolor::Color32`, so these work with either crate.
+	#[cfg(feature = "egui")]
+	pub fn palette_egui(&self) -> Vec<ecolor::Color32> {
+		self.palette
+			.iter()
+			.map(|c| ecolor::Color32::from_rgb(c.r, c.g, c.b))
+			.collect()
+	}
+
+	/// The palette as iced colours. `iced::Color` is a rexport of
+	/// `iced_core::Color`, so these work with either crate.
+	#[cfg(feature = "iced")]
+	pub fn palette_iced(&self) -> Vec<iced_core::Color> {
+		self.palette
+			.iter()
+			.map(|c| iced_core::Color::from_rgb8(c.r, c.g, c.b))
+			.collect()
+	}
+
 	/// Retrieve the palette this squasher is working from
 	pub fn palette(&self) -> &[RGB8] {
 		&self.palette
 	}
 
+	/// The backend the Squasher maps with. If it was built with
+	/// [MapBackend::Auto] this is the one it picked.
+	pub fn map_backend(&self) -> MapBackend {
+		self.map.backend()
+	}
+
 	/// Retrieve the palette as bytes
 	pub fn palette_bytes(&self) -> Vec<u8> {
 		self.palette.as_bytes().to_owned()
 	}
 
-	/// Pick the closest colour in the palette for each unique color in the image
-	fn map_selected(&mut self, unique: &[RGB8]) {
-		for colour in unique {
-			let mut min_diff = f32::MAX;
-			let mut min_index = usize::MAX;
+	/// Estimate how much of RGB space maps to each palette entry. See the
+	/// [regions] module.
+	pub fn regions(&self, step: u8) -> Vec<regions::Region> {
+		regions::partition(&self.palette, &self.difference_fn, step)
+	}
+
+	/// Save the Squasher with serde. Shorthand for [snapshot::Snapshot::new]
+	#[cfg(feature = "serde")]
+	pub fn snapshot(&self) -> snapshot::Snapshot {
+		snapshot::Snapshot::new(self)
+	}
+
+	/// Derive light and dark variants of the palette. See the [theme] module.
+	pub fn theme(&self) -> Theme {
+		Theme::derive(&self.palette)
+	}
+
+	/// Add up to `n` colours to the palette without selecting it again. The
+	/// entries with the most error over the image are split in two, so the
+	/// new colours go where they help the most. Pinned colours aren't split,
+	/// and the palette never grows past what `T` can index.
+	///
+	/// The image's colours are mapped again afterwards. Other colours in the
+	/// map keep their old index, which is still valid but might not be the
+	/// closest anymore.
+	pub fn grow_palette<'a, Img>(&mut self, n: usize, image: Img)
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+
+		let mut histogram: HashMap<RGB8, usize> = HashMap::new();
+		for px in rgb.iter() {
+			*histogram.entry(*px).or_default() += 1;
+		}
+
+		// in a fixed order, so the sums in the averages are always added up the
+		// same way and the same image always grows the same palette
+		let histogram = selection::sort(histogram);
+		let mut members: Vec<Vec<(RGB8, usize)>> = vec![vec![]; self.palette.len()];
+		for &(colour, count) in &histogram {
+			if let Some(entry) = members.get_mut(self.nearest(&colour)) {
+				entry.push((colour, count));
+			}
+		}
+
+		let n = n.min(T::max_colors().saturating_sub(self.palette.len()));
+		for _ in 0..n {
+			let errors = self.palette.iter().zip(&members).enumerate();
+			let worst = errors
+				.filter(|(_, (entry, colours))| colours.len() > 1 && !self.pinned.contains(entry))
+				.map(|(idx, (entry, colours))| {
+					(idx, adjust::error(entry, colours, &self.difference_fn))
+				})
+				.max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+			let Some((worst, _)) = worst else {
+				break;
+			};
+
+			let (lower, upper) = adjust::split(std::mem::take(&mut members[worst]));
+			self.palette[worst] = adjust::weighted_mean(&lower);
+			self.palette.push(adjust::weighted_mean(&upper));
+			members[worst] = lower;
+			members.push(upper);
+		}
+
+		self.max_colours_min1 = T::from_usize(self.palette.len().saturating_sub(1));
+
+		self.palette_changed();
+		let unique: Vec<RGB8> = histogram.into_iter().map(|(colour, _)| colour).collect();
+		// a cancelled fix-up leaves old, but valid, indices
+		let _ = self.map_selected(&unique);
+	}
+
+	/// Remove up to `n` colours from the palette by merging the closest pairs
+	/// into their midpoint. A pinned colour doesn't move when it's merged and
+	/// two pinned colours are never merged.
+	///
+	/// Colours in the map that pointed to a merged entry point to the new one.
+	pub fn shrink_palette(&mut self, n: usize) {
+		// the new index of every old index
+		let mut remap: Vec<usize> = (0..self.palette.len()).collect();
+
+		for _ in 0..n {
+			let mut closest = None;
+			for a in 0..self.palette.len() {
+				for b in (a + 1)..self.palette.len() {
+					let (colour_a, colour_b) = (&self.palette[a], &self.palette[b]);
+					if self.pinned.contains(colour_a) && self.pinned.contains(colour_b) {
+						continue;
+					}
+
+					let diff = self.difference_fn.difference(colour_a, colour_b);
+					if closest.is_none_or(|(_, _, min)| diff < min) {
+						closest = Some((a, b, diff));
+					}
+				}
+			}
+
+			let Some((keep, remove, _)) = closest else {
+				break;
+			};
 
-			for (index, selected) in self.palette.iter().enumerate() {
-				let diff = (self.difference_fn)(colour, selected);
+			let (colour_a, colour_b) = (self.palette[keep], self.palette[remove]);
+			self.palette[keep] = if self.pinned.contains(&colour_a) {
+				colour_a
+			} else if self.pinned.contains(&colour_b) {
+				colour_b
+			} else {
+				adjust::weighted_mean(&[(colour_a, 1), (colour_b, 1)])
+			};
+			self.palette.remove(remove);
 
-				if diff.max(0.0) < min_diff {
-					min_diff = diff;
-					min_index = index;
+			for index in remap.iter_mut() {
+				if *index == remove {
+					*index = keep;
+				} else if *index > remove {
+					*index -= 1;
 				}
 			}
+		}
+
+		self.max_colours_min1 = T::from_usize(self.palette.len().saturating_sub(1));
+
+		self.map.remap(&remap, &self.palette);
+	}
+
+	/// Select a new palette of at most `new_max_colors` colours for an image
+	/// that's already indexed, like a decoded GIF frame, and change the indices
+	/// in place to point into it. The colours are counted from the indices and
+	/// `old_palette`, so the image is never expanded to RGB. Indices past the
+	/// end of `old_palette` are left alone.
+	///
+	/// The sampling isn't used and the max colours of the Squasher becomes
+	/// `new_max_colors`, or as many as `T` can index if that's fewer.
+	pub fn requantize(&mut self, indices: &mut [T], old_palette: &[RGB8], new_max_colors: usize) {
+		let mut counts = vec![0usize; old_palette.len()];
+		for index in indices.iter() {
+			if let Some(count) = counts.get_mut(index.as_usize()) {
+				*count += 1;
+			}
+		}
+
+		self.clear_fed();
+		self.feed_histogram(old_palette.iter().copied().zip(counts));
+		let new_max_colors = new_max_colors.min(T::max_colors());
+		self.max_colours_min1 = T::from_usize(new_max_colors.saturating_sub(1));
+		self.finalize_palette();
+
+		let remap: Vec<T> = old_palette
+			.iter()
+			.map(|colour| T::from_usize(self.nearest(colour)))
+			.collect();
+
+		for index in indices.iter_mut() {
+			if let Some(new) = remap.get(index.as_usize()) {
+				*index = *new;
+			}
+		}
+	}
+
+	/// Map every 24-bit colour, not only those in the images seen so far. This
+	/// compares all 16M colours against the palette so it's slow; it's meant
+	/// for generating a map ahead of time. See the [lut] module.
+	///
+	/// A Squasher with the [MapBackend::Hash] or [MapBackend::KdTree] backend
+	/// switches to [MapBackend::LazyLut] to have somewhere to put them.
+	pub fn fill_map(&mut self) {
+		self.map.fill(&self.palette, &self.difference_fn);
+	}
+
+	/// Write the palette and map to a file that [lut::MapView] can read. Call
+	/// [Squasher::fill_map] first, otherwise colours that haven't been seen
+	/// will come out as index 0.
+	pub fn write_map<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+		lut::write(&self.palette, &self.map.linear(&self.difference_fn), writer)
+	}
+
+	/// Like [Squasher::write_map] but run-length encoded, which is usually a
+	/// lot smaller. Use [lut::decompress] before reading it with a
+	/// [lut::MapView].
+	pub fn write_map_compressed<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+		lut::write_compressed(&self.palette, &self.map.linear(&self.difference_fn), writer)
+	}
+
+	/// Pick the closest colour in the palette for each unique color in the image
+	///
+	/// # Errors
+	/// [Error::Cancelled] if the cancel token was cancelled before every colour
+	/// was mapped.
+	fn map_selected(&mut self, unique: &[RGB8]) -> Result<(), Error> {
+		if !self.map.is_lazy() {
+			return Ok(());
+		}
+
+		// checking the token isn't free, so only do it every so often
+		let chunks = unique.len().div_ceil(4096);
+		for (idx, chunk) in unique.chunks(4096).enumerate() {
+			if self.cancelled() {
+				return Err(Error::Cancelled);
+			}
+			self.report(Stage::Mapping, idx as f32 / chunks as f32);
+
+			for colour in chunk {
+				let index = T::from_usize(self.nearest(colour));
+				self.map.insert(*colour, index);
+			}
+		}
+		self.report(Stage::Mapping, 1.0);
 
-			self.map[color_index(colour)] = T::from_usize(min_index);
+		Ok(())
+	}
+
+	/// [Squasher::map_selected] for every colour in an image, if the map needs
+	/// it. See [ColourMap::is_lazy].
+	fn map_image(&mut self, image: &[RGB8]) -> Result<(), Error> {
+		if !self.map.is_lazy() {
+			return Ok(());
 		}
+
+		let unique = Self::unique_colors(image);
+		self.map_selected(&unique)
+	}
+
+	/// Bring the map up to date after the palette changed
+	fn palette_changed(&mut self) {
+		self.map.palette_changed(&self.palette, &self.difference_fn);
+	}
+
+	fn nearest(&self, colour: &RGB8) -> usize {
+		difference::nearest(colour, &self.palette, &self.difference_fn)
 	}
 
 	fn unique_colors(image: &[RGB8]) -> Vec<RGB8> {
@@ -241,22 +1832,25 @@ impl<T: Count> Squasher<T> {
 	}
 }
 
-impl Squasher<u8> {
+impl<D: ColorDifference> Squasher<u8, D> {
 	/// Takes an RGB image buffer and writes the indicies to the first third of
 	/// that buffer. The buffer is not resized.
 	///
 	/// # Returns
-	/// The new size of the image
+	/// The new size of the image, or 0 if the cancel token was cancelled. The
+	/// image is left untouched if it's cancelled.
 	pub fn map_over(&mut self, image: &mut [u8]) -> usize {
+		span!("map", pixels = image.len() / 3);
 		// We have to map the colours of this image now because it might contain
 		// colours not present in the first image.
-		let unique = Self::unique_colors(image.as_rgb());
-		self.map_selected(&unique);
+		if self.map_image(image.as_rgb()).is_err() {
+			return 0;
+		}
 
 		for idx in 0..(image.len() / 3) {
 			let rgb_idx = idx * 3;
 			let color = RGB8::new(image[rgb_idx], image[rgb_idx + 1], image[rgb_idx + 2]);
-			let color_index = self.map[color_index(&color)];
+			let color_index = self.map.get(&color, &self.difference_fn);
 
 			image[idx] = color_index;
 		}
@@ -270,6 +1864,8 @@ pub trait Count: Copy + Clone {
 	fn as_usize(&self) -> usize;
 	fn from_usize(from: usize) -> Self;
 	fn le(&self, rhs: &usize) -> bool;
+	/// How many colours this can index, one more than its largest value
+	fn max_colors() -> usize;
 }
 
 macro_rules! count_impl {
@@ -292,6 +1888,10 @@ macro_rules! count_impl {
 			fn le(&self, rhs: &usize) -> bool {
 				*self as usize <= *rhs
 			}
+
+			fn max_colors() -> usize {
+				(<$kind>::MAX as usize).saturating_add(1)
+			}
 		}
 	};
 }
@@ -302,23 +1902,234 @@ count_impl!(u32);
 count_impl!(u64);
 count_impl!(usize);
 
-pub struct ImageData<'a>(&'a [RGB8]);
+/// An image to select a palette from or map. Packed RGB, like the `From`
+/// implementations take, is borrowed as it is. Other layouts, like
+/// [ImageData::from_rgba], are rearranged into RGB once here so the caller
+/// doesn't have to.
+#[derive(Clone)]
+pub struct ImageData<'a> {
+	pixels: Cow<'a, [RGB8]>,
+	/// How many times each pixel counts when selecting the palette
+	weights: Option<Cow<'a, [u8]>>,
+	/// The width in pixels, if it's known
+	width: Option<usize>,
+}
+
+impl<'a> ImageData<'a> {
+	fn new(pixels: impl Into<Cow<'a, [RGB8]>>) -> Self {
+		Self {
+			pixels: pixels.into(),
+			weights: None,
+			width: None,
+		}
+	}
+
+	/// Pixels of red, green, blue, and alpha, like a canvas' `ImageData` or an
+	/// OpenGL readback. Pixels with an alpha under `threshold` don't count when
+	/// selecting the palette, so what's hidden doesn't take any of it, but
+	/// they're still mapped. A `threshold` of 0 ignores alpha. Bytes past the
+	/// last whole pixel are dropped.
+	pub fn from_rgba(bytes: &[u8], threshold: u8) -> Self {
+		Self::from_four_channels(bytes, [0, 1, 2], threshold)
+	}
+
+	/// Like [ImageData::from_rgba] but blue, green, red, and alpha, like a
+	/// 32-bit Windows DIB
+	pub fn from_bgra(bytes: &[u8], threshold: u8) -> Self {
+		Self::from_four_channels(bytes, [2, 1, 0], threshold)
+	}
+
+	/// Pixels of blue, green, and red, like a 24-bit Windows DIB. Bytes past
+	/// the last whole pixel are dropped.
+	pub fn from_bgr(bytes: &[u8]) -> Self {
+		let pixels: Vec<RGB8> = bytes
+			.chunks_exact(3)
+			.map(|px| RGB8::new(px[2], px[1], px[0]))
+			.collect();
+
+		Self::new(pixels)
+	}
+
+	/// Four byte pixels with red, green, and blue at `rgb` and alpha last
+	fn from_four_channels(bytes: &[u8], rgb: [usize; 3], threshold: u8) -> Self {
+		let [r, g, b] = rgb;
+		let pixels: Vec<RGB8> = bytes
+			.chunks_exact(4)
+			.map(|px| RGB8::new(px[r], px[g], px[b]))
+			.collect();
+		let weights = (threshold > 0).then(|| {
+			bytes
+				.chunks_exact(4)
+				.map(|px| (px[3] >= threshold) as u8)
+				.collect::<Vec<u8>>()
+		});
+
+		Self {
+			weights: weights.map(Cow::Owned),
+			..Self::new(pixels)
+		}
+	}
+
+	/// Weigh each pixel by a mask when selecting the palette, so the colours
+	/// of the parts that matter most, like a face or the foreground, get more
+	/// of the palette. A pixel counts as many times as its weight instead of
+	/// once, and not at all if it's 0. There's one weight per pixel, like a
+	/// grayscale image the same size. Mapping ignores the weights. They
+	/// replace any from an alpha threshold.
+	///
+	/// # Errors
+	/// If there isn't exactly one weight per pixel.
+	pub fn with_weights(self, weights: &'a [u8]) -> Result<Self, Error> {
+		if weights.len() != self.pixels.len() {
+			return Err(Error::WrongWeights {
+				pixels: self.pixels.len(),
+				len: weights.len(),
+			});
+		}
+
+		Ok(Self {
+			weights: Some(weights.into()),
+			..self
+		})
+	}
+
+	/// Interpret bytes as tightly packed RGB, returning an error if the length
+	/// isn't a multiple of three. The `From` implementations for byte slices
+	/// silently drop any trailing bytes.
+	pub fn try_from_bytes(bytes: &'a [u8]) -> Result<Self, Error> {
+		if bytes.len() % 3 != 0 {
+			Err(Error::NotRgb(bytes.len()))
+		} else {
+			Ok(ImageData::new(bytes.as_rgb()))
+		}
+	}
+
+	/// Interpret any plain-old-data pixels as tightly packed RGB without
+	/// copying them, like a `Vec<[u8; 3]>` from somewhere else or a
+	/// `#[repr(C)]` pixel type of your own. It's an error if they don't add
+	/// up to a whole number of RGB pixels, see [ImageData::try_from_bytes].
+	#[cfg(feature = "bytemuck")]
+	pub fn from_pod<P: bytemuck::Pod>(pixels: &'a [P]) -> Result<Self, Error> {
+		Self::try_from_bytes(bytemuck::cast_slice(pixels))
+	}
+
+	/// Interpret bytes as an RGB image `width` pixels wide and `height` tall,
+	/// with rows `stride` bytes apart, checking that it all adds up. Corrupt or
+	/// mismatched input gives an error instead of a palette selected from
+	/// garbage.
+	///
+	/// Rows can be padded past `width * 3`, like they are in GPU readbacks and
+	/// BMPs. Padded rows are packed together once here, so the padding never
+	/// gets counted or mapped, and tightly packed ones are borrowed as they
+	/// are. The last row doesn't need its padding.
+	pub fn with_stride(
+		bytes: &'a [u8],
+		width: usize,
+		height: usize,
+		stride: usize,
+	) -> Result<Self, Error> {
+		let row = width.checked_mul(3);
+		let expected = stride.checked_mul(height);
+
+		let (Some(row), Some(expected)) = (row, expected) else {
+			return Err(Error::WrongDimensions {
+				width,
+				height,
+				expected: usize::MAX,
+				len: bytes.len(),
+			});
+		};
+
+		if stride < row {
+			return Err(Error::StrideTooSmall { stride, width });
+		}
+
+		let shortest = expected - (stride - row).min(expected);
+		if bytes.len() < shortest || bytes.len() > expected {
+			return Err(Error::WrongDimensions {
+				width,
+				height,
+				expected,
+				len: bytes.len(),
+			});
+		}
+
+		let pixels = if stride == row {
+			Cow::Borrowed(bytes[..row * height].as_rgb())
+		} else {
+			let packed: Vec<RGB8> = bytes
+				.chunks(stride)
+				.flat_map(|padded| padded[..row].as_rgb())
+				.copied()
+				.collect();
+			Cow::Owned(packed)
+		};
+
+		Ok(ImageData {
+			width: Some(width),
+			..ImageData::new(pixels)
+		})
+	}
+}
+
+/// Another look at the same image without copying it, for passing it to more
+/// than one thing
+impl<'a> From<&'a ImageData<'_>> for ImageData<'a> {
+	fn from(image: &'a ImageData<'_>) -> Self {
+		ImageData {
+			pixels: Cow::Borrowed(&image.pixels),
+			weights: image.weights.as_deref().map(Cow::Borrowed),
+			width: image.width,
+		}
+	}
+}
 
 impl<'a> From<&'a Vec<u8>> for ImageData<'a> {
 	fn from(plain: &'a Vec<u8>) -> Self {
-		ImageData(plain.as_rgb())
+		ImageData::new(plain.as_rgb())
 	}
 }
 
 impl<'a> From<&'a [u8]> for ImageData<'a> {
 	fn from(plain: &'a [u8]) -> Self {
-		ImageData(plain.as_rgb())
+		ImageData::new(plain.as_rgb())
 	}
 }
 
 impl<'a> From<&'a [RGB8]> for ImageData<'a> {
 	fn from(rgb: &'a [RGB8]) -> Self {
-		ImageData(rgb)
+		ImageData::new(rgb)
+	}
+}
+
+impl<'a> From<&'a Vec<[u8; 3]>> for ImageData<'a> {
+	fn from(rgb: &'a Vec<[u8; 3]>) -> Self {
+		ImageData::new(rgb.as_flattened().as_rgb())
+	}
+}
+
+impl<'a> From<&'a [[u8; 3]]> for ImageData<'a> {
+	fn from(rgb: &'a [[u8; 3]]) -> Self {
+		ImageData::new(rgb.as_flattened().as_rgb())
+	}
+}
+
+#[cfg(feature = "palette")]
+impl<'a> From<&'a [::palette::Srgb<u8>]> for ImageData<'a> {
+	fn from(srgb: &'a [::palette::Srgb<u8>]) -> Self {
+		ImageData::new(::palette::cast::into_component_slice(srgb).as_rgb())
+	}
+}
+
+/// The pixels of an `RgbImage` are already tightly packed RGB, and its width
+/// comes along for the things that want rows
+#[cfg(feature = "image")]
+impl<'a> From<&'a image::RgbImage> for ImageData<'a> {
+	fn from(image: &'a image::RgbImage) -> Self {
+		ImageData {
+			width: Some(image.width() as usize),
+			..ImageData::new(image.as_raw().as_rgb())
+		}
 	}
 }
 
@@ -327,3 +2138,65 @@ impl<'a> From<&'a [RGB8]> for ImageData<'a> {
 fn color_index(c: &RGB8) -> usize {
 	c.r as usize * (256 * 256) + c.g as usize * 256 + c.b as usize
 }
+
+/// The order of the colours in a Squasher's map. It only changes how fast
+/// lookups are, never which index a colour maps to.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MapLayout {
+	/// `r * 65536 + g * 256 + b`. Colours that differ only in blue are next to
+	/// each other, but a step in red is 64K entries away.
+	#[default]
+	Linear,
+	/// Z-order, with the bits of the channels interleaved. Colours that are
+	/// close in every channel are close in the map, which can miss the cache
+	/// less on large images whose colours wander in red and green. Working
+	/// out the index costs a few more instructions, so measure it on your
+	/// images; for plenty of them Linear is still faster.
+	Morton,
+}
+
+impl MapLayout {
+	/// Where a colour is in a map with this layout
+	#[inline(always)]
+	pub fn index(self, c: &RGB8) -> usize {
+		match self {
+			MapLayout::Linear => color_index(c),
+			MapLayout::Morton => morton_index(c),
+		}
+	}
+
+	/// A map with this layout rearranged into the order of [color_index],
+	/// which is what the [lut] format uses whatever the layout.
+	pub(crate) fn linear<T: Clone>(self, map: &[T]) -> std::borrow::Cow<'_, [T]> {
+		match self {
+			MapLayout::Linear => std::borrow::Cow::Borrowed(map),
+			MapLayout::Morton => (0..map.len())
+				.map(|index| {
+					let colour = RGB8::new((index >> 16) as u8, (index >> 8) as u8, index as u8);
+					map[self.index(&colour)].clone()
+				})
+				.collect(),
+		}
+	}
+}
+
+/// Each bit of a byte moved to every third bit, for interleaving
+const SPREAD: [u32; 256] = {
+	let mut table = [0; 256];
+	let mut byte = 0;
+	while byte < 256 {
+		let mut bit = 0;
+		while bit < 8 {
+			table[byte] |= ((byte as u32 >> bit) & 1) << (bit * 3);
+			bit += 1;
+		}
+		byte += 1;
+	}
+	table
+};
+
+/// The index of a colour in the Z-order map, red's bits highest
+#[inline(always)]
+fn morton_index(c: &RGB8) -> usize {
+	(SPREAD[c.r as usize] << 2 | SPREAD[c.g as usize] << 1 | SPREAD[c.b as usize]) as usize
+}
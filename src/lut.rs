@@ -0,0 +1,400 @@
+//! Colour maps generated ahead of time.
+//!
+//! A [Squasher](crate::Squasher) normally fills its map lazily as it sees new
+//! colours. For a fixed palette you can instead fill the whole thing once with
+//! [Squasher::fill_map](crate::Squasher::fill_map), save it with
+//! [Squasher::write_map](crate::Squasher::write_map), and then embed the file
+//! with `include_bytes!` or memory-map it. [MapView] reads straight out of
+//! those bytes so there's nothing to compute or allocate at startup.
+//!
+//! The file is a header followed by the palette and then one index for every
+//! 24-bit colour, in the order `r * 65536 + g * 256 + b`:
+//!
+//! | bytes | contents                                     |
+//! |-------|----------------------------------------------|
+//! | 6     | the magic `CSQMAP`                           |
+//! | 1     | the width of an index in bytes: 1, 2, 4 or 8 |
+//! | 4     | the number of palette entries, little endian |
+//! | 3 * n | the palette as RGB                           |
+//! | w * 2^24 | the indices, little endian                |
+//!
+//! That's 16MB or more, so there's also a run-length encoded version written by
+//! [write_compressed]. Nearby colours usually map to the same palette entry so
+//! the runs are long, and a 256 colour palette comes out at around a megabyte,
+//! a bit more for perceptual differences. It has the same header with the magic
+//! `CSQRLE` and the indices are replaced with runs, each a LEB128 length
+//! followed by a LEB128 index. Use [decompress] to turn it back into bytes for
+//! a [MapView].
+
+use std::io::{self, Write};
+
+use rgb::{FromSlice, RGB8};
+use thiserror::Error;
+
+use crate::{Count, Error, ImageData};
+
+pub const MAGIC: &[u8; 6] = b"CSQMAP";
+pub const COMPRESSED_MAGIC: &[u8; 6] = b"CSQRLE";
+
+/// The number of colours in the map, every 24-bit colour.
+pub const MAP_LEN: usize = 256 * 256 * 256;
+
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MapError {
+	#[error("not a colorsquash map")]
+	BadMagic,
+	#[error("index width of {0} bytes isn't supported")]
+	BadWidth(u8),
+	#[error("map is {len} bytes but should be {expected}")]
+	BadLength { expected: usize, len: usize },
+	#[error("map ended unexpectedly")]
+	UnexpectedEof,
+	#[error("a run goes past the end of the map or has an index that's too wide")]
+	BadRun,
+	#[error("a palette of {len} colours is more than the index type can count, which is {max}")]
+	PaletteTooLarge { len: usize, max: usize },
+	#[error("index {index} is past the end of a palette of {len} colours")]
+	BadIndex { index: usize, len: usize },
+}
+
+/// Write a palette and its fully-populated map in the format described in the
+/// [module documentation](self).
+pub fn write<T: Count, W: Write>(palette: &[RGB8], map: &[T], mut writer: W) -> io::Result<()> {
+	span!("encode", colors = palette.len());
+	let width = std::mem::size_of::<T>();
+	write_header(MAGIC, width, palette, &mut writer)?;
+
+	// writing an index at a time is very slow on an unbuffered writer
+	let mut chunk = Vec::with_capacity(width * 4096);
+	for indices in map.chunks(4096) {
+		chunk.clear();
+		for index in indices {
+			let bytes = (index.as_usize() as u64).to_le_bytes();
+			chunk.extend_from_slice(&bytes[..width]);
+		}
+		writer.write_all(&chunk)?;
+	}
+
+	Ok(())
+}
+
+/// Like [write] but the indices are run-length encoded. See the
+/// [module documentation](self).
+pub fn write_compressed<T: Count, W: Write>(
+	palette: &[RGB8],
+	map: &[T],
+	mut writer: W,
+) -> io::Result<()> {
+	span!("encode", colors = palette.len(), compressed = true);
+	write_header(
+		COMPRESSED_MAGIC,
+		std::mem::size_of::<T>(),
+		palette,
+		&mut writer,
+	)?;
+
+	let mut runs = vec![];
+	let mut indices = map.iter().map(Count::as_usize);
+	if let Some(mut current) = indices.next() {
+		let mut length = 1;
+		for index in indices {
+			if index == current {
+				length += 1;
+			} else {
+				leb128(&mut runs, length);
+				leb128(&mut runs, current);
+				current = index;
+				length = 1;
+			}
+		}
+
+		leb128(&mut runs, length);
+		leb128(&mut runs, current);
+	}
+
+	writer.write_all(&runs)
+}
+
+/// Expand a map written by [write_compressed] into the format [MapView] reads.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, MapError> {
+	let (width, count) = read_header(COMPRESSED_MAGIC, bytes)?;
+	let palette_end = HEADER_LEN + count * 3;
+	if bytes.len() < palette_end {
+		return Err(MapError::UnexpectedEof);
+	}
+
+	let mut out = Vec::with_capacity(palette_end + MAP_LEN * width);
+	out.extend_from_slice(MAGIC);
+	out.extend_from_slice(&bytes[MAGIC.len()..palette_end]);
+
+	let mut runs = &bytes[palette_end..];
+	let mut mapped = 0;
+	while mapped < MAP_LEN {
+		let length = read_leb128(&mut runs)?;
+		let index = read_leb128(&mut runs)? as u64;
+
+		if length > MAP_LEN - mapped || (width < 8 && index >> (width * 8) != 0) {
+			return Err(MapError::BadRun);
+		}
+
+		let index = &index.to_le_bytes()[..width];
+		for _ in 0..length {
+			out.extend_from_slice(index);
+		}
+		mapped += length;
+	}
+
+	Ok(out)
+}
+
+fn write_header<W: Write>(
+	magic: &[u8; 6],
+	width: usize,
+	palette: &[RGB8],
+	writer: &mut W,
+) -> io::Result<()> {
+	writer.write_all(magic)?;
+	writer.write_all(&[width as u8])?;
+	writer.write_all(&(palette.len() as u32).to_le_bytes())?;
+	for colour in palette {
+		writer.write_all(&[colour.r, colour.g, colour.b])?;
+	}
+
+	Ok(())
+}
+
+/// Check the magic and index width, returning the width and the number of
+/// palette entries.
+fn read_header(magic: &[u8; 6], bytes: &[u8]) -> Result<(usize, usize), MapError> {
+	if bytes.len() < HEADER_LEN || &bytes[..magic.len()] != magic {
+		return Err(MapError::BadMagic);
+	}
+
+	let width = bytes[magic.len()];
+	if !matches!(width, 1 | 2 | 4 | 8) {
+		return Err(MapError::BadWidth(width));
+	}
+
+	let mut count = [0; 4];
+	count.copy_from_slice(&bytes[magic.len() + 1..HEADER_LEN]);
+
+	Ok((width as usize, u32::from_le_bytes(count) as usize))
+}
+
+fn leb128(out: &mut Vec<u8>, mut value: usize) {
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+
+		if value == 0 {
+			out.push(byte);
+			return;
+		}
+		out.push(byte | 0x80);
+	}
+}
+
+fn read_leb128(bytes: &mut &[u8]) -> Result<usize, MapError> {
+	let mut value = 0usize;
+	for shift in (0..usize::BITS).step_by(7) {
+		let (&byte, rest) = bytes.split_first().ok_or(MapError::UnexpectedEof)?;
+		*bytes = rest;
+
+		value |= ((byte & 0x7f) as usize)
+			.checked_shl(shift)
+			.ok_or(MapError::BadRun)?;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	Err(MapError::BadRun)
+}
+
+/// A map borrowed from the bytes of a file written by [write].
+#[derive(Copy, Clone, Debug)]
+pub struct MapView<'a> {
+	width: usize,
+	palette: &'a [RGB8],
+	indices: &'a [u8],
+}
+
+impl<'a> MapView<'a> {
+	/// Check the header and length of the map, and that the palette fits the
+	/// width of its indices. Nothing is copied.
+	pub fn new(bytes: &'a [u8]) -> Result<Self, MapError> {
+		let (width, count) = read_header(MAGIC, bytes)?;
+		if width < 8 && count > 1 << (width * 8) {
+			return Err(MapError::PaletteTooLarge {
+				len: count,
+				max: 1 << (width * 8),
+			});
+		}
+
+		let expected = HEADER_LEN + count * 3 + MAP_LEN * width;
+		if bytes.len() != expected {
+			return Err(MapError::BadLength {
+				expected,
+				len: bytes.len(),
+			});
+		}
+
+		let (palette, indices) = bytes[HEADER_LEN..].split_at(count * 3);
+		Ok(Self {
+			width,
+			palette: palette.as_rgb(),
+			indices,
+		})
+	}
+
+	pub fn palette(&self) -> &'a [RGB8] {
+		self.palette
+	}
+
+	/// The width of an index in bytes
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// The index into the palette of the closest colour
+	pub fn index(&self, colour: RGB8) -> usize {
+		self.index_at(crate::color_index(&colour))
+	}
+
+	/// The index at a position in the map, `r * 65536 + g * 256 + b`
+	pub fn index_at(&self, position: usize) -> usize {
+		let start = position * self.width;
+
+		let mut bytes = [0; 8];
+		bytes[..self.width].copy_from_slice(&self.indices[start..start + self.width]);
+		u64::from_le_bytes(bytes) as usize
+	}
+
+	/// Fill the output buffer with indices into the palette, like
+	/// [Squasher::map](crate::Squasher::map).
+	///
+	/// Returns [MapError::PaletteTooLarge] before writing anything if the
+	/// palette has more colours than `T` can index, and [MapError::BadIndex] if
+	/// the map points past the end of the palette, which means it's corrupt.
+	///
+	/// # Panics
+	/// If the output buffer is smaller than the number of pixels
+	pub fn map<'b, T: Count, Img>(&self, image: Img, buffer: &mut [T]) -> Result<(), MapError>
+	where
+		Img: Into<ImageData<'b>>,
+	{
+		if self.palette.len() > T::max_colors() {
+			return Err(MapError::PaletteTooLarge {
+				len: self.palette.len(),
+				max: T::max_colors(),
+			});
+		}
+
+		let ImageData { pixels: rgb, .. } = image.into();
+		if buffer.len() < rgb.len() {
+			panic!(
+				"{}",
+				Error::BufferTooSmall {
+					needed: rgb.len(),
+					len: buffer.len()
+				}
+			);
+		}
+
+		for (idx, colour) in rgb.iter().enumerate() {
+			let index = self.index(*colour);
+			if index >= self.palette.len() {
+				return Err(MapError::BadIndex {
+					index,
+					len: self.palette.len(),
+				});
+			}
+
+			buffer[idx] = T::from_usize(index);
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PALETTE: [RGB8; 3] = [
+		RGB8::new(0, 0, 0),
+		RGB8::new(255, 0, 0),
+		RGB8::new(255, 255, 255),
+	];
+
+	/// Dark colours to black, red ones to red, and the rest to white, which has
+	/// long runs like a real map
+	fn map() -> Vec<u8> {
+		(0..MAP_LEN)
+			.map(|position| {
+				let [_, r, g, b] = (position as u32).to_be_bytes();
+				match (r, g, b) {
+					(0..=63, 0..=63, 0..=63) => 0,
+					(128.., 0..=63, 0..=63) => 1,
+					_ => 2,
+				}
+			})
+			.collect()
+	}
+
+	#[test]
+	fn map_round_trips() {
+		let map = map();
+		let mut bytes = vec![];
+		write(&PALETTE, &map, &mut bytes).unwrap();
+
+		let view = MapView::new(&bytes).unwrap();
+		assert_eq!(view.width(), 1);
+		assert_eq!(view.palette(), &PALETTE);
+		assert!((0..MAP_LEN).all(|position| view.index_at(position) == map[position] as usize));
+		assert_eq!(view.index(RGB8::new(200, 10, 10)), 1);
+	}
+
+	#[test]
+	fn truncated_map() {
+		let mut bytes = vec![];
+		write(&PALETTE, &map(), &mut bytes).unwrap();
+
+		assert!(matches!(
+			MapView::new(&bytes[..bytes.len() - 1]),
+			Err(MapError::BadLength { .. })
+		));
+		assert!(matches!(
+			MapView::new(&bytes[..HEADER_LEN - 1]),
+			Err(MapError::BadMagic)
+		));
+	}
+
+	#[test]
+	fn compressed_map_round_trips() {
+		let map = map();
+		let (mut plain, mut compressed) = (vec![], vec![]);
+		write(&PALETTE, &map, &mut plain).unwrap();
+		write_compressed(&PALETTE, &map, &mut compressed).unwrap();
+
+		assert!(compressed.len() < plain.len() / 100);
+		assert_eq!(decompress(&compressed).unwrap(), plain);
+	}
+
+	#[test]
+	fn truncated_compressed_map() {
+		let mut compressed = vec![];
+		write_compressed(&PALETTE, &map(), &mut compressed).unwrap();
+
+		assert!(matches!(
+			decompress(&compressed[..compressed.len() - 1]),
+			Err(MapError::UnexpectedEof)
+		));
+		assert!(matches!(
+			decompress(&compressed[..HEADER_LEN + 2]),
+			Err(MapError::UnexpectedEof)
+		));
+	}
+}
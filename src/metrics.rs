@@ -0,0 +1,567 @@
+//! Measuring how close a quantized image is to the original.
+
+use rgb::RGB8;
+
+use crate::{
+	difference::{self, ColorDifference},
+	oklab::Oklab,
+	Count, Error, ImageData,
+};
+
+/// The mean squared error between two images, averaged over every channel of
+/// every pixel. 0.0 means they're identical.
+///
+/// # Panics
+/// If the images are different sizes.
+pub fn mse<'a, 'b, A, B>(original: A, quantized: B) -> f64
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	mean(channel_mse(original, quantized))
+}
+
+/// The mean squared error of red, green, and blue separately.
+///
+/// # Panics
+/// If the images are different sizes.
+pub fn channel_mse<'a, 'b, A, B>(original: A, quantized: B) -> [f64; 3]
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	let (
+		ImageData {
+			pixels: original, ..
+		},
+		ImageData {
+			pixels: quantized, ..
+		},
+	) = (original.into(), quantized.into());
+	assert_eq!(
+		original.len(),
+		quantized.len(),
+		"images are different sizes"
+	);
+
+	squared_errors(original.iter().zip(quantized.iter().copied()))
+}
+
+/// Like [channel_mse] but for an image that's still indices into a palette,
+/// like the output of [Squasher::map](crate::Squasher::map), so it doesn't
+/// have to be turned back into RGB first. Indices past the end of the palette
+/// count as black.
+///
+/// # Panics
+/// If there isn't an index for every pixel.
+pub fn indexed_channel_mse<'a, T, A>(original: A, palette: &[RGB8], indices: &[T]) -> [f64; 3]
+where
+	T: Count,
+	A: Into<ImageData<'a>>,
+{
+	let ImageData {
+		pixels: original, ..
+	} = original.into();
+	assert_eq!(original.len(), indices.len(), "images are different sizes");
+
+	let colour = |index: &T| {
+		palette
+			.get(index.as_usize())
+			.copied()
+			.unwrap_or(RGB8::new(0, 0, 0))
+	};
+	squared_errors(original.iter().zip(indices.iter().map(colour)))
+}
+
+/// The mean of the squared difference of each channel
+fn squared_errors<'a, I>(pixels: I) -> [f64; 3]
+where
+	I: Iterator<Item = (&'a RGB8, RGB8)>,
+{
+	let squared = |a: u8, b: u8| (a as f64 - b as f64).powi(2);
+
+	let mut sums = [0.0; 3];
+	let mut count = 0;
+	for (a, b) in pixels {
+		sums[0] += squared(a.r, b.r);
+		sums[1] += squared(a.g, b.g);
+		sums[2] += squared(a.b, b.b);
+		count += 1;
+	}
+
+	if count == 0 {
+		[0.0; 3]
+	} else {
+		sums.map(|sum| sum / count as f64)
+	}
+}
+
+fn mean(channels: [f64; 3]) -> f64 {
+	channels.iter().sum::<f64>() / 3.0
+}
+
+/// Peak signal-to-noise ratio in decibels. Higher is better; around 30dB and
+/// up is usually hard to tell apart. Identical images are infinite.
+///
+/// # Panics
+/// If the images are different sizes.
+pub fn psnr<'a, 'b, A, B>(original: A, quantized: B) -> f64
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	psnr_from_mse(mse(original, quantized))
+}
+
+/// [psnr] of red, green, and blue separately.
+///
+/// # Panics
+/// If the images are different sizes.
+pub fn channel_psnr<'a, 'b, A, B>(original: A, quantized: B) -> [f64; 3]
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	channel_mse(original, quantized).map(psnr_from_mse)
+}
+
+/// [psnr] for an image that's still indices into a palette, see
+/// [indexed_channel_mse].
+///
+/// # Panics
+/// If there isn't an index for every pixel.
+pub fn indexed_psnr<'a, T, A>(original: A, palette: &[RGB8], indices: &[T]) -> f64
+where
+	T: Count,
+	A: Into<ImageData<'a>>,
+{
+	psnr_from_mse(mean(indexed_channel_mse(original, palette, indices)))
+}
+
+/// [psnr] for an already computed [mse]
+pub fn psnr_from_mse(mse: f64) -> f64 {
+	if mse == 0.0 {
+		f64::INFINITY
+	} else {
+		10.0 * (255.0f64 * 255.0 / mse).log10()
+	}
+}
+
+/// How different a quantized image looks from the original, by the delta-E of
+/// each pixel: the distance between the colours in OKLab, times 100 to be on
+/// about the same scale as the CIE's delta-E. Up to 1 or 2 is hard to see.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DeltaE {
+	pub mean: f32,
+	/// The delta-E 95% of pixels are at or below. The worst few pixels are
+	/// usually odd ones out that don't matter, so this says more than the max.
+	pub p95: f32,
+	pub max: f32,
+}
+
+/// Compare every pixel of two images by [DeltaE]. If there's a `heatmap` the
+/// delta-E of each pixel is written there too, to show where the error is.
+///
+/// # Panics
+/// If the images are different sizes or the heatmap is smaller than them.
+pub fn delta_e<'a, 'b, A, B>(original: A, quantized: B, heatmap: Option<&mut [f32]>) -> DeltaE
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	let (
+		ImageData {
+			pixels: original, ..
+		},
+		ImageData {
+			pixels: quantized, ..
+		},
+	) = (original.into(), quantized.into());
+	assert_eq!(
+		original.len(),
+		quantized.len(),
+		"images are different sizes"
+	);
+
+	delta_e_of(original.iter().zip(quantized.iter().copied()), heatmap)
+}
+
+/// Like [delta_e] but for an image that's still indices into a palette, see
+/// [indexed_channel_mse].
+///
+/// # Panics
+/// If there isn't an index for every pixel or the heatmap is smaller than the
+/// image.
+pub fn indexed_delta_e<'a, T, A>(
+	original: A,
+	palette: &[RGB8],
+	indices: &[T],
+	heatmap: Option<&mut [f32]>,
+) -> DeltaE
+where
+	T: Count,
+	A: Into<ImageData<'a>>,
+{
+	let ImageData {
+		pixels: original, ..
+	} = original.into();
+	assert_eq!(original.len(), indices.len(), "images are different sizes");
+
+	let colour = |index: &T| {
+		palette
+			.get(index.as_usize())
+			.copied()
+			.unwrap_or(RGB8::new(0, 0, 0))
+	};
+	delta_e_of(original.iter().zip(indices.iter().map(colour)), heatmap)
+}
+
+fn delta_e_of<'a, I>(pixels: I, heatmap: Option<&mut [f32]>) -> DeltaE
+where
+	I: ExactSizeIterator<Item = (&'a RGB8, RGB8)>,
+{
+	if let Some(heatmap) = &heatmap {
+		assert!(
+			heatmap.len() >= pixels.len(),
+			"heatmap is smaller than the image"
+		);
+	}
+
+	let mut errors: Vec<f32> = pixels
+		.map(|(a, b)| {
+			let (a, b) = (Oklab::from_rgb(*a), Oklab::from_rgb(b));
+			let (dl, da, db) = (a.l - b.l, a.a - b.a, a.b - b.b);
+			(dl * dl + da * da + db * db).sqrt() * 100.0
+		})
+		.collect();
+
+	if let Some(heatmap) = heatmap {
+		heatmap[..errors.len()].copy_from_slice(&errors);
+	}
+
+	if errors.is_empty() {
+		return DeltaE::default();
+	}
+
+	let mean = (errors.iter().map(|&e| e as f64).sum::<f64>() / errors.len() as f64) as f32;
+	let max = errors.iter().copied().fold(0.0, f32::max);
+	let rank = ((errors.len() - 1) as f32 * 0.95).round() as usize;
+	let (_, &mut p95, _) = errors.select_nth_unstable_by(rank, f32::total_cmp);
+
+	DeltaE { mean, p95, max }
+}
+
+/// The structural similarity (SSIM) of two images, from 1.0 when they're the
+/// same down towards 0.0. Colour error alone doesn't see whether a gradient
+/// turned into bands or a texture went flat, but those change the local
+/// contrast and structure SSIM compares. It's measured on the brightness of
+/// 8x8 windows, a window every 4 pixels, and averaged.
+///
+/// One of the images needs its width, from [ImageData::with_stride].
+///
+/// # Errors
+/// [Error::NoDimensions] if neither image has a width.
+///
+/// # Panics
+/// If the images are different sizes.
+pub fn ssim<'a, 'b, A, B>(original: A, quantized: B) -> Result<f64, Error>
+where
+	A: Into<ImageData<'a>>,
+	B: Into<ImageData<'b>>,
+{
+	let (original, quantized) = (original.into(), quantized.into());
+	assert_eq!(
+		original.pixels.len(),
+		quantized.pixels.len(),
+		"images are different sizes"
+	);
+	let width = original
+		.width
+		.or(quantized.width)
+		.ok_or(Error::NoDimensions)?;
+
+	Ok(ssim_of(
+		&luma(original.pixels.iter().copied()),
+		&luma(quantized.pixels.iter().copied()),
+		width,
+	))
+}
+
+/// Like [ssim] but for an image that's still indices into a palette, see
+/// [indexed_channel_mse]. The original needs its width.
+///
+/// # Errors
+/// [Error::NoDimensions] if the original doesn't have a width.
+///
+/// # Panics
+/// If there isn't an index for every pixel.
+pub fn indexed_ssim<'a, T, A>(original: A, palette: &[RGB8], indices: &[T]) -> Result<f64, Error>
+where
+	T: Count,
+	A: Into<ImageData<'a>>,
+{
+	let original = original.into();
+	assert_eq!(
+		original.pixels.len(),
+		indices.len(),
+		"images are different sizes"
+	);
+	let width = original.width.ok_or(Error::NoDimensions)?;
+
+	let colour = |index: &T| {
+		palette
+			.get(index.as_usize())
+			.copied()
+			.unwrap_or(RGB8::new(0, 0, 0))
+	};
+	Ok(ssim_of(
+		&luma(original.pixels.iter().copied()),
+		&luma(indices.iter().map(colour)),
+		width,
+	))
+}
+
+/// The Rec. 601 luma of every pixel
+fn luma<I: Iterator<Item = RGB8>>(pixels: I) -> Vec<f64> {
+	pixels
+		.map(|px| 0.299 * px.r as f64 + 0.587 * px.g as f64 + 0.114 * px.b as f64)
+		.collect()
+}
+
+fn ssim_of(a: &[f64], b: &[f64], width: usize) -> f64 {
+	const WINDOW: usize = 8;
+	const STEP: usize = 4;
+	// from the paper, to keep flat dark windows from dividing by nearly 0
+	const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+	const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+	let width = width.max(1);
+	let height = a.len() / width;
+	if height == 0 {
+		return 1.0;
+	}
+
+	// images smaller than a window are one window
+	let (window_w, window_h) = (WINDOW.min(width), WINDOW.min(height));
+
+	let mut sum = 0.0;
+	let mut windows = 0;
+	for top in (0..=height - window_h).step_by(STEP) {
+		for left in (0..=width - window_w).step_by(STEP) {
+			let pixels = (top..top + window_h)
+				.flat_map(|y| (left..left + window_w).map(move |x| y * width + x));
+
+			let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+				(0.0, 0.0, 0.0, 0.0, 0.0);
+			for idx in pixels {
+				let (a, b) = (a[idx], b[idx]);
+				sum_a += a;
+				sum_b += b;
+				sum_aa += a * a;
+				sum_bb += b * b;
+				sum_ab += a * b;
+			}
+
+			let n = (window_w * window_h) as f64;
+			let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+			let var_a = sum_aa / n - mean_a * mean_a;
+			let var_b = sum_bb / n - mean_b * mean_b;
+			let covariance = sum_ab / n - mean_a * mean_b;
+
+			sum += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+				/ ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+			windows += 1;
+		}
+	}
+
+	sum / windows as f64
+}
+
+/// How well a palette fits the colours of an image: the difference from each
+/// colour to its closest palette entry, times how many times the colour
+/// appears, summed. Lower is better, so palettes from different selectors or
+/// settings can be compared on the same histogram, like one from
+/// [histogram](crate::selection::histogram). Divided by the number of pixels
+/// it's [Measurements::mean_error], which is what
+/// [HeuristicSorsel](crate::selection::HeuristicSorsel) scores its tolerances
+/// by unless it's given another [Objective].
+///
+/// An empty palette is infinitely bad.
+pub fn palette_score<D: ColorDifference + ?Sized>(
+	histogram: &[(RGB8, usize)],
+	palette: &[RGB8],
+	difference: &D,
+) -> f64 {
+	if palette.is_empty() {
+		return f64::INFINITY;
+	}
+
+	histogram
+		.iter()
+		.map(|(colour, count)| {
+			let nearest = difference::nearest(colour, palette, difference);
+			let error = difference.difference(colour, &palette[nearest]).max(0.0);
+			error as f64 * *count as f64
+		})
+		.sum()
+}
+
+/// Measurements of how well a palette fits the colours of an image, for
+/// comparing palettes by an [Objective].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Measurements {
+	/// The difference from each colour to its closest palette entry, averaged
+	/// over every pixel.
+	pub mean_error: f32,
+	/// The largest difference from any colour to its closest entry.
+	pub max_error: f32,
+	/// The average difference from each palette entry to the closest other one.
+	/// Higher means the palette is more spread out.
+	pub diversity: f32,
+	/// An estimate of the encoded size, in bits per pixel: the entropy of which
+	/// palette entry each pixel uses. It doesn't see compression that depends
+	/// on where the pixels are, like PNG's filters, but it follows how many
+	/// entries there are and how evenly they're used.
+	pub bits_per_pixel: f32,
+}
+
+impl Measurements {
+	/// Measure a palette against colours counted from an image, like those
+	/// from [histogram](crate::selection::histogram). If there are colours but
+	/// the palette is empty, both errors are infinite.
+	pub fn new<D: ColorDifference + ?Sized>(
+		colors: &[(RGB8, usize)],
+		palette: &[RGB8],
+		difference: &D,
+	) -> Self {
+		let total: usize = colors.iter().map(|(_, count)| count).sum();
+		if total == 0 {
+			return Self::default();
+		} else if palette.is_empty() {
+			// nothing to map to is as bad as it gets, like palette_score
+			return Self {
+				mean_error: f32::INFINITY,
+				max_error: f32::INFINITY,
+				..Self::default()
+			};
+		}
+
+		let mut used = vec![0usize; palette.len()];
+		let mut error_sum = 0.0;
+		let mut max_error = 0.0f32;
+		for (colour, count) in colors {
+			let nearest = difference::nearest(colour, palette, difference);
+			let error = difference.difference(colour, &palette[nearest]).max(0.0);
+
+			used[nearest] += count;
+			error_sum += error as f64 * *count as f64;
+			max_error = max_error.max(error);
+		}
+
+		let bits_per_pixel = used
+			.iter()
+			.filter(|&&count| count > 0)
+			.map(|&count| {
+				let p = count as f64 / total as f64;
+				-p * p.log2()
+			})
+			.sum::<f64>();
+
+		let diversity = if palette.len() < 2 {
+			0.0
+		} else {
+			let nearest_other = |(idx, entry): (usize, &RGB8)| {
+				palette
+					.iter()
+					.enumerate()
+					.filter(|&(other, _)| other != idx)
+					.map(|(_, other)| difference.difference(entry, other))
+					.fold(f32::MAX, f32::min)
+			};
+
+			palette.iter().enumerate().map(nearest_other).sum::<f32>() / palette.len() as f32
+		};
+
+		Self {
+			mean_error: (error_sum / total as f64) as f32,
+			max_error,
+			diversity,
+			bits_per_pixel: bits_per_pixel as f32,
+		}
+	}
+}
+
+/// How much each of the [Measurements] matters when deciding which palette is
+/// best. The score is the weighted sum, with diversity subtracted as more is
+/// better, and the lowest score wins.
+///
+/// The default only weighs the mean error, which is what
+/// [HeuristicSorsel](crate::selection::HeuristicSorsel) has always used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Objective {
+	pub mean_error: f32,
+	pub max_error: f32,
+	pub diversity: f32,
+	pub bits_per_pixel: f32,
+}
+
+impl Objective {
+	/// An empty palette measured against any pixels scores infinity, however
+	/// the errors are weighed.
+	pub fn score(&self, measured: &Measurements) -> f32 {
+		// an unweighted infinite error would make the score NaN
+		if measured.mean_error.is_infinite() || measured.max_error.is_infinite() {
+			return f32::INFINITY;
+		}
+
+		self.mean_error * measured.mean_error + self.max_error * measured.max_error
+			- self.diversity * measured.diversity
+			+ self.bits_per_pixel * measured.bits_per_pixel
+	}
+
+	/// Measure the palette and score it.
+	pub fn score_palette<D: ColorDifference + ?Sized>(
+		&self,
+		colors: &[(RGB8, usize)],
+		palette: &[RGB8],
+		difference: &D,
+	) -> f32 {
+		self.score(&Measurements::new(colors, palette, difference))
+	}
+}
+
+impl Default for Objective {
+	fn default() -> Self {
+		Self {
+			mean_error: 1.0,
+			max_error: 0.0,
+			diversity: 0.0,
+			bits_per_pixel: 0.0,
+		}
+	}
+}
+
+/// A score a palette has to reach, for picking the fewest colours that are
+/// good enough. See
+/// [SquasherBuilder::quality_target](crate::SquasherBuilder::quality_target).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Target {
+	pub objective: Objective,
+	/// The highest score by the objective that's still good enough
+	pub max_score: f32,
+}
+
+impl Target {
+	/// A mean difference from each pixel to its closest palette entry of at
+	/// most `max`.
+	pub fn mean_error(max: f32) -> Self {
+		Self {
+			objective: Objective::default(),
+			max_score: max,
+		}
+	}
+
+	pub fn is_met(&self, measured: &Measurements) -> bool {
+		self.objective.score(measured) <= self.max_score
+	}
+}
@@ -1,107 +1,297 @@
-use std::collections::HashMap;
-
 #[cfg(feature = "rand")]
 use rand::{prelude::*, seq::index::sample};
 use rgb::{RGB, RGB8};
 
-pub struct KMeans {
-	samples: Vec<RGB8>,
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-struct HashableRGBF {
-	inner: (u32, u32, u32),
-}
+use crate::rng::SplitMix64;
 
-impl From<RGB<f32>> for HashableRGBF {
-	fn from(value: RGB<f32>) -> Self {
-		Self {
-			inner: (value.r.to_bits(), value.g.to_bits(), value.b.to_bits()),
-		}
-	}
+pub struct KMeans {
+	// colours and how many times they appear
+	samples: Vec<(RGB8, usize)>,
 }
 
 impl KMeans {
-	pub fn new(samples: Vec<RGB8>) -> Self {
+	pub fn new(samples: Vec<(RGB8, usize)>) -> Self {
 		Self { samples }
 	}
-	pub fn get_k_colors(&self, k: usize, max_iter: usize) -> Vec<RGB8> {
-		let mut centroids = self.get_centroid_seeds_simple(k);
-
-		for _ in 0..max_iter {
-			let mut clusters: HashMap<HashableRGBF, Vec<RGB8>> = HashMap::new();
-
-			for &sample in &self.samples {
-				let closest_centroid = Self::closest_centroid(&centroids, sample.into());
-				clusters
-					.entry(closest_centroid.into())
-					.or_default()
-					.push(sample);
-			}
-			centroids = clusters
-				.into_values()
-				.map(|members| vector_avg(&members))
-				.collect()
+	/// Seed `k` centroids to start iterating from
+	pub fn start(self, k: usize, seed: Option<u64>) -> Iterations {
+		let centroids = self.get_centroid_seeds_simple(k, seed);
+		let points: Vec<RGB<f32>> = self.samples.iter().map(|&(c, _)| c.into()).collect();
+
+		Iterations {
+			assigned: vec![0; points.len()],
+			upper: vec![f32::INFINITY; points.len()],
+			lower: vec![0.0; points.len()],
+			half_gap: vec![0.0; centroids.len()],
+			members: vec![(RGB::new(0.0, 0.0, 0.0), 0); centroids.len()],
+			moved: vec![0.0; centroids.len()],
+			samples: self.samples,
+			points,
+			centroids,
 		}
-		centroids
-			.into_iter()
-			.map(|c| RGB8::new(c.r.round() as u8, c.g.round() as u8, c.b.round() as u8))
-			.collect()
 	}
 
-	/// Picks a point at random (if feature rand is enabled) for the first centroid, then iteratively adds the point furthest away from any centroid
+	/// Picks a point at random for the first centroid, then iteratively adds the point furthest away from any centroid
+	/// The point is picked with the seed if there is one, otherwise it's random if feature rand is enabled and the first point if it's not
 	/// A more complex solution is the probabilistic k-means++ algorithm (https://www.mathworks.com/help/stats/kmeans.html#bueq7aj-5)
-	fn get_centroid_seeds_simple(&self, k: usize) -> Vec<RGB<f32>> {
+	fn get_centroid_seeds_simple(&self, k: usize, seed: Option<u64>) -> Vec<RGB<f32>> {
 		if k >= self.samples.len() {
-			return self.samples.iter().map(|&v| v.into()).collect();
+			return self.samples.iter().map(|&(v, _)| v.into()).collect();
 		}
 
-		#[cfg(rand)]
-		let index = thread_rng().gen_range(0..self.samples.len());
-		#[cfg(not(rand))]
-		let index = 0; //lol
+		let index = match seed {
+			Some(seed) => SplitMix64::new(seed).below(self.samples.len()),
+			#[cfg(feature = "rand")]
+			None => thread_rng().gen_range(0..self.samples.len()),
+			#[cfg(not(feature = "rand"))]
+			None => 0, //lol
+		};
+
+		let mut centroids: Vec<RGB<f32>> = vec![self.samples[index].0.into()];
+		// distance from each sample to its closest centroid, updated as each
+		// centroid is added rather than searching every centroid every time
+		let mut closest: Vec<f32> = self
+			.samples
+			.iter()
+			.map(|&(v, _)| vector_diff_2_norm(v.into(), centroids[0]))
+			.collect();
 
-		let mut centroids: Vec<RGB<f32>> = vec![self.samples[index].into()];
 		while centroids.len() < k {
-			let next = *self
-				.samples
-				.iter()
-				.max_by(|&&v1, &&v2| {
-					let v1_closest_centroid = Self::closest_centroid(&centroids, v1.into());
-					let v2_closest_centroid = Self::closest_centroid(&centroids, v2.into());
-
-					vector_diff_2_norm(v1.into(), v1_closest_centroid)
-						.partial_cmp(&vector_diff_2_norm(v2.into(), v2_closest_centroid))
-						.unwrap()
-				})
-				.unwrap();
-			centroids.push(next.into());
+			// the last of equally far samples, like Iterator::max_by
+			let mut furthest = 0;
+			for (idx, &dist) in closest.iter().enumerate() {
+				if dist >= closest[furthest] {
+					furthest = idx;
+				}
+			}
+
+			let next: RGB<f32> = self.samples[furthest].0.into();
+			for (dist, &(v, _)) in closest.iter_mut().zip(&self.samples) {
+				*dist = dist.min(vector_diff_2_norm(v.into(), next));
+			}
+			centroids.push(next);
 		}
 		centroids
 	}
 
-	fn closest_centroid(centroids: &[RGB<f32>], v: RGB<f32>) -> RGB<f32> {
-		*centroids
-			.iter()
-			.min_by(|&&c1, &&c2| {
-				vector_diff_2_norm(c1, v)
-					.partial_cmp(&vector_diff_2_norm(c2, v))
-					.unwrap()
-			})
-			.unwrap()
-	}
-
 	#[cfg(feature = "rand")]
 	fn get_centroid_seeds_random(&self, k: usize) -> Vec<RGB<f32>> {
 		if k >= self.samples.len() {
-			return self.samples.iter().map(|&v| v.into()).collect();
+			return self.samples.iter().map(|&(v, _)| v.into()).collect();
 		}
 
 		sample(&mut thread_rng(), self.samples.len(), k)
 			.into_iter()
-			.map(|i| self.samples[i].into())
+			.map(|i| self.samples[i].0.into())
+			.collect()
+	}
+}
+
+/// K-means part way through, so it can be run an iteration at a time.
+///
+/// This is Hamerly's algorithm. for every sample we keep an upper bound on the
+/// distance to its centroid and a lower bound on the distance to every other
+/// one. if the upper is less than the lower, or less than half the distance
+/// from its centroid to the next nearest centroid, the sample can't have
+/// changed cluster and we skip comparing it to every centroid. the bounds are
+/// loosened by how far the centroids move each iteration.
+///
+/// Hamerly, G. (2010). Making k-means even faster.
+pub struct Iterations {
+	samples: Vec<(RGB8, usize)>,
+	points: Vec<RGB<f32>>,
+	centroids: Vec<RGB<f32>>,
+	assigned: Vec<usize>,
+	upper: Vec<f32>,
+	lower: Vec<f32>,
+	half_gap: Vec<f32>,
+	// the weighted sum and count of each cluster's colours, by centroid index.
+	// allocated once and cleared every iteration
+	members: Vec<(RGB<f32>, usize)>,
+	moved: Vec<f32>,
+}
+
+impl Iterations {
+	/// Run one iteration.
+	///
+	/// # Returns
+	/// False if nothing changed cluster and no centroid moved, so iterating
+	/// again would do nothing.
+	pub fn step(&mut self) -> bool {
+		let centroids = &mut self.centroids;
+		for (idx, centroid) in centroids.iter().enumerate() {
+			let nearest = centroids
+				.iter()
+				.enumerate()
+				.filter(|&(other, _)| other != idx)
+				.map(|(_, &other)| vector_diff_2_norm(*centroid, other))
+				.fold(f32::INFINITY, f32::min);
+			self.half_gap[idx] = nearest / 2.0;
+		}
+
+		let (assigned, upper, lower) = (&mut self.assigned, &mut self.upper, &mut self.lower);
+		let mut changed = false;
+		for (idx, &sample) in self.points.iter().enumerate() {
+			let bound = self.half_gap[assigned[idx]].max(lower[idx]);
+			if upper[idx] <= bound {
+				continue;
+			}
+
+			// the upper bound is loose, so tighten it and try again
+			upper[idx] = vector_diff_2_norm(sample, centroids[assigned[idx]]);
+			if upper[idx] <= bound {
+				continue;
+			}
+
+			let (closest, closest_dist, second_dist) = Self::two_closest(centroids, sample);
+			if closest != assigned[idx] {
+				assigned[idx] = closest;
+				changed = true;
+			}
+			upper[idx] = closest_dist;
+			lower[idx] = second_dist;
+		}
+
+		self.moved.fill(0.0);
+		if Self::reseed_empty(
+			&self.points,
+			centroids,
+			assigned,
+			upper,
+			lower,
+			&mut self.moved,
+		) {
+			changed = true;
+		}
+
+		self.members.fill((RGB::new(0.0, 0.0, 0.0), 0));
+		for (&(colour, count), &cluster) in self.samples.iter().zip(assigned.iter()) {
+			let (sum, total) = &mut self.members[cluster];
+			let colour: RGB<f32> = colour.into();
+			let weight = count as f32;
+
+			*sum = vector_sum(
+				*sum,
+				RGB::new(colour.r * weight, colour.g * weight, colour.b * weight),
+			);
+			*total += count;
+		}
+
+		for (idx, &(sum, total)) in self.members.iter().enumerate() {
+			// only when there are fewer colours than centroids, which the
+			// seeding doesn't allow, but the mean would be NaN
+			if total == 0 {
+				continue;
+			}
+
+			let total = total as f32;
+			let new = RGB::new(sum.r / total, sum.g / total, sum.b / total);
+			self.moved[idx] += vector_diff_2_norm(centroids[idx], new);
+			centroids[idx] = new;
+		}
+
+		let most_moved = self.moved.iter().copied().fold(0.0, f32::max);
+		if !changed && most_moved == 0.0 {
+			return false;
+		}
+
+		for (idx, &cluster) in assigned.iter().enumerate() {
+			upper[idx] += self.moved[cluster];
+			lower[idx] -= most_moved;
+		}
+
+		true
+	}
+
+	pub fn centroids(&self) -> Vec<RGB8> {
+		self.centroids
+			.iter()
+			.map(|c| RGB8::new(c.r.round() as u8, c.g.round() as u8, c.b.round() as u8))
 			.collect()
 	}
+
+	/// Move the centroid of every cluster nothing was assigned to onto the
+	/// sample furthest from its own centroid, the one the clustering describes
+	/// worst, so that no palette entries are lost. That sample is reassigned to
+	/// the moved centroid, and how far the centroid jumped is added to `moved`.
+	///
+	/// # Returns
+	/// True if any centroid was moved
+	fn reseed_empty(
+		samples: &[RGB<f32>],
+		centroids: &mut [RGB<f32>],
+		assigned: &mut [usize],
+		upper: &mut [f32],
+		lower: &mut [f32],
+		moved: &mut [f32],
+	) -> bool {
+		let mut sizes = vec![0usize; centroids.len()];
+		for &cluster in assigned.iter() {
+			sizes[cluster] += 1;
+		}
+
+		if !sizes.contains(&0) {
+			return false;
+		}
+
+		let mut distances: Vec<f32> = samples
+			.iter()
+			.zip(assigned.iter())
+			.map(|(&sample, &cluster)| vector_diff_2_norm(sample, centroids[cluster]))
+			.collect();
+
+		for empty in 0..centroids.len() {
+			if sizes[empty] > 0 {
+				continue;
+			}
+
+			// taking a sample from a cluster of one would only empty that one
+			let furthest = distances
+				.iter()
+				.enumerate()
+				.filter(|&(idx, &dist)| sizes[assigned[idx]] > 1 && dist > 0.0)
+				.max_by(|(_, a), (_, b)| a.total_cmp(b))
+				.map(|(idx, _)| idx);
+
+			let Some(furthest) = furthest else {
+				// every sample is already a centroid
+				break;
+			};
+
+			sizes[assigned[furthest]] -= 1;
+			sizes[empty] = 1;
+			assigned[furthest] = empty;
+			moved[empty] += vector_diff_2_norm(centroids[empty], samples[furthest]);
+			centroids[empty] = samples[furthest];
+			distances[furthest] = 0.0;
+
+			// the centroid is exactly on the sample. nothing is known about
+			// how far the others are
+			upper[furthest] = 0.0;
+			lower[furthest] = 0.0;
+		}
+
+		true
+	}
+
+	/// The index of the closest centroid, its distance, and the distance to the
+	/// second closest. The first of equally close centroids is the closest.
+	fn two_closest(centroids: &[RGB<f32>], v: RGB<f32>) -> (usize, f32, f32) {
+		let mut closest = (0, f32::INFINITY);
+		let mut second = f32::INFINITY;
+
+		for (idx, &centroid) in centroids.iter().enumerate() {
+			let dist = vector_diff_2_norm(centroid, v);
+			if dist < closest.1 {
+				second = closest.1;
+				closest = (idx, dist);
+			} else if dist < second {
+				second = dist;
+			}
+		}
+
+		(closest.0, closest.1, second)
+	}
 }
 
 fn vector_diff(v1: RGB<f32>, v2: RGB<f32>) -> RGB<f32> {
@@ -116,14 +306,3 @@ fn vector_diff_2_norm(v1: RGB<f32>, v2: RGB<f32>) -> f32 {
 fn vector_sum(acc: RGB<f32>, elem: RGB<f32>) -> RGB<f32> {
 	RGB::new(acc.r + elem.r, acc.g + elem.g, acc.b + elem.b)
 }
-
-fn vector_avg(vs: &[RGB8]) -> RGB<f32> {
-	let summed = vs.iter().fold(RGB::new(0.0, 0.0, 0.0), |acc, elem| {
-		vector_sum(acc, (*elem).into())
-	});
-	RGB::new(
-		summed.r / vs.len() as f32,
-		summed.g / vs.len() as f32,
-		summed.b / vs.len() as f32,
-	)
-}
@@ -0,0 +1,88 @@
+//! Conversions between sRGB and the OKLab colour space.
+//!
+//! <https://bottosson.github.io/posts/oklab/>
+
+// the matrices are copied straight from the reference above
+#![allow(clippy::excessive_precision)]
+
+use rgb::RGB8;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Oklab {
+	pub l: f32,
+	pub a: f32,
+	pub b: f32,
+}
+
+impl Oklab {
+	pub fn from_rgb(rgb: RGB8) -> Self {
+		let r = to_linear(rgb.r);
+		let g = to_linear(rgb.g);
+		let b = to_linear(rgb.b);
+
+		let l = 0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b;
+		let m = 0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b;
+		let s = 0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b;
+
+		let l = l.cbrt();
+		let m = m.cbrt();
+		let s = s.cbrt();
+
+		Self {
+			l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+			a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+			b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+		}
+	}
+
+	/// Linear sRGB, unclamped. Components outside of 0.0..=1.0 mean the colour
+	/// is outside of the sRGB gamut.
+	pub fn to_linear_rgb(self) -> [f32; 3] {
+		let l = self.l + 0.3963377774 * self.a + 0.2158037573 * self.b;
+		let m = self.l - 0.1055613458 * self.a - 0.0638541728 * self.b;
+		let s = self.l - 0.0894841775 * self.a - 1.2914855480 * self.b;
+
+		let l = l * l * l;
+		let m = m * m * m;
+		let s = s * s * s;
+
+		[
+			4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+			-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+			-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+		]
+	}
+
+	pub fn in_gamut(self) -> bool {
+		// a little slack so colours we just converted from sRGB don't fail
+		self.to_linear_rgb()
+			.iter()
+			.all(|c| (-0.0001..=1.0001).contains(c))
+	}
+
+	/// Convert back to sRGB, clamping anything out of gamut
+	pub fn to_rgb(self) -> RGB8 {
+		let [r, g, b] = self.to_linear_rgb();
+		RGB8::new(from_linear(r), from_linear(g), from_linear(b))
+	}
+}
+
+fn to_linear(c: u8) -> f32 {
+	let c = c as f32 / 255.0;
+	if c <= 0.04045 {
+		c / 12.92
+	} else {
+		((c + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+fn from_linear(c: f32) -> u8 {
+	let c = c.clamp(0.0, 1.0);
+	let srgb = if c <= 0.0031308 {
+		c * 12.92
+	} else {
+		1.055 * c.powf(1.0 / 2.4) - 0.055
+	};
+
+	(srgb * 255.0).round() as u8
+}
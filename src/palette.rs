@@ -0,0 +1,5 @@
+//! Reading and writing palette files.
+
+pub mod export;
+pub mod fixed;
+pub mod parse;
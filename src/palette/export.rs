@@ -0,0 +1,64 @@
+//! Writing palettes to files other tools can read, like the palette from
+//! [Squasher::palette](crate::Squasher::palette).
+
+use std::io::{self, Write};
+
+use rgb::RGB8;
+
+/// The palette as a GIMP palette (`.gpl`). Each colour is named with its hex
+/// code.
+pub fn gpl(palette: &[RGB8], name: &str) -> String {
+	let mut gpl = format!("GIMP Palette\nName: {name}\nColumns: 16\n#\n");
+
+	for c in palette {
+		gpl.push_str(&format!(
+			"{:3} {:3} {:3}\t#{:02x}{:02x}{:02x}\n",
+			c.r, c.g, c.b, c.r, c.g, c.b
+		));
+	}
+
+	gpl
+}
+
+/// Write the palette as a GIMP palette. See [gpl].
+pub fn write_gpl<W: Write>(palette: &[RGB8], name: &str, mut writer: W) -> io::Result<()> {
+	writer.write_all(gpl(palette, name).as_bytes())
+}
+
+/// The palette as an Adobe Color Table (`.act`), or `None` if it has more than
+/// the 256 colours the format can hold or none at all. A count of 0 is read as
+/// all 256, so an empty palette wouldn't come back empty.
+///
+/// The table is padded to 256 colours with black and followed by the number of
+/// colours actually used, so it's always 772 bytes.
+pub fn act(palette: &[RGB8]) -> Option<Vec<u8>> {
+	if palette.is_empty() || palette.len() > 256 {
+		return None;
+	}
+
+	let mut act = Vec::with_capacity(772);
+	for c in palette {
+		act.extend_from_slice(&[c.r, c.g, c.b]);
+	}
+	act.resize(768, 0);
+
+	act.extend_from_slice(&(palette.len() as u16).to_be_bytes());
+	// no transparent colour
+	act.extend_from_slice(&0xFFFFu16.to_be_bytes());
+
+	Some(act)
+}
+
+/// Write the palette as an Adobe Color Table. See [act]. Returns an error of
+/// kind [io::ErrorKind::InvalidInput] if the palette is empty or has more than
+/// 256 colours.
+pub fn write_act<W: Write>(palette: &[RGB8], mut writer: W) -> io::Result<()> {
+	let act = act(palette).ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"act palettes need between 1 and 256 colours",
+		)
+	})?;
+
+	writer.write_all(&act)
+}
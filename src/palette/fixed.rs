@@ -0,0 +1,130 @@
+//! Well known palettes, built at compile time.
+//!
+//! Everything here is a `const` so it costs nothing at runtime and can be used
+//! in other constants and in `no_std`-style firmware that just wants the
+//! colours.
+
+use rgb::RGB8;
+
+/// The 216 colour "web-safe" palette. Every combination of the channel values
+/// 0, 51, 102, 153, 204, and 255, ordered by red, then green, then blue.
+pub const WEB_SAFE: [RGB8; 216] = web_safe();
+
+/// Black and white
+pub const MONOCHROME: [RGB8; 2] = gray_ramp();
+/// Four evenly spaced grays from black to white
+pub const GRAYSCALE_4: [RGB8; 4] = gray_ramp();
+/// Sixteen evenly spaced grays from black to white
+pub const GRAYSCALE_16: [RGB8; 16] = gray_ramp();
+/// Every gray, from black to white
+pub const GRAYSCALE_256: [RGB8; 256] = gray_ramp();
+
+/// The four greens of the original Game Boy, darkest to lightest
+pub const GAME_BOY: [RGB8; 4] = [hex(0x0f380f), hex(0x306230), hex(0x8bac0f), hex(0x9bbc0f)];
+
+/// The sixteen colours of CGA in their usual order
+pub const CGA: [RGB8; 16] = [
+	hex(0x000000),
+	hex(0x0000aa),
+	hex(0x00aa00),
+	hex(0x00aaaa),
+	hex(0xaa0000),
+	hex(0xaa00aa),
+	hex(0xaa5500),
+	hex(0xaaaaaa),
+	hex(0x555555),
+	hex(0x5555ff),
+	hex(0x55ff55),
+	hex(0x55ffff),
+	hex(0xff5555),
+	hex(0xff55ff),
+	hex(0xffff55),
+	hex(0xffffff),
+];
+
+/// The Commodore 64 palette as measured by Pepto
+pub const C64: [RGB8; 16] = [
+	hex(0x000000),
+	hex(0xffffff),
+	hex(0x68372b),
+	hex(0x70a4b2),
+	hex(0x6f3d86),
+	hex(0x588d43),
+	hex(0x352879),
+	hex(0xb8c76f),
+	hex(0x6f4f25),
+	hex(0x433900),
+	hex(0x9a6759),
+	hex(0x444444),
+	hex(0x6c6c6c),
+	hex(0x9ad284),
+	hex(0x6c5eb5),
+	hex(0x959595),
+];
+
+/// The sixteen colours of the PICO-8 fantasy console
+pub const PICO_8: [RGB8; 16] = [
+	hex(0x000000),
+	hex(0x1d2b53),
+	hex(0x7e2553),
+	hex(0x008751),
+	hex(0xab5236),
+	hex(0x5f574f),
+	hex(0xc2c3c7),
+	hex(0xfff1e8),
+	hex(0xff004d),
+	hex(0xffa300),
+	hex(0xffec27),
+	hex(0x00e436),
+	hex(0x29adff),
+	hex(0x83769c),
+	hex(0xff77a8),
+	hex(0xffccaa),
+];
+
+/// A colour from a `0xRRGGBB` integer.
+pub const fn hex(rgb: u32) -> RGB8 {
+	RGB8 {
+		r: (rgb >> 16) as u8,
+		g: (rgb >> 8) as u8,
+		b: rgb as u8,
+	}
+}
+
+/// `N` evenly spaced grays from black to white. A ramp of one is just black.
+pub const fn gray_ramp<const N: usize>() -> [RGB8; N] {
+	let mut ramp = [RGB8 { r: 0, g: 0, b: 0 }; N];
+
+	let mut idx = 0;
+	while idx < N {
+		let level = if N > 1 {
+			(idx * 255 / (N - 1)) as u8
+		} else {
+			0
+		};
+		ramp[idx] = RGB8 {
+			r: level,
+			g: level,
+			b: level,
+		};
+		idx += 1;
+	}
+
+	ramp
+}
+
+const fn web_safe() -> [RGB8; 216] {
+	let mut palette = [RGB8 { r: 0, g: 0, b: 0 }; 216];
+
+	let mut idx = 0;
+	while idx < 216 {
+		palette[idx] = RGB8 {
+			r: (idx / 36 * 51) as u8,
+			g: (idx / 6 % 6 * 51) as u8,
+			b: (idx % 6 * 51) as u8,
+		};
+		idx += 1;
+	}
+
+	palette
+}
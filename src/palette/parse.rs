@@ -0,0 +1,402 @@
+//! Parsers for palette files.
+//!
+//! These are written expecting untrusted input: every length is checked before
+//! it's used, nothing is allocated based on a length in the file until it's been
+//! checked against the data actually present, and palettes are limited to
+//! [MAX_COLORS] entries.
+//!
+//! Supported are GIMP (`.gpl`), Adobe Color Table (`.act`), Adobe Swatch
+//! Exchange (`.ase`), JASC and RIFF palettes (`.pal`), and lists of hex colours
+//! (`.hex`).
+
+use rgb::RGB8;
+use thiserror::Error;
+
+/// The most colours a parsed palette may have.
+pub const MAX_COLORS: usize = 65536;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseError {
+	#[error("file ended unexpectedly")]
+	UnexpectedEof,
+	#[error("not the expected file format: {0}")]
+	BadMagic(&'static str),
+	#[error("file is not valid UTF-8")]
+	NotUtf8,
+	#[error("line {line}: {reason}")]
+	BadLine { line: usize, reason: &'static str },
+	#[error("file has an invalid length of {0} bytes")]
+	BadLength(usize),
+	#[error("colour model '{0}' isn't supported")]
+	UnsupportedModel(String),
+	#[error("palette has more than {MAX_COLORS} colours")]
+	TooManyColors,
+	#[error("palette has no colours")]
+	Empty,
+}
+
+/// The formats we can parse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+	Gpl,
+	Act,
+	Ase,
+	Pal,
+	Hex,
+}
+
+impl Format {
+	/// Guess the format from a file extension, without the dot. Case
+	/// insensitive.
+	pub fn from_extension(ext: &str) -> Option<Self> {
+		match ext.to_ascii_lowercase().as_str() {
+			"gpl" => Some(Format::Gpl),
+			"act" => Some(Format::Act),
+			"ase" => Some(Format::Ase),
+			"pal" => Some(Format::Pal),
+			"hex" | "txt" => Some(Format::Hex),
+			_ => None,
+		}
+	}
+}
+
+/// Parse a palette of the given format.
+pub fn parse(data: &[u8], format: Format) -> Result<Vec<RGB8>, ParseError> {
+	match format {
+		Format::Gpl => gpl(text(data)?),
+		Format::Act => act(data),
+		Format::Ase => ase(data),
+		Format::Pal => pal(data),
+		Format::Hex => hex(text(data)?),
+	}
+}
+
+/// A GIMP palette. Colours are lines of three whitespace separated integers
+/// from 0 to 255 that may be followed by a name.
+pub fn gpl(data: &str) -> Result<Vec<RGB8>, ParseError> {
+	let mut lines = data.lines().enumerate();
+
+	match lines.next() {
+		Some((_, header)) if header.trim_end() == "GIMP Palette" => (),
+		_ => return Err(ParseError::BadMagic("GIMP Palette")),
+	}
+
+	let mut colors = vec![];
+	for (idx, line) in lines {
+		let line = line.trim();
+
+		if line.is_empty()
+			|| line.starts_with('#')
+			|| line.starts_with("Name:")
+			|| line.starts_with("Columns:")
+		{
+			continue;
+		}
+
+		let mut parts = line.split_whitespace();
+		let mut component = || {
+			parts
+				.next()
+				.ok_or(ParseError::BadLine {
+					line: idx + 1,
+					reason: "expected three colour components",
+				})?
+				.parse::<u8>()
+				.map_err(|_| ParseError::BadLine {
+					line: idx + 1,
+					reason: "colour components must be integers from 0 to 255",
+				})
+		};
+
+		let color = RGB8::new(component()?, component()?, component()?);
+		push(&mut colors, color)?;
+	}
+
+	non_empty(colors)
+}
+
+/// An Adobe Color Table. It's 256 RGB triplets, 768 bytes, optionally followed
+/// by a big-endian u16 colour count and u16 transparent index.
+pub fn act(data: &[u8]) -> Result<Vec<RGB8>, ParseError> {
+	let count = match data.len() {
+		768 => 256,
+		772 => {
+			let count = u16::from_be_bytes([data[768], data[769]]) as usize;
+			// some writers use 0 to mean all 256
+			if count == 0 {
+				256
+			} else {
+				count
+			}
+		}
+		len => return Err(ParseError::BadLength(len)),
+	};
+
+	if count > 256 {
+		return Err(ParseError::BadLength(data.len()));
+	}
+
+	non_empty(
+		data[..count * 3]
+			.chunks_exact(3)
+			.map(|c| RGB8::new(c[0], c[1], c[2]))
+			.collect(),
+	)
+}
+
+/// An Adobe Swatch Exchange file. RGB and Gray swatches are read, any other
+/// colour model is an error. Groups are flattened.
+pub fn ase(data: &[u8]) -> Result<Vec<RGB8>, ParseError> {
+	let mut reader = Reader::new(data);
+
+	if reader.take(4)? != b"ASEF" {
+		return Err(ParseError::BadMagic("ASEF"));
+	}
+	// major and minor version, which we don't care about
+	reader.take(4)?;
+
+	let blocks = reader.u32()?;
+	let mut colors = vec![];
+	for _ in 0..blocks {
+		let kind = reader.u16()?;
+		let len = reader.u32()? as usize;
+		let mut block = Reader::new(reader.take(len)?);
+
+		// 0x0001 colour entry, 0xc001 group start, 0xc002 group end
+		if kind != 0x0001 {
+			continue;
+		}
+
+		// the name is a u16 count of UTF-16 code units, null included
+		let name_len = block.u16()? as usize;
+		block.take(name_len * 2)?;
+
+		let model = block.take(4)?;
+		let mut channel = || -> Result<u8, ParseError> {
+			let value = f32::from_bits(block.u32()?);
+			Ok((value.clamp(0.0, 1.0) * 255.0).round() as u8)
+		};
+
+		let color = match model {
+			b"RGB " => RGB8::new(channel()?, channel()?, channel()?),
+			b"Gray" => {
+				let gray = channel()?;
+				RGB8::new(gray, gray, gray)
+			}
+			other => {
+				return Err(ParseError::UnsupportedModel(
+					String::from_utf8_lossy(other).trim().to_owned(),
+				))
+			}
+		};
+
+		push(&mut colors, color)?;
+	}
+
+	non_empty(colors)
+}
+
+/// A `.pal` file, which is either a JASC palette (the text format Paint Shop
+/// Pro uses) or a Microsoft RIFF palette.
+pub fn pal(data: &[u8]) -> Result<Vec<RGB8>, ParseError> {
+	if data.starts_with(b"RIFF") {
+		riff_pal(data)
+	} else {
+		jasc_pal(text(data)?)
+	}
+}
+
+/// A JASC palette. A `JASC-PAL` line, a version line, a count, and then that
+/// many lines of three integers.
+pub fn jasc_pal(data: &str) -> Result<Vec<RGB8>, ParseError> {
+	let mut lines = data.lines().map(str::trim).enumerate();
+
+	match lines.next() {
+		Some((_, "JASC-PAL")) => (),
+		_ => return Err(ParseError::BadMagic("JASC-PAL")),
+	}
+
+	match lines.next() {
+		Some((_, "0100")) => (),
+		Some((idx, _)) => {
+			return Err(ParseError::BadLine {
+				line: idx + 1,
+				reason: "unknown version. expected 0100",
+			})
+		}
+		None => return Err(ParseError::UnexpectedEof),
+	}
+
+	let count = match lines.next() {
+		Some((idx, count)) => count.parse::<usize>().map_err(|_| ParseError::BadLine {
+			line: idx + 1,
+			reason: "colour count must be an integer",
+		})?,
+		None => return Err(ParseError::UnexpectedEof),
+	};
+
+	if count > MAX_COLORS {
+		return Err(ParseError::TooManyColors);
+	}
+
+	let mut colors = vec![];
+	for (idx, line) in lines.filter(|(_, line)| !line.is_empty()) {
+		let components = line
+			.split_whitespace()
+			.map(|c| c.parse::<u8>())
+			.collect::<Result<Vec<u8>, _>>();
+
+		match components.as_deref() {
+			Ok([r, g, b]) => push(&mut colors, RGB8::new(*r, *g, *b))?,
+			_ => {
+				return Err(ParseError::BadLine {
+					line: idx + 1,
+					reason: "expected three integers from 0 to 255",
+				})
+			}
+		}
+	}
+
+	if colors.len() != count {
+		return Err(ParseError::UnexpectedEof);
+	}
+
+	non_empty(colors)
+}
+
+/// A Microsoft RIFF palette. The colours are in the `data` chunk of a `PAL `
+/// form as a u16 version, u16 count, and then four bytes per colour: red, green,
+/// blue, and flags.
+pub fn riff_pal(data: &[u8]) -> Result<Vec<RGB8>, ParseError> {
+	let mut reader = Reader::new(data);
+
+	if reader.take(4)? != b"RIFF" {
+		return Err(ParseError::BadMagic("RIFF"));
+	}
+	// RIFF sizes are little-endian, unlike everything else in this file
+	let riff_len = reader.u32_le()? as usize;
+	let mut riff = Reader::new(reader.take(riff_len)?);
+
+	if riff.take(4)? != b"PAL " {
+		return Err(ParseError::BadMagic("PAL "));
+	}
+
+	while !riff.is_empty() {
+		let id = riff.take(4)?;
+		let len = riff.u32_le()? as usize;
+		let chunk = riff.take(len)?;
+		// chunks are padded to an even length
+		if len % 2 == 1 && !riff.is_empty() {
+			riff.take(1)?;
+		}
+
+		if id != b"data" {
+			continue;
+		}
+
+		let mut chunk = Reader::new(chunk);
+		let _version = chunk.take(2)?;
+		let count = u16::from_le_bytes(chunk.take(2)?.try_into().unwrap()) as usize;
+		let entries = chunk.take(count * 4)?;
+
+		return non_empty(
+			entries
+				.chunks_exact(4)
+				.map(|c| RGB8::new(c[0], c[1], c[2]))
+				.collect(),
+		);
+	}
+
+	Err(ParseError::UnexpectedEof)
+}
+
+/// A list of hex colours, one per line, like `ff0080` or `#ff0080`. Blank lines
+/// are skipped.
+pub fn hex(data: &str) -> Result<Vec<RGB8>, ParseError> {
+	let mut colors = vec![];
+
+	for (idx, line) in data.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let digits = line.strip_prefix('#').unwrap_or(line);
+		let color = parse_hex(digits).ok_or(ParseError::BadLine {
+			line: idx + 1,
+			reason: "expected a six digit hex colour",
+		})?;
+
+		push(&mut colors, color)?;
+	}
+
+	non_empty(colors)
+}
+
+fn parse_hex(digits: &str) -> Option<RGB8> {
+	// checking for ASCII also makes the byte slicing below safe
+	if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None;
+	}
+
+	let channel = |idx: usize| u8::from_str_radix(&digits[idx..idx + 2], 16).ok();
+	Some(RGB8::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn text(data: &[u8]) -> Result<&str, ParseError> {
+	std::str::from_utf8(data).map_err(|_| ParseError::NotUtf8)
+}
+
+fn push(colors: &mut Vec<RGB8>, color: RGB8) -> Result<(), ParseError> {
+	if colors.len() >= MAX_COLORS {
+		Err(ParseError::TooManyColors)
+	} else {
+		colors.push(color);
+		Ok(())
+	}
+}
+
+fn non_empty(colors: Vec<RGB8>) -> Result<Vec<RGB8>, ParseError> {
+	if colors.is_empty() {
+		Err(ParseError::Empty)
+	} else {
+		Ok(colors)
+	}
+}
+
+/// Bounds checked reads from a byte slice.
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		Self { data }
+	}
+
+	fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
+	fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+		if len > self.data.len() {
+			return Err(ParseError::UnexpectedEof);
+		}
+
+		let (taken, rest) = self.data.split_at(len);
+		self.data = rest;
+		Ok(taken)
+	}
+
+	fn u16(&mut self) -> Result<u16, ParseError> {
+		Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	fn u32(&mut self) -> Result<u32, ParseError> {
+		Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+	}
+
+	fn u32_le(&mut self) -> Result<u32, ParseError> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+}
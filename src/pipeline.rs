@@ -0,0 +1,114 @@
+//! Quantizing a stream of images with decoding, selection, and mapping
+//! overlapped.
+//!
+//! Each stage runs on its own thread and hands its work to the next through a
+//! channel, so while one image is being mapped the next is having its palette
+//! selected and the one after that is being decoded. A [Squasher] isn't
+//! [Send], so every stage builds its own from the closure given to [run] and
+//! only the images and palettes move between threads.
+
+use std::{
+	sync::{
+		mpsc::{self, Receiver},
+		Arc,
+	},
+	thread,
+};
+
+use rgb::RGB8;
+
+use crate::{Count, Squasher, SquasherBuilder};
+
+/// How many images can wait between two stages. Keeps a fast decoder from
+/// filling memory while mapping catches up.
+const STAGE_BUFFER: usize = 2;
+
+/// An image that's come out of the end of the pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quantized<T> {
+	/// Where the image was in the input
+	pub index: usize,
+	pub palette: Vec<RGB8>,
+	/// One index into the palette for every pixel
+	pub indices: Vec<T>,
+}
+
+/// Quantize every input, in order, on three threads: one calling `decode` to
+/// turn an input into RGB bytes, one selecting a palette, and one mapping.
+///
+/// `make` is called once by the selection and mapping threads, each, to get a
+/// builder with the selector and other settings to use. It must set a
+/// selector.
+///
+/// Results come out in the same order as the inputs. A decode error is passed
+/// through in place of that image and the rest keep going. If a stage panics
+/// the receiver's iterator ends early; dropping the receiver stops the
+/// pipeline.
+pub fn run<I, E, T, Dec, Make>(
+	inputs: I,
+	mut decode: Dec,
+	make: Make,
+) -> Receiver<Result<Quantized<T>, E>>
+where
+	I: IntoIterator,
+	I::IntoIter: Send + 'static,
+	E: Send + 'static,
+	T: Count + Send + 'static,
+	Dec: FnMut(I::Item) -> Result<Vec<u8>, E> + Send + 'static,
+	Make: Fn() -> SquasherBuilder<T> + Send + Sync + 'static,
+{
+	let make = Arc::new(make);
+	let inputs = inputs.into_iter();
+
+	let (decoded_tx, decoded_rx) = mpsc::sync_channel(STAGE_BUFFER);
+	thread::spawn(move || {
+		for (index, input) in inputs.enumerate() {
+			if decoded_tx.send((index, decode(input))).is_err() {
+				break;
+			}
+		}
+	});
+
+	let (selected_tx, selected_rx) = mpsc::sync_channel(STAGE_BUFFER);
+	let select_make = make.clone();
+	thread::spawn(move || {
+		let mut squasher: Squasher<T> = select_make().build_empty();
+
+		for (index, decoded) in decoded_rx {
+			let selected = decoded.map(|rgb| {
+				squasher.recolor(&rgb);
+				(rgb, squasher.palette().to_vec())
+			});
+
+			if selected_tx.send((index, selected)).is_err() {
+				break;
+			}
+		}
+	});
+
+	let (mapped_tx, mapped_rx) = mpsc::sync_channel(STAGE_BUFFER);
+	thread::spawn(move || {
+		let mut squasher: Squasher<T> = make().build_with_palette(vec![]);
+
+		for (index, selected) in selected_rx {
+			let mapped = selected.map(|(rgb, palette)| {
+				squasher.set_palette(palette);
+
+				let mut indices = vec![T::zero(); rgb.len() / 3];
+				squasher.map(&rgb, &mut indices);
+
+				Quantized {
+					index,
+					palette: squasher.palette().to_vec(),
+					indices,
+				}
+			});
+
+			if mapped_tx.send(mapped).is_err() {
+				break;
+			}
+		}
+	});
+
+	mapped_rx
+}
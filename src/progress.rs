@@ -0,0 +1,20 @@
+//! Hearing how far along a quantization is, to show a progress bar while a big
+//! image is squashed.
+//!
+//! Give a callback to [SquasherBuilder::progress](crate::SquasherBuilder::progress).
+//! It's called with the [Stage] and how much of it is done, from 0.0 to 1.0,
+//! at the same points the [cancel token](crate::cancel) is checked, so every
+//! few thousand colours or every row rather than every pixel. Selectors don't
+//! say how far along they are, so selecting is only ever 0.0 and then 1.0.
+
+/// What a [Squasher](crate::Squasher) is doing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+	/// Selecting the palette from the colours fed to it
+	Selecting,
+	/// Finding the nearest colour in the palette for the colours of the image
+	Mapping,
+}
+
+/// Called with the stage and how much of it is done, from 0.0 to 1.0
+pub type ProgressFn = dyn Fn(Stage, f32);
@@ -0,0 +1,82 @@
+//! The partition of RGB space made by a palette.
+//!
+//! Every 24-bit colour maps to its closest palette entry, so the palette and
+//! difference function split the RGB cube into one region per entry, like a
+//! Voronoi diagram. How big those regions are says which entries are doing the
+//! most work, which is useful when deciding what to split when growing a
+//! palette or how well a map will compress.
+
+use rgb::RGB8;
+
+use crate::difference::{self, ColorDifference};
+
+/// The number of 24-bit colours.
+const CUBE_VOLUME: usize = 256 * 256 * 256;
+
+/// The part of RGB space that maps to one palette entry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Region {
+	/// The number of 24-bit colours that map to this entry
+	pub volume: usize,
+	/// The smallest box containing the region, as inclusive minimum and maximum
+	/// corners. None if nothing maps to this entry.
+	pub bounds: Option<(RGB8, RGB8)>,
+}
+
+impl Region {
+	/// The fraction of RGB space in the region, between 0.0 and 1.0
+	pub fn fraction(&self) -> f32 {
+		self.volume as f32 / CUBE_VOLUME as f32
+	}
+
+	fn add(&mut self, min: RGB8, max: RGB8, volume: usize) {
+		self.volume += volume;
+		self.bounds = Some(match self.bounds {
+			None => (min, max),
+			Some((lo, hi)) => (
+				RGB8::new(lo.r.min(min.r), lo.g.min(min.g), lo.b.min(min.b)),
+				RGB8::new(hi.r.max(max.r), hi.g.max(max.g), hi.b.max(max.b)),
+			),
+		});
+	}
+}
+
+/// Find the region of each palette entry, returned in palette order.
+///
+/// Only every `step`th value of each channel is checked and the whole cell
+/// around it is counted as mapping to the same entry. A `step` of 1 is exact
+/// but compares all 16M colours against the palette; 4 is a lot faster and
+/// usually close enough. A `step` of 0 is treated as 1.
+pub fn partition<D: ColorDifference + ?Sized>(
+	palette: &[RGB8],
+	difference: &D,
+	step: u8,
+) -> Vec<Region> {
+	let step = step.max(1) as usize;
+	let mut regions = vec![Region::default(); palette.len()];
+
+	// the first value of each cell and how many values it covers
+	let cells: Vec<(u8, u8, usize)> = (0..256)
+		.step_by(step)
+		.map(|start| {
+			let len = step.min(256 - start);
+			(start as u8, (start + len - 1) as u8, len)
+		})
+		.collect();
+
+	for &(r, r_end, r_len) in &cells {
+		for &(g, g_end, g_len) in &cells {
+			for &(b, b_end, b_len) in &cells {
+				let colour = RGB8::new(r, g, b);
+				let index = difference::nearest(&colour, palette, difference);
+
+				if let Some(region) = regions.get_mut(index) {
+					let max = RGB8::new(r_end, g_end, b_end);
+					region.add(colour, max, r_len * g_len * b_len);
+				}
+			}
+		}
+	}
+
+	regions
+}
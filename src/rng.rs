@@ -0,0 +1,33 @@
+/// SplitMix64. Small, fast, and, most importantly, the same on every platform
+/// so a seed always gives the same result.
+///
+/// <https://prng.di.unimi.it/splitmix64.c>
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64 {
+	state: u64,
+}
+
+impl SplitMix64 {
+	pub fn new(seed: u64) -> Self {
+		Self { state: seed }
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// A float in `0.0..1.0`
+	pub fn next_f32(&mut self) -> f32 {
+		// top 24 bits, as that's all the precision an f32 has
+		(self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+	}
+
+	/// An integer in `0..max`. `max` must not be zero.
+	pub fn below(&mut self, max: usize) -> usize {
+		(self.next_u64() % max as u64) as usize
+	}
+}
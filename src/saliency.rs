@@ -0,0 +1,50 @@
+//! Weighting pixels by how much they stand out from their neighbours. See
+//! [SquasherBuilder::saliency](crate::SquasherBuilder::saliency).
+//!
+//! A big flat background is millions of pixels of nearly the same colour, so
+//! it takes most of the histogram and small details that matter, like a logo or
+//! the eyes in a portrait, don't get any palette entries. Pixels inside a flat
+//! area are exactly like their neighbours, and the ones on edges and in detail
+//! aren't, so the contrast with the pixels around it is a cheap guess at how
+//! much a pixel matters.
+
+use rgb::RGB8;
+
+/// How different a pixel is from the 8 around it: the mean of the RGB
+/// distances to each of them. Pixels off the edge of the image are skipped.
+pub(crate) fn contrast(pixels: &[RGB8], width: usize, idx: usize) -> f32 {
+	let width = width.max(1);
+	let height = pixels.len().div_ceil(width);
+	let (x, y) = (idx % width, idx / width);
+	let centre = pixels[idx];
+
+	let mut sum = 0.0;
+	let mut count = 0.0;
+	for ny in y.saturating_sub(1)..(y + 2).min(height) {
+		for nx in x.saturating_sub(1)..(x + 2).min(width) {
+			let neighbour = ny * width + nx;
+			if neighbour == idx {
+				continue;
+			}
+
+			if let Some(px) = pixels.get(neighbour) {
+				let dr = px.r as f32 - centre.r as f32;
+				let dg = px.g as f32 - centre.g as f32;
+				let db = px.b as f32 - centre.b as f32;
+				sum += (dr * dr + dg * dg + db * db).sqrt();
+				count += 1.0;
+			}
+		}
+	}
+
+	if count == 0.0 {
+		0.0
+	} else {
+		sum / count
+	}
+}
+
+/// The weight of a pixel: 1, plus the contrast times the strength.
+pub(crate) fn weight(pixels: &[RGB8], width: usize, idx: usize, strength: f32) -> usize {
+	1 + (contrast(pixels, width, idx) * strength).round() as usize
+}
@@ -0,0 +1,252 @@
+//! Picking which pixels of an image are considered while selecting a palette.
+//!
+//! Large images rarely need every pixel looked at to get a good palette, and
+//! selecting from a fraction of them can be a lot faster.
+
+use rgb::RGB8;
+
+use crate::{
+	difference::{self, ColorDifference},
+	rng::SplitMix64,
+};
+
+/// How to pick the pixels of the image used for palette selection. Mapping
+/// always uses every pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Sampling {
+	/// Consider every pixel.
+	#[default]
+	All,
+	/// Consider every nth pixel, starting with the first. `EveryNth(1)` is the
+	/// same as [Sampling::All].
+	EveryNth(usize),
+	/// Consider each pixel with a probability of `fraction`, between 0.0 and
+	/// 1.0. The same seed and image always pick the same pixels.
+	Random { fraction: f32, seed: u64 },
+	/// Split the image into runs of `1 / fraction` pixels and consider one
+	/// pixel picked at random from each. This spreads samples more evenly
+	/// across the image than [Sampling::Random] does.
+	Stratified { fraction: f32, seed: u64 },
+}
+
+impl Sampling {
+	/// Consider `percent` of the pixels, an integer between 1 and 100
+	/// (inclusive), by taking evenly spaced pixels.
+	pub fn from_scale(percent: u8) -> Self {
+		assert!(percent > 0 && percent <= 100);
+
+		match 100 / percent as usize {
+			1 => Sampling::All,
+			nth => Sampling::EveryNth(nth),
+		}
+	}
+
+	/// Pick the pixels from the image.
+	pub fn sample(&self, pixels: &[RGB8]) -> Vec<RGB8> {
+		match *self {
+			Sampling::All | Sampling::EveryNth(0) | Sampling::EveryNth(1) => pixels.to_vec(),
+			_ => self
+				.sample_indices(pixels.len())
+				.into_iter()
+				.map(|idx| pixels[idx])
+				.collect(),
+		}
+	}
+
+	/// Like [Sampling::sample] but for pixels that aren't in a slice, like ones
+	/// being decoded or generated, so they don't have to be collected first.
+	/// It picks the same pixels, except [Sampling::Stratified] doesn't know how
+	/// long the last run is and might pick one past the end, which means none.
+	pub fn sample_iter<I>(&self, pixels: I) -> impl Iterator<Item = RGB8>
+	where
+		I: IntoIterator<Item = RGB8>,
+	{
+		let mut sampler = Sampler::new(*self);
+		pixels.into_iter().filter(move |_| sampler.keep())
+	}
+
+	/// The positions of the pixels [Sampling::sample] picks from `len` pixels
+	pub(crate) fn sample_indices(&self, len: usize) -> Vec<usize> {
+		match *self {
+			Sampling::All | Sampling::EveryNth(0) | Sampling::EveryNth(1) => (0..len).collect(),
+			Sampling::EveryNth(nth) => (0..len).step_by(nth).collect(),
+			Sampling::Random { fraction, seed } => {
+				let mut rng = SplitMix64::new(seed);
+				(0..len).filter(|_| rng.next_f32() < fraction).collect()
+			}
+			Sampling::Stratified { fraction, seed } => {
+				let mut rng = SplitMix64::new(seed);
+				let stratum = Self::stratum_len(fraction);
+
+				(0..len)
+					.step_by(stratum)
+					.map(|start| start + rng.below(stratum.min(len - start)))
+					.collect()
+			}
+		}
+	}
+
+	/// Roughly what fraction of the pixels are picked, between 0.0 and 1.0.
+	pub fn fraction(&self) -> f32 {
+		match *self {
+			Sampling::All | Sampling::EveryNth(0) => 1.0,
+			Sampling::EveryNth(nth) => 1.0 / nth as f32,
+			Sampling::Random { fraction, .. } | Sampling::Stratified { fraction, .. } => {
+				fraction.clamp(0.0, 1.0)
+			}
+		}
+	}
+
+	/// The same amount of sampling but picking different pixels. Evenly spaced
+	/// sampling becomes [Sampling::Stratified], which is the closest thing that
+	/// can be moved around.
+	pub(crate) fn reseeded(&self, seed: u64) -> Self {
+		match *self {
+			Sampling::All | Sampling::EveryNth(0) | Sampling::EveryNth(1) => Sampling::All,
+			Sampling::EveryNth(_) => Sampling::Stratified {
+				fraction: self.fraction(),
+				seed,
+			},
+			Sampling::Random {
+				fraction,
+				seed: old,
+			} => Sampling::Random {
+				fraction,
+				seed: old.wrapping_add(seed),
+			},
+			Sampling::Stratified {
+				fraction,
+				seed: old,
+			} => Sampling::Stratified {
+				fraction,
+				seed: old.wrapping_add(seed),
+			},
+		}
+	}
+
+	fn stratum_len(fraction: f32) -> usize {
+		if fraction <= 0.0 {
+			usize::MAX
+		} else {
+			((1.0 / fraction).round() as usize).max(1)
+		}
+	}
+}
+
+/// Where a [Sampling] is up to in pixels that come a piece at a time, so
+/// sampling the pieces picks the same pixels as sampling them all at once
+/// through [Sampling::sample_iter].
+#[derive(Clone, Debug)]
+pub(crate) struct Sampler {
+	sampling: Sampling,
+	rng: SplitMix64,
+	// how many pixels have gone by
+	position: usize,
+	// where the pixel is in the current run of a stratified sample
+	pick: usize,
+}
+
+impl Sampler {
+	pub fn new(sampling: Sampling) -> Self {
+		let rng = SplitMix64::new(match sampling {
+			Sampling::Random { seed, .. } | Sampling::Stratified { seed, .. } => seed,
+			_ => 0,
+		});
+
+		Self {
+			sampling,
+			rng,
+			position: 0,
+			pick: 0,
+		}
+	}
+
+	/// Whether the next pixel is picked
+	pub fn keep(&mut self) -> bool {
+		let idx = self.position;
+		self.position += 1;
+
+		match self.sampling {
+			Sampling::All | Sampling::EveryNth(0) | Sampling::EveryNth(1) => true,
+			Sampling::EveryNth(nth) => idx % nth == 0,
+			Sampling::Random { fraction, .. } => self.rng.next_f32() < fraction,
+			Sampling::Stratified { fraction, .. } => {
+				let stratum = Sampling::stratum_len(fraction);
+				if idx % stratum == 0 {
+					self.pick = self.rng.below(stratum);
+				}
+				idx % stratum == self.pick
+			}
+		}
+	}
+
+	/// The positions in the next `len` pixels that are picked
+	pub fn indices(&mut self, len: usize) -> impl Iterator<Item = usize> + '_ {
+		(0..len).filter(|_| self.keep())
+	}
+}
+
+/// How much the palette depends on which pixels were sampled; see
+/// [Squasher::sampling_confidence](crate::Squasher::sampling_confidence).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplingReport {
+	/// The fraction of pixels sampled
+	pub fraction: f32,
+	/// Between 0.0 and 1.0, how much of the palette came out the same, within
+	/// the tolerance, when selected from different samples. 1.0 is every entry.
+	pub confidence: f32,
+	/// The average difference from an entry in one palette to the closest entry
+	/// in another.
+	pub spread: f32,
+}
+
+impl SamplingReport {
+	/// Compare palettes selected from different samples of the same image.
+	pub(crate) fn compare<D: ColorDifference>(
+		fraction: f32,
+		palettes: &[Vec<RGB8>],
+		tolerance: f32,
+		difference: &D,
+	) -> Self {
+		let mut matched = 0;
+		let mut entries = 0;
+		let mut spread = 0.0;
+
+		for (idx, a) in palettes.iter().enumerate() {
+			for b in palettes.iter().skip(idx + 1) {
+				// both ways round, as one palette can have an entry the other is
+				// missing without it being true the other way
+				for (from, to) in [(a, b), (b, a)] {
+					for colour in from {
+						let nearest = difference::nearest(colour, to, difference);
+						let diff = match to.get(nearest) {
+							Some(near) => difference.difference(colour, near),
+							None => f32::MAX,
+						};
+
+						if diff <= tolerance {
+							matched += 1;
+						}
+						entries += 1;
+						spread += diff.min(255.0 * 3.0);
+					}
+				}
+			}
+		}
+
+		if entries == 0 {
+			return Self {
+				fraction,
+				confidence: 1.0,
+				spread: 0.0,
+			};
+		}
+
+		Self {
+			fraction,
+			confidence: matched as f32 / entries as f32,
+			spread: spread / entries as f32,
+		}
+	}
+}
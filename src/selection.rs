@@ -1,20 +1,180 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Arc, Mutex, PoisonError},
+};
 
-#[cfg(not(feature = "simd-kmeans"))]
-use crate::nih_kmeans::KMeans;
 #[cfg(feature = "simd-kmeans")]
 use kmeans::{KMeans, KMeansConfig};
 use rgb::RGB8;
 
 use crate::{
-	difference::{self, DiffFn},
+	cancel::CancelToken,
+	difference::{self, DiffFn, SyncDiffFn},
+	metrics::Objective,
+	nih_kmeans,
+	rng::SplitMix64,
 	ImageData,
 };
 
 pub trait Selector {
-	// wanted Into<ImageData> here but rustc got mad about vtable building
-	// because we store this as Box<dyn Selector> in Squasher and it's builder
-	fn select(&mut self, max_colors: usize, image: ImageData) -> Vec<RGB8>;
+	/// Pick at most `max_colors` colours for the palette. `colors` are the
+	/// colours of the image with the number of times each appears, sorted from
+	/// most to least frequent. See [histogram].
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8>;
+}
+
+/// How far the iterations of a [Steps] have got
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Progress {
+	/// The palette might still change. `iteration` is how many have been run.
+	Running { iteration: usize },
+	/// The palette stopped changing after `iteration` iterations. Stepping
+	/// again does nothing.
+	Converged { iteration: usize },
+	/// The cancel token was cancelled before the iteration was run. Reset it
+	/// to carry on.
+	Cancelled { iteration: usize },
+}
+
+/// A selector that's run an iteration at a time, like [Kmeans::steps], so an
+/// interactive tool can show the palette as it improves and stop whenever
+/// it's good enough. Give the palette to
+/// [Squasher::set_palette](crate::Squasher::set_palette) to use it.
+pub trait Steps {
+	/// Run one more iteration.
+	fn step(&mut self) -> Progress;
+
+	/// The palette as of the last iteration
+	fn current_palette(&self) -> Vec<RGB8>;
+
+	/// Step until the palette converges, the cancel token is cancelled, or the
+	/// selector's `max_iter` iterations have been run in total. Calling it
+	/// again after stepping by hand, or after resetting the cancel token,
+	/// carries on from where it stopped.
+	fn resume(&mut self) -> Progress;
+}
+
+/// The iteration count, limit, and cancel token shared by the [Steps]
+/// implementations
+#[derive(Debug)]
+struct Stepping {
+	iteration: usize,
+	max_iter: usize,
+	converged: bool,
+	cancel: Option<CancelToken>,
+}
+
+impl Stepping {
+	fn new(max_iter: usize, cancel: Option<CancelToken>) -> Self {
+		Self {
+			iteration: 0,
+			max_iter,
+			converged: false,
+			cancel,
+		}
+	}
+
+	fn progress(&self) -> Progress {
+		if self.converged {
+			Progress::Converged {
+				iteration: self.iteration,
+			}
+		} else {
+			Progress::Running {
+				iteration: self.iteration,
+			}
+		}
+	}
+
+	/// Run `iterate` once unless it's converged or cancelled. It returns false
+	/// when it's converged.
+	fn step(&mut self, iterate: impl FnOnce() -> bool) -> Progress {
+		if self.converged {
+			return self.progress();
+		}
+
+		if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+			return Progress::Cancelled {
+				iteration: self.iteration,
+			};
+		}
+
+		self.iteration += 1;
+		self.converged = !iterate();
+		self.progress()
+	}
+
+	fn resume(&mut self, mut iterate: impl FnMut() -> bool) -> Progress {
+		while self.iteration < self.max_iter {
+			match self.step(&mut iterate) {
+				Progress::Running { .. } => (),
+				stopped => return stopped,
+			}
+		}
+
+		self.progress()
+	}
+}
+
+/// Count the colours of an image, sorted the way [Selector::select] expects
+/// them. Useful if you're calling a selector yourself. If the image has
+/// [weights](ImageData::with_weights) they're applied.
+pub fn histogram<'a, Img>(image: Img) -> Vec<(RGB8, usize)>
+where
+	Img: Into<ImageData<'a>>,
+{
+	let ImageData {
+		pixels: rgb,
+		weights,
+		..
+	} = image.into();
+	let mut colors: HashMap<RGB8, usize> = HashMap::default();
+
+	match weights {
+		None => {
+			for px in rgb.iter() {
+				*colors.entry(*px).or_default() += 1;
+			}
+		}
+		Some(weights) => {
+			for (px, &weight) in rgb.iter().zip(weights.iter()) {
+				if weight > 0 {
+					*colors.entry(*px).or_default() += weight as usize;
+				}
+			}
+		}
+	}
+
+	sort(colors)
+}
+
+/// Like [histogram] but for pixels that aren't in a slice, like ones being
+/// decoded or generated, so they don't have to be collected first.
+pub fn histogram_pixels<I>(pixels: I) -> Vec<(RGB8, usize)>
+where
+	I: IntoIterator<Item = RGB8>,
+{
+	let mut colors: HashMap<RGB8, usize> = HashMap::default();
+	for px in pixels {
+		*colors.entry(px).or_default() += 1;
+	}
+
+	sort(colors)
+}
+
+/// Sort counted colours most to least frequent. Ties are broken by the colour
+/// so the order doesn't depend on the HashMap's.
+pub(crate) fn sort(map: HashMap<RGB8, usize>) -> Vec<(RGB8, usize)> {
+	let mut sorted: Vec<(RGB8, usize)> = map.into_iter().collect();
+	sorted.sort_by(|(colour1, freq1), (colour2, freq2)| {
+		freq2
+			.cmp(freq1)
+			.then(colour2.r.cmp(&colour1.r))
+			.then(colour2.g.cmp(&colour1.g))
+			.then(colour2.b.cmp(&colour1.b))
+	});
+
+	sorted
 }
 
 pub struct SortSelect {
@@ -25,12 +185,11 @@ pub struct SortSelect {
 impl Selector for SortSelect {
 	/// Pick the colors in the palette from a Vec of colors sorted by number
 	/// of times they occur, high to low.
-	fn select(&mut self, max_colours: usize, image: ImageData) -> Vec<RGB8> {
-		let sorted = Self::unique_and_sort(image);
+	fn select(&mut self, max_colours: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
 		let tolerance = (self.tolerance / 100.0) * 765.0;
 		let mut selected_colors: Vec<RGB8> = Vec::with_capacity(max_colours);
 
-		for sorted_color in sorted {
+		for &(sorted_color, _) in colors {
 			if max_colours <= selected_colors.len() {
 				break;
 			} else if selected_colors.iter().all(|selected_color| {
@@ -60,40 +219,6 @@ impl SortSelect {
 		self.difference_fn = Box::new(diff_fn);
 		self
 	}
-
-	/// Takes an image buffer of RGB data and fill the color map
-	fn unique_and_sort<'a, Img>(buffer: Img) -> Vec<RGB8>
-	where
-		Img: Into<ImageData<'a>>,
-	{
-		let ImageData(rgb) = buffer.into();
-		let mut colors: HashMap<RGB8, usize> = HashMap::default();
-
-		//count pixels
-		for px in rgb {
-			match colors.get_mut(px) {
-				None => {
-					colors.insert(*px, 1);
-				}
-				Some(n) => *n += 1,
-			}
-		}
-
-		Self::sort(colors)
-	}
-
-	fn sort(map: HashMap<RGB8, usize>) -> Vec<RGB8> {
-		let mut sorted: Vec<(RGB8, usize)> = map.into_iter().collect();
-		sorted.sort_by(|(colour1, freq1), (colour2, freq2)| {
-			freq2
-				.cmp(freq1)
-				.then(colour2.r.cmp(&colour1.r))
-				.then(colour2.g.cmp(&colour1.g))
-				.then(colour2.b.cmp(&colour1.b))
-		});
-
-		sorted.into_iter().map(|(color, _count)| color).collect()
-	}
 }
 
 impl Default for SortSelect {
@@ -108,33 +233,78 @@ impl Default for SortSelect {
 #[derive(Debug, Default)]
 pub struct Kmeans {
 	pub max_iter: usize,
+	/// Picks the first centroid so the same seed and image always give the
+	/// same palette, on every platform. Without one it's random with the `rand`
+	/// feature and the most frequent colour without.
+	///
+	/// With the `simd-kmeans` feature the kmeans crate does its own seeding
+	/// and this is ignored.
+	pub seed: Option<u64>,
+	/// Checked between iterations. See the [cancel](crate::cancel) module.
+	pub cancel: Option<CancelToken>,
+}
+
+impl Kmeans {
+	/// Start selecting a palette an iteration at a time, see [Steps]. This is
+	/// always the internal k-means, even with the `simd-kmeans` feature, because
+	/// the kmeans crate can't be stopped part way.
+	pub fn steps(&self, max_colors: usize, colors: &[(RGB8, usize)]) -> KmeansSteps {
+		KmeansSteps {
+			iterations: nih_kmeans::KMeans::new(colors.to_vec()).start(max_colors, self.seed),
+			stepping: Stepping::new(self.max_iter, self.cancel.clone()),
+		}
+	}
+}
+
+/// [Kmeans] part way through. See [Kmeans::steps].
+pub struct KmeansSteps {
+	iterations: nih_kmeans::Iterations,
+	stepping: Stepping,
+}
+
+impl Steps for KmeansSteps {
+	fn step(&mut self) -> Progress {
+		self.stepping.step(|| self.iterations.step())
+	}
+
+	fn current_palette(&self) -> Vec<RGB8> {
+		self.iterations.centroids()
+	}
+
+	fn resume(&mut self) -> Progress {
+		self.stepping.resume(|| self.iterations.step())
+	}
 }
 
 #[cfg(not(feature = "simd-kmeans"))]
 impl Selector for Kmeans {
-	fn select(&mut self, max_colors: usize, image: ImageData) -> Vec<RGB8> {
-		let ImageData(rgb) = image;
-
-		let kmean = KMeans::new(rgb.to_vec());
-		kmean.get_k_colors(max_colors, self.max_iter)
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let mut steps = self.steps(max_colors, colors);
+		steps.resume();
+		steps.current_palette()
 	}
 }
 
 #[cfg(feature = "simd-kmeans")]
 impl Selector for Kmeans {
-	fn select(&mut self, max_colors: usize, image: ImageData) -> Vec<RGB8> {
-		use rgb::ComponentBytes;
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		// the kmeans crate runs to the end once it's started, so this is the
+		// only place we can check
+		if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+			return vec![];
+		}
 
-		let ImageData(rgb) = image;
+		// the kmeans crate doesn't take weights, so every colour is repeated
+		// as many times as it appears
+		let samples: Vec<f32> = colors
+			.iter()
+			.flat_map(|(c, count)| std::iter::repeat([c.r, c.g, c.b]).take(*count))
+			.flatten()
+			.map(|u| u as f32)
+			.collect();
+		let count = samples.len() / 3;
 
-		let kmean = KMeans::new(
-			rgb.as_bytes()
-				.iter()
-				.map(|u| *u as f32)
-				.collect::<Vec<f32>>(),
-			rgb.as_bytes().len() / 3,
-			3,
-		);
+		let kmean = KMeans::new(samples, count, 3);
 
 		let result = kmean.kmeans_lloyd(
 			max_colors,
@@ -157,60 +327,301 @@ impl Selector for Kmeans {
 	}
 }
 
+/// K-means that moves the centroids towards a small random batch of colours
+/// every iteration instead of assigning every colour. A lot faster than
+/// [Kmeans] on large images, with a palette that's nearly as good.
+///
+/// Colours are picked for a batch in proportion to how often they appear.
+#[derive(Debug)]
+pub struct MiniBatchKmeans {
+	/// How many colours are drawn for each iteration
+	pub batch_size: usize,
+	pub max_iter: usize,
+	/// The same seed and image always give the same palette
+	pub seed: u64,
+	/// Checked between iterations. See the [cancel](crate::cancel) module.
+	pub cancel: Option<CancelToken>,
+}
+
+impl Default for MiniBatchKmeans {
+	fn default() -> Self {
+		Self {
+			batch_size: 1024,
+			max_iter: 100,
+			seed: 0,
+			cancel: None,
+		}
+	}
+}
+
+impl Selector for MiniBatchKmeans {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		if colors.is_empty() || max_colors == 0 {
+			return vec![];
+		}
+
+		let mut steps = self.steps(max_colors, colors);
+		steps.resume();
+		steps.current_palette()
+	}
+}
+
+impl MiniBatchKmeans {
+	/// Start selecting a palette an iteration at a time, see [Steps]. It never
+	/// converges, so [Steps::resume] always runs `max_iter` iterations.
+	pub fn steps(&self, max_colors: usize, colors: &[(RGB8, usize)]) -> MiniBatchSteps {
+		let mut stepping = Stepping::new(self.max_iter, self.cancel.clone());
+
+		// the colours are sorted by frequency, so start from the most common.
+		// if there aren't more colours than that we're already done
+		let centroids: Vec<[f32; 3]> = colors
+			.iter()
+			.take(max_colors)
+			.map(|(c, _)| [c.r as f32, c.g as f32, c.b as f32])
+			.collect();
+		stepping.converged = colors.len() <= max_colors;
+
+		// running total of the counts to draw from in proportion to them
+		let cumulative: Vec<usize> = colors
+			.iter()
+			.scan(0, |total, (_, count)| {
+				*total += count;
+				Some(*total)
+			})
+			.collect();
+
+		// with no centroids or nothing to draw there's nothing to iterate
+		let total = cumulative.last().copied().unwrap_or(0);
+		stepping.converged |= centroids.is_empty() || total == 0;
+
+		MiniBatchSteps {
+			batches: Batches {
+				colors: colors.iter().map(|(c, _)| *c).collect(),
+				total,
+				cumulative,
+				seen: vec![0; centroids.len()],
+				centroids,
+				rng: SplitMix64::new(self.seed),
+				batch: Vec::with_capacity(self.batch_size),
+				batch_size: self.batch_size,
+			},
+			stepping,
+		}
+	}
+
+	fn nearest(centroids: &[[f32; 3]], colour: &[f32; 3]) -> usize {
+		let distance = |c: &[f32; 3]| {
+			(c[0] - colour[0]).powi(2) + (c[1] - colour[1]).powi(2) + (c[2] - colour[2]).powi(2)
+		};
+
+		centroids
+			.iter()
+			.enumerate()
+			.min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+			.map(|(idx, _)| idx)
+			.unwrap_or(0)
+	}
+}
+
+/// [MiniBatchKmeans] part way through. See [MiniBatchKmeans::steps].
+#[derive(Debug)]
+pub struct MiniBatchSteps {
+	batches: Batches,
+	stepping: Stepping,
+}
+
+#[derive(Debug)]
+struct Batches {
+	colors: Vec<RGB8>,
+	cumulative: Vec<usize>,
+	total: usize,
+	centroids: Vec<[f32; 3]>,
+	// how many colours have moved each centroid. it moves less the more it's
+	// seen so it settles down
+	seen: Vec<usize>,
+	rng: SplitMix64,
+	batch: Vec<([f32; 3], usize)>,
+	batch_size: usize,
+}
+
+impl Batches {
+	fn iterate(&mut self) -> bool {
+		self.batch.clear();
+		for _ in 0..self.batch_size {
+			let draw = self.rng.below(self.total);
+			let idx = self.cumulative.partition_point(|&sum| sum <= draw);
+			let colour = self.colors[idx];
+
+			self.batch
+				.push(([colour.r as f32, colour.g as f32, colour.b as f32], 0));
+		}
+
+		for (colour, nearest) in self.batch.iter_mut() {
+			*nearest = MiniBatchKmeans::nearest(&self.centroids, colour);
+		}
+
+		for (colour, nearest) in &self.batch {
+			self.seen[*nearest] += 1;
+			let rate = 1.0 / self.seen[*nearest] as f32;
+
+			let centroid = &mut self.centroids[*nearest];
+			for channel in 0..3 {
+				centroid[channel] += (colour[channel] - centroid[channel]) * rate;
+			}
+		}
+
+		true
+	}
+}
+
+impl Steps for MiniBatchSteps {
+	fn step(&mut self) -> Progress {
+		self.stepping.step(|| self.batches.iterate())
+	}
+
+	fn current_palette(&self) -> Vec<RGB8> {
+		self.batches
+			.centroids
+			.iter()
+			.map(|[r, g, b]| RGB8::new(r.round() as u8, g.round() as u8, b.round() as u8))
+			.collect()
+	}
+
+	fn resume(&mut self) -> Progress {
+		self.stepping.resume(|| self.batches.iterate())
+	}
+}
+
+/// [SortSelect] that searches for the tolerance, starting from its own and
+/// moving up or down by the variance while the palette gets better, halving
+/// the variance when neither way helps.
+///
+/// Each round can try more than one step each way, see
+/// [HeuristicSorsel::candidates], and the search can start from more than one
+/// tolerance at once, see [HeuristicSorsel::starts]. Every tolerance of a round
+/// is tried at the same time on [HeuristicSorsel::threads] threads, so trying
+/// more of them doesn't take much longer.
 pub struct HeuristicSorsel {
-	tolerance: f32,
+	/// The tolerances to search from
+	starts: Vec<f32>,
 	variance: f32,
 	max_attempts: usize,
-	difference_fn: Box<DiffFn>,
+	/// How many steps of the variance to try each way
+	steps: usize,
+	threads: usize,
+	difference_fn: &'static DiffFn,
+	/// The same function if it can be used from more than one thread
+	sync_difference: Option<&'static SyncDiffFn>,
+	objective: Objective,
+	/// The tolerance of the last palette selected
+	chosen: Option<f32>,
+	report: Option<ToleranceReport>,
+}
+
+/// Where a [HeuristicSorsel] leaves the tolerance it settled on, so it can be
+/// read after the selector's been given to a Squasher. Clones share it, like
+/// a [CancelToken].
+#[derive(Clone, Debug, Default)]
+pub struct ToleranceReport(Arc<Mutex<Option<f32>>>);
+
+impl ToleranceReport {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The tolerance of the last palette selected, if one has been
+	pub fn get(&self) -> Option<f32> {
+		*self.0.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn set(&self, tolerance: f32) {
+		*self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(tolerance);
+	}
 }
 
 impl Selector for HeuristicSorsel {
 	/// Pick the colors in the palette from a Vec of colors sorted by number
 	/// of times they occur, high to low.
-	fn select(&mut self, max_colours: usize, image: ImageData) -> Vec<RGB8> {
-		let colors = Self::unique_and_sort(image);
+	fn select(&mut self, max_colours: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let mut searches: Vec<Search> = self
+			.starts
+			.iter()
+			.map(|&tolerance| Search {
+				tolerance,
+				variance: self.variance,
+				best: RunData {
+					score: f32::MAX,
+					palette: vec![],
+				},
+				done: false,
+			})
+			.collect();
 
-		let mut best = RunData {
-			score: f32::MAX,
-			palette: vec![],
-		};
+		for _ in 0..self.max_attempts {
+			// every search's candidates for this round, below and above by one
+			// step, then two, and so on
+			let mut tolerances = vec![];
+			for search in searches.iter().filter(|search| !search.done) {
+				for step in 1..=self.steps {
+					let offset = search.variance * step as f32;
+					tolerances.push(search.tolerance - offset);
+					tolerances.push(search.tolerance + offset);
+				}
+			}
 
-		let mut attempts = 0;
-		//let mut current_tolerance = 9.0 - (max_colours as f32).log2();
-		let mut current_tolerance = self.tolerance;
-		let mut current_variance = self.variance;
-
-		while attempts < self.max_attempts {
-			attempts += 1;
-
-			let higher = current_tolerance + current_variance;
-			let lower = current_tolerance - current_variance;
-
-			let run_up = Self::compute_once(&colors, max_colours, higher, &self.difference_fn);
-			let run_down = Self::compute_once(&colors, max_colours, lower, &self.difference_fn);
-
-			if run_up.score >= best.score && run_down.score >= best.score {
-				// neither was better than the previous best. can we cut the
-				// variance to try and fine tune?
-				if current_variance > 0.01 {
-					// Yes, cut it in half and run again.
-					current_variance /= 2.0;
-				} else {
-					// No, we've reached our limit. Break from the loop
-					break;
+			if tolerances.is_empty() {
+				break;
+			}
+
+			let mut runs = self
+				.compute_all(colors, max_colours, &tolerances)
+				.into_iter();
+			let mut tolerances = tolerances.into_iter();
+			for search in searches.iter_mut().filter(|search| !search.done) {
+				// the first of the best, so a tie goes to the lower tolerance
+				let round_best = (0..self.steps * 2)
+					.map_while(|_| Some((tolerances.next()?, runs.next()?)))
+					.reduce(|best, next| {
+						if next.1.score < best.1.score {
+							next
+						} else {
+							best
+						}
+					});
+
+				match round_best {
+					Some((tolerance, run)) if run.score < search.best.score => {
+						search.tolerance = tolerance;
+						search.best = run;
+					}
+					// nothing was better than the previous best. can we cut the
+					// variance to try and fine tune?
+					_ if search.variance > 0.01 => search.variance /= 2.0,
+					// No, we've reached our limit
+					_ => search.done = true,
 				}
-			} else if run_up.score < run_down.score {
-				current_tolerance = higher;
-				best = run_up;
-			} else {
-				current_tolerance = lower;
-				best = run_down;
 			}
 		}
 
-		println!("final tolerance {:.2}", current_tolerance);
+		// the first of the best again, so with one start it's as it always was
+		let best = searches.into_iter().reduce(|best, next| {
+			if next.best.score < best.best.score {
+				next
+			} else {
+				best
+			}
+		});
 
-		best.palette
+		let Some(best) = best else {
+			return vec![];
+		};
+
+		self.chosen = Some(best.tolerance);
+		if let Some(report) = &self.report {
+			report.set(best.tolerance);
+		}
+
+		best.best.palette
 	}
 }
 
@@ -219,12 +630,82 @@ struct RunData {
 	score: f32,
 }
 
+/// One of the searches of a [HeuristicSorsel], from one of its starts
+struct Search {
+	tolerance: f32,
+	variance: f32,
+	best: RunData,
+	/// The variance can't be cut any more
+	done: bool,
+}
+
 impl HeuristicSorsel {
+	/// Select a palette with each tolerance, split between the threads
+	fn compute_all(
+		&self,
+		colors: &[(RGB8, usize)],
+		max_colours: usize,
+		tolerances: &[f32],
+	) -> Vec<RunData> {
+		let threads = self.threads.clamp(1, tolerances.len().max(1));
+		let sync_difference = match self.sync_difference {
+			Some(diff_fn) if threads > 1 => diff_fn,
+			_ => {
+				return tolerances
+					.iter()
+					.map(|&tolerance| {
+						Self::compute_once(
+							colors,
+							max_colours,
+							tolerance,
+							self.difference_fn,
+							&self.objective,
+						)
+					})
+					.collect();
+			}
+		};
+
+		let objective = &self.objective;
+		let per_thread = tolerances.len().div_ceil(threads);
+		std::thread::scope(|scope| {
+			let handles: Vec<_> = tolerances
+				.chunks(per_thread)
+				.map(|chunk| {
+					scope.spawn(move || {
+						chunk
+							.iter()
+							.map(|&tolerance| {
+								Self::compute_once(
+									colors,
+									max_colours,
+									tolerance,
+									sync_difference,
+									objective,
+								)
+							})
+							.collect::<Vec<_>>()
+					})
+				})
+				.collect();
+
+			handles
+				.into_iter()
+				.flat_map(|handle| {
+					handle
+						.join()
+						.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+				})
+				.collect()
+		})
+	}
+
 	fn compute_once(
 		colors: &[(RGB8, usize)],
 		max_colours: usize,
 		tolerance: f32,
 		diff_fn: &DiffFn,
+		objective: &Objective,
 	) -> RunData {
 		let tolerance = (tolerance / 100.0) * 765.0;
 		let mut selected_colors: Vec<RGB8> = Vec::with_capacity(max_colours);
@@ -240,22 +721,9 @@ impl HeuristicSorsel {
 			}
 		}
 
-		// Calculate a score for this tolerance. The total score is the sum of
-		// the color scores. The color score is the number of times that colour
-		// occures multiplied with the least difference.
-		let mut score = 0.0;
-		for (color, count) in colors {
-			let mut min_diff = f32::MAX;
-
-			for selected in &selected_colors {
-				let diff = (diff_fn)(selected, color);
-				if diff.max(0.0) < min_diff {
-					min_diff = diff;
-				}
-			}
-
-			score += min_diff * (*count as f32);
-		}
+		// Calculate a score for this tolerance. With the default objective
+		// that's the least difference to the palette averaged over every pixel.
+		let score = objective.score_palette(colors, &selected_colors, &diff_fn);
 
 		RunData {
 			palette: selected_colors,
@@ -263,8 +731,23 @@ impl HeuristicSorsel {
 		}
 	}
 
+	/// The tolerance to start searching from. Replaces any
+	/// [HeuristicSorsel::starts].
 	pub fn tolerance(mut self, tolerance: f32) -> Self {
-		self.tolerance = tolerance;
+		self.starts = vec![tolerance];
+		self
+	}
+
+	/// Search from each of these tolerances at once and keep whichever palette
+	/// is best. A few spread out, like 1, 3, and 8, are less likely to all get
+	/// stuck on a palette that's only better than the ones just either side of
+	/// it. Replaces [HeuristicSorsel::tolerance]; nothing changes if it's
+	/// empty.
+	pub fn starts(mut self, tolerances: impl Into<Vec<f32>>) -> Self {
+		let tolerances = tolerances.into();
+		if !tolerances.is_empty() {
+			self.starts = tolerances;
+		}
 		self
 	}
 
@@ -278,91 +761,598 @@ impl HeuristicSorsel {
 		self
 	}
 
+	/// How many tolerances to try each round, half below the current one and
+	/// half above, one step of the variance apart. The default is 2, one each
+	/// way. More go further in each round, which is quicker with the threads to
+	/// try them at once. Odd numbers are rounded up.
+	pub fn candidates(mut self, count: usize) -> Self {
+		self.steps = count.div_ceil(2).max(1);
+		self
+	}
+
+	/// How many threads to try the tolerances of a round on. The default is 1,
+	/// which tries them one after the other without starting any. More only
+	/// helps with a few [candidates](HeuristicSorsel::candidates), and needs
+	/// the difference function to be given with
+	/// [HeuristicSorsel::sync_difference].
+	pub fn threads(mut self, threads: usize) -> Self {
+		self.threads = threads.max(1);
+		self
+	}
+
 	/// The function to use to compare colours while selecting the palette.
+	/// Tolerances are only tried on one thread with it; see
+	/// [HeuristicSorsel::sync_difference].
 	///
 	/// see the [difference] module for functions included with the crate and
 	/// information on implementing your own.
 	pub fn difference(mut self, diff_fn: &'static DiffFn) -> Self {
-		self.difference_fn = Box::new(diff_fn);
+		self.difference_fn = diff_fn;
+		self.sync_difference = None;
 		self
 	}
 
-	/// Takes an image buffer of RGB data and fill the color map
-	fn unique_and_sort<'a, Img>(buffer: Img) -> Vec<(RGB8, usize)>
-	where
-		Img: Into<ImageData<'a>>,
-	{
-		let ImageData(rgb) = buffer.into();
-		let mut colors: HashMap<RGB8, usize> = HashMap::default();
-
-		//count pixels
-		for px in rgb {
-			match colors.get_mut(px) {
-				None => {
-					colors.insert(*px, 1);
-				}
-				Some(n) => *n += 1,
-			}
-		}
+	/// [HeuristicSorsel::difference] for a function that's [Sync], so the
+	/// tolerances can be tried on more than one [thread](HeuristicSorsel::threads).
+	/// The functions in the [difference] module all are.
+	pub fn sync_difference(mut self, diff_fn: &'static SyncDiffFn) -> Self {
+		self.difference_fn = diff_fn;
+		self.sync_difference = Some(diff_fn);
+		self
+	}
 
-		Self::sort(colors)
+	/// What makes one palette better than another while searching. See
+	/// [Objective].
+	pub fn objective(mut self, objective: Objective) -> Self {
+		self.objective = objective;
+		self
 	}
 
-	fn sort(map: HashMap<RGB8, usize>) -> Vec<(RGB8, usize)> {
-		let mut sorted: Vec<(RGB8, usize)> = map.into_iter().collect();
-		sorted.sort_by(|(colour1, freq1), (colour2, freq2)| {
-			freq2
-				.cmp(freq1)
-				.then(colour2.r.cmp(&colour1.r))
-				.then(colour2.g.cmp(&colour1.g))
-				.then(colour2.b.cmp(&colour1.b))
-		});
+	/// Also leave the tolerance it settles on in a [ToleranceReport].
+	pub fn report(mut self, report: ToleranceReport) -> Self {
+		self.report = Some(report);
+		self
+	}
 
-		sorted.into_iter().collect()
+	/// The tolerance the last palette was selected with, if one has been
+	pub fn chosen_tolerance(&self) -> Option<f32> {
+		self.chosen
 	}
 }
 
 impl Default for HeuristicSorsel {
 	fn default() -> Self {
 		Self {
-			tolerance: 3.0,
+			starts: vec![3.0],
 			variance: 0.25,
 			max_attempts: 10,
+			steps: 1,
+			threads: 1,
+			difference_fn: &difference::rgb,
+			sync_difference: Some(&difference::rgb),
+			objective: Objective::default(),
+			chosen: None,
+			report: None,
+		}
+	}
+}
+
+/// Groups the colours into cells of the histogram and then merges the two
+/// closest clusters until only `max_colors` are left. Merging is weighted by
+/// how many pixels each cluster covers (Ward's method) so a small, vivid group
+/// of colours isn't swallowed by a large one nearby. Every run on the same
+/// image gives the same palette, and that palette changes little when the
+/// image does.
+///
+/// Distances are Euclidean in RGB.
+#[derive(Copy, Clone, Debug)]
+pub struct Agglomerative {
+	/// The colours are grouped by the top this-many bits of each channel
+	/// before merging starts. 4 is 4096 cells; more is slower but merges from
+	/// finer detail. Between 1 and 8.
+	pub cell_bits: u8,
+}
+
+impl Default for Agglomerative {
+	fn default() -> Self {
+		Self { cell_bits: 4 }
+	}
+}
+
+/// The sum of a cluster's colours, weighted, and how many pixels it covers
+#[derive(Copy, Clone)]
+struct Cluster {
+	sum: [f64; 3],
+	count: f64,
+}
+
+impl Cluster {
+	fn mean(&self) -> [f64; 3] {
+		self.sum.map(|channel| channel / self.count)
+	}
+
+	fn color(&self) -> RGB8 {
+		let [r, g, b] = self.mean().map(|channel| channel.round() as u8);
+		RGB8::new(r, g, b)
+	}
+
+	/// How much merging the clusters increases the squared error
+	fn ward(&self, other: &Cluster) -> f64 {
+		let (a, b) = (self.mean(), other.mean());
+		let distance: f64 = (0..3).map(|idx| (a[idx] - b[idx]).powi(2)).sum();
+
+		distance * self.count * other.count / (self.count + other.count)
+	}
+}
+
+/// Group the colours into cells by the top `cell_bits` bits of each channel.
+/// The cells are in the same order every time.
+fn cells(colors: &[(RGB8, usize)], cell_bits: u8) -> Vec<Cluster> {
+	let shift = 8 - cell_bits.clamp(1, 8);
+
+	let mut cells: BTreeMap<(u8, u8, u8), Cluster> = BTreeMap::new();
+	for (colour, count) in colors {
+		let cell = cells
+			.entry((colour.r >> shift, colour.g >> shift, colour.b >> shift))
+			.or_insert(Cluster {
+				sum: [0.0; 3],
+				count: 0.0,
+			});
+
+		let count = *count as f64;
+		cell.sum[0] += colour.r as f64 * count;
+		cell.sum[1] += colour.g as f64 * count;
+		cell.sum[2] += colour.b as f64 * count;
+		cell.count += count;
+	}
+
+	cells.into_values().collect()
+}
+
+impl Selector for Agglomerative {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let mut clusters = cells(colors, self.cell_bits);
+		let mut alive = vec![true; clusters.len()];
+		let mut remaining = clusters.len();
+
+		let nearest_to = |clusters: &[Cluster], alive: &[bool], idx: usize| {
+			let mut nearest = (usize::MAX, f64::INFINITY);
+			for (other, cluster) in clusters.iter().enumerate() {
+				if other != idx && alive[other] {
+					let cost = clusters[idx].ward(cluster);
+					if cost < nearest.1 {
+						nearest = (other, cost);
+					}
+				}
+			}
+			nearest
+		};
+
+		let mut nearest: Vec<(usize, f64)> = (0..clusters.len())
+			.map(|idx| nearest_to(&clusters, &alive, idx))
+			.collect();
+
+		while remaining > max_colors.max(1) {
+			let Some((merge_into, &(merge_from, _))) = nearest
+				.iter()
+				.enumerate()
+				.filter(|&(idx, _)| alive[idx])
+				.min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+			else {
+				break;
+			};
+
+			let from = clusters[merge_from];
+			let into = &mut clusters[merge_into];
+			for channel in 0..3 {
+				into.sum[channel] += from.sum[channel];
+			}
+			into.count += from.count;
+			alive[merge_from] = false;
+			remaining -= 1;
+
+			// Ward's distance never drops below the closer of the two merged
+			// clusters, so only clusters whose nearest was one of them need
+			// looking at again
+			for idx in 0..clusters.len() {
+				let stale = nearest[idx].0 == merge_into || nearest[idx].0 == merge_from;
+				if alive[idx] && (idx == merge_into || stale) {
+					nearest[idx] = nearest_to(&clusters, &alive, idx);
+				}
+			}
+		}
+
+		let mut merged: Vec<Cluster> = clusters
+			.into_iter()
+			.zip(alive)
+			.filter(|&(_, alive)| alive)
+			.map(|(cluster, _)| cluster)
+			.collect();
+		merged.sort_by(|a, b| b.count.total_cmp(&a.count));
+
+		merged.iter().map(Cluster::color).collect()
+	}
+}
+
+/// [Kmeans] on the average colour of each cell of a grid rather than on every
+/// colour, weighted by how many pixels each cell covers. A photo can have
+/// millions of unique colours but there are only 32768 cells in the default
+/// grid, so this is a lot faster on big images for nearly the same palette.
+#[derive(Debug)]
+pub struct GridKmeans {
+	/// The colours are grouped by the top this-many bits of each channel. 5 is
+	/// 32x32x32 cells. Between 1 and 8.
+	pub cell_bits: u8,
+	pub kmeans: Kmeans,
+}
+
+impl Default for GridKmeans {
+	fn default() -> Self {
+		Self {
+			cell_bits: 5,
+			kmeans: Kmeans {
+				max_iter: 100,
+				..Default::default()
+			},
+		}
+	}
+}
+
+impl GridKmeans {
+	/// The cells' average colours and the pixels they cover, sorted from most
+	/// to least. Colours in different cells can't average to the same colour.
+	fn cells(&self, colors: &[(RGB8, usize)]) -> Vec<(RGB8, usize)> {
+		let mut cells: Vec<(RGB8, usize)> = cells(colors, self.cell_bits)
+			.iter()
+			.map(|cell| (cell.color(), cell.count as usize))
+			.collect();
+		cells.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+		cells
+	}
+
+	/// Like [Kmeans::steps] but on the grid, see [Steps].
+	pub fn steps(&self, max_colors: usize, colors: &[(RGB8, usize)]) -> KmeansSteps {
+		self.kmeans.steps(max_colors, &self.cells(colors))
+	}
+}
+
+impl Selector for GridKmeans {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let cells = self.cells(colors);
+		self.kmeans.select(max_colors, &cells)
+	}
+}
+
+/// Starts with every colour in one cluster and splits the cluster with the
+/// most squared error in two until there are `max_colors` of them. Each split
+/// is across the cluster's principal axis, the direction its colours are most
+/// spread along, at the mean. It's deterministic like [Agglomerative] and
+/// usually faster; the palette is better than median cut but not quite as
+/// good as k-means.
+///
+/// Distances are Euclidean in RGB.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BinarySplit {}
+
+/// The colours of one cluster of a [BinarySplit]
+struct Split {
+	colors: Vec<(RGB8, usize)>,
+	count: f64,
+	mean: [f64; 3],
+	/// The weighted sum of squared distances from the mean
+	error: f64,
+	/// False if every colour projects to the same point on the axis, so it
+	/// can't be split.
+	splittable: bool,
+}
+
+impl Split {
+	fn new(colors: Vec<(RGB8, usize)>) -> Self {
+		let count: f64 = colors.iter().map(|(_, count)| *count as f64).sum();
+
+		let mut mean = [0.0; 3];
+		for (colour, weight) in &colors {
+			let weight = *weight as f64 / count;
+			mean[0] += colour.r as f64 * weight;
+			mean[1] += colour.g as f64 * weight;
+			mean[2] += colour.b as f64 * weight;
+		}
+
+		let error = colors
+			.iter()
+			.map(|(colour, weight)| {
+				let offset = Self::offset(*colour, mean);
+				(offset[0].powi(2) + offset[1].powi(2) + offset[2].powi(2)) * *weight as f64
+			})
+			.sum();
+
+		Self {
+			splittable: colors.len() > 1,
+			colors,
+			count,
+			mean,
+			error,
+		}
+	}
+
+	fn offset(colour: RGB8, mean: [f64; 3]) -> [f64; 3] {
+		[
+			colour.r as f64 - mean[0],
+			colour.g as f64 - mean[1],
+			colour.b as f64 - mean[2],
+		]
+	}
+
+	/// The eigenvector of the covariance with the largest eigenvalue, found by
+	/// power iteration.
+	fn principal_axis(&self) -> [f64; 3] {
+		let mut covariance = [[0.0; 3]; 3];
+		for (colour, weight) in &self.colors {
+			let offset = Self::offset(*colour, self.mean);
+			for row in 0..3 {
+				for col in 0..3 {
+					covariance[row][col] += offset[row] * offset[col] * *weight as f64;
+				}
+			}
+		}
+
+		// start along the channel with the most variance. it can't be
+		// orthogonal to the principal axis unless they're all equal
+		let widest = (0..3)
+			.max_by(|&a, &b| covariance[a][a].total_cmp(&covariance[b][b]))
+			.unwrap_or(0);
+		let mut axis = [0.0; 3];
+		axis[widest] = 1.0;
+
+		for _ in 0..16 {
+			let next: [f64; 3] =
+				std::array::from_fn(|row| (0..3).map(|col| covariance[row][col] * axis[col]).sum());
+			let length = next.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+			if length == 0.0 {
+				break;
+			}
+			axis = next.map(|v| v / length);
+		}
+
+		axis
+	}
+
+	/// Split in two across the principal axis. Returns None if all of the
+	/// colours fall on one side.
+	fn split(&self) -> Option<(Split, Split)> {
+		let axis = self.principal_axis();
+		let project = |colour: RGB8| {
+			let offset = Self::offset(colour, self.mean);
+			offset[0] * axis[0] + offset[1] * axis[1] + offset[2] * axis[2]
+		};
+
+		let (below, above): (Vec<_>, Vec<_>) = self
+			.colors
+			.iter()
+			.partition(|(colour, _)| project(*colour) <= 0.0);
+
+		if below.is_empty() || above.is_empty() {
+			None
+		} else {
+			Some((Split::new(below), Split::new(above)))
+		}
+	}
+
+	fn color(&self) -> RGB8 {
+		let [r, g, b] = self.mean.map(|channel| channel.round() as u8);
+		RGB8::new(r, g, b)
+	}
+}
+
+impl Selector for BinarySplit {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		if colors.is_empty() || max_colors == 0 {
+			return vec![];
+		}
+
+		let mut clusters = vec![Split::new(colors.to_vec())];
+		while clusters.len() < max_colors {
+			let Some(worst) = clusters
+				.iter()
+				.enumerate()
+				.filter(|(_, cluster)| cluster.splittable)
+				.max_by(|(_, a), (_, b)| a.error.total_cmp(&b.error))
+				.map(|(idx, _)| idx)
+			else {
+				break;
+			};
+
+			match clusters[worst].split() {
+				Some((below, above)) => {
+					clusters[worst] = below;
+					clusters.push(above);
+				}
+				None => clusters[worst].splittable = false,
+			}
+		}
+
+		clusters.sort_by(|a, b| b.count.total_cmp(&a.count));
+		clusters.iter().map(Split::color).collect()
+	}
+}
+
+/// "Selects" the palette it was given, ignoring the image. This is what
+/// [Squasher::from_palette](crate::Squasher::from_palette) uses.
+pub struct Fixed(pub Vec<RGB8>);
+
+impl Selector for Fixed {
+	fn select(&mut self, max_colors: usize, _colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		self.0.iter().take(max_colors).copied().collect()
+	}
+}
+
+/// Runs each of its selectors and keeps whichever palette scores best by an
+/// [Objective], for when it's not clear which suits the image. The default
+/// tries [SortSelect], [Kmeans], [Agglomerative], and [BinarySplit].
+///
+/// It takes as long as all of them together. [BestOf::sample] compares them
+/// on fewer colours and only runs the winner on all of them.
+pub struct BestOf {
+	selectors: Vec<Box<dyn Selector>>,
+	objective: Objective,
+	difference_fn: Box<DiffFn>,
+	sample: Option<usize>,
+	/// Which selector made the last palette
+	chosen: Option<usize>,
+}
+
+impl BestOf {
+	pub fn new(selectors: Vec<Box<dyn Selector>>) -> Self {
+		Self {
+			selectors,
+			objective: Objective::default(),
 			difference_fn: Box::new(difference::rgb),
+			sample: None,
+			chosen: None,
+		}
+	}
+
+	/// What makes one palette better than another. The default is the mean
+	/// difference to the palette over every pixel. See [Objective].
+	pub fn objective(mut self, objective: Objective) -> Self {
+		self.objective = objective;
+		self
+	}
+
+	/// The function palettes are scored with. It doesn't change how the
+	/// selectors compare colours, they each have their own.
+	///
+	/// see the [difference] module for functions included with the crate and
+	/// information on implementing your own.
+	pub fn difference(mut self, diff_fn: &'static DiffFn) -> Self {
+		self.difference_fn = Box::new(diff_fn);
+		self
+	}
+
+	/// Compare the selectors on at most this many of the colours, evenly
+	/// spaced from most to least frequent, and then run the winner again on
+	/// all of them. A few thousand is usually enough to tell them apart.
+	pub fn sample(mut self, colours: usize) -> Self {
+		self.sample = Some(colours.max(1));
+		self
+	}
+
+	/// Which of the selectors, by its position, made the last palette
+	pub fn chosen(&self) -> Option<usize> {
+		self.chosen
+	}
+}
+
+impl Default for BestOf {
+	fn default() -> Self {
+		Self::new(vec![
+			Box::new(SortSelect::default()),
+			Box::new(Kmeans {
+				max_iter: 10,
+				..Default::default()
+			}),
+			Box::new(Agglomerative::default()),
+			Box::new(BinarySplit {}),
+		])
+	}
+}
+
+impl Selector for BestOf {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let sample: Option<Vec<(RGB8, usize)>> = match self.sample {
+			Some(size) if size < colors.len() => {
+				let step = colors.len().div_ceil(size);
+				Some(colors.iter().step_by(step).copied().collect())
+			}
+			_ => None,
+		};
+		let trial = sample.as_deref().unwrap_or(colors);
+
+		// the first of the best, so a tie goes to whichever was given first
+		let mut best: Option<(usize, f32, Vec<RGB8>)> = None;
+		for (idx, selector) in self.selectors.iter_mut().enumerate() {
+			let palette = selector.select(max_colors, trial);
+			let score = self
+				.objective
+				.score_palette(trial, &palette, &self.difference_fn);
+
+			let better = match &best {
+				Some((_, best, _)) => score < *best,
+				None => true,
+			};
+			if better {
+				best = Some((idx, score, palette));
+			}
+		}
+
+		let Some((idx, _, palette)) = best else {
+			return vec![];
+		};
+
+		self.chosen = Some(idx);
+		if sample.is_some() {
+			self.selectors[idx].select(max_colors, colors)
+		} else {
+			palette
 		}
 	}
 }
 
-pub struct HighestBits {}
+/// Keeps only the highest bits of each channel, snapping every colour to a
+/// grid. If there are more grid cells in the image than `max_colors`, the ones
+/// covering the most pixels are kept.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HighestBits {
+	/// How many bits of red, green, and blue to keep, each at most 8. Like
+	/// `Some([5, 6, 5])` for RGB565. If it's None, as many as fit in
+	/// `max_colors` are kept, with any left over going to green and then red,
+	/// so 100 colours keeps 6 bits and at most 64 cells.
+	pub bits: Option<[u8; 3]>,
+}
 
-impl Selector for HighestBits {
-	fn select(&mut self, max_colors: usize, image: ImageData) -> Vec<RGB8> {
-		let bits = max_colors.next_power_of_two().ilog2();
-		let leftover = bits % 3;
-		let shift = 8 - (bits / 3);
+impl HighestBits {
+	/// The bits to keep of each channel when they aren't set
+	fn fitting(max_colors: usize) -> [u8; 3] {
+		// round down, so the grid never has more cells than max_colors
+		let bits = max_colors.checked_ilog2().unwrap_or(0).min(24) as u8;
+		let even = bits / 3;
 
 		//TODO: gen- we're taking red/green here because, as i remember, they
 		// are the colours to which we are most sensetive? but it would be cool
 		// if this was selectable
-		let (rshift, gshift, bshift) = match leftover {
-			0 => (shift, shift, shift),
-			1 => (shift, shift - 1, shift),
-			2 => (shift - 1, shift - 1, shift),
+		match bits % 3 {
+			0 => [even, even, even],
+			1 => [even, even + 1, even],
+			2 => [even + 1, even + 1, even],
 			_ => unreachable!(),
+		}
+	}
+}
+
+impl Selector for HighestBits {
+	fn select(&mut self, max_colors: usize, colors: &[(RGB8, usize)]) -> Vec<RGB8> {
+		let [rbits, gbits, bbits] = self.bits.unwrap_or_else(|| Self::fitting(max_colors));
+		let keep = |channel: u8, bits: u8| match bits.min(8) {
+			0 => 0,
+			bits => channel >> (8 - bits) << (8 - bits),
 		};
 
-		image
-			.0
-			.iter()
-			.map(|color| {
-				RGB8::new(
-					color.r >> rshift << rshift,
-					color.g >> gshift << gshift,
-					color.b >> bshift << bshift,
-				)
-			})
-			.collect::<HashSet<_>>()
+		let mut cells: HashMap<RGB8, usize> = HashMap::new();
+		for (color, count) in colors {
+			let cell = RGB8::new(
+				keep(color.r, rbits),
+				keep(color.g, gbits),
+				keep(color.b, bbits),
+			);
+			*cells.entry(cell).or_default() += count;
+		}
+
+		sort(cells)
 			.into_iter()
+			.take(max_colors)
+			.map(|(c, _)| c)
 			.collect()
 	}
 }
@@ -0,0 +1,247 @@
+//! A palette that many threads map to at once while another replaces it, like
+//! a server quantizing requests to a palette it keeps up to date with what it's
+//! been sent.
+//!
+//! A [Squasher](crate::Squasher) can't be shared like that. It isn't
+//! [Send], `map_no_recolor` gives index 0 for colours a lazy map hasn't seen,
+//! and after the palette changes the map has to be brought up to date before
+//! anything is mapped. [SharedSquasher] keeps the palette and a map that knows
+//! every colour together in one [Arc]. Readers take the current one and map
+//! without holding a lock, and [SharedSquasher::publish] builds the next one
+//! before swapping it in, so nobody sees a palette with the wrong map.
+//!
+//! The usual setup is worker threads calling [SharedSquasher::record] and
+//! [SharedSquasher::map_no_recolor] on each image, and a background thread with
+//! its own Squasher that now and then takes the recorded colours with
+//! [SharedSquasher::take_histogram], passes them to
+//! [Squasher::feed_histogram](crate::Squasher::feed_histogram), calls
+//! [Squasher::finalize_palette](crate::Squasher::finalize_palette), and
+//! publishes the new palette.
+
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex, PoisonError, RwLock},
+};
+
+use rgb::RGB8;
+
+use crate::{
+	backend::{ColourMap, MapBackend},
+	difference::{ColorDifference, Rgb},
+	Count, Error, ImageData, MapLayout,
+};
+
+/// A palette with a map for it, as published to a [SharedSquasher].
+pub struct Published<T, D = Rgb> {
+	palette: Vec<RGB8>,
+	map: ColourMap<T>,
+	difference: D,
+	generation: u64,
+}
+
+impl<T: Count, D: ColorDifference> Published<T, D> {
+	pub fn palette(&self) -> &[RGB8] {
+		&self.palette
+	}
+
+	/// How many palettes were published before this one
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+
+	/// Fill the output buffer with indices into this palette, like
+	/// [Squasher::map](crate::Squasher::map). Every colour is mapped, seen
+	/// before or not.
+	///
+	/// # Panics
+	/// If the output buffer is too small. See [Published::try_map]
+	pub fn map<'a, Img>(&self, image: Img, buffer: &mut [T])
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		if let Err(e) = self.try_map(image, buffer) {
+			panic!("{e}");
+		}
+	}
+
+	/// Like [Published::map] but returns an error if the output buffer is too
+	/// small instead of panicking.
+	pub fn try_map<'a, Img>(&self, image: Img, buffer: &mut [T]) -> Result<(), Error>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+		span!("map", pixels = rgb.len());
+		if buffer.len() < rgb.len() {
+			return Err(Error::BufferTooSmall {
+				needed: rgb.len(),
+				len: buffer.len(),
+			});
+		}
+
+		for (idx, color) in rgb.iter().enumerate() {
+			buffer[idx] = self.map.get(color, &self.difference);
+		}
+
+		Ok(())
+	}
+}
+
+/// A palette and map that can be read from any number of threads and
+/// replaced from another. See the [module documentation](self).
+pub struct SharedSquasher<T, D = Rgb> {
+	current: RwLock<Arc<Published<T, D>>>,
+	difference: D,
+	backend: MapBackend,
+	/// Colours recorded from the images mapped since the histogram was last
+	/// taken
+	histogram: Mutex<HashMap<RGB8, usize>>,
+	sample_every: usize,
+}
+
+impl<T: Count, D: ColorDifference + Clone> SharedSquasher<T, D> {
+	/// Share a palette, looking up each pixel in a
+	/// [k-d tree](MapBackend::KdTree) of it. That's exact for [Rgb] and nearly
+	/// for [Redmean](crate::difference::Redmean); see [SharedSquasher::full_map]
+	/// for the other way.
+	pub fn new(palette: impl Into<Vec<RGB8>>, difference: D) -> Self {
+		Self::with_backend(palette.into(), difference, MapBackend::KdTree, 16)
+	}
+
+	/// Look up pixels in a table of every 24-bit colour, filled in whenever a
+	/// palette is published, instead of a tree. Mapping is quicker and right
+	/// for any difference function, but it's 16MB or more for each palette
+	/// and [SharedSquasher::publish] takes several seconds. The current palette
+	/// is published again.
+	pub fn full_map(self, full: bool) -> Self {
+		let backend = if full {
+			MapBackend::Lut
+		} else {
+			MapBackend::KdTree
+		};
+
+		let palette = self.current().palette.clone();
+		Self::with_backend(palette, self.difference, backend, self.sample_every)
+	}
+
+	/// Record one pixel out of every `n` in [SharedSquasher::record]. The
+	/// default is 16.
+	pub fn sample_every(mut self, n: usize) -> Self {
+		self.sample_every = n.max(1);
+		self
+	}
+
+	fn with_backend(
+		palette: Vec<RGB8>,
+		difference: D,
+		backend: MapBackend,
+		sample_every: usize,
+	) -> Self {
+		let published = Self::prepare(palette, &difference, backend, 0);
+
+		Self {
+			current: RwLock::new(Arc::new(published)),
+			difference,
+			backend,
+			histogram: Mutex::new(HashMap::new()),
+			sample_every,
+		}
+	}
+
+	fn prepare(
+		palette: Vec<RGB8>,
+		difference: &D,
+		backend: MapBackend,
+		generation: u64,
+	) -> Published<T, D> {
+		let mut map = ColourMap::new(backend, MapLayout::Linear);
+		map.palette_changed(&palette, difference);
+
+		Published {
+			palette,
+			map,
+			difference: difference.clone(),
+			generation,
+		}
+	}
+
+	/// The palette and map being mapped to now. Keep hold of it to map more
+	/// than one image to the same palette.
+	pub fn current(&self) -> Arc<Published<T, D>> {
+		// the lock only guards swapping an Arc, which can't be left half done
+		self.current
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	/// Map an image to the current palette, see [Published::map].
+	///
+	/// # Returns
+	/// The palette it was mapped to, which might not be current anymore by the
+	/// time this returns.
+	///
+	/// # Panics
+	/// If the output buffer is too small.
+	pub fn map_no_recolor<'a, Img>(&self, image: Img, buffer: &mut [T]) -> Arc<Published<T, D>>
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let current = self.current();
+		current.map(image, buffer);
+		current
+	}
+
+	/// Replace the palette. The map for it is made first, without blocking
+	/// anything mapping to the old one, and then it's swapped in. Images being
+	/// mapped keep mapping to the palette they started with.
+	///
+	/// An empty palette is ignored, so a quiet spell with nothing recorded to
+	/// select from doesn't leave readers with nothing to map to.
+	pub fn publish(&self, palette: impl Into<Vec<RGB8>>) {
+		let palette = palette.into();
+		if palette.is_empty() {
+			return;
+		}
+
+		let mut published = Self::prepare(palette, &self.difference, self.backend, 0);
+
+		let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+		published.generation = current.generation + 1;
+		*current = Arc::new(published);
+	}
+
+	/// Count some of the pixels of an image, see
+	/// [SharedSquasher::sample_every], to select the next palette from.
+	pub fn record<'a, Img>(&self, image: Img)
+	where
+		Img: Into<ImageData<'a>>,
+	{
+		let ImageData { pixels: rgb, .. } = image.into();
+
+		// count outside of the lock so other threads can record at the same time
+		let mut counted: HashMap<RGB8, usize> = HashMap::new();
+		for colour in rgb.iter().step_by(self.sample_every) {
+			*counted.entry(*colour).or_default() += 1;
+		}
+
+		let mut histogram = self
+			.histogram
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		for (colour, count) in counted {
+			*histogram.entry(colour).or_default() += count;
+		}
+	}
+
+	/// Everything recorded since the last time this was called, for
+	/// [Squasher::feed_histogram](crate::Squasher::feed_histogram).
+	pub fn take_histogram(&self) -> HashMap<RGB8, usize> {
+		std::mem::take(
+			&mut *self
+				.histogram
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner),
+		)
+	}
+}
@@ -0,0 +1,261 @@
+//! Saving a [Squasher] with serde so it can be loaded somewhere else, like a
+//! server selecting a palette once and handing it to worker processes.
+//!
+//! Selectors and difference functions are trait objects, so a [Snapshot]
+//! can't read them out of a Squasher. If you want them saved, say which ones
+//! you used with [Snapshot::selector] and [Snapshot::difference].
+
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	backend::ColourMap,
+	difference::{self, ColorDifference, SyncDiffFn},
+	lut::{self, MapError},
+	sampling::Sampling,
+	selection::{
+		Agglomerative, BestOf, BinarySplit, Fixed, GridKmeans, HeuristicSorsel, HighestBits,
+		Kmeans, MiniBatchKmeans, Selector, SortSelect,
+	},
+	Count, MapLayout, Squasher, SquasherBuilder,
+};
+
+/// The difference functions included with the crate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difference {
+	#[default]
+	Rgb,
+	Redmean,
+}
+
+impl Difference {
+	pub fn function(self) -> &'static SyncDiffFn {
+		match self {
+			Difference::Rgb => &difference::rgb,
+			Difference::Redmean => &difference::redmean,
+		}
+	}
+}
+
+/// The configuration of one of the selectors included with the crate.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SelectorConfig {
+	SortSelect {
+		tolerance: f32,
+		difference: Difference,
+	},
+	HeuristicSorsel {
+		tolerance: f32,
+		variance: f32,
+		max_attempts: usize,
+		difference: Difference,
+	},
+	Kmeans {
+		max_iter: usize,
+		seed: Option<u64>,
+	},
+	MiniBatchKmeans {
+		batch_size: usize,
+		max_iter: usize,
+		seed: u64,
+	},
+	Agglomerative {
+		cell_bits: u8,
+	},
+	GridKmeans {
+		cell_bits: u8,
+		max_iter: usize,
+		seed: Option<u64>,
+	},
+	BinarySplit,
+	HighestBits {
+		bits: Option<[u8; 3]>,
+	},
+	Fixed(Vec<RGB8>),
+	/// [BestOf] the selectors of these configurations
+	BestOf {
+		selectors: Vec<SelectorConfig>,
+		sample: Option<usize>,
+	},
+}
+
+impl SelectorConfig {
+	pub fn build(&self) -> Box<dyn Selector> {
+		match self {
+			SelectorConfig::SortSelect {
+				tolerance,
+				difference,
+			} => Box::new(
+				SortSelect::default()
+					.tolerance(*tolerance)
+					.difference(difference.function()),
+			),
+			SelectorConfig::HeuristicSorsel {
+				tolerance,
+				variance,
+				max_attempts,
+				difference,
+			} => Box::new(
+				HeuristicSorsel::default()
+					.tolerance(*tolerance)
+					.variance(*variance)
+					.max_attempts(*max_attempts)
+					.difference(difference.function()),
+			),
+			SelectorConfig::Kmeans { max_iter, seed } => Box::new(Kmeans {
+				max_iter: *max_iter,
+				seed: *seed,
+				..Default::default()
+			}),
+			SelectorConfig::MiniBatchKmeans {
+				batch_size,
+				max_iter,
+				seed,
+			} => Box::new(MiniBatchKmeans {
+				batch_size: *batch_size,
+				max_iter: *max_iter,
+				seed: *seed,
+				cancel: None,
+			}),
+			SelectorConfig::Agglomerative { cell_bits } => Box::new(Agglomerative {
+				cell_bits: *cell_bits,
+			}),
+			SelectorConfig::GridKmeans {
+				cell_bits,
+				max_iter,
+				seed,
+			} => Box::new(GridKmeans {
+				cell_bits: *cell_bits,
+				kmeans: Kmeans {
+					max_iter: *max_iter,
+					seed: *seed,
+					..Default::default()
+				},
+			}),
+			SelectorConfig::BinarySplit => Box::new(BinarySplit {}),
+			SelectorConfig::HighestBits { bits } => Box::new(HighestBits { bits: *bits }),
+			SelectorConfig::Fixed(palette) => Box::new(Fixed(palette.clone())),
+			SelectorConfig::BestOf { selectors, sample } => {
+				let best = BestOf::new(selectors.iter().map(SelectorConfig::build).collect());
+				match sample {
+					Some(sample) => Box::new(best.sample(*sample)),
+					None => Box::new(best),
+				}
+			}
+		}
+	}
+}
+
+/// The saved state of a [Squasher].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+	pub palette: Vec<RGB8>,
+	pub max_colors: usize,
+	pub sampling: Sampling,
+	pub pinned: Vec<RGB8>,
+	/// The difference function used while mapping
+	pub difference: Difference,
+	/// The selector used when recoloring. If there isn't one, the restored
+	/// Squasher keeps the saved palette when recolored.
+	pub selector: Option<SelectorConfig>,
+	/// The colour map in the run-length encoded format of the [lut] module.
+	map: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+	/// Save the palette, max colours, sampling, and pinned colours of the
+	/// Squasher. The difference is assumed to be [Difference::Rgb] and the map
+	/// isn't saved; see [Snapshot::with_map].
+	pub fn new<T: Count, D>(squasher: &Squasher<T, D>) -> Self {
+		Self {
+			palette: squasher.palette.clone(),
+			max_colors: squasher.max_colours_min1.as_usize() + 1,
+			sampling: squasher.sampling,
+			pinned: squasher.pinned.clone(),
+			difference: Difference::Rgb,
+			selector: None,
+			map: None,
+		}
+	}
+
+	pub fn selector(mut self, selector: SelectorConfig) -> Self {
+		self.selector = Some(selector);
+		self
+	}
+
+	pub fn difference(mut self, difference: Difference) -> Self {
+		self.difference = difference;
+		self
+	}
+
+	/// Save the Squasher's colour map too, so colours it's already seen don't
+	/// need mapping again after it's restored. Call [Squasher::fill_map] first
+	/// to save every colour. It's compressed, but can still be a megabyte or so.
+	pub fn with_map<T: Count, D: ColorDifference>(mut self, squasher: &Squasher<T, D>) -> Self {
+		let mut map = vec![];
+		// writing to a Vec can't fail
+		lut::write_compressed(
+			&squasher.palette,
+			&squasher.map.linear(&squasher.difference_fn),
+			&mut map,
+		)
+		.unwrap();
+
+		self.map = Some(map);
+		self
+	}
+
+	pub fn has_map(&self) -> bool {
+		self.map.is_some()
+	}
+
+	/// Make a Squasher from the snapshot.
+	///
+	/// # Errors
+	/// If the saved map is corrupt, was saved from a Squasher with a
+	/// different index type than `T`, or the palette has more colours than `T`
+	/// can index.
+	pub fn restore<T: Count>(&self) -> Result<Squasher<T>, MapError> {
+		let selector = match &self.selector {
+			Some(config) => config.build(),
+			None => Box::new(Fixed(self.palette.clone())),
+		};
+
+		if self.palette.len() > T::max_colors() {
+			return Err(MapError::PaletteTooLarge {
+				len: self.palette.len(),
+				max: T::max_colors(),
+			});
+		}
+
+		let mut squasher = SquasherBuilder::new()
+			.mapper_difference(self.difference.function())
+			.build_with_palette(self.palette.clone());
+		let max_colors = self.max_colors.min(T::max_colors());
+		squasher.max_colours_min1 = T::from_usize(max_colors.saturating_sub(1));
+		squasher.sampling = self.sampling;
+		squasher.pinned = self.pinned.clone();
+		squasher.selector = selector;
+
+		if let Some(compressed) = &self.map {
+			let bytes = lut::decompress(compressed)?;
+			let view = lut::MapView::new(&bytes)?;
+
+			let width = std::mem::size_of::<T>();
+			if view.width() != width {
+				return Err(MapError::BadWidth(view.width() as u8));
+			}
+
+			let map = (0..lut::MAP_LEN)
+				.map(|index| T::from_usize(view.index_at(index)))
+				.collect();
+			squasher.map = ColourMap::Table {
+				map,
+				layout: MapLayout::Linear,
+				eager: false,
+			};
+		}
+
+		Ok(squasher)
+	}
+}
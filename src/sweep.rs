@@ -0,0 +1,114 @@
+//! Trying a range of values for a setting and keeping whichever gives the
+//! closest image, with [best], or whatever else matters to you, with [best_by]
+//! and an [Objective]. [HeuristicSorsel](crate::selection::HeuristicSorsel)
+//! does this for the tolerance of sort/select; [sweep] works for any number
+//! you can set on a [SquasherBuilder].
+
+use rgb::RGB8;
+
+use crate::{
+	difference::ColorDifference,
+	metrics::{self, Measurements, Objective},
+	selection, Count, ImageData, SquasherBuilder,
+};
+
+/// Values from `start` to `end`, inclusive, `step` apart.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Range {
+	pub start: f64,
+	pub end: f64,
+	pub step: f64,
+}
+
+impl Range {
+	pub fn new(start: f64, end: f64, step: f64) -> Self {
+		Self { start, end, step }
+	}
+
+	/// The values in the range. Empty if `step` isn't positive or `end` is
+	/// before `start`.
+	pub fn values(&self) -> Vec<f64> {
+		// written this way round so NaN is empty too
+		let usable = self.step > 0.0 && self.end >= self.start;
+		if !usable {
+			return vec![];
+		}
+
+		// multiplying rather than adding the step over and over, so the error
+		// doesn't build up and skip the end
+		let steps = ((self.end - self.start) / self.step + 1e-9).floor() as usize;
+		(0..=steps)
+			.map(|idx| self.start + self.step * idx as f64)
+			.collect()
+	}
+}
+
+/// One value of the range and how it did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trial {
+	pub value: f64,
+	/// See [metrics::psnr]. Higher is better.
+	pub psnr: f64,
+	/// Measured with the difference function the Squasher maps with
+	pub measurements: Measurements,
+	pub palette: Vec<RGB8>,
+}
+
+/// Select a palette and map the image for every value in the range, using the
+/// builder `make` returns for that value, and score each with [metrics::psnr]
+/// and [Measurements]. The trials are in the same order as the values.
+pub fn sweep<'a, T, D, Img, F>(image: Img, range: Range, mut make: F) -> Vec<Trial>
+where
+	T: Count,
+	D: ColorDifference,
+	Img: Into<ImageData<'a>>,
+	F: FnMut(f64) -> SquasherBuilder<T, D>,
+{
+	let image = image.into();
+	let pixels = image.pixels.len();
+
+	let mut indices = vec![T::zero(); pixels];
+	let mut quantized = vec![RGB8::new(0, 0, 0); pixels];
+	let colors = selection::histogram(&image);
+
+	range
+		.values()
+		.into_iter()
+		.map(|value| {
+			let mut squasher = make(value).build(&image);
+			squasher.map(&image, &mut indices);
+
+			let palette = squasher.palette();
+			for (out, index) in quantized.iter_mut().zip(&indices) {
+				*out = palette
+					.get(index.as_usize())
+					.copied()
+					.unwrap_or(RGB8::new(0, 0, 0));
+			}
+
+			Trial {
+				value,
+				psnr: metrics::psnr(&image, quantized.as_slice()),
+				measurements: Measurements::new(&colors, palette, &squasher.difference_fn),
+				palette: palette.to_vec(),
+			}
+		})
+		.collect()
+}
+
+/// The trial with the highest PSNR. If some are equal it's the first.
+pub fn best(trials: &[Trial]) -> Option<&Trial> {
+	trials
+		.iter()
+		.reduce(|best, trial| if trial.psnr > best.psnr { trial } else { best })
+}
+
+/// The trial with the lowest score by the objective. If some are equal it's
+/// the first.
+pub fn best_by<'a>(trials: &'a [Trial], objective: &Objective) -> Option<&'a Trial> {
+	trials
+		.iter()
+		.map(|trial| (trial, objective.score(&trial.measurements)))
+		.reduce(|best, trial| if trial.1 < best.1 { trial } else { best })
+		.map(|(trial, _)| trial)
+}
@@ -0,0 +1,103 @@
+//! Derive light and dark variants of a palette for theming.
+//!
+//! The lightness of every colour is remapped into a range suitable for the
+//! theme while the hue and chroma are kept, all in OKLab. If a colour would
+//! fall outside of sRGB at its new lightness, the chroma is reduced just
+//! enough to bring it back in.
+
+use rgb::RGB8;
+
+use crate::oklab::Oklab;
+
+/// A palette alongside its light and dark variants. Each variant is in the same
+/// order as the original, so `light[i]` and `dark[i]` are both derived from
+/// `original[i]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+	pub original: Vec<RGB8>,
+	pub light: Vec<RGB8>,
+	pub dark: Vec<RGB8>,
+}
+
+impl Theme {
+	/// The OKLab lightness range, 0.0 to 1.0, the light variant is mapped into.
+	pub const LIGHT_RANGE: (f32, f32) = (0.55, 0.97);
+	/// The OKLab lightness range, 0.0 to 1.0, the dark variant is mapped into.
+	pub const DARK_RANGE: (f32, f32) = (0.15, 0.55);
+
+	/// Derive the light and dark variants with [Theme::LIGHT_RANGE] and
+	/// [Theme::DARK_RANGE].
+	pub fn derive(palette: &[RGB8]) -> Self {
+		Self::derive_with(palette, Self::LIGHT_RANGE, Self::DARK_RANGE)
+	}
+
+	/// Derive the light and dark variants, mapping the lightness of the palette
+	/// into the given `(low, high)` ranges. The relative lightness of the colours
+	/// is preserved, the darkest colour in the palette becomes `low` and the
+	/// lightest becomes `high`.
+	pub fn derive_with(palette: &[RGB8], light: (f32, f32), dark: (f32, f32)) -> Self {
+		let labs: Vec<Oklab> = palette.iter().map(|c| Oklab::from_rgb(*c)).collect();
+
+		let (min, max) = labs.iter().fold((f32::MAX, f32::MIN), |(min, max), lab| {
+			(min.min(lab.l), max.max(lab.l))
+		});
+
+		Self {
+			original: palette.to_vec(),
+			light: remap(&labs, min, max, light),
+			dark: remap(&labs, min, max, dark),
+		}
+	}
+}
+
+fn remap(labs: &[Oklab], min: f32, max: f32, (low, high): (f32, f32)) -> Vec<RGB8> {
+	let span = max - min;
+
+	labs.iter()
+		.map(|lab| {
+			// a palette of one lightness lands in the middle of the range
+			let t = if span > f32::EPSILON {
+				(lab.l - min) / span
+			} else {
+				0.5
+			};
+
+			let mapped = Oklab {
+				l: low + t * (high - low),
+				..*lab
+			};
+
+			fit_chroma(mapped).to_rgb()
+		})
+		.collect()
+}
+
+/// Reduce the chroma of the colour, keeping the hue, until it fits in sRGB.
+fn fit_chroma(lab: Oklab) -> Oklab {
+	if lab.in_gamut() {
+		return lab;
+	}
+
+	let mut lower = 0.0;
+	let mut upper = 1.0;
+	for _ in 0..16 {
+		let mid = (lower + upper) / 2.0;
+		let scaled = Oklab {
+			a: lab.a * mid,
+			b: lab.b * mid,
+			..lab
+		};
+
+		if scaled.in_gamut() {
+			lower = mid;
+		} else {
+			upper = mid;
+		}
+	}
+
+	Oklab {
+		a: lab.a * lower,
+		b: lab.b * lower,
+		..lab
+	}
+}
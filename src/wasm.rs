@@ -0,0 +1,94 @@
+//! Bindings for WebAssembly. They're built by the `colorsquash-wasm` crate, and
+//! the `js` directory at the root of the repository has the wrapper package
+//! that uses them.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{selection::SortSelect, SquasherBuilder};
+
+/// The result of [quantize]. The palette is RGB, three bytes per colour, and
+/// there is one index per pixel.
+#[wasm_bindgen]
+pub struct Quantized {
+	palette: Vec<u8>,
+	indices: Vec<u8>,
+	width: u32,
+	height: u32,
+}
+
+#[wasm_bindgen]
+impl Quantized {
+	#[wasm_bindgen(getter)]
+	pub fn palette(&self) -> Vec<u8> {
+		self.palette.clone()
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn indices(&self) -> Vec<u8> {
+		self.indices.clone()
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+}
+
+/// Quantize an image of RGB or RGBA pixels, like a canvas' `ImageData`. Alpha
+/// is ignored. `max_colors` is between 1 and 256, inclusive.
+#[wasm_bindgen]
+pub fn quantize(
+	pixels: &[u8],
+	width: u32,
+	height: u32,
+	channels: u32,
+	max_colors: u32,
+	tolerance: Option<f32>,
+) -> Result<Quantized, JsError> {
+	if max_colors == 0 || max_colors > 256 {
+		return Err(JsError::new("max_colors must be >= 1 and <= 256"));
+	}
+
+	// usize is 32 bits on wasm32, so this can overflow
+	let count = (width as usize)
+		.checked_mul(height as usize)
+		.ok_or_else(|| JsError::new("width * height is too large"))?;
+	let rgb: Vec<u8> = match channels {
+		3 if count.checked_mul(3) == Some(pixels.len()) => pixels.to_vec(),
+		4 if count.checked_mul(4) == Some(pixels.len()) => pixels
+			.chunks_exact(4)
+			.flat_map(|px| [px[0], px[1], px[2]])
+			.collect(),
+		3 | 4 => {
+			return Err(JsError::new(
+				"pixels is not width * height * channels bytes long",
+			))
+		}
+		_ => return Err(JsError::new("channels must be 3 or 4")),
+	};
+
+	let mut sorsel = SortSelect::default();
+	if let Some(tol) = tolerance {
+		sorsel = sorsel.tolerance(tol);
+	}
+
+	let mut squasher = SquasherBuilder::new()
+		.max_colors((max_colors - 1) as u8)
+		.selector(sorsel)
+		.build(&rgb);
+
+	let mut indices = vec![0; count];
+	squasher.map(&rgb, &mut indices);
+
+	Ok(Quantized {
+		palette: squasher.palette_bytes(),
+		indices,
+		width,
+		height,
+	})
+}